@@ -0,0 +1,162 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use micro_http::{Body, Method};
+use serde_json::{json, Value};
+use utils::time::{get_time_us, ClockType};
+
+/// Journal of the API requests which changed the microVM, one JSON object per line.
+///
+/// The recorded requests can be issued again, in the same order, with the `firecracker-replay`
+/// tool. The MMDS requests are only recorded when asked for, since the data store often holds
+/// credentials.
+pub struct ApiJournal {
+    file: File,
+    record_mmds: bool,
+}
+
+impl ApiJournal {
+    /// Opens the journal file found at `path`, creating it if needed with permissions for its
+    /// owner only. New entries are appended.
+    ///
+    /// The requests to the `/mmds` endpoints are left out unless `record_mmds` is set.
+    pub fn new(path: &Path, record_mmds: bool) -> io::Result<ApiJournal> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(ApiJournal { file, record_mmds })
+    }
+
+    /// Appends a request to the journal.
+    pub(crate) fn record(
+        &mut self,
+        method: Method,
+        path: &str,
+        body: Option<&Body>,
+    ) -> io::Result<()> {
+        if !self.record_mmds && (path == "/mmds" || path.starts_with("/mmds/")) {
+            return Ok(());
+        }
+
+        let body = body.map(|body| {
+            // The bodies of the successful requests are JSON documents, but err on the safe side.
+            serde_json::from_slice::<Value>(body.raw())
+                .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body.raw()).into()))
+        });
+        let entry = json!({
+            "timestamp_us": get_time_us(ClockType::Real),
+            "method": method_name(method),
+            "path": path,
+            "body": body,
+        });
+
+        // A single write, so that the entries stay whole.
+        self.file.write_all(format!("{}\n", entry).as_bytes())
+    }
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Put => "PUT",
+        Method::Patch => "PATCH",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use utils::tempfile::TempFile;
+
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let tmp_file = TempFile::new().unwrap();
+        let mut journal = ApiJournal::new(tmp_file.as_path(), false).unwrap();
+
+        journal
+            .record(
+                Method::Put,
+                "/boot-source",
+                Some(&Body::new("{ \"kernel_image_path\": \"vmlinux\" }")),
+            )
+            .unwrap();
+        journal
+            .record(Method::Patch, "/vm", Some(&Body::new("not json")))
+            .unwrap();
+        journal.record(Method::Put, "/actions", None).unwrap();
+
+        let content = fs::read_to_string(tmp_file.as_path()).unwrap();
+        let entries: Vec<Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 3);
+
+        assert!(entries[0]["timestamp_us"].as_u64().unwrap() > 0);
+        assert_eq!(entries[0]["method"], "PUT");
+        assert_eq!(entries[0]["path"], "/boot-source");
+        assert_eq!(
+            entries[0]["body"],
+            json!({ "kernel_image_path": "vmlinux" })
+        );
+        assert_eq!(entries[1]["method"], "PATCH");
+        assert_eq!(entries[1]["body"], "not json");
+        assert_eq!(entries[2]["body"], Value::Null);
+
+        // Reopening the journal doesn't truncate it.
+        let mut journal = ApiJournal::new(tmp_file.as_path(), false).unwrap();
+        journal.record(Method::Put, "/actions", None).unwrap();
+        let content = fs::read_to_string(tmp_file.as_path()).unwrap();
+        assert_eq!(content.lines().count(), 4);
+    }
+
+    #[test]
+    fn test_mode() {
+        let tmp_file = TempFile::new().unwrap();
+        let path = tmp_file.as_path().with_extension("journal");
+        ApiJournal::new(&path, false).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[test]
+    fn test_record_mmds() {
+        let tmp_file = TempFile::new().unwrap();
+        let mmds_body = Body::new("{ \"token\": \"secret\" }");
+
+        // The MMDS requests are left out by default.
+        let mut journal = ApiJournal::new(tmp_file.as_path(), false).unwrap();
+        journal
+            .record(Method::Put, "/mmds", Some(&mmds_body))
+            .unwrap();
+        journal
+            .record(Method::Put, "/mmds/config", Some(&Body::new("{}")))
+            .unwrap();
+        journal.record(Method::Put, "/mmdsx", None).unwrap();
+        let content = fs::read_to_string(tmp_file.as_path()).unwrap();
+        assert!(!content.contains("secret"));
+        assert_eq!(content.lines().count(), 1);
+
+        let mut journal = ApiJournal::new(tmp_file.as_path(), true).unwrap();
+        journal
+            .record(Method::Patch, "/mmds", Some(&mmds_body))
+            .unwrap();
+        let content = fs::read_to_string(tmp_file.as_path()).unwrap();
+        let entry: Value = serde_json::from_str(content.lines().last().unwrap()).unwrap();
+        assert_eq!(entry["path"], "/mmds");
+        assert_eq!(entry["body"], json!({ "token": "secret" }));
+    }
+}
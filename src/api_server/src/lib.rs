@@ -9,6 +9,7 @@
 //! and responding to the user.
 //! It is constructed on top of an HTTP Server that uses Unix Domain Sockets and `EPOLL` to
 //! handle multiple connections on the same thread.
+mod journal;
 mod parsed_request;
 mod request;
 
@@ -29,6 +30,7 @@ use utils::eventfd::EventFd;
 use vmm::rpc_interface::{VmmAction, VmmActionError, VmmData};
 use vmm::vmm_config::snapshot::SnapshotType;
 
+pub use crate::journal::ApiJournal;
 use crate::parsed_request::{ParsedRequest, RequestAction};
 
 /// Shorthand type for a request containing a boxed VmmAction.
@@ -69,6 +71,8 @@ pub struct ApiServer {
     to_vmm_fd: EventFd,
     /// If this flag is set, the API thread will go down.
     shutdown_flag: bool,
+    /// Journal recording the successful requests.
+    journal: Option<ApiJournal>,
 }
 
 impl ApiServer {
@@ -85,9 +89,15 @@ impl ApiServer {
             vmm_response_receiver,
             to_vmm_fd,
             shutdown_flag: false,
+            journal: None,
         }
     }
 
+    /// Records the successful requests, other than the GET ones, in `journal`.
+    pub fn set_journal(&mut self, journal: ApiJournal) {
+        self.journal = Some(journal);
+    }
+
     /// Starts the HTTP Server by binding to the socket path provided as
     /// an argument.
     ///
@@ -246,7 +256,10 @@ impl ApiServer {
             Ok((req_action, mut parsing_info)) => {
                 let mut response = match req_action {
                     RequestAction::Sync(vmm_action) => {
-                        self.serve_vmm_action_request(vmm_action, request_processing_start_us)
+                        let response =
+                            self.serve_vmm_action_request(vmm_action, request_processing_start_us);
+                        self.journal_request(request, &response);
+                        response
                     }
                     RequestAction::ShutdownInternal => {
                         self.shutdown_flag = true;
@@ -285,8 +298,8 @@ impl ApiServer {
             VmmAction::LoadSnapshot(_) => {
                 Some((&METRICS.latencies_us.load_snapshot, "load snapshot"))
             }
-            VmmAction::Pause => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
-            VmmAction::Resume => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
+            VmmAction::Pause(_) => Some((&METRICS.latencies_us.pause_vm, "pause vm")),
+            VmmAction::Resume(_) => Some((&METRICS.latencies_us.resume_vm, "resume vm")),
             _ => None,
         };
 
@@ -307,6 +320,27 @@ impl ApiServer {
         response
     }
 
+    // The GET requests don't change the microVM, so there is no point in replaying them.
+    fn journal_request(&mut self, request: &Request, response: &Response) {
+        let journal = match self.journal.as_mut() {
+            Some(journal) => journal,
+            None => return,
+        };
+        if matches!(request.method(), Method::Get)
+            || !matches!(response.status(), StatusCode::OK | StatusCode::NoContent)
+        {
+            return;
+        }
+
+        if let Err(err) = journal.record(
+            request.method(),
+            request.uri().get_abs_path(),
+            request.body.as_ref(),
+        ) {
+            error!("Failed to write to the API journal: {}", err);
+        }
+    }
+
     /// An HTTP response which also includes a body.
     pub(crate) fn json_response<T: Into<String>>(status: StatusCode, body: T) -> Response {
         let mut response = Response::new(Version::Http11, status);
@@ -334,7 +368,7 @@ mod tests {
     use vmm::rpc_interface::VmmActionError;
     use vmm::seccomp_filters::{get_filters, SeccompConfig};
     use vmm::vmm_config::instance_info::InstanceInfo;
-    use vmm::vmm_config::snapshot::CreateSnapshotParams;
+    use vmm::vmm_config::snapshot::{CreateSnapshotParams, VmStateChangeConfig};
 
     use super::*;
 
@@ -384,8 +418,10 @@ mod tests {
         let start_time_us = utils::time::get_time_us(ClockType::Monotonic);
         assert_eq!(METRICS.latencies_us.pause_vm.fetch(), 0);
         to_api.send(Box::new(Ok(VmmData::Empty))).unwrap();
-        let response =
-            api_server.serve_vmm_action_request(Box::new(VmmAction::Pause), start_time_us);
+        let response = api_server.serve_vmm_action_request(
+            Box::new(VmmAction::Pause(VmStateChangeConfig::default())),
+            start_time_us,
+        );
         assert_eq!(response.status(), StatusCode::NoContent);
         assert_ne!(METRICS.latencies_us.pause_vm.fetch(), 0);
 
@@ -473,6 +509,61 @@ mod tests {
         assert_eq!(response.status(), StatusCode::BadRequest);
     }
 
+    #[test]
+    fn test_handle_request_journal() {
+        let to_vmm_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        let (api_request_sender, _from_api) = channel();
+        let (to_api, vmm_response_receiver) = channel();
+        let journal_file = TempFile::new().unwrap();
+
+        let mut api_server = ApiServer::new(api_request_sender, vmm_response_receiver, to_vmm_fd);
+        api_server.set_journal(ApiJournal::new(journal_file.as_path(), false).unwrap());
+
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let mut send_request =
+            |request: &[u8], vmm_outcome: std::result::Result<VmmData, VmmActionError>| {
+                to_api.send(Box::new(vmm_outcome)).unwrap();
+                sender.write_all(request).unwrap();
+                assert!(connection.try_read().is_ok());
+                let req = connection.pop_parsed_request().unwrap();
+                api_server.handle_request(&req, 0).status()
+            };
+        let flush_request = b"PUT /actions HTTP/1.1\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: 33\r\n\r\n{ \"action_type\": \"FlushMetrics\" }";
+
+        // Only the successful requests that aren't GETs are recorded.
+        assert_eq!(
+            send_request(flush_request, Ok(VmmData::Empty)),
+            StatusCode::NoContent
+        );
+        assert_eq!(
+            send_request(
+                flush_request,
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            ),
+            StatusCode::BadRequest
+        );
+        assert_eq!(
+            send_request(
+                b"GET / HTTP/1.1\r\n\r\n",
+                Ok(VmmData::InstanceInformation(InstanceInfo::default()))
+            ),
+            StatusCode::OK
+        );
+
+        let journal = std::fs::read_to_string(journal_file.as_path()).unwrap();
+        let entries: Vec<serde_json::Value> = journal
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["method"], "PUT");
+        assert_eq!(entries[0]["path"], "/actions");
+        assert_eq!(entries[0]["body"], json!({ "action_type": "FlushMetrics" }));
+    }
+
     #[test]
     fn test_bind_and_run() {
         let mut tmp_socket = TempFile::new().unwrap();
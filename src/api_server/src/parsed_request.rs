@@ -6,23 +6,30 @@ use micro_http::{Body, Method, Request, Response, StatusCode, Version};
 use serde::ser::Serialize;
 use serde_json::Value;
 use vmm::rpc_interface::{VmmAction, VmmActionError};
+use vmm::Error as VmmError;
 
 use super::VmmData;
 use crate::request::actions::parse_put_actions;
 use crate::request::balloon::{parse_get_balloon, parse_patch_balloon, parse_put_balloon};
 use crate::request::boot_source::parse_put_boot_source;
-use crate::request::drive::{parse_patch_drive, parse_put_drive};
-use crate::request::instance_info::parse_get_instance_info;
+use crate::request::drive::{parse_get_drive_stats, parse_patch_drive, parse_put_drive};
+use crate::request::hotplug::parse_get_cpu_hotplug;
+use crate::request::instance_info::{
+    parse_get_instance_info, parse_get_vcpus, parse_put_vcpu_single_step,
+};
 use crate::request::logger::parse_put_logger;
 use crate::request::machine_configuration::{
-    parse_get_machine_config, parse_patch_machine_config, parse_put_machine_config,
+    parse_get_irq_routing, parse_get_machine_config, parse_patch_machine_config,
+    parse_put_machine_config,
 };
 use crate::request::metrics::parse_put_metrics;
 use crate::request::mmds::{parse_get_mmds, parse_patch_mmds, parse_put_mmds};
-use crate::request::net::{parse_patch_net, parse_put_net};
-use crate::request::snapshot::{parse_patch_vm_state, parse_put_snapshot};
+use crate::request::net::{parse_get_net_stats, parse_patch_net, parse_put_net};
+use crate::request::snapshot::{
+    parse_get_snapshot_metadata, parse_patch_vm_state, parse_put_snapshot, parse_put_vm_action,
+};
 use crate::request::version::parse_get_version;
-use crate::request::vsock::parse_put_vsock;
+use crate::request::vsock::{parse_get_vsock_stats, parse_put_vsock};
 use crate::ApiServer;
 
 pub(crate) enum RequestAction {
@@ -78,10 +85,16 @@ impl ParsedRequest {
             request.body.as_ref(),
         ));
 
+        // The query string, if any, is only used by some GET requests.
+        let (request_path, query) = match request_uri.split_once('?') {
+            Some((request_path, query)) => (request_path, Some(query)),
+            None => (request_uri.as_str(), None),
+        };
+
         // Split request uri by '/' by doing:
         // 1. Trim starting '/' characters
         // 2. Splitting by '/'
-        let path_tokens: Vec<&str> = request_uri
+        let path_tokens: Vec<&str> = request_path
             .trim_start_matches('/')
             .split_terminator('/')
             .collect();
@@ -94,12 +107,39 @@ impl ParsedRequest {
         match (request.method(), path, request.body.as_ref()) {
             (Method::Get, "", None) => parse_get_instance_info(),
             (Method::Get, "balloon", None) => parse_get_balloon(path_tokens.get(1)),
+            (Method::Get, "drives", None) if path_tokens.get(2) == Some(&"stats") => {
+                parse_get_drive_stats(path_tokens.get(1))
+            }
             (Method::Get, "version", None) => parse_get_version(),
+            (Method::Get, "vm", None)
+                if path_tokens.get(1) == Some(&"hot-plug")
+                    && path_tokens.get(2) == Some(&"cpu") =>
+            {
+                parse_get_cpu_hotplug()
+            }
             (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"config") => {
                 Ok(ParsedRequest::new_sync(VmmAction::GetFullVmConfig))
             }
+            (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"irq-routing") => {
+                parse_get_irq_routing()
+            }
+            (Method::Get, "vm", None)
+                if path_tokens.get(1) == Some(&"snapshot")
+                    && path_tokens.get(2) == Some(&"metadata") =>
+            {
+                parse_get_snapshot_metadata(query)
+            }
+            (Method::Get, "vm", None) if path_tokens.get(1) == Some(&"vcpus") => parse_get_vcpus(),
+            (Method::Get, "vm", None)
+                if path_tokens.get(1) == Some(&"vsock") && path_tokens.get(2) == Some(&"stats") =>
+            {
+                parse_get_vsock_stats()
+            }
             (Method::Get, "machine-config", None) => parse_get_machine_config(),
             (Method::Get, "mmds", None) => parse_get_mmds(),
+            (Method::Get, "network-interfaces", None) if path_tokens.get(2) == Some(&"stats") => {
+                parse_get_net_stats(path_tokens.get(1))
+            }
             (Method::Get, _, Some(_)) => method_to_error(Method::Get),
             (Method::Put, "actions", Some(body)) => parse_put_actions(body),
             (Method::Put, "balloon", Some(body)) => parse_put_balloon(body),
@@ -116,6 +156,15 @@ impl ParsedRequest {
                 Ok(ParsedRequest::new(RequestAction::ShutdownInternal))
             }
             (Method::Put, "snapshot", Some(body)) => parse_put_snapshot(body, path_tokens.get(1)),
+            (Method::Put, "vm", Some(body)) if path_tokens.get(1) == Some(&"actions") => {
+                parse_put_vm_action(body, path_tokens.get(2))
+            }
+            (Method::Put, "vm", Some(body))
+                if path_tokens.get(1) == Some(&"vcpus")
+                    && path_tokens.get(3) == Some(&"single-step") =>
+            {
+                parse_put_vcpu_single_step(body, path_tokens.get(2))
+            }
             (Method::Put, "vsock", Some(body)) => parse_put_vsock(body),
             (Method::Put, _, None) => method_to_error(Method::Put),
             (Method::Patch, "balloon", Some(body)) => parse_patch_balloon(body, path_tokens.get(1)),
@@ -167,15 +216,23 @@ impl ParsedRequest {
                     Self::success_response_with_data(vm_config)
                 }
                 VmmData::MmdsValue(value) => Self::success_response_with_mmds_value(value),
+                VmmData::NetworkInterfaceStats(stats) => Self::success_response_with_data(stats),
+                VmmData::SnapshotMetadata(metadata) => Self::success_response_with_data(metadata),
                 VmmData::BalloonConfig(balloon_config) => {
                     Self::success_response_with_data(balloon_config)
                 }
                 VmmData::BalloonStats(stats) => Self::success_response_with_data(stats),
+                VmmData::BlockDeviceStats(stats) => Self::success_response_with_data(stats),
+                VmmData::CpuHotPlugSupport(support) => Self::success_response_with_data(support),
                 VmmData::InstanceInformation(info) => Self::success_response_with_data(info),
+                VmmData::VcpusInfo(vcpus) => Self::success_response_with_data(vcpus),
                 VmmData::VmmVersion(version) => Self::success_response_with_data(
                     &serde_json::json!({ "firecracker_version": version.as_str() }),
                 ),
                 VmmData::FullVmConfig(config) => Self::success_response_with_data(config),
+                VmmData::VsockStats(stats) => Self::success_response_with_data(stats),
+                #[cfg(target_arch = "x86_64")]
+                VmmData::IrqRouting(table) => Self::success_response_with_data(table),
             },
             Err(vmm_action_error) => {
                 let mut response = match vmm_action_error {
@@ -186,6 +243,27 @@ impl ParsedRequest {
                         );
                         Response::new(Version::Http11, StatusCode::PayloadTooLarge)
                     }
+                    VmmActionError::InternalVmm(
+                        VmmError::VcpuPauseTimeout(vcpu_ids)
+                        | VmmError::VcpuResumeTimeout(vcpu_ids),
+                    ) => {
+                        error!(
+                            "Received Error. Status code: 503 Service Unavailable. Message: {}",
+                            vmm_action_error
+                        );
+                        // The vCPUs which didn't acknowledge the state change are listed
+                        // along with the fault message.
+                        let mut response =
+                            Response::new(Version::Http11, StatusCode::ServiceUnavailable);
+                        response.set_body(Body::new(
+                            serde_json::json!({
+                                "fault_message": vmm_action_error.to_string(),
+                                "vcpu_ids": vcpu_ids,
+                            })
+                            .to_string(),
+                        ));
+                        return response;
+                    }
                     _ => {
                         error!(
                             "Received Error. Status code: 400 Bad Request. Message: {}",
@@ -326,15 +404,23 @@ pub(crate) fn checked_id(id: &str) -> Result<&str, Error> {
 pub(crate) mod tests {
     use std::io::{Cursor, Write};
     use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
     use std::str::FromStr;
 
     use micro_http::HttpConnection;
     use vmm::builder::StartMicrovmError;
+    use vmm::persist::SnapshotMetadata;
     use vmm::resources::VmmConfig;
     use vmm::rpc_interface::VmmActionError;
     use vmm::vmm_config::balloon::{BalloonDeviceConfig, BalloonStats};
-    use vmm::vmm_config::instance_info::InstanceInfo;
-    use vmm::vmm_config::machine_config::VmConfig;
+    use vmm::vmm_config::drive::BlockDeviceStats;
+    use vmm::vmm_config::hotplug::CpuHotPlugSupport;
+    use vmm::vmm_config::instance_info::{InstanceInfo, VcpuExitReason, VcpuInfo, VcpuRunState};
+    use vmm::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig};
+    #[cfg(target_arch = "x86_64")]
+    use vmm::vmm_config::machine_config::{IrqChipMode, IrqRoute, IrqRoutingTable};
+    use vmm::vmm_config::net::NetDeviceStats;
+    use vmm::vmm_config::vsock::VsockStats;
 
     use super::*;
 
@@ -572,23 +658,45 @@ pub(crate) mod tests {
                 VmmData::BalloonStats(stats) => {
                     http_response(&serde_json::to_string(stats).unwrap(), 200)
                 }
+                VmmData::BlockDeviceStats(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
+                VmmData::CpuHotPlugSupport(support) => {
+                    http_response(&serde_json::to_string(support).unwrap(), 200)
+                }
                 VmmData::Empty => http_response("", 204),
                 VmmData::FullVmConfig(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
+                #[cfg(target_arch = "x86_64")]
+                VmmData::IrqRouting(table) => {
+                    http_response(&serde_json::to_string(table).unwrap(), 200)
+                }
                 VmmData::MachineConfiguration(cfg) => {
                     http_response(&serde_json::to_string(cfg).unwrap(), 200)
                 }
                 VmmData::MmdsValue(value) => {
                     http_response(&serde_json::to_string(value).unwrap(), 200)
                 }
+                VmmData::NetworkInterfaceStats(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
+                VmmData::SnapshotMetadata(metadata) => {
+                    http_response(&serde_json::to_string(metadata).unwrap(), 200)
+                }
                 VmmData::InstanceInformation(info) => {
                     http_response(&serde_json::to_string(info).unwrap(), 200)
                 }
+                VmmData::VcpusInfo(vcpus) => {
+                    http_response(&serde_json::to_string(vcpus).unwrap(), 200)
+                }
                 VmmData::VmmVersion(version) => http_response(
                     &serde_json::json!({ "firecracker_version": version.as_str() }).to_string(),
                     200,
                 ),
+                VmmData::VsockStats(stats) => {
+                    http_response(&serde_json::to_string(stats).unwrap(), 200)
+                }
             };
             let response = ParsedRequest::convert_to_response(&data);
             assert!(response.write_all(&mut buf).is_ok());
@@ -601,12 +709,46 @@ pub(crate) mod tests {
             swap_out: Some(1),
             ..Default::default()
         }));
+        verify_ok_response_with(VmmData::BlockDeviceStats(BlockDeviceStats {
+            read_ops: 1,
+            ..Default::default()
+        }));
+        verify_ok_response_with(VmmData::CpuHotPlugSupport(CpuHotPlugSupport::current()));
         verify_ok_response_with(VmmData::Empty);
         verify_ok_response_with(VmmData::FullVmConfig(VmmConfig::default()));
+        #[cfg(target_arch = "x86_64")]
+        verify_ok_response_with(VmmData::IrqRouting(IrqRoutingTable {
+            irqchip_mode: IrqChipMode::Split,
+            routes: vec![IrqRoute::Msi {
+                gsi: 4,
+                address: 0xfee0_0000,
+                data: 0x24,
+            }],
+        }));
         verify_ok_response_with(VmmData::MachineConfiguration(VmConfig::default()));
         verify_ok_response_with(VmmData::MmdsValue(serde_json::from_str("{}").unwrap()));
+        verify_ok_response_with(VmmData::NetworkInterfaceStats(NetDeviceStats {
+            rx_packets: 1,
+            ..Default::default()
+        }));
+        verify_ok_response_with(VmmData::SnapshotMetadata(SnapshotMetadata {
+            snapshot_version: 6,
+            firecracker_version: Some(String::from("1.2.0")),
+            mem_size_mib: 128,
+            smt: false,
+            cpu_template: CpuFeaturesTemplate::None,
+        }));
         verify_ok_response_with(VmmData::InstanceInformation(InstanceInfo::default()));
+        verify_ok_response_with(VmmData::VcpusInfo(vec![VcpuInfo {
+            id: 1,
+            state: VcpuRunState::Paused,
+            exit_reason: Some(VcpuExitReason::Interrupted),
+        }]));
         verify_ok_response_with(VmmData::VmmVersion(String::default()));
+        verify_ok_response_with(VmmData::VsockStats(VsockStats {
+            tx_bytes: 1,
+            ..Default::default()
+        }));
 
         // Error.
         let error = VmmActionError::StartMicrovm(StartMicrovmError::MissingKernelConfig);
@@ -617,6 +759,20 @@ pub(crate) mod tests {
 
         let expected_response = http_response(&json, 400);
         assert_eq!(buf.into_inner(), expected_response.as_bytes());
+
+        // A timed out pause lists the vCPUs which are still running.
+        let error = VmmActionError::InternalVmm(VmmError::VcpuPauseTimeout(vec![1, 3]));
+        let mut buf = Cursor::new(vec![0]);
+        let json = serde_json::json!({
+            "fault_message": error.to_string(),
+            "vcpu_ids": [1, 3],
+        })
+        .to_string();
+        let response = ParsedRequest::convert_to_response(&Err(error));
+        response.write_all(&mut buf).unwrap();
+
+        let expected_response = http_response(&json, 503);
+        assert_eq!(buf.into_inner(), expected_response.as_bytes());
     }
 
     #[test]
@@ -655,6 +811,68 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_drive_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/drives/rootfs/stats", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_vsock_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/vsock/stats", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_snapshot_metadata() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(
+                http_request("GET", "/vm/snapshot/metadata?path=/srv/vm%201/snap", None).as_bytes(),
+            )
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        match vmm_action_from_request(ParsedRequest::try_from_request(&req).unwrap()) {
+            VmmAction::GetSnapshotMetadata(path) => {
+                assert_eq!(path, PathBuf::from("/srv/vm 1/snap"))
+            }
+            _ => panic!("Test failed."),
+        }
+
+        sender
+            .write_all(http_request("GET", "/vm/snapshot/metadata", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_try_from_get_net_stats() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/network-interfaces/eth0/stats", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
     #[test]
     fn test_try_from_get_machine_config() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -667,6 +885,61 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_get_irq_routing() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/irq-routing", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        #[cfg(target_arch = "x86_64")]
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
+    #[test]
+    fn test_try_from_get_cpu_hotplug() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/hot-plug/cpu", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_get_vcpus() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        sender
+            .write_all(http_request("GET", "/vm/vcpus", None).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+    }
+
+    #[test]
+    fn test_try_from_put_vcpu_single_step() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"vcpu_id\": 0, \"enabled\": true }";
+        sender
+            .write_all(http_request("PUT", "/vm/vcpus/0/single-step", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        #[cfg(target_arch = "x86_64")]
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+        #[cfg(target_arch = "aarch64")]
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
     #[test]
     fn test_try_from_get_mmds() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -900,6 +1173,33 @@ pub(crate) mod tests {
         assert!(ParsedRequest::try_from_request(&req).is_ok());
     }
 
+    #[test]
+    fn test_try_from_put_vm_action() {
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        let mut connection = HttpConnection::new(receiver);
+        let body = "{ \"timeout_ms\": 100 }";
+        sender
+            .write_all(http_request("PUT", "/vm/actions/pause", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(http_request("PUT", "/vm/actions/resume", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_ok());
+
+        sender
+            .write_all(http_request("PUT", "/vm/actions/stop", Some(body)).as_bytes())
+            .unwrap();
+        assert!(connection.try_read().is_ok());
+        let req = connection.pop_parsed_request().unwrap();
+        assert!(ParsedRequest::try_from_request(&req).is_err());
+    }
+
     #[test]
     fn test_try_from_put_vsock() {
         let (mut sender, receiver) = UnixStream::pair().unwrap();
@@ -6,9 +6,7 @@ use serde::{Deserialize, Serialize};
 
 use super::super::VmmAction;
 use crate::parsed_request::{Error, ParsedRequest};
-use crate::request::Body;
-#[cfg(target_arch = "aarch64")]
-use crate::request::StatusCode;
+use crate::request::{Body, StatusCode};
 
 // The names of the members from this enum must precisely correspond (as a string) to the possible
 // values of "action_type" from the json request body. This is useful to get a strongly typed
@@ -16,6 +14,8 @@ use crate::request::StatusCode;
 #[derive(Debug, Deserialize, Serialize)]
 enum ActionType {
     FlushMetrics,
+    // Placeholder for the CPU hot-add, see `vmm::vmm_config::hotplug::HotPlugCpuConfig`.
+    HotPlugCpu,
     InstanceStart,
     SendCtrlAltDel,
 }
@@ -37,6 +37,10 @@ pub(crate) fn parse_put_actions(body: &Body) -> Result<ParsedRequest, Error> {
 
     match action_body.action_type {
         ActionType::FlushMetrics => Ok(ParsedRequest::new_sync(VmmAction::FlushMetrics)),
+        ActionType::HotPlugCpu => Err(Error::Generic(
+            StatusCode::NotImplemented,
+            "CPU hot-plug is not implemented.".to_string(),
+        )),
         ActionType::InstanceStart => Ok(ParsedRequest::new_sync(VmmAction::StartMicroVm)),
         ActionType::SendCtrlAltDel => {
             // SendCtrlAltDel not supported on aarch64.
@@ -103,5 +107,16 @@ mod tests {
             assert!(result.is_ok());
             assert!(result.unwrap().eq(&req));
         }
+
+        {
+            let json = r#"{
+                "action_type": "HotPlugCpu"
+            }"#;
+
+            assert!(matches!(
+                parse_put_actions(&Body::new(json)),
+                Err(Error::Generic(StatusCode::NotImplemented, _))
+            ));
+        }
     }
 }
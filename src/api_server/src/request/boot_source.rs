@@ -20,6 +20,8 @@ pub(crate) fn parse_put_boot_source(body: &Body) -> Result<ParsedRequest, Error>
 
 #[cfg(test)]
 mod tests {
+    use vmm::vmm_config::boot_source::GuestOs;
+
     use super::*;
 
     #[test]
@@ -35,11 +37,25 @@ mod tests {
             kernel_image_path: String::from("/foo/bar"),
             initrd_path: Some(String::from("/bar/foo")),
             boot_args: Some(String::from("foobar")),
+            guest_os: GuestOs::Linux,
         };
         let result = parse_put_boot_source(&Body::new(body));
         assert!(result.is_ok());
         let parsed_req = result.unwrap_or_else(|_e| panic!("Failed test."));
 
         assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
+
+        let body = r#"{
+                "kernel_image_path": "/foo/bar",
+                "guest_os": "Windows"
+              }"#;
+        let same_body = BootSourceConfig {
+            kernel_image_path: String::from("/foo/bar"),
+            initrd_path: None,
+            boot_args: None,
+            guest_os: GuestOs::Windows,
+        };
+        let parsed_req = parse_put_boot_source(&Body::new(body)).unwrap();
+        assert!(parsed_req == ParsedRequest::new_sync(VmmAction::ConfigureBootSource(same_body)));
     }
 }
@@ -8,6 +8,18 @@ use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 
+pub(crate) fn parse_get_drive_stats(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::new_sync(VmmAction::GetBlockDeviceStats(
+        id.to_string(),
+    )))
+}
+
 pub(crate) fn parse_put_drive(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -89,6 +101,17 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_drive_stats_request() {
+        assert!(parse_get_drive_stats(None).is_err());
+        assert!(parse_get_drive_stats(Some(&"bad-id")).is_err());
+
+        match vmm_action_from_request(parse_get_drive_stats(Some(&"foo")).unwrap()) {
+            VmmAction::GetBlockDeviceStats(id) => assert_eq!(id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_patch_drive_request() {
         assert!(parse_patch_drive(&Body::new("invalid_payload"), None).is_err());
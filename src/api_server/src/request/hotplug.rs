@@ -0,0 +1,24 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use vmm::rpc_interface::VmmAction;
+
+use crate::parsed_request::{Error, ParsedRequest};
+
+pub(crate) fn parse_get_cpu_hotplug() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetCpuHotPlugSupport))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RequestAction;
+
+    #[test]
+    fn test_parse_get_cpu_hotplug_request() {
+        match parse_get_cpu_hotplug().unwrap().into_parts() {
+            (RequestAction::Sync(action), _) if *action == VmmAction::GetCpuHotPlugSupport => {}
+            _ => panic!("Test failed."),
+        }
+    }
+}
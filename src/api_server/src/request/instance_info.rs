@@ -3,14 +3,56 @@
 
 use logger::{IncMetric, METRICS};
 use vmm::rpc_interface::VmmAction;
+#[cfg(target_arch = "x86_64")]
+use vmm::vmm_config::instance_info::VcpuSingleStepConfig;
 
+#[cfg(target_arch = "x86_64")]
+use crate::parsed_request::checked_id;
 use crate::parsed_request::{Error, ParsedRequest};
+use crate::request::{Body, StatusCode};
 
 pub(crate) fn parse_get_instance_info() -> Result<ParsedRequest, Error> {
     METRICS.get_api_requests.instance_info_count.inc();
     Ok(ParsedRequest::new_sync(VmmAction::GetVmInstanceInfo))
 }
 
+pub(crate) fn parse_get_vcpus() -> Result<ParsedRequest, Error> {
+    Ok(ParsedRequest::new_sync(VmmAction::GetVcpus))
+}
+
+#[cfg_attr(target_arch = "aarch64", allow(unused_variables))]
+pub(crate) fn parse_put_vcpu_single_step(
+    body: &Body,
+    id_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    // The single-step debug exits are only reported on x86_64.
+    #[cfg(target_arch = "aarch64")]
+    return Err(Error::Generic(
+        StatusCode::BadRequest,
+        "The single-step mode is not available on aarch64.".to_string(),
+    ));
+
+    #[cfg(target_arch = "x86_64")]
+    {
+        let id = match id_from_path {
+            Some(id) => checked_id(id)?,
+            None => return Err(Error::EmptyID),
+        };
+
+        let config = serde_json::from_slice::<VcpuSingleStepConfig>(body.raw())?;
+        if id.parse::<u8>() != Ok(config.vcpu_id) {
+            return Err(Error::Generic(
+                StatusCode::BadRequest,
+                "The id from the path does not match the id from the body!".to_string(),
+            ));
+        }
+
+        Ok(ParsedRequest::new_sync(VmmAction::SetVcpuSingleStep(
+            config,
+        )))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -23,4 +65,54 @@ mod tests {
             _ => panic!("Test failed."),
         }
     }
+
+    #[test]
+    fn test_parse_put_vcpu_single_step_request() {
+        let body = r#"{
+                "vcpu_id": 1,
+                "enabled": true
+              }"#;
+
+        #[cfg(target_arch = "x86_64")]
+        {
+            use vmm::vmm_config::instance_info::VcpuSingleStepConfig;
+
+            match parse_put_vcpu_single_step(&Body::new(body), Some(&"1"))
+                .unwrap()
+                .into_parts()
+            {
+                (RequestAction::Sync(action), _)
+                    if *action
+                        == VmmAction::SetVcpuSingleStep(VcpuSingleStepConfig {
+                            vcpu_id: 1,
+                            enabled: true,
+                        }) => {}
+                _ => panic!("Test failed."),
+            }
+
+            // The id from the path must match the body.
+            assert!(parse_put_vcpu_single_step(&Body::new(body), Some(&"0")).is_err());
+            assert!(parse_put_vcpu_single_step(&Body::new(body), Some(&"one")).is_err());
+            assert!(parse_put_vcpu_single_step(&Body::new(body), None).is_err());
+
+            // Unknown fields are rejected.
+            let body = r#"{
+                "vcpu_id": 1,
+                "enabled": true,
+                "rip": 0
+              }"#;
+            assert!(parse_put_vcpu_single_step(&Body::new(body), Some(&"1")).is_err());
+        }
+
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_put_vcpu_single_step(&Body::new(body), Some(&"1")).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_vcpus_request() {
+        match parse_get_vcpus().unwrap().into_parts() {
+            (RequestAction::Sync(action), _) if *action == VmmAction::GetVcpus => {}
+            _ => panic!("Test failed."),
+        }
+    }
 }
@@ -6,6 +6,8 @@ use vmm::vmm_config::machine_config::{VmConfig, VmUpdateConfig};
 
 use super::super::VmmAction;
 use crate::parsed_request::{method_to_error, Error, ParsedRequest};
+#[cfg(target_arch = "aarch64")]
+use crate::request::StatusCode;
 use crate::request::{Body, Method};
 
 pub(crate) fn parse_get_machine_config() -> Result<ParsedRequest, Error> {
@@ -13,6 +15,18 @@ pub(crate) fn parse_get_machine_config() -> Result<ParsedRequest, Error> {
     Ok(ParsedRequest::new_sync(VmmAction::GetVmMachineConfig))
 }
 
+pub(crate) fn parse_get_irq_routing() -> Result<ParsedRequest, Error> {
+    // The split irqchip and the IOAPIC routes only exist on x86_64.
+    #[cfg(target_arch = "aarch64")]
+    return Err(Error::Generic(
+        StatusCode::BadRequest,
+        "The IRQ routing table is not available on aarch64.".to_string(),
+    ));
+
+    #[cfg(target_arch = "x86_64")]
+    Ok(ParsedRequest::new_sync(VmmAction::GetIrqRouting))
+}
+
 pub(crate) fn parse_put_machine_config(body: &Body) -> Result<ParsedRequest, Error> {
     METRICS.put_api_requests.machine_cfg_count.inc();
     let vm_config = serde_json::from_slice::<VmConfig>(body.raw()).map_err(|err| {
@@ -45,7 +59,7 @@ pub(crate) fn parse_patch_machine_config(body: &Body) -> Result<ParsedRequest, E
 
 #[cfg(test)]
 mod tests {
-    use vmm::vmm_config::machine_config::CpuFeaturesTemplate;
+    use vmm::vmm_config::machine_config::{CpuFeaturesTemplate, HugePageMode, IrqChipMode};
 
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
@@ -56,6 +70,17 @@ mod tests {
         assert!(METRICS.get_api_requests.machine_cfg_count.count() > 0);
     }
 
+    #[test]
+    fn test_parse_get_irq_routing_request() {
+        #[cfg(target_arch = "x86_64")]
+        match vmm_action_from_request(parse_get_irq_routing().unwrap()) {
+            VmmAction::GetIrqRouting => (),
+            _ => panic!("Test failed."),
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_get_irq_routing().is_err());
+    }
+
     #[test]
     fn test_parse_put_machine_config_request() {
         // 1. Test case for invalid payload.
@@ -84,6 +109,9 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::None),
             track_dirty_pages: Some(false),
+            mlock_guest_memory: Some(false),
+            huge_pages: Some(HugePageMode::None),
+            irqchip_mode: Some(IrqChipMode::InKernel),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -103,6 +131,9 @@ mod tests {
             smt: Some(false),
             cpu_template: Some(CpuFeaturesTemplate::None),
             track_dirty_pages: Some(true),
+            mlock_guest_memory: Some(false),
+            huge_pages: Some(HugePageMode::None),
+            irqchip_mode: Some(IrqChipMode::InKernel),
         };
 
         match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -128,6 +159,9 @@ mod tests {
                 smt: Some(false),
                 cpu_template: Some(CpuFeaturesTemplate::T2),
                 track_dirty_pages: Some(true),
+                mlock_guest_memory: Some(false),
+                huge_pages: Some(HugePageMode::None),
+                irqchip_mode: Some(IrqChipMode::InKernel),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -157,6 +191,9 @@ mod tests {
                 smt: Some(true),
                 cpu_template: Some(CpuFeaturesTemplate::None),
                 track_dirty_pages: Some(true),
+                mlock_guest_memory: Some(false),
+                huge_pages: Some(HugePageMode::None),
+                irqchip_mode: Some(IrqChipMode::InKernel),
             };
 
             match vmm_action_from_request(parse_put_machine_config(&Body::new(body)).unwrap()) {
@@ -191,6 +228,15 @@ mod tests {
         #[cfg(target_arch = "x86_64")]
         assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
 
+        // The split irqchip is only available on x86_64.
+        let body = r#"{
+                "irqchip_mode": "Split"
+              }"#;
+        #[cfg(target_arch = "aarch64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_err());
+        #[cfg(target_arch = "x86_64")]
+        assert!(parse_patch_machine_config(&Body::new(body)).is_ok());
+
         let body = r#"{
                 "vcpu_count": 8,
                 "mem_size_mib": 1024
@@ -5,6 +5,7 @@ pub mod actions;
 pub mod balloon;
 pub mod boot_source;
 pub mod drive;
+pub mod hotplug;
 pub mod instance_info;
 pub mod logger;
 pub mod machine_configuration;
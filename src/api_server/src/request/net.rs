@@ -8,6 +8,18 @@ use super::super::VmmAction;
 use crate::parsed_request::{checked_id, Error, ParsedRequest};
 use crate::request::{Body, StatusCode};
 
+pub(crate) fn parse_get_net_stats(id_from_path: Option<&&str>) -> Result<ParsedRequest, Error> {
+    let id = if let Some(id) = id_from_path {
+        checked_id(id)?
+    } else {
+        return Err(Error::EmptyID);
+    };
+
+    Ok(ParsedRequest::new_sync(
+        VmmAction::GetNetworkInterfaceStats(id.to_string()),
+    ))
+}
+
 pub(crate) fn parse_put_net(
     body: &Body,
     id_from_path: Option<&&str>,
@@ -78,6 +90,17 @@ mod tests {
     use super::*;
     use crate::parsed_request::tests::vmm_action_from_request;
 
+    #[test]
+    fn test_parse_get_net_stats_request() {
+        assert!(parse_get_net_stats(None).is_err());
+        assert!(parse_get_net_stats(Some(&"bad-id")).is_err());
+
+        match vmm_action_from_request(parse_get_net_stats(Some(&"foo")).unwrap()) {
+            VmmAction::GetNetworkInterfaceStats(id) => assert_eq!(id, "foo"),
+            _ => panic!("Test failed."),
+        }
+    }
+
     #[test]
     fn test_parse_put_net_request() {
         let body = r#"{
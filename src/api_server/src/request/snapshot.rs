@@ -1,11 +1,13 @@
 // Copyright 2020 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use std::path::PathBuf;
+
 use logger::{IncMetric, METRICS};
 use serde::de::Error as DeserializeError;
 use vmm::vmm_config::snapshot::{
     CreateSnapshotParams, LoadSnapshotConfig, LoadSnapshotParams, MemBackendConfig, MemBackendType,
-    Vm, VmState,
+    Vm, VmState, VmStateChangeConfig,
 };
 
 use super::super::VmmAction;
@@ -48,9 +50,85 @@ pub(crate) fn parse_patch_vm_state(body: &Body) -> Result<ParsedRequest, Error>
     let vm = serde_json::from_slice::<Vm>(body.raw())?;
 
     match vm.state {
-        VmState::Paused => Ok(ParsedRequest::new_sync(VmmAction::Pause)),
-        VmState::Resumed => Ok(ParsedRequest::new_sync(VmmAction::Resume)),
+        VmState::Paused => Ok(ParsedRequest::new_sync(VmmAction::Pause(
+            VmStateChangeConfig::default(),
+        ))),
+        VmState::Resumed => Ok(ParsedRequest::new_sync(VmmAction::Resume(
+            VmStateChangeConfig::default(),
+        ))),
+    }
+}
+
+pub(crate) fn parse_put_vm_action(
+    body: &Body,
+    action_from_path: Option<&&str>,
+) -> Result<ParsedRequest, Error> {
+    // An empty body keeps the default timeout.
+    let config = if body.is_empty() {
+        VmStateChangeConfig::default()
+    } else {
+        serde_json::from_slice::<VmStateChangeConfig>(body.raw())?
+    };
+
+    match action_from_path {
+        Some(&"pause") => Ok(ParsedRequest::new_sync(VmmAction::Pause(config))),
+        Some(&"resume") => Ok(ParsedRequest::new_sync(VmmAction::Resume(config))),
+        Some(&action) => Err(Error::InvalidPathMethod(
+            format!("/vm/actions/{}", action),
+            Method::Put,
+        )),
+        None => Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing vm action type.".to_string(),
+        )),
+    }
+}
+
+pub(crate) fn parse_get_snapshot_metadata(query: Option<&str>) -> Result<ParsedRequest, Error> {
+    let path = query
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("path="))
+        .map(percent_decode)
+        .transpose()?
+        .unwrap_or_default();
+
+    if path.is_empty() {
+        return Err(Error::Generic(
+            StatusCode::BadRequest,
+            "Missing the `path` query parameter.".to_string(),
+        ));
+    }
+
+    Ok(ParsedRequest::new_sync(VmmAction::GetSnapshotMetadata(
+        PathBuf::from(path),
+    )))
+}
+
+// Decodes the `%XX` escapes of a query parameter value.
+fn percent_decode(value: &str) -> Result<String, Error> {
+    let invalid = || {
+        Error::Generic(
+            StatusCode::BadRequest,
+            format!("Invalid query parameter value: {}", value),
+        )
+    };
+
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(byte) = iter.next() {
+        if byte == b'%' {
+            let hex = [
+                iter.next().ok_or_else(invalid)?,
+                iter.next().ok_or_else(invalid)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        } else {
+            bytes.push(byte);
+        }
     }
+    String::from_utf8(bytes).map_err(|_| invalid())
 }
 
 fn parse_put_snapshot_load(body: &Body) -> Result<ParsedRequest, Error> {
@@ -365,7 +443,9 @@ mod tests {
 
         assert!(parse_patch_vm_state(&Body::new(body))
             .unwrap()
-            .eq(&ParsedRequest::new_sync(VmmAction::Pause)));
+            .eq(&ParsedRequest::new_sync(VmmAction::Pause(
+                VmStateChangeConfig::default()
+            ))));
 
         body = r#"{
                 "state": "Resumed"
@@ -373,7 +453,9 @@ mod tests {
 
         assert!(parse_patch_vm_state(&Body::new(body))
             .unwrap()
-            .eq(&ParsedRequest::new_sync(VmmAction::Resume)));
+            .eq(&ParsedRequest::new_sync(VmmAction::Resume(
+                VmStateChangeConfig::default()
+            ))));
 
         let invalid_body = r#"{
                 "invalid": "Paused"
@@ -381,4 +463,63 @@ mod tests {
 
         assert!(parse_patch_vm_state(&Body::new(invalid_body)).is_err());
     }
+
+    #[test]
+    fn test_parse_put_vm_action() {
+        let body = r#"{
+                "timeout_ms": 500
+              }"#;
+        let config = VmStateChangeConfig {
+            timeout_ms: Some(500),
+        };
+
+        assert!(parse_put_vm_action(&Body::new(body), Some(&"pause"))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Pause(config))));
+        assert!(parse_put_vm_action(&Body::new(body), Some(&"resume"))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Resume(config))));
+
+        // The timeout is optional.
+        assert!(parse_put_vm_action(&Body::new("{}"), Some(&"pause"))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Pause(
+                VmStateChangeConfig::default()
+            ))));
+        assert!(parse_put_vm_action(&Body::new(""), Some(&"resume"))
+            .unwrap()
+            .eq(&ParsedRequest::new_sync(VmmAction::Resume(
+                VmStateChangeConfig::default()
+            ))));
+
+        let invalid_body = r#"{
+                "timeout": 500
+              }"#;
+        assert!(parse_put_vm_action(&Body::new(invalid_body), Some(&"pause")).is_err());
+        assert!(parse_put_vm_action(&Body::new(body), Some(&"reboot")).is_err());
+        assert!(parse_put_vm_action(&Body::new(body), None).is_err());
+    }
+
+    #[test]
+    fn test_parse_get_snapshot_metadata() {
+        assert!(
+            vmm_action_from_request(
+                parse_get_snapshot_metadata(Some("path=/srv/snapshot")).unwrap()
+            ) == VmmAction::GetSnapshotMetadata(PathBuf::from("/srv/snapshot"))
+        );
+        // Escaped characters are decoded and the other parameters are ignored.
+        assert!(
+            vmm_action_from_request(
+                parse_get_snapshot_metadata(Some("foo=bar&path=/srv/vm%201/snap%C3%A9")).unwrap()
+            ) == VmmAction::GetSnapshotMetadata(PathBuf::from("/srv/vm 1/snapé"))
+        );
+
+        assert!(parse_get_snapshot_metadata(None).is_err());
+        assert!(parse_get_snapshot_metadata(Some("")).is_err());
+        assert!(parse_get_snapshot_metadata(Some("path=")).is_err());
+        assert!(parse_get_snapshot_metadata(Some("file=/srv/snapshot")).is_err());
+        assert!(parse_get_snapshot_metadata(Some("path=%2")).is_err());
+        assert!(parse_get_snapshot_metadata(Some("path=%zz")).is_err());
+        assert!(parse_get_snapshot_metadata(Some("path=%ff")).is_err());
+    }
 }
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::arch::x86_64::__cpuid as host_cpuid;
+use std::ops::Range;
 use std::slice;
 
 use crate::common::{VENDOR_ID_AMD, VENDOR_ID_INTEL};
@@ -211,11 +212,46 @@ impl BrandString {
         Ok(())
     }
 
+    /// Creates a brand string from the host one, with its frequency blanked (see
+    /// `scrub_freq`), so that the guest sees the model but not the exact SKU clock.
+    pub fn from_host_cpuid_scrubbed() -> Result<Self, Error> {
+        let mut this = Self::from_host_cpuid()?;
+        this.scrub_freq();
+        Ok(this)
+    }
+
+    /// Replaces the CPU frequency data the brand string may contain with blanks, together with
+    /// the "@ " preceding it (e.g. "Intel(R) Xeon(R) CPU E5-2686 v4 @ 2.30GHz" becomes
+    /// "Intel(R) Xeon(R) CPU E5-2686 v4          "). The length of the string is kept.
+    pub fn scrub_freq(&mut self) {
+        if let Some(range) = self.find_freq_range() {
+            let bytes = self.as_bytes_mut();
+            let mut start = range.start;
+            while start > 0 && bytes[start - 1] == b' ' {
+                start -= 1;
+            }
+            // Only the spaces after the '@' are part of the frequency segment.
+            start = if start > 0 && bytes[start - 1] == b'@' {
+                start - 1
+            } else {
+                range.start
+            };
+            for byte in &mut bytes[start..range.end] {
+                *byte = b' ';
+            }
+        }
+    }
+
     /// Searches the brand string for the CPU frequency data it may contain (e.g. 4.01GHz),
     /// and, if found, returns it as an `u8` slice.
     ///
     /// Basically, we're implementing a search for this regex: "([0-9]+\.[0-9]+[MGT]Hz)".
     pub fn find_freq(&self) -> Option<&[u8]> {
+        self.find_freq_range().map(|range| &self.as_bytes()[range])
+    }
+
+    /// Returns the position of the CPU frequency data in the brand string, see `find_freq`.
+    fn find_freq_range(&self) -> Option<Range<usize>> {
         // The algorithm for matching the regular expression above is based
         // on a Moore machine, and 'stage' represents the current state of
         // the machine.
@@ -296,7 +332,7 @@ impl BrandString {
                     // If found, we stop the search and return the slice.
                     if ch == b'z' {
                         let freq_end = i + 1;
-                        return Some(&self.as_bytes()[freq_start..freq_end]);
+                        return Some(freq_start..freq_end);
                     } else if ch.is_ascii_digit() {
                         stage = Stages::FoundFreqIntPart;
                         freq_start = i;
@@ -446,6 +482,28 @@ mod tests {
         assert_eq!(null_terminator_index(bytes.as_slice()), 2);
     }
 
+    #[test]
+    fn test_scrub_freq() {
+        let mut bstr =
+            BrandString::from_bytes_unchecked(b"Intel(R) Xeon(R) Platinum 8259CL CPU @ 2.50GHz");
+        bstr.scrub_freq();
+        assert_eq!(
+            &bstr.as_bytes()[..bstr.len],
+            b"Intel(R) Xeon(R) Platinum 8259CL CPU          "
+        );
+        assert!(bstr.find_freq().is_none());
+
+        // Without the "@ ", only the frequency is blanked.
+        let mut bstr = BrandString::from_bytes_unchecked(b"CPU 3.10GHz (ES)");
+        bstr.scrub_freq();
+        assert_eq!(&bstr.as_bytes()[..bstr.len], b"CPU         (ES)");
+
+        // The brand strings without a frequency are left untouched.
+        let mut bstr = BrandString::from_bytes_unchecked(b"AMD EPYC 7R13 Processor");
+        bstr.scrub_freq();
+        assert_eq!(&bstr.as_bytes()[..bstr.len], b"AMD EPYC 7R13 Processor");
+    }
+
     #[test]
     fn test_find_freq_fails() {
         let bstr_thz = BrandString::from_bytes_unchecked(b"5.20THz");
@@ -7,7 +7,7 @@ use std::arch::x86::{CpuidResult, __cpuid_count, __get_cpuid_max};
 use std::arch::x86_64::{CpuidResult, __cpuid_count, __get_cpuid_max};
 
 #[cfg(target_arch = "x86_64")]
-use kvm_bindings::CpuId;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
 
 use crate::bit_helper::BitHelper;
 use crate::cpu_leaf::*;
@@ -161,6 +161,100 @@ pub fn is_same_model(cpuid: &CpuId) -> bool {
     true
 }
 
+/// The online host CPUs, in the kernel's cpu list format (e.g. `0-3,5`).
+const HOST_ONLINE_CPUS_PATH: &str = "/sys/devices/system/cpu/online";
+
+/// Counts the CPUs of a list in the kernel's cpu list format (e.g. `0-3,5`).
+fn parse_cpu_list(cpu_list: &str) -> Result<u8, crate::transformer::Error> {
+    use crate::transformer::Error as TransformerError;
+
+    let cpu_count: Option<usize> = cpu_list
+        .trim()
+        .split(',')
+        .map(|range| match range.split_once('-') {
+            Some((first, last)) => {
+                let first = first.parse::<usize>().ok()?;
+                let last = last.parse::<usize>().ok()?;
+                last.checked_sub(first).map(|count| count + 1)
+            }
+            None => range.parse::<usize>().ok().map(|_| 1),
+        })
+        .sum();
+    let cpu_count = cpu_count
+        .ok_or_else(|| TransformerError::HostCpuCount(format!("Invalid cpu list: {}", cpu_list)))?;
+
+    u8::try_from(cpu_count).map_err(|_| TransformerError::VcpuCountOverflow)
+}
+
+/// Returns the number of online host CPUs, as reported by `/sys/devices/system/cpu/online`.
+///
+/// Fails with `Error::VcpuCountOverflow` if the host has more than 255 CPUs online.
+pub fn get_host_physical_cpu_count() -> Result<u8, crate::transformer::Error> {
+    let cpu_list = std::fs::read_to_string(HOST_ONLINE_CPUS_PATH)
+        .map_err(|err| crate::transformer::Error::HostCpuCount(err.to_string()))?;
+
+    parse_cpu_list(&cpu_list)
+}
+
+/// Wrapper used for displaying a CPUID entry in a human readable format.
+#[cfg(target_arch = "x86_64")]
+pub struct CpuidEntryDisplay<'a>(pub &'a kvm_cpuid_entry2);
+
+#[cfg(target_arch = "x86_64")]
+impl std::fmt::Display for CpuidEntryDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "function: 0x{:08x}, index: 0x{:x}, flags: 0x{:x}, eax: 0x{:08x}, ebx: 0x{:08x}, \
+             ecx: 0x{:08x}, edx: 0x{:08x}",
+            self.0.function,
+            self.0.index,
+            self.0.flags,
+            self.0.eax,
+            self.0.ebx,
+            self.0.ecx,
+            self.0.edx
+        )
+    }
+}
+
+/// Returns a stable hash of the CPUID entries, so that the CPUIDs presented by different hosts
+/// can be compared without exchanging them.
+///
+/// The entries are hashed in sorted order, so the fingerprint doesn't depend on the order in
+/// which they are stored. FNV-1a is used rather than the std `DefaultHasher`, whose output can
+/// change between Rust releases.
+#[cfg(target_arch = "x86_64")]
+pub fn cpuid_fingerprint(cpuid: &CpuId) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut entries: Vec<[u32; 7]> = cpuid
+        .as_slice()
+        .iter()
+        .map(|entry| {
+            [
+                entry.function,
+                entry.index,
+                entry.flags,
+                entry.eax,
+                entry.ebx,
+                entry.ecx,
+                entry.edx,
+            ]
+        })
+        .collect();
+    entries.sort_unstable();
+
+    entries
+        .iter()
+        .flatten()
+        .flat_map(|value| value.to_le_bytes())
+        .fold(FNV_OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+        })
+}
+
 /// Scans through the CPUID and determines if a feature bit is set.
 // TODO: This currently involves a linear search which would be improved
 //       when we'll refactor the cpuid crate.
@@ -237,6 +331,105 @@ pub mod tests {
         matches!(&vendor_id.ok().unwrap(), VENDOR_ID_INTEL | VENDOR_ID_AMD);
     }
 
+    #[test]
+    fn test_parse_cpu_list() {
+        use crate::transformer::Error;
+
+        assert_eq!(parse_cpu_list("0\n").unwrap(), 1);
+        assert_eq!(parse_cpu_list("0-3").unwrap(), 4);
+        assert_eq!(parse_cpu_list("0-3,5,8-9\n").unwrap(), 7);
+        assert_eq!(parse_cpu_list("0-254").unwrap(), 255);
+        assert!(matches!(
+            parse_cpu_list("0-255"),
+            Err(Error::VcpuCountOverflow)
+        ));
+        assert!(matches!(
+            parse_cpu_list("0-127,256-383"),
+            Err(Error::VcpuCountOverflow)
+        ));
+        assert!(matches!(parse_cpu_list("3-1"), Err(Error::HostCpuCount(_))));
+        assert!(matches!(parse_cpu_list("0-a"), Err(Error::HostCpuCount(_))));
+        assert!(matches!(parse_cpu_list(""), Err(Error::HostCpuCount(_))));
+    }
+
+    #[test]
+    fn test_get_host_physical_cpu_count() {
+        // SAFETY: Safe because the call doesn't touch any memory.
+        let online_cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+
+        match get_host_physical_cpu_count() {
+            Ok(cpu_count) => assert_eq!(i64::from(cpu_count), online_cpus as i64),
+            Err(crate::transformer::Error::VcpuCountOverflow) => assert!(online_cpus > 255),
+            Err(err) => panic!("Unexpected error: {}", err),
+        }
+    }
+
+    #[test]
+    fn test_cpuid_entry_display() {
+        let entry = kvm_cpuid_entry2 {
+            function: 0x8000_0001,
+            index: 1,
+            flags: 0,
+            eax: 0x1,
+            ebx: 0xdead_beef,
+            ecx: 0,
+            edx: 0xffff_ffff,
+            padding: [0, 0, 0],
+        };
+
+        assert_eq!(
+            CpuidEntryDisplay(&entry).to_string(),
+            "function: 0x80000001, index: 0x1, flags: 0x0, eax: 0x00000001, ebx: 0xdeadbeef, \
+             ecx: 0x00000000, edx: 0xffffffff"
+        );
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_cpuid_fingerprint() {
+        let entries = [
+            kvm_cpuid_entry2 {
+                function: 0x0,
+                eax: 0xd,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                flags: 1,
+                ebx: 0xf1bf_07ab,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                index: 1,
+                flags: 1,
+                ..Default::default()
+            },
+        ];
+        let cpuid = CpuId::from_entries(&entries).unwrap();
+        let fingerprint = cpuid_fingerprint(&cpuid);
+
+        // The fingerprint is deterministic and doesn't depend on the order of the entries.
+        assert_eq!(cpuid_fingerprint(&cpuid.clone()), fingerprint);
+        let reordered = CpuId::from_entries(&[entries[2], entries[0], entries[1]]).unwrap();
+        assert_eq!(cpuid_fingerprint(&reordered), fingerprint);
+
+        // Any single bit change is detected, including in the subleaf index and the flags.
+        let mut changed = cpuid.clone();
+        changed.as_mut_slice()[1].ebx ^= 1 << 5;
+        assert_ne!(cpuid_fingerprint(&changed), fingerprint);
+        let mut changed = cpuid.clone();
+        changed.as_mut_slice()[2].index = 2;
+        assert_ne!(cpuid_fingerprint(&changed), fingerprint);
+        let mut changed = cpuid.clone();
+        changed.as_mut_slice()[0].flags = 1;
+        assert_ne!(cpuid_fingerprint(&changed), fingerprint);
+
+        // A missing entry is detected too.
+        let truncated = CpuId::from_entries(&entries[..2]).unwrap();
+        assert_ne!(cpuid_fingerprint(&truncated), fingerprint);
+    }
+
     #[test]
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_is_same_model() {
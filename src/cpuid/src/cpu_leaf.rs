@@ -55,18 +55,27 @@ pub mod leaf_0x1 {
         pub const DCA_BITINDEX: u32 = 18;
         pub const MOVBE_BITINDEX: u32 = 22;
         pub const TSC_DEADLINE_TIMER_BITINDEX: u32 = 24;
+        // XSAVE = XSAVE/XRSTOR/XSETBV/XGETBV instructions and XCR0
+        pub const XSAVE_BITINDEX: u32 = 26;
         pub const OSXSAVE_BITINDEX: u32 = 27;
+        // AVX = Advanced Vector Extensions
+        pub const AVX_BITINDEX: u32 = 28;
+        // RDRAND = hardware random number generator
+        pub const RDRAND_BITINDEX: u32 = 30;
         // Cpu is running on a hypervisor.
         pub const HYPERVISOR_BITINDEX: u32 = 31;
     }
 
     pub mod edx {
+        pub const FPU_BITINDEX: u32 = 0; // x87 FPU on Chip
         pub const MCE_BITINDEX: u32 = 7; // Memory Check Exception
+        pub const APIC_BITINDEX: u32 = 9; // APIC on Chip
         pub const MTRR_BITINDEX: u32 = 12; // Memory Type Range Registers
         pub const PSN_BITINDEX: u32 = 18; // Processor Serial Number
         pub const SSE42_BITINDEX: u32 = 20; // SSE 4.2
         pub const DS_BITINDEX: u32 = 21; // Debug Store.
         pub const ACPI_BITINDEX: u32 = 22; // Thermal Monitor and Software Controlled Clock Facilities.
+        pub const SSE2_BITINDEX: u32 = 26; // SSE2
         pub const SS_BITINDEX: u32 = 27; // Self Snoop
         pub const HTT_BITINDEX: u32 = 28; // Max APIC IDs reserved field is valid
         pub const TM_BITINDEX: u32 = 29; // Thermal Monitor.
@@ -79,9 +88,24 @@ pub mod leaf_cache_parameters {
     pub mod eax {
         use crate::bit_helper::BitRange;
 
+        pub const CACHE_TYPE_BITRANGE: BitRange = bit_range!(4, 0);
         pub const CACHE_LEVEL_BITRANGE: BitRange = bit_range!(7, 5);
         pub const MAX_CPUS_PER_CORE_BITRANGE: BitRange = bit_range!(25, 14);
     }
+
+    pub mod ebx {
+        use crate::bit_helper::BitRange;
+
+        // The fields below hold their value - 1.
+        pub const WAYS_BITRANGE: BitRange = bit_range!(31, 22);
+        pub const PARTITIONS_BITRANGE: BitRange = bit_range!(21, 12);
+        pub const LINE_SIZE_BITRANGE: BitRange = bit_range!(11, 0);
+    }
+}
+
+// Processor Serial Number Leaf
+pub mod leaf_0x3 {
+    pub const LEAF_NUM: u32 = 0x3;
 }
 
 // Deterministic Cache Parameters Leaf
@@ -117,6 +141,8 @@ pub mod leaf_0x7 {
 
     pub mod index0 {
         pub mod ebx {
+            // RDFSBASE/RDGSBASE/WRFSBASE/WRGSBASE, if CR4.FSGSBASE is set.
+            pub const FSGSBASE_BITINDEX: u32 = 0;
             // 1 = TSC_ADJUST
             pub const SGX_BITINDEX: u32 = 2;
             pub const BMI1_BITINDEX: u32 = 3;
@@ -171,6 +197,8 @@ pub mod leaf_0x7 {
         }
 
         pub mod ecx {
+            use crate::bit_helper::BitRange;
+
             // 0 = PREFETCHWT1 (move data closer to the processor in anticipation of future use)
             // AVX512_VBMI = AVX-512 Vector Byte Manipulation Instructions
             pub const AVX512_VBMI_BITINDEX: u32 = 1;
@@ -182,10 +210,13 @@ pub mod leaf_0x7 {
             pub const OSPKE_BITINDEX: u32 = 4;
             // 5 = WAITPKG
             // 6 = AVX512_VBMI2
-            // 7 reserved
+            // CET_SS = Control-flow Enforcement Technology shadow stacks
+            pub const CET_SS_BITINDEX: u32 = 7;
             // 8 = GFNI
-            // 9 = VAES
-            // 10 = VPCLMULQDQ
+            // VAES = Vector AES instructions
+            pub const VAES_BITINDEX: u32 = 9;
+            // VPCLMULQDQ = Carry-less multiplication of quadwords on vectors
+            pub const VPCLMULQDQ_BITINDEX: u32 = 10;
             // AVX512_VNNI = Vector Neural Network Instructions
             pub const AVX512_VNNI_BITINDEX: u32 = 11;
             // 12 = AVX512_BITALG
@@ -195,9 +226,13 @@ pub mod leaf_0x7 {
             // LA57 = 5-level page tables.
             pub const LA57: u32 = 16;
             // 21 - 17 = The value of MAWAU used by the BNDLDX and BNDSTX instructions in 64-bit
-            // mode. Read Processor ID
+            // mode.
+            pub const MAWAU_BITRANGE: BitRange = bit_range!(21, 17);
+            // Read Processor ID
             pub const RDPID_BITINDEX: u32 = 22;
-            // 23 - 29 reserved
+            // 23 - 28 reserved
+            // ENQCMD = Enqueue Stores
+            pub const ENQCMD_BITINDEX: u32 = 29;
             // SGX_LC = SGX Launch Configuration
             pub const SGX_LC_BITINDEX: u32 = 30;
             // 31 reserved
@@ -208,6 +243,20 @@ pub mod leaf_0x7 {
             pub const AVX512_4VNNIW_BITINDEX: u32 = 2;
             // AVX-512 4-register Multiply Accumulation Single precision
             pub const AVX512_4FMAPS_BITINDEX: u32 = 3;
+            // SERIALIZE = Serialize instruction execution
+            pub const SERIALIZE_BITINDEX: u32 = 14;
+            // HYBRID = The processor is identified as a hybrid part (e.g. P-cores and E-cores)
+            pub const HYBRID_BITINDEX: u32 = 15;
+            // Platform Configuration, used to program the MKTME keys
+            pub const PCONFIG_BITINDEX: u32 = 18;
+            // CET_IBT = Control-flow Enforcement Technology indirect branch tracking
+            pub const CET_IBT_BITINDEX: u32 = 20;
+            // AMX tile computations on bfloat16 numbers
+            pub const AMX_BF16_BITINDEX: u32 = 22;
+            // AMX tile architecture
+            pub const AMX_TILE_BITINDEX: u32 = 24;
+            // AMX tile computations on 8-bit integers
+            pub const AMX_INT8_BITINDEX: u32 = 25;
             pub const ARCH_CAPABILITIES_BITINDEX: u32 = 29;
         }
     }
@@ -259,6 +308,8 @@ pub mod leaf_0xd {
             pub const MPX_STATE_BITRANGE: BitRange = bit_range!(4, 3);
             pub const AVX512_STATE_BITRANGE: BitRange = bit_range!(7, 5);
             pub const PKRU_BITINDEX: u32 = 9;
+            // XTILECFG and XTILEDATA, the AMX state components.
+            pub const AMX_STATE_BITRANGE: BitRange = bit_range!(18, 17);
         }
     }
 
@@ -268,9 +319,94 @@ pub mod leaf_0xd {
             pub const XGETBV_SHIFT: u32 = 2;
             pub const XSAVES_SHIFT: u32 = 3;
         }
+
+        pub mod ecx {
+            use crate::bit_helper::BitRange;
+
+            // PT state, used by Intel Processor Trace.
+            pub const PT_STATE_BITINDEX: u32 = 8;
+            // PASID state, used by ENQCMD.
+            pub const PASID_STATE_BITINDEX: u32 = 10;
+            // CET_U and CET_S states, used by the CET shadow stacks and indirect branch
+            // tracking.
+            pub const CET_STATE_BITRANGE: BitRange = bit_range!(12, 11);
+        }
+    }
+
+    // The sub-leaves describing each one of the state components (index >= 2).
+    pub mod component {
+        // Size in bytes of the legacy region and the header of an XSAVE area.
+        pub const LEGACY_AND_HEADER_SIZE: u32 = 576;
+
+        pub mod ecx {
+            // Set if the state component is 64-byte aligned in the compacted format.
+            pub const ALIGNED_BITINDEX: u32 = 1;
+        }
+    }
+}
+
+// Intel Processor Trace Enumeration Main Leaf
+pub mod leaf_0x14 {
+    pub const LEAF_NUM: u32 = 0x14;
+}
+
+// Deterministic Address Translation Parameters Leaf
+pub mod leaf_0x18 {
+    pub const LEAF_NUM: u32 = 0x18;
+
+    pub mod ebx {
+        use crate::bit_helper::BitRange;
+
+        pub const PAGE_SIZES_BITRANGE: BitRange = bit_range!(3, 0);
+        pub const WAYS_BITRANGE: BitRange = bit_range!(31, 16);
+    }
+
+    pub mod edx {
+        use crate::bit_helper::BitRange;
+
+        // 0 = Null (no more TLBs), 1 = Data, 2 = Instruction, 3 = Unified, 4 = Load only,
+        // 5 = Store only
+        pub const TLB_TYPE_BITRANGE: BitRange = bit_range!(4, 0);
+        pub const TLB_LEVEL_BITRANGE: BitRange = bit_range!(7, 5);
+        pub const FULLY_ASSOCIATIVE_BITINDEX: u32 = 8;
+        // The number of logical processors sharing the TLB - 1
+        pub const MAX_SHARING_CPUS_BITRANGE: BitRange = bit_range!(25, 14);
+    }
+}
+
+// Hybrid Information Enumeration Leaf
+pub mod leaf_0x1a {
+    pub const LEAF_NUM: u32 = 0x1a;
+
+    pub mod eax {
+        use crate::bit_helper::BitRange;
+
+        // 0x20 = Atom (E-core), 0x40 = Core (P-core)
+        pub const CORE_TYPE_BITRANGE: BitRange = bit_range!(31, 24);
+        pub const NATIVE_MODEL_ID_BITRANGE: BitRange = bit_range!(23, 0);
     }
 }
 
+// Tile Information Main Leaf (AMX palettes)
+pub mod leaf_0x1d {
+    pub const LEAF_NUM: u32 = 0x1d;
+}
+
+// TMUL Information Main Leaf (AMX)
+pub mod leaf_0x1e {
+    pub const LEAF_NUM: u32 = 0x1e;
+}
+
+// V2 Extended Topology Leaf, with the same layout as leaf 0xB
+pub mod leaf_0x1f {
+    pub const LEAF_NUM: u32 = 0x1f;
+}
+
+// Hypervisor Vendor Leaf
+pub mod leaf_0x40000000 {
+    pub const LEAF_NUM: u32 = 0x4000_0000;
+}
+
 pub mod leaf_0x80000000 {
     pub const LEAF_NUM: u32 = 0x8000_0000;
 
@@ -288,16 +424,66 @@ pub mod leaf_0x80000001 {
         pub const TOPOEXT_INDEX: u32 = 22;
         pub const PREFETCH_BITINDEX: u32 = 8; // 3DNow! PREFETCH/PREFETCHW instructions
         pub const LZCNT_BITINDEX: u32 = 5; // advanced bit manipulation
+        pub const SSE4A_BITINDEX: u32 = 6; // EXTRQ, INSERTQ, MOVNTSS and MOVNTSD (AMD only)
+        pub const MISALIGN_SSE_BITINDEX: u32 = 7; // misaligned SSE mode (AMD only)
+        pub const OSVW_BITINDEX: u32 = 9; // OS visible workaround (AMD only)
+        pub const IBS_BITINDEX: u32 = 10; // instruction based sampling (AMD only)
+        pub const PERFCTR_CORE_BITINDEX: u32 = 23; // core performance counter extensions (AMD only)
+        pub const MONITORX_BITINDEX: u32 = 29; // MONITORX/MWAITX instructions (AMD only)
     }
 
     pub mod edx {
+        pub const MMXEXT_BITINDEX: u32 = 22; // AMD extensions to MMX instructions (AMD only)
+        pub const FFXSR_BITINDEX: u32 = 25; // FXSAVE/FXRSTOR optimizations (AMD only)
         pub const PDPE1GB_BITINDEX: u32 = 26; // 1-GByte pages are available if 1.
+        pub const RDTSCP_BITINDEX: u32 = 27; // RDTSCP and IA32_TSC_AUX are available if 1.
+        pub const LM_BITINDEX: u32 = 29; // Long mode (64-bit) is available if 1.
+    }
+}
+
+// L1 Cache and TLB Identifiers Leaf
+pub mod leaf_0x80000005 {
+    pub const LEAF_NUM: u32 = 0x8000_0005;
+
+    pub mod ecx {
+        use crate::bit_helper::BitRange;
+
+        // L1 data cache size in KB
+        pub const L1_DC_SIZE_BITRANGE: BitRange = bit_range!(31, 24);
+    }
+
+    pub mod edx {
+        use crate::bit_helper::BitRange;
+
+        // L1 instruction cache size in KB
+        pub const L1_IC_SIZE_BITRANGE: BitRange = bit_range!(31, 24);
     }
 }
 
 pub mod leaf_0x80000008 {
     pub const LEAF_NUM: u32 = 0x8000_0008;
 
+    pub mod eax {
+        use crate::bit_helper::BitRange;
+
+        // 7 - 0 = The number of physical address bits.
+        // The number of linear address bits.
+        pub const LINEAR_ADDRESS_SIZE_BITRANGE: BitRange = bit_range!(15, 8);
+        // The linear address width with 5-level paging (LA57).
+        pub const LINEAR_ADDRESS_SIZE_5_LEVEL: u32 = 57;
+        // The linear address width with 4-level paging.
+        pub const LINEAR_ADDRESS_SIZE_4_LEVEL: u32 = 48;
+    }
+
+    pub mod ebx {
+        // CLZERO instruction (AMD only)
+        pub const CLZERO_BITINDEX: u32 = 0;
+        // XSAVE always saves/restores the error pointers (AMD only)
+        pub const RSTR_FP_ERR_PTRS_BITINDEX: u32 = 2;
+        // WBNOINVD instruction
+        pub const WBNOINVD_BITINDEX: u32 = 9;
+    }
+
     pub mod ecx {
         use crate::bit_helper::BitRange;
 
@@ -350,3 +536,42 @@ pub mod leaf_0x8000001e {
         pub const NODE_ID_BITRANGE: BitRange = bit_range!(7, 0);
     }
 }
+
+// Encrypted Memory Capabilities Leaf (SME, SEV, SEV-ES, SEV-SNP)
+pub mod leaf_0x8000001f {
+    pub const LEAF_NUM: u32 = 0x8000_001f;
+}
+
+// Extended Feature Identification 2 Leaf (AMD Zen 4 and later)
+pub mod leaf_0x80000021 {
+    pub const LEAF_NUM: u32 = 0x8000_0021;
+
+    pub mod eax {
+        // No nested data breakpoints.
+        pub const NO_NESTED_DATA_BP_BITINDEX: u32 = 0;
+        // WRMSR to FS_BASE, GS_BASE and KERNEL_GS_BASE is non-serializing.
+        pub const FS_GS_BASE_NS_BITINDEX: u32 = 1;
+        // LFENCE is always dispatch serializing.
+        pub const LFENCE_ALWAYS_SERIALIZING_BITINDEX: u32 = 2;
+        // 3 = SmmPgCfgLock (SMM paging configuration lock)
+        // Null segment selector loads also clear the destination segment register base.
+        pub const NULL_SELECT_CLEARS_BASE_BITINDEX: u32 = 6;
+        // 7 = UpperAddressIgnore (aka Top Byte Ignore)
+        // Automatic IBRS.
+        pub const AUTOMATIC_IBRS_BITINDEX: u32 = 8;
+        // The SMM_CTL MSR is not supported.
+        pub const NO_SMM_CTL_MSR_BITINDEX: u32 = 9;
+        // 13 = PrefetchCtlMsr (the prefetch control MSR is supported)
+        // 17 = CpuidUserDis (CPUID can be disabled for user mode)
+        // Enhanced Predictive Store Forwarding.
+        pub const EPSF_BITINDEX: u32 = 18;
+        // Selective Branch Predictor Barrier.
+        pub const SBPB_BITINDEX: u32 = 27;
+        // IBPB also flushes the branch type predictions.
+        pub const IBPB_BRTYPE_BITINDEX: u32 = 28;
+        // Not vulnerable to Speculative Return Stack Overflow.
+        pub const SRSO_NO_BITINDEX: u32 = 29;
+    }
+
+    // ebx[11:0] = MicrocodePatchSize (in 16 byte units)
+}
@@ -0,0 +1,800 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::collections::HashMap;
+
+use kvm_bindings::{kvm_cpuid2, kvm_cpuid_entry2, CpuId};
+use utils::fam::FamStruct;
+
+use crate::bit_helper::{BitHelper, BitRangeExt};
+use crate::cpu_leaf::*;
+use crate::features::Leaf7Features;
+use crate::Error;
+
+// The size of the entry count prefixing the binary representation of a cpuid.
+const BYTES_COUNT_LEN: usize = 4;
+// The size of an entry in the binary representation of a cpuid: the function, the index, the
+// flags and the 4 registers, the padding is left out.
+const BYTES_ENTRY_LEN: usize = 7 * 4;
+
+/// The feature registers of a leaf, along with the bits of each register which are not
+/// host features and are therefore left untouched by `CpuIdExt::intersect_with_host`.
+struct FeatureLeaf {
+    function: u32,
+    index: u32,
+    // Exempted bits for the ebx, ecx and edx registers, `None` if the register doesn't
+    // hold feature bits.
+    ebx: Option<u32>,
+    ecx: Option<u32>,
+    edx: Option<u32>,
+}
+
+fn feature_leaves() -> [FeatureLeaf; 3] {
+    [
+        FeatureLeaf {
+            function: leaf_0x1::LEAF_NUM,
+            index: 0,
+            ebx: None,
+            // Both are emulated by KVM, regardless of the host support.
+            ecx: Some(
+                *0.write_bit(leaf_0x1::ecx::HYPERVISOR_BITINDEX, true)
+                    .write_bit(leaf_0x1::ecx::TSC_DEADLINE_TIMER_BITINDEX, true),
+            ),
+            // HTT only describes the topology set up by Firecracker.
+            edx: Some(*0.write_bit(leaf_0x1::edx::HTT_BITINDEX, true)),
+        },
+        FeatureLeaf {
+            function: leaf_0x7::LEAF_NUM,
+            index: 0,
+            ebx: Some(0),
+            ecx: Some(leaf_0x7::index0::ecx::MAWAU_BITRANGE.get_mask()),
+            edx: Some(0),
+        },
+        FeatureLeaf {
+            function: leaf_0x80000001::LEAF_NUM,
+            index: 0,
+            ebx: None,
+            // The extended topology leaves are built by the transformers.
+            ecx: Some(*0.write_bit(leaf_0x80000001::ecx::TOPOEXT_INDEX, true)),
+            edx: Some(0),
+        },
+    ]
+}
+
+/// Extension methods for the KVM `CpuId` structure.
+pub trait CpuIdExt {
+    /// Returns the number of valid entries.
+    fn len(&self) -> usize;
+
+    /// Returns `true` if there are no entries.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of entries the structure can hold.
+    fn capacity(&self) -> usize;
+
+    /// Returns the entry matching `function` and `index`, if any.
+    fn get_entry(&self, function: u32, index: u32) -> Option<&kvm_cpuid_entry2>;
+
+    /// Returns the `(function, index)` pairs of all the entries, in table order.
+    fn keys(&self) -> Vec<(u32, u32)>;
+
+    /// Returns a mutable reference to the entry matching `function` and `index`, if any.
+    fn get_entry_mut(&mut self, function: u32, index: u32) -> Option<&mut kvm_cpuid_entry2>;
+
+    /// Replaces the entries with the same function and index as the ones of `entries`, and
+    /// adds the others.
+    ///
+    /// Fails with `Error::Fam` without changing anything if there is no room for the added
+    /// entries, see `capacity`.
+    fn set_entries(&mut self, entries: &[kvm_cpuid_entry2]) -> Result<(), Error>;
+
+    /// Sorts the entries by `(function, index)`.
+    ///
+    /// This places the standard leaves first, followed by the hypervisor leaves
+    /// (0x40000000 - 0x4fffffff) and the extended leaves (>= 0x80000000), which makes the
+    /// resulting table independent of the order in which KVM reported the entries.
+    fn sort_entries(&mut self);
+
+    /// Returns the structured extended features of leaf 0x7, subleaf 0, if present.
+    fn leaf7_features(&self) -> Option<Leaf7Features>;
+
+    /// Returns the maximum standard leaf advertised in leaf 0x0, if present.
+    fn max_std_leaf(&self) -> Option<u32>;
+
+    /// Returns the maximum extended leaf advertised in leaf 0x80000000, if present.
+    fn max_ext_leaf(&self) -> Option<u32>;
+
+    /// Clears the feature bits of leaves 0x1, 0x7 and 0x80000001 which are not set in `host`.
+    ///
+    /// This is meant to be the last step of any processing pipeline, ensuring that no template
+    /// exposes more features than the host supports. The bits emulated or synthesized by
+    /// Firecracker regardless of the host (e.g. the hypervisor bit) are left untouched.
+    fn intersect_with_host(&mut self, host: &CpuId);
+
+    /// Sets RDRAND (leaf 0x1) and RDSEED (leaf 0x7) if `enabled` and they are set in `host`,
+    /// clears them otherwise.
+    ///
+    /// A guest relying on entropy instructions which aren't backed by the host would block
+    /// waiting for entropy, so they are never advertised without host support.
+    fn set_entropy_features(&mut self, host: &CpuId, enabled: bool);
+
+    /// Sets LA57 (leaf 0x7) if `enabled`, clears it otherwise, and updates the linear address
+    /// width (leaf 0x80000008) to match the paging mode.
+    ///
+    /// The guest has to be configured for 5-level paging when LA57 is set. The host support is
+    /// not checked, see `intersect_with_host`.
+    fn set_la57(&mut self, enabled: bool);
+
+    /// Writes `signature` to EBX, ECX and EDX of the hypervisor leaf 0x40000000, see
+    /// `hypervisor_signatures` for the well-known ones. Nothing is done if the leaf is missing.
+    fn set_hypervisor_signature(&mut self, signature: &[u8; 12]);
+
+    /// Returns the signature of the hypervisor leaf 0x40000000, if present.
+    fn hypervisor_signature(&self) -> Option<[u8; 12]>;
+
+    /// Returns a compact binary representation of the entries, e.g. to store them in a
+    /// snapshot.
+    ///
+    /// The entry count comes first, followed by the function, index, flags, eax, ebx, ecx and
+    /// edx of each entry, all as little endian `u32`s.
+    fn to_bytes(&self) -> Vec<u8>;
+
+    /// Builds a cpuid from the binary representation returned by `to_bytes`.
+    ///
+    /// Fails with `Error::InvalidBytes` if `bytes` is truncated or has trailing data.
+    fn from_bytes(bytes: &[u8]) -> Result<CpuId, Error>;
+}
+
+impl CpuIdExt for CpuId {
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn capacity(&self) -> usize {
+        kvm_cpuid2::max_len()
+    }
+
+    fn get_entry(&self, function: u32, index: u32) -> Option<&kvm_cpuid_entry2> {
+        self.as_slice()
+            .iter()
+            .find(|entry| entry.function == function && entry.index == index)
+    }
+
+    fn keys(&self) -> Vec<(u32, u32)> {
+        self.as_slice()
+            .iter()
+            .map(|entry| (entry.function, entry.index))
+            .collect()
+    }
+
+    fn get_entry_mut(&mut self, function: u32, index: u32) -> Option<&mut kvm_cpuid_entry2> {
+        self.as_mut_slice()
+            .iter_mut()
+            .find(|entry| entry.function == function && entry.index == index)
+    }
+
+    fn set_entries(&mut self, entries: &[kvm_cpuid_entry2]) -> Result<(), Error> {
+        let mut added_keys: Vec<(u32, u32)> = entries
+            .iter()
+            .filter(|entry| self.get_entry(entry.function, entry.index).is_none())
+            .map(|entry| (entry.function, entry.index))
+            .collect();
+        added_keys.sort_unstable();
+        added_keys.dedup();
+        if self.len() + added_keys.len() > self.capacity() {
+            return Err(Error::Fam(utils::fam::Error::SizeLimitExceeded));
+        }
+
+        for new_entry in entries {
+            match self.get_entry_mut(new_entry.function, new_entry.index) {
+                Some(entry) => *entry = *new_entry,
+                None => self.push(*new_entry).map_err(Error::Fam)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sort_entries(&mut self) {
+        self.as_mut_slice()
+            .sort_unstable_by_key(|entry| (entry.function, entry.index));
+    }
+
+    fn leaf7_features(&self) -> Option<Leaf7Features> {
+        self.get_entry(leaf_0x7::LEAF_NUM, 0)
+            .map(Leaf7Features::from)
+    }
+
+    fn max_std_leaf(&self) -> Option<u32> {
+        self.get_entry(0x0, 0).map(|entry| entry.eax)
+    }
+
+    fn max_ext_leaf(&self) -> Option<u32> {
+        self.get_entry(leaf_0x80000000::LEAF_NUM, 0)
+            .map(|entry| entry.eax)
+    }
+
+    fn intersect_with_host(&mut self, host: &CpuId) {
+        fn intersect(value: &mut u32, host_value: u32, exempted: Option<u32>) {
+            if let Some(exempted) = exempted {
+                *value &= host_value | exempted;
+            }
+        }
+
+        for leaf in feature_leaves().iter() {
+            let host_entry = host
+                .get_entry(leaf.function, leaf.index)
+                .copied()
+                .unwrap_or_default();
+
+            if let Some(entry) = self.get_entry_mut(leaf.function, leaf.index) {
+                intersect(&mut entry.ebx, host_entry.ebx, leaf.ebx);
+                intersect(&mut entry.ecx, host_entry.ecx, leaf.ecx);
+                intersect(&mut entry.edx, host_entry.edx, leaf.edx);
+            }
+        }
+    }
+
+    fn set_entropy_features(&mut self, host: &CpuId, enabled: bool) {
+        let rdrand = enabled
+            && matches!(
+                host.get_entry(leaf_0x1::LEAF_NUM, 0),
+                Some(entry) if entry.ecx.read_bit(leaf_0x1::ecx::RDRAND_BITINDEX)
+            );
+        let rdseed = enabled
+            && matches!(
+                host.get_entry(leaf_0x7::LEAF_NUM, 0),
+                Some(entry) if entry.ebx.read_bit(leaf_0x7::index0::ebx::RDSEED_BITINDEX)
+            );
+
+        if let Some(entry) = self.get_entry_mut(leaf_0x1::LEAF_NUM, 0) {
+            entry.ecx.write_bit(leaf_0x1::ecx::RDRAND_BITINDEX, rdrand);
+        }
+        if let Some(entry) = self.get_entry_mut(leaf_0x7::LEAF_NUM, 0) {
+            entry
+                .ebx
+                .write_bit(leaf_0x7::index0::ebx::RDSEED_BITINDEX, rdseed);
+        }
+    }
+
+    fn set_la57(&mut self, enabled: bool) {
+        use crate::cpu_leaf::leaf_0x80000008::eax::*;
+
+        if let Some(entry) = self.get_entry_mut(leaf_0x7::LEAF_NUM, 0) {
+            entry.ecx.write_bit(leaf_0x7::index0::ecx::LA57, enabled);
+        }
+        if let Some(entry) = self.get_entry_mut(leaf_0x80000008::LEAF_NUM, 0) {
+            let linear_address_size = if enabled {
+                LINEAR_ADDRESS_SIZE_5_LEVEL
+            } else {
+                LINEAR_ADDRESS_SIZE_4_LEVEL
+            };
+            entry
+                .eax
+                .write_bits_in_range(&LINEAR_ADDRESS_SIZE_BITRANGE, linear_address_size);
+        }
+    }
+
+    fn set_hypervisor_signature(&mut self, signature: &[u8; 12]) {
+        let reg = |i: usize| u32::from_le_bytes(signature[i..i + 4].try_into().unwrap());
+        if let Some(entry) = self.get_entry_mut(leaf_0x40000000::LEAF_NUM, 0) {
+            entry.ebx = reg(0);
+            entry.ecx = reg(4);
+            entry.edx = reg(8);
+        }
+    }
+
+    fn hypervisor_signature(&self) -> Option<[u8; 12]> {
+        self.get_entry(leaf_0x40000000::LEAF_NUM, 0).map(|entry| {
+            let mut signature = [0; 12];
+            signature[..4].copy_from_slice(&entry.ebx.to_le_bytes());
+            signature[4..8].copy_from_slice(&entry.ecx.to_le_bytes());
+            signature[8..].copy_from_slice(&entry.edx.to_le_bytes());
+            signature
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let entries = self.as_slice();
+        let mut bytes = Vec::with_capacity(BYTES_COUNT_LEN + entries.len() * BYTES_ENTRY_LEN);
+        bytes.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+        for entry in entries {
+            for value in [
+                entry.function,
+                entry.index,
+                entry.flags,
+                entry.eax,
+                entry.ebx,
+                entry.ecx,
+                entry.edx,
+            ] {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<CpuId, Error> {
+        if bytes.len() < BYTES_COUNT_LEN {
+            return Err(Error::InvalidBytes("missing entry count"));
+        }
+        let (count, entries) = bytes.split_at(BYTES_COUNT_LEN);
+        let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+        if count > kvm_cpuid2::max_len() {
+            return Err(Error::InvalidBytes("too many entries"));
+        }
+        if entries.len() != count * BYTES_ENTRY_LEN {
+            return Err(Error::InvalidBytes("size doesn't match the entry count"));
+        }
+
+        let entries: Vec<kvm_cpuid_entry2> = entries
+            .chunks_exact(BYTES_ENTRY_LEN)
+            .map(|entry| {
+                let mut values = entry
+                    .chunks_exact(4)
+                    .map(|value| u32::from_le_bytes(value.try_into().unwrap()));
+                // The chunk holds exactly 7 values.
+                let mut next = || values.next().unwrap();
+                kvm_cpuid_entry2 {
+                    function: next(),
+                    index: next(),
+                    flags: next(),
+                    eax: next(),
+                    ebx: next(),
+                    ecx: next(),
+                    edx: next(),
+                    padding: [0; 3],
+                }
+            })
+            .collect();
+        CpuId::from_entries(&entries).map_err(Error::Fam)
+    }
+}
+
+/// An index of the entries of a cpuid by `(function, index)`, e.g. to avoid scanning the whole
+/// table on every lookup when the same cpuid is processed repeatedly.
+///
+/// The cache holds the positions of the entries, so it goes stale when entries are added,
+/// removed or reordered. A stale position is detected and the lookup falls back to a scan,
+/// but the cache should then be built again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpuidCache {
+    positions: HashMap<(u32, u32), usize>,
+}
+
+impl CpuidCache {
+    /// Builds the cache of the entries of `cpuid`.
+    ///
+    /// If several entries share the same `(function, index)`, the first one is cached, like
+    /// `CpuIdExt::get_entry` would return.
+    pub fn build(cpuid: &CpuId) -> CpuidCache {
+        let mut positions = HashMap::with_capacity(cpuid.len());
+        for (position, entry) in cpuid.as_slice().iter().enumerate() {
+            positions
+                .entry((entry.function, entry.index))
+                .or_insert(position);
+        }
+        CpuidCache { positions }
+    }
+
+    /// Returns a mutable reference to the entry of `cpuid` matching `function` and `index`, if
+    /// any.
+    pub fn get_mut<'a>(
+        &self,
+        cpuid: &'a mut CpuId,
+        function: u32,
+        index: u32,
+    ) -> Option<&'a mut kvm_cpuid_entry2> {
+        let position = self.positions.get(&(function, index)).copied();
+        // Check that the entry didn't move since the cache was built.
+        let valid = |position: &usize| {
+            matches!(
+                cpuid.as_slice().get(*position),
+                Some(entry) if entry.function == function && entry.index == index
+            )
+        };
+
+        match position.filter(valid) {
+            Some(position) => cpuid.as_mut_slice().get_mut(position),
+            None => cpuid.get_entry_mut(function, index),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(function: u32, index: u32) -> kvm_cpuid_entry2 {
+        kvm_cpuid_entry2 {
+            function,
+            index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut leaf_0x7 = entry(leaf_0x7::LEAF_NUM, 0);
+        leaf_0x7.flags = 1;
+        leaf_0x7.ebx = 0xf1bf_07ab;
+        leaf_0x7.edx = 0xac00_0410;
+        let cpuid = CpuId::from_entries(&[entry(0x0, 0), leaf_0x7, entry(0x8000_0000, 0)]).unwrap();
+
+        let bytes = cpuid.to_bytes();
+        assert_eq!(bytes.len(), 4 + 3 * 28);
+        assert_eq!(&bytes[..4], &[3, 0, 0, 0]);
+        assert_eq!(
+            CpuId::from_bytes(&bytes).unwrap().as_slice(),
+            cpuid.as_slice()
+        );
+
+        let empty = CpuId::new(0).unwrap();
+        assert_eq!(empty.to_bytes(), vec![0; 4]);
+        assert!(CpuId::from_bytes(&empty.to_bytes()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_from_bytes_invalid() {
+        let bytes = CpuId::from_entries(&[entry(0x0, 0), entry(0x1, 0)])
+            .unwrap()
+            .to_bytes();
+
+        for invalid in [
+            // Truncated entry count.
+            &bytes[..3],
+            // Truncated entry.
+            &bytes[..bytes.len() - 1],
+            // Missing entry.
+            &bytes[..4 + 28],
+        ] {
+            assert!(matches!(
+                CpuId::from_bytes(invalid),
+                Err(Error::InvalidBytes(_))
+            ));
+        }
+
+        // Trailing data.
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            CpuId::from_bytes(&trailing),
+            Err(Error::InvalidBytes(_))
+        ));
+
+        // A corrupt entry count larger than what KVM supports.
+        let mut corrupt = bytes;
+        corrupt[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            CpuId::from_bytes(&corrupt),
+            Err(Error::InvalidBytes(_))
+        ));
+    }
+
+    #[test]
+    fn test_len_capacity() {
+        let mut cpuid = CpuId::new(0).unwrap();
+        assert_eq!(cpuid.len(), 0);
+        assert!(cpuid.is_empty());
+        assert_eq!(cpuid.capacity(), kvm_bindings::KVM_MAX_CPUID_ENTRIES);
+
+        cpuid.push(entry(0x0, 0)).unwrap();
+        assert_eq!(cpuid.len(), 1);
+        assert!(!cpuid.is_empty());
+
+        let mut cpuid = CpuId::new(cpuid.capacity()).unwrap();
+        assert_eq!(cpuid.len(), cpuid.capacity());
+        assert!(cpuid.push(entry(0x0, 0)).is_err());
+    }
+
+    #[test]
+    fn test_set_entries() {
+        let mut cpuid = CpuId::from_entries(&[entry(0x0, 0), entry(0x7, 0)]).unwrap();
+        let mut leaf_0x7 = entry(0x7, 0);
+        leaf_0x7.ebx = 1;
+        let mut leaf_0x7_1 = entry(0x7, 1);
+        leaf_0x7_1.eax = 2;
+
+        cpuid.set_entries(&[leaf_0x7, leaf_0x7_1]).unwrap();
+        assert_eq!(cpuid.keys(), vec![(0x0, 0), (0x7, 0), (0x7, 1)]);
+        assert_eq!(cpuid.get_entry(0x7, 0).unwrap().ebx, 1);
+        assert_eq!(cpuid.get_entry(0x7, 1).unwrap().eax, 2);
+
+        // A full cpuid only accepts the entries replacing existing ones.
+        let mut cpuid = CpuId::new(cpuid.capacity()).unwrap();
+        let mut leaf_0x0 = entry(0x0, 0);
+        leaf_0x0.eax = 3;
+        cpuid.set_entries(&[leaf_0x0]).unwrap();
+        assert_eq!(cpuid.as_slice()[0].eax, 3);
+        assert!(matches!(
+            cpuid.set_entries(&[leaf_0x0, leaf_0x7]),
+            Err(Error::Fam(utils::fam::Error::SizeLimitExceeded))
+        ));
+        assert!(cpuid.get_entry(0x7, 0).is_none());
+    }
+
+    #[test]
+    fn test_get_entry() {
+        let mut cpuid = CpuId::from_entries(&[entry(0x7, 0), entry(0x7, 1)]).unwrap();
+
+        assert_eq!(cpuid.get_entry(0x7, 1), Some(&entry(0x7, 1)));
+        assert_eq!(cpuid.get_entry(0x7, 2), None);
+        assert_eq!(cpuid.get_entry(0x1, 0), None);
+
+        cpuid.get_entry_mut(0x7, 1).unwrap().ebx = 1;
+        assert_eq!(cpuid.as_slice()[1].ebx, 1);
+        assert!(cpuid.get_entry_mut(0x8000_0000, 0).is_none());
+    }
+
+    #[test]
+    fn test_intersect_with_host() {
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let mut host_leaf_0x7 = entry(leaf_0x7::LEAF_NUM, 0);
+        host_leaf_0x7.ebx.write_bit(ebx::AVX2_BITINDEX, true);
+        let host = CpuId::from_entries(&[entry(leaf_0x1::LEAF_NUM, 0), host_leaf_0x7]).unwrap();
+
+        // A template over-advertising AVX-512 on top of AVX2.
+        let mut leaf_0x7 = entry(leaf_0x7::LEAF_NUM, 0);
+        leaf_0x7.eax = 1;
+        leaf_0x7
+            .ebx
+            .write_bit(ebx::AVX2_BITINDEX, true)
+            .write_bit(ebx::AVX512F_BITINDEX, true);
+        leaf_0x7.ecx.write_bits_in_range(&ecx::MAWAU_BITRANGE, 3);
+        let mut leaf_0x1 = entry(leaf_0x1::LEAF_NUM, 0);
+        leaf_0x1
+            .ecx
+            .write_bit(leaf_0x1::ecx::HYPERVISOR_BITINDEX, true)
+            .write_bit(leaf_0x1::ecx::MOVBE_BITINDEX, true);
+        leaf_0x1.ebx = 0x0100_0800;
+        let mut leaf_0x80000001 = entry(leaf_0x80000001::LEAF_NUM, 0);
+        leaf_0x80000001.edx = u32::MAX;
+        let mut cpuid = CpuId::from_entries(&[leaf_0x1, leaf_0x7, leaf_0x80000001]).unwrap();
+
+        cpuid.intersect_with_host(&host);
+
+        let leaf_0x7 = cpuid.get_entry(leaf_0x7::LEAF_NUM, 0).unwrap();
+        assert!(leaf_0x7.ebx.read_bit(ebx::AVX2_BITINDEX));
+        assert!(!leaf_0x7.ebx.read_bit(ebx::AVX512F_BITINDEX));
+        // Non feature fields are left untouched.
+        assert_eq!(leaf_0x7.eax, 1);
+        assert_eq!(leaf_0x7.ecx.read_bits_in_range(&ecx::MAWAU_BITRANGE), 3);
+
+        let leaf_0x1 = cpuid.get_entry(leaf_0x1::LEAF_NUM, 0).unwrap();
+        assert!(leaf_0x1.ecx.read_bit(leaf_0x1::ecx::HYPERVISOR_BITINDEX));
+        assert!(!leaf_0x1.ecx.read_bit(leaf_0x1::ecx::MOVBE_BITINDEX));
+        assert_eq!(leaf_0x1.ebx, 0x0100_0800);
+
+        // The leaf is missing from the host, so none of its features are supported.
+        let leaf_0x80000001 = cpuid.get_entry(leaf_0x80000001::LEAF_NUM, 0).unwrap();
+        assert_eq!(leaf_0x80000001.edx, 0);
+    }
+
+    #[test]
+    fn test_intersect_with_host_htt() {
+        use crate::cpu_leaf::leaf_0x1::edx::*;
+
+        // A host without SMT, e.g. a single core VM, which doesn't report HTT.
+        let mut host_leaf_0x1 = entry(leaf_0x1::LEAF_NUM, 0);
+        host_leaf_0x1.edx.write_bit(SSE2_BITINDEX, true);
+        let host = CpuId::from_entries(&[host_leaf_0x1]).unwrap();
+
+        // The guest topology advertises several logical processors per package.
+        let mut leaf_0x1 = entry(leaf_0x1::LEAF_NUM, 0);
+        leaf_0x1
+            .edx
+            .write_bit(SSE2_BITINDEX, true)
+            .write_bit(HTT_BITINDEX, true)
+            .write_bit(PBE_BITINDEX, true);
+        let mut cpuid = CpuId::from_entries(&[leaf_0x1]).unwrap();
+
+        cpuid.intersect_with_host(&host);
+
+        let leaf_0x1 = cpuid.get_entry(leaf_0x1::LEAF_NUM, 0).unwrap();
+        assert!(leaf_0x1.edx.read_bit(HTT_BITINDEX));
+        assert!(leaf_0x1.edx.read_bit(SSE2_BITINDEX));
+        assert!(!leaf_0x1.edx.read_bit(PBE_BITINDEX));
+    }
+
+    #[test]
+    fn test_set_la57() {
+        use crate::cpu_leaf::leaf_0x7::index0::ecx::LA57;
+        use crate::cpu_leaf::leaf_0x80000008::eax::*;
+
+        let mut leaf_0x80000008 = entry(leaf_0x80000008::LEAF_NUM, 0);
+        // 48 linear and 46 physical address bits.
+        leaf_0x80000008.eax = 0x302e;
+        let mut cpuid =
+            CpuId::from_entries(&[entry(leaf_0x7::LEAF_NUM, 0), leaf_0x80000008]).unwrap();
+        let la57 = |cpuid: &CpuId| {
+            (
+                cpuid
+                    .get_entry(leaf_0x7::LEAF_NUM, 0)
+                    .unwrap()
+                    .ecx
+                    .read_bit(LA57),
+                cpuid
+                    .get_entry(leaf_0x80000008::LEAF_NUM, 0)
+                    .unwrap()
+                    .eax
+                    .read_bits_in_range(&LINEAR_ADDRESS_SIZE_BITRANGE),
+            )
+        };
+
+        cpuid.set_la57(true);
+        assert_eq!(la57(&cpuid), (true, LINEAR_ADDRESS_SIZE_5_LEVEL));
+        // The physical address width is left untouched.
+        assert_eq!(
+            cpuid.get_entry(leaf_0x80000008::LEAF_NUM, 0).unwrap().eax,
+            0x392e
+        );
+
+        cpuid.set_la57(false);
+        assert_eq!(la57(&cpuid), (false, LINEAR_ADDRESS_SIZE_4_LEVEL));
+        assert_eq!(
+            cpuid.get_entry(leaf_0x80000008::LEAF_NUM, 0).unwrap().eax,
+            0x302e
+        );
+
+        // The missing leaves are not added.
+        let mut cpuid = CpuId::from_entries(&[entry(leaf_0x7::LEAF_NUM, 0)]).unwrap();
+        cpuid.set_la57(true);
+        assert_eq!(cpuid.len(), 1);
+    }
+
+    #[test]
+    fn test_set_entropy_features() {
+        use crate::cpu_leaf::leaf_0x1::ecx::RDRAND_BITINDEX;
+        use crate::cpu_leaf::leaf_0x7::index0::ebx::RDSEED_BITINDEX;
+
+        let entropy = |cpuid: &CpuId| {
+            (
+                cpuid
+                    .get_entry(leaf_0x1::LEAF_NUM, 0)
+                    .unwrap()
+                    .ecx
+                    .read_bit(RDRAND_BITINDEX),
+                cpuid
+                    .get_entry(leaf_0x7::LEAF_NUM, 0)
+                    .unwrap()
+                    .ebx
+                    .read_bit(RDSEED_BITINDEX),
+            )
+        };
+        let lacking_host =
+            CpuId::from_entries(&[entry(leaf_0x1::LEAF_NUM, 0), entry(leaf_0x7::LEAF_NUM, 0)])
+                .unwrap();
+        let mut host = lacking_host.clone();
+        host.get_entry_mut(leaf_0x1::LEAF_NUM, 0)
+            .unwrap()
+            .ecx
+            .write_bit(RDRAND_BITINDEX, true);
+        host.get_entry_mut(leaf_0x7::LEAF_NUM, 0)
+            .unwrap()
+            .ebx
+            .write_bit(RDSEED_BITINDEX, true);
+
+        // The host has both instructions.
+        let mut cpuid = lacking_host.clone();
+        cpuid.set_entropy_features(&host, true);
+        assert_eq!(entropy(&cpuid), (true, true));
+        cpuid.set_entropy_features(&host, false);
+        assert_eq!(entropy(&cpuid), (false, false));
+
+        // The host lacks them, so they are cleared even when enabled.
+        let mut cpuid = host.clone();
+        cpuid.set_entropy_features(&lacking_host, true);
+        assert_eq!(entropy(&cpuid), (false, false));
+        let mut cpuid = host.clone();
+        cpuid.set_entropy_features(&CpuId::new(0).unwrap(), true);
+        assert_eq!(entropy(&cpuid), (false, false));
+
+        // Missing leaves aren't added.
+        let mut cpuid = CpuId::new(0).unwrap();
+        cpuid.set_entropy_features(&host, true);
+        assert!(cpuid.is_empty());
+    }
+
+    #[test]
+    fn test_leaf7_features() {
+        use crate::features::{Leaf7Sub0Ebx, Leaf7Sub0Ecx};
+
+        let mut cpuid = CpuId::from_entries(&[entry(leaf_0x7::LEAF_NUM, 1)]).unwrap();
+        assert_eq!(cpuid.leaf7_features(), None);
+
+        let mut leaf_0x7 = entry(leaf_0x7::LEAF_NUM, 0);
+        leaf_0x7.ebx = (Leaf7Sub0Ebx::SMEP | Leaf7Sub0Ebx::SMAP).bits();
+        leaf_0x7.ecx = Leaf7Sub0Ecx::UMIP.bits();
+        cpuid.push(leaf_0x7).unwrap();
+
+        let features = cpuid.leaf7_features().unwrap();
+        assert_eq!(features.ebx, Leaf7Sub0Ebx::SMEP | Leaf7Sub0Ebx::SMAP);
+        assert_eq!(features.ecx, Leaf7Sub0Ecx::UMIP);
+        assert!(features.edx.is_empty());
+    }
+
+    #[test]
+    fn test_max_leaves() {
+        let mut cpuid = CpuId::from_entries(&[entry(0x1, 0)]).unwrap();
+        assert_eq!(cpuid.max_std_leaf(), None);
+        assert_eq!(cpuid.max_ext_leaf(), None);
+
+        let mut leaf_0x0 = entry(0x0, 0);
+        leaf_0x0.eax = 0x16;
+        let mut leaf_0x80000000 = entry(leaf_0x80000000::LEAF_NUM, 0);
+        leaf_0x80000000.eax = 0x8000_0008;
+        cpuid.push(leaf_0x80000000).unwrap();
+        cpuid.push(leaf_0x0).unwrap();
+        assert_eq!(cpuid.max_std_leaf(), Some(0x16));
+        assert_eq!(cpuid.max_ext_leaf(), Some(0x8000_0008));
+    }
+
+    #[test]
+    fn test_cpuid_cache() {
+        let mut cpuid =
+            CpuId::from_entries(&[entry(0x0, 0), entry(0x7, 0), entry(0x7, 1)]).unwrap();
+        let cache = CpuidCache::build(&cpuid);
+
+        cache.get_mut(&mut cpuid, 0x7, 1).unwrap().ebx = 1;
+        assert_eq!(cpuid.as_slice()[2].ebx, 1);
+        assert_eq!(cache.get_mut(&mut cpuid, 0x0, 0), Some(&mut entry(0x0, 0)));
+        assert!(cache.get_mut(&mut cpuid, 0x7, 2).is_none());
+
+        // The reordered entries are still found.
+        cpuid.as_mut_slice().reverse();
+        cache.get_mut(&mut cpuid, 0x7, 1).unwrap().ecx = 1;
+        assert_eq!(cpuid.as_slice()[0].ecx, 1);
+
+        // So are the entries added after the cache was built, and the removed ones are not.
+        let mut cpuid = CpuId::from_entries(&[entry(0x0, 0), entry(0x1, 0)]).unwrap();
+        assert!(cache.get_mut(&mut cpuid, 0x1, 0).is_some());
+        assert!(cache.get_mut(&mut cpuid, 0x7, 1).is_none());
+
+        assert_eq!(
+            CpuidCache::build(&CpuId::new(0).unwrap()),
+            CpuidCache::default()
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        assert!(CpuId::new(0).unwrap().keys().is_empty());
+
+        let cpuid = CpuId::from_entries(&[entry(0x1, 0), entry(0x7, 0), entry(0x7, 1)]).unwrap();
+        assert_eq!(cpuid.keys(), vec![(0x1, 0), (0x7, 0), (0x7, 1)]);
+    }
+
+    #[test]
+    fn test_sort_entries() {
+        let mut cpuid = CpuId::from_entries(&[
+            entry(0x8000_0001, 0),
+            entry(0x7, 1),
+            entry(0x4000_0001, 0),
+            entry(0x1, 0),
+            entry(0x8000_0000, 0),
+            entry(0x7, 0),
+            entry(0x4000_0000, 0),
+            entry(0x0, 0),
+        ])
+        .unwrap();
+
+        cpuid.sort_entries();
+
+        assert_eq!(
+            cpuid.keys(),
+            vec![
+                (0x0, 0),
+                (0x1, 0),
+                (0x7, 0),
+                (0x7, 1),
+                (0x4000_0000, 0),
+                (0x4000_0001, 0),
+                (0x8000_0000, 0),
+                (0x8000_0001, 0),
+            ]
+        );
+    }
+}
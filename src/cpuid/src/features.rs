@@ -0,0 +1,941 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use bitflags::bitflags;
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use serde_json::Value;
+
+use crate::cpu_leaf::{leaf_0x1, leaf_0x7};
+use crate::cpuid_ext::CpuIdExt;
+
+bitflags! {
+    /// Structured extended features enumerated in CPUID.(EAX=07H, ECX=0):EBX.
+    #[derive(Default)]
+    pub struct Leaf7Sub0Ebx: u32 {
+        /// RDFSBASE/RDGSBASE/WRFSBASE/WRGSBASE instructions.
+        const FSGSBASE             = 1 << 0;
+        /// IA32_TSC_ADJUST MSR.
+        const TSC_ADJUST           = 1 << 1;
+        /// Software Guard Extensions.
+        const SGX                  = 1 << 2;
+        /// Bit Manipulation Instruction Set 1.
+        const BMI1                 = 1 << 3;
+        /// Hardware Lock Elision (TSX).
+        const HLE                  = 1 << 4;
+        /// Advanced Vector Extensions 2.
+        const AVX2                 = 1 << 5;
+        /// The x87 FPU data pointer is updated only on x87 exceptions.
+        const FDP_EXCPTN_ONLY      = 1 << 6;
+        /// Supervisor-Mode Execution Prevention.
+        const SMEP                 = 1 << 7;
+        /// Bit Manipulation Instruction Set 2.
+        const BMI2                 = 1 << 8;
+        /// Enhanced REP MOVSB/STOSB.
+        const ERMS                 = 1 << 9;
+        /// INVPCID instruction.
+        const INVPCID              = 1 << 10;
+        /// Restricted Transactional Memory (TSX).
+        const RTM                  = 1 << 11;
+        /// Resource Director Technology Monitoring.
+        const RDT_M                = 1 << 12;
+        /// The FPU CS and FPU DS values are deprecated.
+        const FPU_CS_DS_DEPRECATED = 1 << 13;
+        /// Memory Protection Extensions.
+        const MPX                  = 1 << 14;
+        /// Resource Director Technology Allocation.
+        const RDT_A                = 1 << 15;
+        /// AVX-512 Foundation.
+        const AVX512F              = 1 << 16;
+        /// AVX-512 Doubleword and Quadword Instructions.
+        const AVX512DQ             = 1 << 17;
+        /// RDSEED instruction.
+        const RDSEED               = 1 << 18;
+        /// Multi-Precision Add-Carry Instruction Extensions.
+        const ADX                  = 1 << 19;
+        /// Supervisor-Mode Access Prevention.
+        const SMAP                 = 1 << 20;
+        /// AVX-512 Integer Fused Multiply-Add Instructions.
+        const AVX512_IFMA          = 1 << 21;
+        /// PCOMMIT instruction.
+        const PCOMMIT              = 1 << 22;
+        /// CLFLUSHOPT instruction.
+        const CLFLUSHOPT           = 1 << 23;
+        /// CLWB instruction.
+        const CLWB                 = 1 << 24;
+        /// Intel Processor Trace.
+        const PT                   = 1 << 25;
+        /// AVX-512 Prefetch Instructions.
+        const AVX512PF             = 1 << 26;
+        /// AVX-512 Exponential and Reciprocal Instructions.
+        const AVX512ER             = 1 << 27;
+        /// AVX-512 Conflict Detection Instructions.
+        const AVX512CD             = 1 << 28;
+        /// Secure Hash Algorithm Extensions.
+        const SHA                  = 1 << 29;
+        /// AVX-512 Byte and Word Instructions.
+        const AVX512BW             = 1 << 30;
+        /// AVX-512 Vector Length Extensions.
+        const AVX512VL             = 1 << 31;
+    }
+}
+
+bitflags! {
+    /// Structured extended features enumerated in CPUID.(EAX=07H, ECX=0):ECX.
+    ///
+    /// Bits 21:17 hold the MAWAU value rather than feature flags, so they are not represented.
+    #[derive(Default)]
+    pub struct Leaf7Sub0Ecx: u32 {
+        /// PREFETCHWT1 instruction.
+        const PREFETCHWT1      = 1 << 0;
+        /// AVX-512 Vector Byte Manipulation Instructions.
+        const AVX512_VBMI      = 1 << 1;
+        /// User-Mode Instruction Prevention.
+        const UMIP             = 1 << 2;
+        /// Protection Keys for user-mode pages.
+        const PKU              = 1 << 3;
+        /// The OS has set CR4.PKE to enable protection keys.
+        const OSPKE            = 1 << 4;
+        /// TPAUSE/UMONITOR/UMWAIT instructions.
+        const WAITPKG          = 1 << 5;
+        /// AVX-512 Vector Byte Manipulation Instructions 2.
+        const AVX512_VBMI2     = 1 << 6;
+        /// CET shadow stack.
+        const CET_SS           = 1 << 7;
+        /// Galois Field instructions.
+        const GFNI             = 1 << 8;
+        /// Vector AES instructions.
+        const VAES             = 1 << 9;
+        /// Carry-Less Multiplication Quadword vector instructions.
+        const VPCLMULQDQ       = 1 << 10;
+        /// AVX-512 Vector Neural Network Instructions.
+        const AVX512_VNNI      = 1 << 11;
+        /// AVX-512 BITALG instructions.
+        const AVX512_BITALG    = 1 << 12;
+        /// Total Memory Encryption.
+        const TME_EN           = 1 << 13;
+        /// AVX-512 Vector Population Count Doubleword and Quadword.
+        const AVX512_VPOPCNTDQ = 1 << 14;
+        /// 5-level page tables.
+        const LA57             = 1 << 16;
+        /// RDPID instruction.
+        const RDPID            = 1 << 22;
+        /// Key Locker.
+        const KL               = 1 << 23;
+        /// OS bus-lock detection.
+        const BUS_LOCK_DETECT  = 1 << 24;
+        /// CLDEMOTE instruction.
+        const CLDEMOTE         = 1 << 25;
+        /// MOVDIRI instruction.
+        const MOVDIRI          = 1 << 27;
+        /// MOVDIR64B instruction.
+        const MOVDIR64B        = 1 << 28;
+        /// Enqueue Stores.
+        const ENQCMD           = 1 << 29;
+        /// SGX Launch Configuration.
+        const SGX_LC           = 1 << 30;
+        /// Protection Keys for supervisor-mode pages.
+        const PKS              = 1 << 31;
+    }
+}
+
+bitflags! {
+    /// Structured extended features enumerated in CPUID.(EAX=07H, ECX=0):EDX.
+    #[derive(Default)]
+    pub struct Leaf7Sub0Edx: u32 {
+        /// SGX attestation services.
+        const SGX_KEYS            = 1 << 1;
+        /// AVX-512 4-register Neural Network Instructions.
+        const AVX512_4VNNIW       = 1 << 2;
+        /// AVX-512 4-register Multiply Accumulation Single precision.
+        const AVX512_4FMAPS       = 1 << 3;
+        /// Fast Short REP MOV.
+        const FSRM                = 1 << 4;
+        /// User Interrupts.
+        const UINTR               = 1 << 5;
+        /// AVX-512 VP2INTERSECT instructions.
+        const AVX512_VP2INTERSECT = 1 << 8;
+        /// Special Register Buffer Data Sampling mitigation MSR.
+        const SRBDS_CTRL          = 1 << 9;
+        /// VERW clears the CPU buffers.
+        const MD_CLEAR            = 1 << 10;
+        /// RTM transactions always abort.
+        const RTM_ALWAYS_ABORT    = 1 << 11;
+        /// TSX_FORCE_ABORT MSR.
+        const TSX_FORCE_ABORT     = 1 << 13;
+        /// SERIALIZE instruction.
+        const SERIALIZE           = 1 << 14;
+        /// Hybrid part (e.g. P-cores and E-cores).
+        const HYBRID              = 1 << 15;
+        /// TSX suspend load address tracking.
+        const TSXLDTRK            = 1 << 16;
+        /// Platform Configuration (memory encryption keys).
+        const PCONFIG             = 1 << 18;
+        /// Architectural Last Branch Records.
+        const ARCH_LBR            = 1 << 19;
+        /// CET indirect branch tracking.
+        const CET_IBT             = 1 << 20;
+        /// AMX tile computations on bfloat16 numbers.
+        const AMX_BF16            = 1 << 22;
+        /// AVX-512 half precision floating point instructions.
+        const AVX512_FP16         = 1 << 23;
+        /// AMX tile architecture.
+        const AMX_TILE            = 1 << 24;
+        /// AMX tile computations on 8-bit integers.
+        const AMX_INT8            = 1 << 25;
+        /// IBRS and IBPB speculation control.
+        const IBRS_IBPB           = 1 << 26;
+        /// Single Thread Indirect Branch Predictors.
+        const STIBP               = 1 << 27;
+        /// IA32_FLUSH_CMD MSR.
+        const L1D_FLUSH           = 1 << 28;
+        /// IA32_ARCH_CAPABILITIES MSR.
+        const ARCH_CAPABILITIES   = 1 << 29;
+        /// IA32_CORE_CAPABILITIES MSR.
+        const CORE_CAPABILITIES   = 1 << 30;
+        /// Speculative Store Bypass Disable.
+        const SSBD                = 1 << 31;
+    }
+}
+
+/// The structured extended features of leaf 0x7, subleaf 0.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leaf7Features {
+    /// The features enumerated in EBX.
+    pub ebx: Leaf7Sub0Ebx,
+    /// The features enumerated in ECX.
+    pub ecx: Leaf7Sub0Ecx,
+    /// The features enumerated in EDX.
+    pub edx: Leaf7Sub0Edx,
+}
+
+impl From<&kvm_cpuid_entry2> for Leaf7Features {
+    /// Decodes the features of a leaf 0x7, subleaf 0 entry. Unknown bits are dropped.
+    fn from(entry: &kvm_cpuid_entry2) -> Self {
+        Leaf7Features {
+            ebx: Leaf7Sub0Ebx::from_bits_truncate(entry.ebx),
+            ecx: Leaf7Sub0Ecx::from_bits_truncate(entry.ecx),
+            edx: Leaf7Sub0Edx::from_bits_truncate(entry.edx),
+        }
+    }
+}
+
+/// A register of a cpuid entry holding feature bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FeatureRegister {
+    /// EBX.
+    Ebx,
+    /// ECX.
+    Ecx,
+    /// EDX.
+    Edx,
+}
+
+impl FeatureRegister {
+    /// Returns the value of the register in `entry`.
+    pub fn read(self, entry: &kvm_cpuid_entry2) -> u32 {
+        match self {
+            FeatureRegister::Ebx => entry.ebx,
+            FeatureRegister::Ecx => entry.ecx,
+            FeatureRegister::Edx => entry.edx,
+        }
+    }
+}
+
+/// A named feature bit of the cpuid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeatureBit {
+    /// The lowercase name of the feature, e.g. "avx512f".
+    pub name: &'static str,
+    /// The leaf holding the feature.
+    pub function: u32,
+    /// The subleaf holding the feature.
+    pub index: u32,
+    /// The register holding the feature.
+    pub register: FeatureRegister,
+    /// The index of the feature bit in the register.
+    pub bit: u32,
+}
+
+const fn leaf_0x1_bit(name: &'static str, register: FeatureRegister, bit: u32) -> FeatureBit {
+    FeatureBit {
+        name,
+        function: leaf_0x1::LEAF_NUM,
+        index: 0,
+        register,
+        bit,
+    }
+}
+
+// The leaf 0x7 bits are taken from the bitflags types, named after their constants.
+const fn leaf_0x7_bit(name: &'static str, register: FeatureRegister, mask: u32) -> FeatureBit {
+    FeatureBit {
+        name,
+        function: leaf_0x7::LEAF_NUM,
+        index: 0,
+        register,
+        bit: mask.trailing_zeros(),
+    }
+}
+
+/// The named feature bits of leaves 0x1 and 0x7 (subleaf 0).
+pub const FEATURE_BITS: &[FeatureBit] = &[
+    // CPUID.01H:ECX
+    leaf_0x1_bit("sse3", FeatureRegister::Ecx, 0),
+    leaf_0x1_bit("pclmulqdq", FeatureRegister::Ecx, 1),
+    leaf_0x1_bit("dtes64", FeatureRegister::Ecx, 2),
+    leaf_0x1_bit("monitor", FeatureRegister::Ecx, 3),
+    leaf_0x1_bit("ds_cpl", FeatureRegister::Ecx, 4),
+    leaf_0x1_bit("vmx", FeatureRegister::Ecx, 5),
+    leaf_0x1_bit("smx", FeatureRegister::Ecx, 6),
+    leaf_0x1_bit("est", FeatureRegister::Ecx, 7),
+    leaf_0x1_bit("tm2", FeatureRegister::Ecx, 8),
+    leaf_0x1_bit("ssse3", FeatureRegister::Ecx, 9),
+    leaf_0x1_bit("cid", FeatureRegister::Ecx, 10),
+    leaf_0x1_bit("sdbg", FeatureRegister::Ecx, 11),
+    leaf_0x1_bit("fma", FeatureRegister::Ecx, 12),
+    leaf_0x1_bit("cx16", FeatureRegister::Ecx, 13),
+    leaf_0x1_bit("xtpr", FeatureRegister::Ecx, 14),
+    leaf_0x1_bit("pdcm", FeatureRegister::Ecx, 15),
+    leaf_0x1_bit("pcid", FeatureRegister::Ecx, 17),
+    leaf_0x1_bit("dca", FeatureRegister::Ecx, 18),
+    leaf_0x1_bit("sse4_1", FeatureRegister::Ecx, 19),
+    leaf_0x1_bit("sse4_2", FeatureRegister::Ecx, 20),
+    leaf_0x1_bit("x2apic", FeatureRegister::Ecx, 21),
+    leaf_0x1_bit("movbe", FeatureRegister::Ecx, 22),
+    leaf_0x1_bit("popcnt", FeatureRegister::Ecx, 23),
+    leaf_0x1_bit("tsc_deadline_timer", FeatureRegister::Ecx, 24),
+    leaf_0x1_bit("aes", FeatureRegister::Ecx, 25),
+    leaf_0x1_bit("xsave", FeatureRegister::Ecx, 26),
+    leaf_0x1_bit("osxsave", FeatureRegister::Ecx, 27),
+    leaf_0x1_bit("avx", FeatureRegister::Ecx, 28),
+    leaf_0x1_bit("f16c", FeatureRegister::Ecx, 29),
+    leaf_0x1_bit("rdrand", FeatureRegister::Ecx, 30),
+    leaf_0x1_bit("hypervisor", FeatureRegister::Ecx, 31),
+    // CPUID.01H:EDX
+    leaf_0x1_bit("fpu", FeatureRegister::Edx, 0),
+    leaf_0x1_bit("vme", FeatureRegister::Edx, 1),
+    leaf_0x1_bit("de", FeatureRegister::Edx, 2),
+    leaf_0x1_bit("pse", FeatureRegister::Edx, 3),
+    leaf_0x1_bit("tsc", FeatureRegister::Edx, 4),
+    leaf_0x1_bit("msr", FeatureRegister::Edx, 5),
+    leaf_0x1_bit("pae", FeatureRegister::Edx, 6),
+    leaf_0x1_bit("mce", FeatureRegister::Edx, 7),
+    leaf_0x1_bit("cx8", FeatureRegister::Edx, 8),
+    leaf_0x1_bit("apic", FeatureRegister::Edx, 9),
+    leaf_0x1_bit("sep", FeatureRegister::Edx, 11),
+    leaf_0x1_bit("mtrr", FeatureRegister::Edx, 12),
+    leaf_0x1_bit("pge", FeatureRegister::Edx, 13),
+    leaf_0x1_bit("mca", FeatureRegister::Edx, 14),
+    leaf_0x1_bit("cmov", FeatureRegister::Edx, 15),
+    leaf_0x1_bit("pat", FeatureRegister::Edx, 16),
+    leaf_0x1_bit("pse36", FeatureRegister::Edx, 17),
+    leaf_0x1_bit("pn", FeatureRegister::Edx, 18),
+    leaf_0x1_bit("clflush", FeatureRegister::Edx, 19),
+    leaf_0x1_bit("ds", FeatureRegister::Edx, 21),
+    leaf_0x1_bit("acpi", FeatureRegister::Edx, 22),
+    leaf_0x1_bit("mmx", FeatureRegister::Edx, 23),
+    leaf_0x1_bit("fxsr", FeatureRegister::Edx, 24),
+    leaf_0x1_bit("sse", FeatureRegister::Edx, 25),
+    leaf_0x1_bit("sse2", FeatureRegister::Edx, 26),
+    leaf_0x1_bit("ss", FeatureRegister::Edx, 27),
+    leaf_0x1_bit("ht", FeatureRegister::Edx, 28),
+    leaf_0x1_bit("tm", FeatureRegister::Edx, 29),
+    leaf_0x1_bit("ia64", FeatureRegister::Edx, 30),
+    leaf_0x1_bit("pbe", FeatureRegister::Edx, 31),
+    // CPUID.(EAX=07H, ECX=0):EBX
+    leaf_0x7_bit(
+        "fsgsbase",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::FSGSBASE.bits(),
+    ),
+    leaf_0x7_bit(
+        "tsc_adjust",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::TSC_ADJUST.bits(),
+    ),
+    leaf_0x7_bit("sgx", FeatureRegister::Ebx, Leaf7Sub0Ebx::SGX.bits()),
+    leaf_0x7_bit("bmi1", FeatureRegister::Ebx, Leaf7Sub0Ebx::BMI1.bits()),
+    leaf_0x7_bit("hle", FeatureRegister::Ebx, Leaf7Sub0Ebx::HLE.bits()),
+    leaf_0x7_bit("avx2", FeatureRegister::Ebx, Leaf7Sub0Ebx::AVX2.bits()),
+    leaf_0x7_bit(
+        "fdp_excptn_only",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::FDP_EXCPTN_ONLY.bits(),
+    ),
+    leaf_0x7_bit("smep", FeatureRegister::Ebx, Leaf7Sub0Ebx::SMEP.bits()),
+    leaf_0x7_bit("bmi2", FeatureRegister::Ebx, Leaf7Sub0Ebx::BMI2.bits()),
+    leaf_0x7_bit("erms", FeatureRegister::Ebx, Leaf7Sub0Ebx::ERMS.bits()),
+    leaf_0x7_bit(
+        "invpcid",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::INVPCID.bits(),
+    ),
+    leaf_0x7_bit("rtm", FeatureRegister::Ebx, Leaf7Sub0Ebx::RTM.bits()),
+    leaf_0x7_bit("rdt_m", FeatureRegister::Ebx, Leaf7Sub0Ebx::RDT_M.bits()),
+    leaf_0x7_bit(
+        "fpu_cs_ds_deprecated",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::FPU_CS_DS_DEPRECATED.bits(),
+    ),
+    leaf_0x7_bit("mpx", FeatureRegister::Ebx, Leaf7Sub0Ebx::MPX.bits()),
+    leaf_0x7_bit("rdt_a", FeatureRegister::Ebx, Leaf7Sub0Ebx::RDT_A.bits()),
+    leaf_0x7_bit(
+        "avx512f",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512F.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512dq",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512DQ.bits(),
+    ),
+    leaf_0x7_bit("rdseed", FeatureRegister::Ebx, Leaf7Sub0Ebx::RDSEED.bits()),
+    leaf_0x7_bit("adx", FeatureRegister::Ebx, Leaf7Sub0Ebx::ADX.bits()),
+    leaf_0x7_bit("smap", FeatureRegister::Ebx, Leaf7Sub0Ebx::SMAP.bits()),
+    leaf_0x7_bit(
+        "avx512_ifma",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512_IFMA.bits(),
+    ),
+    leaf_0x7_bit(
+        "pcommit",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::PCOMMIT.bits(),
+    ),
+    leaf_0x7_bit(
+        "clflushopt",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::CLFLUSHOPT.bits(),
+    ),
+    leaf_0x7_bit("clwb", FeatureRegister::Ebx, Leaf7Sub0Ebx::CLWB.bits()),
+    leaf_0x7_bit("pt", FeatureRegister::Ebx, Leaf7Sub0Ebx::PT.bits()),
+    leaf_0x7_bit(
+        "avx512pf",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512PF.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512er",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512ER.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512cd",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512CD.bits(),
+    ),
+    leaf_0x7_bit("sha", FeatureRegister::Ebx, Leaf7Sub0Ebx::SHA.bits()),
+    leaf_0x7_bit(
+        "avx512bw",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512BW.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512vl",
+        FeatureRegister::Ebx,
+        Leaf7Sub0Ebx::AVX512VL.bits(),
+    ),
+    // CPUID.(EAX=07H, ECX=0):ECX
+    leaf_0x7_bit(
+        "prefetchwt1",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::PREFETCHWT1.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_vbmi",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::AVX512_VBMI.bits(),
+    ),
+    leaf_0x7_bit("umip", FeatureRegister::Ecx, Leaf7Sub0Ecx::UMIP.bits()),
+    leaf_0x7_bit("pku", FeatureRegister::Ecx, Leaf7Sub0Ecx::PKU.bits()),
+    leaf_0x7_bit("ospke", FeatureRegister::Ecx, Leaf7Sub0Ecx::OSPKE.bits()),
+    leaf_0x7_bit(
+        "waitpkg",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::WAITPKG.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_vbmi2",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::AVX512_VBMI2.bits(),
+    ),
+    leaf_0x7_bit("cet_ss", FeatureRegister::Ecx, Leaf7Sub0Ecx::CET_SS.bits()),
+    leaf_0x7_bit("gfni", FeatureRegister::Ecx, Leaf7Sub0Ecx::GFNI.bits()),
+    leaf_0x7_bit("vaes", FeatureRegister::Ecx, Leaf7Sub0Ecx::VAES.bits()),
+    leaf_0x7_bit(
+        "vpclmulqdq",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::VPCLMULQDQ.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_vnni",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::AVX512_VNNI.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_bitalg",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::AVX512_BITALG.bits(),
+    ),
+    leaf_0x7_bit("tme_en", FeatureRegister::Ecx, Leaf7Sub0Ecx::TME_EN.bits()),
+    leaf_0x7_bit(
+        "avx512_vpopcntdq",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::AVX512_VPOPCNTDQ.bits(),
+    ),
+    leaf_0x7_bit("la57", FeatureRegister::Ecx, Leaf7Sub0Ecx::LA57.bits()),
+    leaf_0x7_bit("rdpid", FeatureRegister::Ecx, Leaf7Sub0Ecx::RDPID.bits()),
+    leaf_0x7_bit("kl", FeatureRegister::Ecx, Leaf7Sub0Ecx::KL.bits()),
+    leaf_0x7_bit(
+        "bus_lock_detect",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::BUS_LOCK_DETECT.bits(),
+    ),
+    leaf_0x7_bit(
+        "cldemote",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::CLDEMOTE.bits(),
+    ),
+    leaf_0x7_bit(
+        "movdiri",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::MOVDIRI.bits(),
+    ),
+    leaf_0x7_bit(
+        "movdir64b",
+        FeatureRegister::Ecx,
+        Leaf7Sub0Ecx::MOVDIR64B.bits(),
+    ),
+    leaf_0x7_bit("enqcmd", FeatureRegister::Ecx, Leaf7Sub0Ecx::ENQCMD.bits()),
+    leaf_0x7_bit("sgx_lc", FeatureRegister::Ecx, Leaf7Sub0Ecx::SGX_LC.bits()),
+    leaf_0x7_bit("pks", FeatureRegister::Ecx, Leaf7Sub0Ecx::PKS.bits()),
+    // CPUID.(EAX=07H, ECX=0):EDX
+    leaf_0x7_bit(
+        "sgx_keys",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::SGX_KEYS.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_4vnniw",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AVX512_4VNNIW.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_4fmaps",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AVX512_4FMAPS.bits(),
+    ),
+    leaf_0x7_bit("fsrm", FeatureRegister::Edx, Leaf7Sub0Edx::FSRM.bits()),
+    leaf_0x7_bit("uintr", FeatureRegister::Edx, Leaf7Sub0Edx::UINTR.bits()),
+    leaf_0x7_bit(
+        "avx512_vp2intersect",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AVX512_VP2INTERSECT.bits(),
+    ),
+    leaf_0x7_bit(
+        "srbds_ctrl",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::SRBDS_CTRL.bits(),
+    ),
+    leaf_0x7_bit(
+        "md_clear",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::MD_CLEAR.bits(),
+    ),
+    leaf_0x7_bit(
+        "rtm_always_abort",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::RTM_ALWAYS_ABORT.bits(),
+    ),
+    leaf_0x7_bit(
+        "tsx_force_abort",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::TSX_FORCE_ABORT.bits(),
+    ),
+    leaf_0x7_bit(
+        "serialize",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::SERIALIZE.bits(),
+    ),
+    leaf_0x7_bit("hybrid", FeatureRegister::Edx, Leaf7Sub0Edx::HYBRID.bits()),
+    leaf_0x7_bit(
+        "tsxldtrk",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::TSXLDTRK.bits(),
+    ),
+    leaf_0x7_bit(
+        "pconfig",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::PCONFIG.bits(),
+    ),
+    leaf_0x7_bit(
+        "arch_lbr",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::ARCH_LBR.bits(),
+    ),
+    leaf_0x7_bit(
+        "cet_ibt",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::CET_IBT.bits(),
+    ),
+    leaf_0x7_bit(
+        "amx_bf16",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AMX_BF16.bits(),
+    ),
+    leaf_0x7_bit(
+        "avx512_fp16",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AVX512_FP16.bits(),
+    ),
+    leaf_0x7_bit(
+        "amx_tile",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AMX_TILE.bits(),
+    ),
+    leaf_0x7_bit(
+        "amx_int8",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::AMX_INT8.bits(),
+    ),
+    leaf_0x7_bit(
+        "ibrs_ibpb",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::IBRS_IBPB.bits(),
+    ),
+    leaf_0x7_bit("stibp", FeatureRegister::Edx, Leaf7Sub0Edx::STIBP.bits()),
+    leaf_0x7_bit(
+        "l1d_flush",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::L1D_FLUSH.bits(),
+    ),
+    leaf_0x7_bit(
+        "arch_capabilities",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::ARCH_CAPABILITIES.bits(),
+    ),
+    leaf_0x7_bit(
+        "core_capabilities",
+        FeatureRegister::Edx,
+        Leaf7Sub0Edx::CORE_CAPABILITIES.bits(),
+    ),
+    leaf_0x7_bit("ssbd", FeatureRegister::Edx, Leaf7Sub0Edx::SSBD.bits()),
+];
+
+impl FeatureBit {
+    /// Looks up a feature bit by name, ignoring the case, e.g. "rdrand" or "AVX512F".
+    pub fn from_name(name: &str) -> Option<FeatureBit> {
+        let name = name.trim();
+        FEATURE_BITS
+            .iter()
+            .find(|feature| feature.name.eq_ignore_ascii_case(name))
+            .copied()
+    }
+
+    /// Returns whether the feature is set in `cpuid`. A missing leaf means it isn't.
+    pub fn is_set(&self, cpuid: &CpuId) -> bool {
+        matches!(
+            cpuid.get_entry(self.function, self.index),
+            Some(entry) if self.register.read(entry) & (1 << self.bit) != 0
+        )
+    }
+
+    /// Decodes the named features set in `cpuid`.
+    pub fn decode(cpuid: &CpuId) -> Vec<FeatureBit> {
+        FEATURE_BITS
+            .iter()
+            .filter(|feature| feature.is_set(cpuid))
+            .copied()
+            .collect()
+    }
+}
+
+/// Returns all the named features as a JSON object mapping their name to whether they're set
+/// in `cpuid`, e.g. `{ "avx2": true, "rdrand": false, ... }`, for monitoring systems.
+pub fn features_to_json(cpuid: &CpuId) -> Value {
+    Value::Object(
+        FEATURE_BITS
+            .iter()
+            .map(|feature| (feature.name.to_string(), Value::Bool(feature.is_set(cpuid))))
+            .collect(),
+    )
+}
+
+/// Returns the named features set in `guest` which aren't set in `host`, i.e. the ones that
+/// intersecting `guest` with the host capabilities would clear.
+///
+/// Useful to tell which features of a template won't be available on a given host.
+pub fn clamp_report(guest: &CpuId, host: &CpuId) -> Vec<FeatureBit> {
+    FEATURE_BITS
+        .iter()
+        .filter(|feature| feature.is_set(guest) && !feature.is_set(host))
+        .copied()
+        .collect()
+}
+
+// The features that KVM_GET_SUPPORTED_CPUID doesn't report although they are available to
+// the guest: OSXSAVE mirrors CR4.OSXSAVE, the TSC deadline timer has its own capability
+// (KVM_CAP_TSC_DEADLINE_TIMER) and HTT only describes the topology set up by Firecracker.
+const UNREPORTED_SUPPORTED_FEATURES: &[&str] = &["osxsave", "tsc_deadline_timer", "ht"];
+
+/// Checks that `guest` doesn't set any named feature missing from `supported`, the cpuid
+/// returned by `KVM_GET_SUPPORTED_CPUID`, returning the offending features otherwise.
+///
+/// Unlike the raw host cpuid, KVM reports what it can actually virtualize, so this is the
+/// authoritative check that a processed cpuid only exposes features available to the guest.
+/// The features that KVM emulates without reporting them are ignored.
+pub fn assert_subset_of_supported(guest: &CpuId, supported: &CpuId) -> Result<(), Vec<FeatureBit>> {
+    let unsupported: Vec<FeatureBit> = clamp_report(guest, supported)
+        .into_iter()
+        .filter(|feature| !UNREPORTED_SUPPORTED_FEATURES.contains(&feature.name))
+        .collect();
+
+    match unsupported.is_empty() {
+        true => Ok(()),
+        false => Err(unsupported),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leaf7_features() {
+        // Leaf 0x7, subleaf 0 of an Ice Lake server, as reported by KVM.
+        let entry = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            flags: 1,
+            eax: 0,
+            ebx: 0xf1bf_07ab,
+            ecx: 0x0040_5f4e,
+            edx: 0xac00_0410,
+            padding: [0, 0, 0],
+        };
+
+        let features = Leaf7Features::from(&entry);
+
+        assert!(features.ebx.contains(
+            Leaf7Sub0Ebx::FSGSBASE
+                | Leaf7Sub0Ebx::AVX2
+                | Leaf7Sub0Ebx::SMEP
+                | Leaf7Sub0Ebx::AVX512F
+                | Leaf7Sub0Ebx::SMAP
+                | Leaf7Sub0Ebx::AVX512VL
+        ));
+        assert!(!features.ebx.intersects(
+            Leaf7Sub0Ebx::SGX | Leaf7Sub0Ebx::HLE | Leaf7Sub0Ebx::RTM | Leaf7Sub0Ebx::MPX
+        ));
+        assert_eq!(features.ebx.bits(), entry.ebx);
+
+        assert!(features
+            .ecx
+            .contains(Leaf7Sub0Ecx::AVX512_VBMI | Leaf7Sub0Ecx::PKU | Leaf7Sub0Ecx::RDPID));
+        assert!(!features.ecx.contains(Leaf7Sub0Ecx::LA57));
+        assert_eq!(features.ecx.bits(), entry.ecx);
+
+        assert!(features.edx.contains(
+            Leaf7Sub0Edx::FSRM
+                | Leaf7Sub0Edx::MD_CLEAR
+                | Leaf7Sub0Edx::IBRS_IBPB
+                | Leaf7Sub0Edx::ARCH_CAPABILITIES
+                | Leaf7Sub0Edx::SSBD
+        ));
+        assert_eq!(features.edx.bits(), entry.edx);
+
+        // The MAWAU value and the reserved bits are not features.
+        let entry = kvm_cpuid_entry2 {
+            ecx: 0x003e_0000 | (1 << 15),
+            edx: 1,
+            ..entry
+        };
+        let features = Leaf7Features::from(&entry);
+        assert!(features.ecx.is_empty());
+        assert!(features.edx.is_empty());
+    }
+
+    #[test]
+    fn test_feature_bits_table() {
+        for (i, feature) in FEATURE_BITS.iter().enumerate() {
+            assert_eq!(feature.name, feature.name.to_ascii_lowercase());
+            assert!(feature.bit < 32);
+            for other in &FEATURE_BITS[i + 1..] {
+                assert_ne!(feature.name, other.name);
+                assert!(
+                    (
+                        feature.function,
+                        feature.index,
+                        feature.register,
+                        feature.bit
+                    ) != (other.function, other.index, other.register, other.bit),
+                    "{} and {} are the same bit",
+                    feature.name,
+                    other.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        let rdrand = FeatureBit::from_name("rdrand").unwrap();
+        assert_eq!(
+            (rdrand.function, rdrand.register, rdrand.bit),
+            (0x1, FeatureRegister::Ecx, 30)
+        );
+        let avx512f = FeatureBit::from_name("AVX512F").unwrap();
+        assert_eq!(
+            (
+                avx512f.function,
+                avx512f.index,
+                avx512f.register,
+                avx512f.bit
+            ),
+            (0x7, 0, FeatureRegister::Ebx, 16)
+        );
+        let ssbd = FeatureBit::from_name(" ssbd ").unwrap();
+        assert_eq!(
+            (ssbd.function, ssbd.register, ssbd.bit),
+            (0x7, FeatureRegister::Edx, 31)
+        );
+
+        assert_eq!(FeatureBit::from_name("avx1024"), None);
+        assert_eq!(FeatureBit::from_name(""), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        let cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                ecx: 1 << 30,
+                edx: 1 << 25,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                flags: 1,
+                ebx: Leaf7Sub0Ebx::AVX512F.bits(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let names: Vec<_> = FeatureBit::decode(&cpuid)
+            .iter()
+            .map(|feature| feature.name)
+            .collect();
+        assert_eq!(names, ["rdrand", "sse", "avx512f"]);
+        assert!(FeatureBit::from_name("avx512f").unwrap().is_set(&cpuid));
+        assert!(!FeatureBit::from_name("avx2").unwrap().is_set(&cpuid));
+    }
+
+    #[test]
+    fn test_features_to_json() {
+        let cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                ecx: 1 << 30,
+                edx: 1 << 25,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                flags: 1,
+                ebx: Leaf7Sub0Ebx::AVX2.bits(),
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let json = features_to_json(&cpuid);
+        let features = json.as_object().unwrap();
+        assert_eq!(features.len(), FEATURE_BITS.len());
+        assert_eq!(features["rdrand"], Value::Bool(true));
+        assert_eq!(features["sse"], Value::Bool(true));
+        assert_eq!(features["avx2"], Value::Bool(true));
+        assert_eq!(features["avx512f"], Value::Bool(false));
+        assert_eq!(
+            features
+                .iter()
+                .filter(|(_, set)| **set == Value::Bool(true))
+                .count(),
+            3
+        );
+
+        // Without the feature leaves, nothing is set.
+        let json = features_to_json(&CpuId::new(0).unwrap());
+        assert!(json
+            .as_object()
+            .unwrap()
+            .values()
+            .all(|set| *set == Value::Bool(false)));
+    }
+
+    #[test]
+    fn test_clamp_report() {
+        let cpuid = |ebx: Leaf7Sub0Ebx| {
+            CpuId::from_entries(&[kvm_cpuid_entry2 {
+                function: 0x7,
+                flags: 1,
+                ebx: ebx.bits(),
+                ..Default::default()
+            }])
+            .unwrap()
+        };
+        let guest = cpuid(Leaf7Sub0Ebx::AVX2 | Leaf7Sub0Ebx::AVX512F | Leaf7Sub0Ebx::AVX512BW);
+        let host = cpuid(Leaf7Sub0Ebx::AVX2 | Leaf7Sub0Ebx::SMAP);
+
+        let names: Vec<_> = clamp_report(&guest, &host)
+            .iter()
+            .map(|feature| feature.name)
+            .collect();
+        assert_eq!(names, ["avx512f", "avx512bw"]);
+
+        // Nothing is cleared on a host which supports all the guest features.
+        assert!(clamp_report(&guest, &guest).is_empty());
+        assert!(clamp_report(&host, &guest).contains(&FeatureBit::from_name("smap").unwrap()));
+        // Without the feature leaf, the host doesn't support any of them.
+        assert_eq!(
+            clamp_report(&guest, &CpuId::new(0).unwrap()),
+            FeatureBit::decode(&guest)
+        );
+    }
+
+    #[test]
+    fn test_assert_subset_of_supported() {
+        use kvm_bindings::KVM_MAX_CPUID_ENTRIES;
+        use kvm_ioctls::Kvm;
+
+        let supported = Kvm::new()
+            .unwrap()
+            .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
+            .unwrap();
+
+        // A compliant table: the output of the transformers for the supported cpuid.
+        let mut guest = supported.clone();
+        crate::filter_cpuid(&mut guest, &crate::VmSpec::new(0, 2, true).unwrap()).unwrap();
+        assert_eq!(assert_subset_of_supported(&guest, &supported), Ok(()));
+
+        // A non-compliant table, setting features the supported cpuid doesn't report.
+        let cpuid = |ecx: u32, edx: u32| {
+            CpuId::from_entries(&[kvm_cpuid_entry2 {
+                function: 0x1,
+                ecx,
+                edx,
+                ..Default::default()
+            }])
+            .unwrap()
+        };
+        let fpu = FeatureBit::from_name("fpu").unwrap();
+        let vmx = FeatureBit::from_name("vmx").unwrap();
+        let osxsave = FeatureBit::from_name("osxsave").unwrap();
+        let supported = cpuid(0, 1 << fpu.bit);
+        let guest = cpuid((1 << vmx.bit) | (1 << osxsave.bit), 1 << fpu.bit);
+        assert_eq!(
+            assert_subset_of_supported(&guest, &supported),
+            Err(vec![vmx])
+        );
+    }
+}
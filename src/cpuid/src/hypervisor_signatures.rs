@@ -0,0 +1,106 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+/// KVM, the signature of the cpuid returned by KVM.
+pub const KVM: [u8; 12] = *b"KVMKVMKVM\0\0\0";
+/// Microsoft Hyper-V.
+pub const HYPER_V: [u8; 12] = *b"Microsoft Hv";
+/// VMware.
+pub const VMWARE: [u8; 12] = *b"VMwareVMware";
+/// Xen, in HVM mode.
+pub const XEN: [u8; 12] = *b"XenVMMXenVMM";
+/// Parallels.
+pub const PARALLELS: [u8; 12] = *b" lrpepyh  vr";
+/// bhyve.
+pub const BHYVE: [u8; 12] = *b"bhyve bhyve ";
+/// ACRN.
+pub const ACRN: [u8; 12] = *b"ACRNACRNACRN";
+/// QEMU, when emulating the CPU with TCG.
+pub const QEMU_TCG: [u8; 12] = *b"TCGTCGTCGTCG";
+
+/// The signatures, along with the name of their hypervisor.
+pub const SIGNATURES: [(&str, [u8; 12]); 8] = [
+    ("kvm", KVM),
+    ("hyper-v", HYPER_V),
+    ("vmware", VMWARE),
+    ("xen", XEN),
+    ("parallels", PARALLELS),
+    ("bhyve", BHYVE),
+    ("acrn", ACRN),
+    ("qemu-tcg", QEMU_TCG),
+];
+
+/// Returns the signature of the hypervisor named `name` in `SIGNATURES`, ignoring the case.
+pub fn by_name(name: &str) -> Option<[u8; 12]> {
+    SIGNATURES
+        .iter()
+        .find(|(known_name, _)| known_name.eq_ignore_ascii_case(name))
+        .map(|(_, signature)| *signature)
+}
+
+/// Returns the name of the hypervisor reporting `signature`, if it is a well-known one.
+pub fn name_of(signature: &[u8; 12]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, known_signature)| known_signature == signature)
+        .map(|(name, _)| *name)
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+    use super::*;
+    use crate::cpuid_ext::CpuIdExt;
+
+    #[test]
+    fn test_signatures_round_trip() {
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: 0x4000_0000,
+            eax: 0x4000_0001,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        for (name, signature) in SIGNATURES {
+            cpuid.set_hypervisor_signature(&signature);
+            assert_eq!(cpuid.hypervisor_signature(), Some(signature));
+            assert_eq!(name_of(&cpuid.hypervisor_signature().unwrap()), Some(name));
+            assert_eq!(by_name(name), Some(signature));
+            // The highest hypervisor leaf is left untouched.
+            assert_eq!(cpuid.get_entry(0x4000_0000, 0).unwrap().eax, 0x4000_0001);
+        }
+
+        // The names and the signatures are unique.
+        for (i, (name, signature)) in SIGNATURES.iter().enumerate() {
+            assert!(SIGNATURES[i + 1..]
+                .iter()
+                .all(|(other_name, other_signature)| other_name != name
+                    && other_signature != signature));
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(by_name("Hyper-V"), Some(HYPER_V));
+        assert_eq!(by_name("KVM"), Some(KVM));
+        assert_eq!(by_name("firecracker"), None);
+        assert_eq!(name_of(b"GenuineIntel"), None);
+
+        // KVM stores the signature as "KVMK", "VMKV" and "M\0\0\0".
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: 0x4000_0000,
+            ebx: 0x4b4d_564b,
+            ecx: 0x564b_4d56,
+            edx: 0x4d,
+            ..Default::default()
+        }])
+        .unwrap();
+        assert_eq!(cpuid.hypervisor_signature(), Some(KVM));
+
+        // The leaf is not added when missing.
+        cpuid.retain(|_| false);
+        cpuid.set_hypervisor_signature(&XEN);
+        assert_eq!(cpuid.hypervisor_signature(), None);
+    }
+}
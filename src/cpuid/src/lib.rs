@@ -21,18 +21,72 @@ use crate::common::*;
 /// Contains helper methods for bit operations.
 pub mod bit_helper;
 
+/// Extension methods for the KVM `CpuId` structure.
+pub mod cpuid_ext;
+
+/// Structured representations of the CPUID feature leaves.
+pub mod features;
+
+/// The signatures of well-known hypervisors, as reported in the hypervisor leaf 0x40000000.
+pub mod hypervisor_signatures;
+
+/// Seccomp filter for the thread running the cpuid transformers.
+pub mod seccomp;
+
 mod template;
-pub use crate::template::intel::{c3, t2, t2s};
+pub use crate::template::intel::t2s;
+pub use crate::template::microarch::{Microarch, ICE_LAKE, MICROARCHES, SKYLAKE};
 pub use crate::template::msrs_to_save_by_cpuid;
+pub use crate::template::processed::{
+    CpuidTemplate, CpuidTemplateEntry, RegisterMasks, TemplateMode,
+};
 
 mod cpu_leaf;
 
 mod transformer;
+pub use crate::transformer::cache::{cache_summary, CacheSummary};
+pub use crate::transformer::common::{
+    assert_only_topology_differs, lint_cpuid, validate_bootable, validate_xsave_consistency,
+};
+pub use crate::transformer::debug::{DebugTopologyTransformer, DEBUG_TOPOLOGY_LEAF};
+pub use crate::transformer::equivalence::{
+    transformers_equivalent, CpuidDiff, PassthroughTransformer,
+};
+pub use crate::transformer::logging::LoggingTransformer;
+pub use crate::transformer::record::{
+    CpuidChange, CpuidRegister, RecordedEntry, RecordingTransformer, RegisterWrite,
+    ReplayTransformer,
+};
+pub use crate::transformer::synthetic::{
+    CacheGeometry, SyntheticModelTransformer, UniformSteppingTransformer,
+};
+pub use crate::transformer::templates::apply_cpu_template;
 use crate::transformer::*;
-pub use crate::transformer::{Error, VmSpec};
+pub use crate::transformer::{
+    BootabilityError, BrandStringMode, CacheIdPolicy, CpuTemplate, CpuidTransformer,
+    CpuidViolation, Error, Lint, ParanoidTiming, TlbGeometry, VmSpec, VmSpecBuilder, XsaveError,
+};
 
 mod brand_string;
 
+/// The vendor ids handled by the vendor specific transformers.
+const SUPPORTED_VENDORS: [&str; 2] = ["GenuineIntel", "AuthenticAMD"];
+
+/// Returns the ids of the CPU vendors whose CPUID can be processed, e.g. to validate a vendor
+/// id up front instead of getting `Error::InvalidVendor` when processing the CPUID.
+pub fn supported_vendors() -> &'static [&'static str] {
+    &SUPPORTED_VENDORS
+}
+
+// Returns the transformer handling the CPUID of the given vendor.
+fn vendor_cpuid_transformer(vendor_id: &[u8; 12]) -> Option<&'static dyn CpuidTransformer> {
+    match vendor_id {
+        VENDOR_ID_INTEL => Some(&intel::IntelCpuidTransformer {}),
+        VENDOR_ID_AMD => Some(&amd::AmdCpuidTransformer {}),
+        _ => None,
+    }
+}
+
 /// Sets up the CPUID entries for the given vcpu.
 ///
 /// # Arguments
@@ -58,15 +112,43 @@ mod brand_string;
 /// ```
 #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
 pub fn filter_cpuid(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
-    let maybe_cpuid_transformer: Option<&dyn CpuidTransformer> = match vm_spec.cpu_vendor_id() {
-        VENDOR_ID_INTEL => Some(&intel::IntelCpuidTransformer {}),
-        VENDOR_ID_AMD => Some(&amd::AmdCpuidTransformer {}),
-        _ => None,
-    };
+    let maybe_cpuid_transformer = vendor_cpuid_transformer(vm_spec.cpu_vendor_id());
 
     if let Some(cpuid_transformer) = maybe_cpuid_transformer {
         cpuid_transformer.process_cpuid(kvm_cpuid, vm_spec)?;
     }
 
-    Ok(())
+    // The template masks what the vendor specific transformer exposed.
+    apply_cpu_template(kvm_cpuid, vm_spec)
+}
+
+/// Checks that the CPUID entries of the given vcpu hold the invariants set up by `filter_cpuid`.
+///
+/// # Arguments
+///
+/// * `kvm_cpuid` - KVM related structure holding the processed CPUID info.
+/// * `vm_spec` - The specifications of the VM.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+pub fn verify_cpuid(kvm_cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+    let maybe_cpuid_transformer = vendor_cpuid_transformer(vm_spec.cpu_vendor_id());
+
+    match maybe_cpuid_transformer {
+        Some(cpuid_transformer) => cpuid_transformer.verify(kvm_cpuid, vm_spec),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supported_vendors() {
+        for vendor in supported_vendors() {
+            let vendor_id: &[u8; 12] = vendor.as_bytes().try_into().unwrap();
+            assert!(vendor_cpuid_transformer(vendor_id).is_some(), "{}", vendor);
+        }
+        assert_eq!(supported_vendors(), ["GenuineIntel", "AuthenticAMD"]);
+        assert!(vendor_cpuid_transformer(b"HygonGenuine").is_none());
+    }
 }
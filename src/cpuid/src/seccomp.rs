@@ -0,0 +1,7 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The filter is built by seccompiler, along with the other seccomp policies, and only
+//! re-exported here next to the transformers it is meant for.
+
+pub use seccompiler::cpuid_transformer_seccomp_filter;
@@ -1,11 +1,10 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use kvm_bindings::kvm_cpuid_entry2;
 
 use crate::bit_helper::BitHelper;
 use crate::cpu_leaf::*;
-use crate::template::intel::validate_vendor_id;
 use crate::transformer::*;
 
 fn update_feature_info_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
@@ -101,6 +100,7 @@ fn update_structured_extended_entry(
             .write_bit(ecx::AVX512_VPOPCNTDQ_BITINDEX, false)
             .write_bit(ecx::LA57, false)
             .write_bit(ecx::RDPID_BITINDEX, false)
+            .write_bit(ecx::ENQCMD_BITINDEX, false)
             .write_bit(ecx::SGX_LC_BITINDEX, false);
 
         entry
@@ -164,7 +164,7 @@ fn update_extended_feature_info_entry(
 }
 
 /// Sets up the cpuid entries for a given VCPU following a C3 template.
-struct C3CpuidTransformer {}
+pub(crate) struct C3CpuidTransformer {}
 
 impl CpuidTransformer for C3CpuidTransformer {
     fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
@@ -177,9 +177,3 @@ impl CpuidTransformer for C3CpuidTransformer {
         }
     }
 }
-
-/// Sets up the cpuid entries for a given VCPU following a C3 template.
-pub fn set_cpuid_entries(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
-    validate_vendor_id()?;
-    C3CpuidTransformer {}.process_cpuid(kvm_cpuid, vm_spec)
-}
@@ -14,18 +14,7 @@ use std::collections::HashSet;
 use arch_gen::x86::msr_index::*;
 use kvm_bindings::CpuId;
 
-use crate::common::{get_vendor_id_from_host, VENDOR_ID_INTEL};
 use crate::cpuid_is_feature_set;
-use crate::transformer::Error;
-
-pub fn validate_vendor_id() -> Result<(), Error> {
-    let vendor_id = get_vendor_id_from_host()?;
-    if &vendor_id != VENDOR_ID_INTEL {
-        return Err(Error::InvalidVendor);
-    }
-
-    Ok(())
-}
 
 /// Returns MSRs to be saved based on the Intel CPUID features that are enabled.
 pub(crate) fn msrs_to_save_by_cpuid(cpuid: &CpuId) -> HashSet<u32> {
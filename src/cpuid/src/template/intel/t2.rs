@@ -1,11 +1,10 @@
 // Copyright 2018 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
-use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use kvm_bindings::kvm_cpuid_entry2;
 
 use crate::bit_helper::BitHelper;
 use crate::cpu_leaf::*;
-use crate::template::intel::validate_vendor_id;
 use crate::transformer::*;
 
 pub(crate) fn update_feature_info_entry(
@@ -105,6 +104,7 @@ pub(crate) fn update_structured_extended_entry(
             .write_bit(ecx::AVX512_VPOPCNTDQ_BITINDEX, false)
             .write_bit(ecx::LA57, false)
             .write_bit(ecx::RDPID_BITINDEX, false)
+            .write_bit(ecx::ENQCMD_BITINDEX, false)
             .write_bit(ecx::SGX_LC_BITINDEX, false);
 
         entry
@@ -165,7 +165,7 @@ pub(crate) fn update_extended_feature_info_entry(
 }
 
 /// Sets up the cpuid entries for a given VCPU following a T2 template.
-struct T2CpuidTransformer {}
+pub(crate) struct T2CpuidTransformer {}
 
 impl CpuidTransformer for T2CpuidTransformer {
     fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
@@ -178,9 +178,3 @@ impl CpuidTransformer for T2CpuidTransformer {
         }
     }
 }
-
-/// Sets up the cpuid entries for a given VCPU following a T2 template.
-pub fn set_cpuid_entries(kvm_cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
-    validate_vendor_id()?;
-    T2CpuidTransformer {}.process_cpuid(kvm_cpuid, vm_spec)
-}
@@ -0,0 +1,284 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
+
+use crate::bit_helper::BitRangeExt;
+use crate::cpu_leaf::{leaf_0x1, leaf_0x7};
+use crate::features::{Leaf7Features, Leaf7Sub0Ebx, Leaf7Sub0Ecx, Leaf7Sub0Edx};
+use crate::template::processed::{CpuidTemplate, CpuidTemplateEntry, RegisterMasks, TemplateMode};
+use crate::transformer::synthetic::write_signature;
+
+/// A named Intel microarchitecture the guest cpuid can be made to look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Microarch {
+    /// The name of the microarchitecture, e.g. "Skylake".
+    pub name: &'static str,
+    /// The displayed family.
+    pub family: u16,
+    /// The displayed model.
+    pub model: u8,
+    /// The stepping.
+    pub stepping: u8,
+    /// The structured extended features (leaf 0x7, subleaf 0) the microarchitecture has.
+    ///
+    /// The host features that are not part of it are masked, the missing ones are not added.
+    pub features: Leaf7Features,
+}
+
+const SKYLAKE_EBX: Leaf7Sub0Ebx = Leaf7Sub0Ebx::from_bits_truncate(
+    Leaf7Sub0Ebx::FSGSBASE.bits()
+        | Leaf7Sub0Ebx::TSC_ADJUST.bits()
+        | Leaf7Sub0Ebx::BMI1.bits()
+        | Leaf7Sub0Ebx::HLE.bits()
+        | Leaf7Sub0Ebx::AVX2.bits()
+        | Leaf7Sub0Ebx::FDP_EXCPTN_ONLY.bits()
+        | Leaf7Sub0Ebx::SMEP.bits()
+        | Leaf7Sub0Ebx::BMI2.bits()
+        | Leaf7Sub0Ebx::ERMS.bits()
+        | Leaf7Sub0Ebx::INVPCID.bits()
+        | Leaf7Sub0Ebx::RTM.bits()
+        | Leaf7Sub0Ebx::RDT_M.bits()
+        | Leaf7Sub0Ebx::FPU_CS_DS_DEPRECATED.bits()
+        | Leaf7Sub0Ebx::MPX.bits()
+        | Leaf7Sub0Ebx::RDT_A.bits()
+        | Leaf7Sub0Ebx::AVX512F.bits()
+        | Leaf7Sub0Ebx::AVX512DQ.bits()
+        | Leaf7Sub0Ebx::RDSEED.bits()
+        | Leaf7Sub0Ebx::ADX.bits()
+        | Leaf7Sub0Ebx::SMAP.bits()
+        | Leaf7Sub0Ebx::CLFLUSHOPT.bits()
+        | Leaf7Sub0Ebx::CLWB.bits()
+        | Leaf7Sub0Ebx::PT.bits()
+        | Leaf7Sub0Ebx::AVX512CD.bits()
+        | Leaf7Sub0Ebx::AVX512BW.bits()
+        | Leaf7Sub0Ebx::AVX512VL.bits(),
+);
+
+// The speculation control features, which are enumerated by the microcode updates.
+const SPEC_CTRL_EDX: Leaf7Sub0Edx = Leaf7Sub0Edx::from_bits_truncate(
+    Leaf7Sub0Edx::MD_CLEAR.bits()
+        | Leaf7Sub0Edx::IBRS_IBPB.bits()
+        | Leaf7Sub0Edx::STIBP.bits()
+        | Leaf7Sub0Edx::L1D_FLUSH.bits()
+        | Leaf7Sub0Edx::ARCH_CAPABILITIES.bits()
+        | Leaf7Sub0Edx::CORE_CAPABILITIES.bits()
+        | Leaf7Sub0Edx::SSBD.bits(),
+);
+
+/// Skylake server (Xeon Scalable, 1st generation).
+pub const SKYLAKE: Microarch = Microarch {
+    name: "Skylake",
+    family: 0x6,
+    model: 0x55,
+    stepping: 0x4,
+    features: Leaf7Features {
+        ebx: SKYLAKE_EBX,
+        ecx: Leaf7Sub0Ecx::from_bits_truncate(
+            Leaf7Sub0Ecx::PKU.bits() | Leaf7Sub0Ecx::OSPKE.bits(),
+        ),
+        edx: SPEC_CTRL_EDX,
+    },
+};
+
+/// Ice Lake server (Xeon Scalable, 3rd generation).
+pub const ICE_LAKE: Microarch = Microarch {
+    name: "Ice Lake",
+    family: 0x6,
+    model: 0x6a,
+    stepping: 0x6,
+    features: Leaf7Features {
+        // MPX was dropped.
+        ebx: SKYLAKE_EBX
+            .difference(Leaf7Sub0Ebx::MPX)
+            .union(Leaf7Sub0Ebx::from_bits_truncate(
+                Leaf7Sub0Ebx::AVX512_IFMA.bits() | Leaf7Sub0Ebx::SHA.bits(),
+            )),
+        ecx: Leaf7Sub0Ecx::from_bits_truncate(
+            Leaf7Sub0Ecx::AVX512_VBMI.bits()
+                | Leaf7Sub0Ecx::UMIP.bits()
+                | Leaf7Sub0Ecx::PKU.bits()
+                | Leaf7Sub0Ecx::OSPKE.bits()
+                | Leaf7Sub0Ecx::AVX512_VBMI2.bits()
+                | Leaf7Sub0Ecx::GFNI.bits()
+                | Leaf7Sub0Ecx::VAES.bits()
+                | Leaf7Sub0Ecx::VPCLMULQDQ.bits()
+                | Leaf7Sub0Ecx::AVX512_VNNI.bits()
+                | Leaf7Sub0Ecx::AVX512_BITALG.bits()
+                | Leaf7Sub0Ecx::TME_EN.bits()
+                | Leaf7Sub0Ecx::AVX512_VPOPCNTDQ.bits()
+                | Leaf7Sub0Ecx::LA57.bits()
+                | Leaf7Sub0Ecx::RDPID.bits()
+                | Leaf7Sub0Ecx::SGX_LC.bits(),
+        ),
+        edx: SPEC_CTRL_EDX.union(Leaf7Sub0Edx::from_bits_truncate(
+            Leaf7Sub0Edx::FSRM.bits() | Leaf7Sub0Edx::PCONFIG.bits(),
+        )),
+    },
+};
+
+/// The registry of the microarchitectures that can be looked up by name.
+pub const MICROARCHES: &[Microarch] = &[SKYLAKE, ICE_LAKE];
+
+impl Microarch {
+    /// Looks up a microarchitecture of the registry by name.
+    ///
+    /// The lookup ignores the case and the spaces, so "icelake" matches "Ice Lake".
+    pub fn from_name(name: &str) -> Option<&'static Microarch> {
+        let normalize = |name: &str| -> String {
+            name.chars()
+                .filter(|c| *c != ' ')
+                .map(|c| c.to_ascii_lowercase())
+                .collect()
+        };
+        let name = normalize(name);
+
+        MICROARCHES
+            .iter()
+            .find(|microarch| normalize(microarch.name) == name)
+    }
+
+    /// Returns the cpuid template setting the signature of the microarchitecture and masking
+    /// the host features it doesn't have.
+    ///
+    /// The template is meant to be applied on top of an Intel cpuid processed by
+    /// `filter_cpuid`, like the static templates.
+    pub fn template(&self) -> CpuidTemplate {
+        let unchanged = RegisterMasks {
+            and: u32::MAX,
+            or: 0,
+        };
+
+        // Writing a null signature clears all the signature fields.
+        let mut signature_mask = u32::MAX;
+        write_signature(&mut signature_mask, 0, 0, 0);
+        let mut signature = 0;
+        write_signature(&mut signature, self.family, self.model, self.stepping);
+
+        CpuidTemplate {
+            entries: vec![
+                CpuidTemplateEntry {
+                    function: leaf_0x1::LEAF_NUM,
+                    index: 0,
+                    flags: 0,
+                    eax: RegisterMasks {
+                        and: signature_mask,
+                        or: signature,
+                    },
+                    ebx: unchanged,
+                    ecx: unchanged,
+                    edx: unchanged,
+                },
+                CpuidTemplateEntry {
+                    function: leaf_0x7::LEAF_NUM,
+                    index: 0,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    eax: unchanged,
+                    ebx: RegisterMasks {
+                        and: self.features.ebx.bits(),
+                        or: 0,
+                    },
+                    // MAWAU is a value, not a feature.
+                    ecx: RegisterMasks {
+                        and: self.features.ecx.bits()
+                            | leaf_0x7::index0::ecx::MAWAU_BITRANGE.get_mask(),
+                        or: 0,
+                    },
+                    edx: RegisterMasks {
+                        and: self.features.edx.bits(),
+                        or: 0,
+                    },
+                },
+            ],
+            removed: Vec::new(),
+            mode: TemplateMode::Additive,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+    use super::*;
+    use crate::bit_helper::BitHelper;
+    use crate::cpuid_ext::CpuIdExt;
+
+    // A host exposing every leaf 0x7 feature, with a MAWAU value.
+    fn host_cpuid() -> CpuId {
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                eax: 0x0008_06f8,
+                ebx: 0x0010_0800,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                index: 0,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                eax: 1,
+                ebx: u32::MAX,
+                ecx: u32::MAX,
+                edx: u32::MAX,
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    fn emulate(microarch: &Microarch) -> (u32, Leaf7Features) {
+        let mut cpuid = host_cpuid();
+        microarch.template().apply(&mut cpuid).unwrap();
+        let leaf_0x1 = cpuid.get_entry(leaf_0x1::LEAF_NUM, 0).unwrap();
+        let leaf_0x7 = cpuid.get_entry(leaf_0x7::LEAF_NUM, 0).unwrap();
+
+        // Only the signature and the features are changed.
+        assert_eq!(leaf_0x1.ebx, 0x0010_0800);
+        assert_eq!(leaf_0x7.eax, 1);
+        assert_eq!(
+            leaf_0x7
+                .ecx
+                .read_bits_in_range(&leaf_0x7::index0::ecx::MAWAU_BITRANGE),
+            0x1f
+        );
+
+        (leaf_0x1.eax, Leaf7Features::from(leaf_0x7))
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Microarch::from_name("Skylake"), Some(&SKYLAKE));
+        assert_eq!(Microarch::from_name("skylake"), Some(&SKYLAKE));
+        assert_eq!(Microarch::from_name("Ice Lake"), Some(&ICE_LAKE));
+        assert_eq!(Microarch::from_name("ICELAKE"), Some(&ICE_LAKE));
+        assert_eq!(Microarch::from_name("Sapphire Rapids"), None);
+    }
+
+    #[test]
+    fn test_skylake() {
+        let (signature, features) = emulate(&SKYLAKE);
+
+        // Family 0x6, model 0x55, stepping 0x4.
+        assert_eq!(signature, 0x0005_0654);
+        assert_eq!(features, SKYLAKE.features);
+        assert!(features
+            .ebx
+            .contains(Leaf7Sub0Ebx::MPX | Leaf7Sub0Ebx::AVX512F));
+        // The Ice Lake instructions are not available.
+        assert!(!features.ecx.contains(Leaf7Sub0Ecx::AVX512_VBMI));
+    }
+
+    #[test]
+    fn test_ice_lake() {
+        let (signature, features) = emulate(&ICE_LAKE);
+
+        // Family 0x6, model 0x6a, stepping 0x6.
+        assert_eq!(signature, 0x0006_06a6);
+        assert_eq!(features, ICE_LAKE.features);
+        assert!(features
+            .ecx
+            .contains(Leaf7Sub0Ecx::AVX512_VBMI | Leaf7Sub0Ecx::GFNI));
+        assert!(!features.ebx.contains(Leaf7Sub0Ebx::MPX));
+    }
+}
@@ -3,6 +3,10 @@
 
 // Contains Intel specific templates.
 pub mod intel;
+// Contains the registry of the named microarchitectures.
+pub mod microarch;
+// Contains templates built from already processed cpuids.
+pub mod processed;
 
 use std::collections::HashSet;
 
@@ -0,0 +1,309 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use serde::{Deserialize, Serialize};
+
+use crate::cpuid_ext::CpuIdExt;
+use crate::transformer::common::mask_cpuid_entry;
+use crate::transformer::Error;
+
+/// Masks turning the value of a register into the desired one: `(value & and) | or`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterMasks {
+    /// The bits to keep from the original value.
+    pub and: u32,
+    /// The bits to set on top of the kept ones.
+    pub or: u32,
+}
+
+impl RegisterMasks {
+    /// Computes the masks that turn `base` into `processed`, leaving the bits which didn't
+    /// change untouched.
+    fn from_values(base: u32, processed: u32) -> Self {
+        RegisterMasks {
+            and: !(base & !processed),
+            or: processed & !base,
+        }
+    }
+
+    /// Applies the masks to `value`.
+    pub fn apply(&self, value: u32) -> u32 {
+        (value & self.and) | self.or
+    }
+}
+
+/// The masks to apply to a single cpuid entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuidTemplateEntry {
+    /// The leaf function.
+    pub function: u32,
+    /// The leaf index.
+    pub index: u32,
+    /// The KVM flags of the resulting entry.
+    pub flags: u32,
+    /// The masks for the `eax` register.
+    pub eax: RegisterMasks,
+    /// The masks for the `ebx` register.
+    pub ebx: RegisterMasks,
+    /// The masks for the `ecx` register.
+    pub ecx: RegisterMasks,
+    /// The masks for the `edx` register.
+    pub edx: RegisterMasks,
+}
+
+impl CpuidTemplateEntry {
+    fn from_entries(base: &kvm_cpuid_entry2, processed: &kvm_cpuid_entry2) -> Self {
+        CpuidTemplateEntry {
+            function: processed.function,
+            index: processed.index,
+            flags: processed.flags,
+            eax: RegisterMasks::from_values(base.eax, processed.eax),
+            ebx: RegisterMasks::from_values(base.ebx, processed.ebx),
+            ecx: RegisterMasks::from_values(base.ecx, processed.ecx),
+            edx: RegisterMasks::from_values(base.edx, processed.edx),
+        }
+    }
+
+    fn apply(&self, entry: &mut kvm_cpuid_entry2) {
+        entry.flags = self.flags;
+        mask_cpuid_entry(
+            entry,
+            self.eax.and,
+            self.eax.or,
+            self.ebx.and,
+            self.ebx.or,
+            self.ecx.and,
+            self.ecx.or,
+            self.edx.and,
+            self.edx.or,
+        );
+    }
+}
+
+/// How a template treats the entries it doesn't list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TemplateMode {
+    /// The entries which aren't listed in the template are left untouched.
+    #[default]
+    Additive,
+    /// Only the entries listed in the template are kept, all the others are removed.
+    Exclusive,
+}
+
+/// A portable cpuid template, capturing the changes done to a cpuid by a Firecracker build.
+///
+/// The template can be serialized (e.g. to JSON) and applied later, possibly by another
+/// Firecracker version, in order to reproduce the same cpuid.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuidTemplate {
+    /// The entries which were modified or added.
+    pub entries: Vec<CpuidTemplateEntry>,
+    /// The `(function, index)` of the entries which were removed.
+    pub removed: Vec<(u32, u32)>,
+    /// How the entries which aren't listed in `entries` are treated. Templates computed with
+    /// `from_processed` only list the modified entries, so they are additive.
+    #[serde(default)]
+    pub mode: TemplateMode,
+}
+
+impl CpuidTemplate {
+    /// Computes the template that transforms `base` into `processed`.
+    ///
+    /// Entries that are identical in both cpuids are not part of the template.
+    pub fn from_processed(base: &CpuId, processed: &CpuId) -> CpuidTemplate {
+        let empty_entry = kvm_cpuid_entry2::default();
+
+        let entries = processed
+            .as_slice()
+            .iter()
+            .filter_map(|entry| {
+                match base.get_entry(entry.function, entry.index) {
+                    Some(base_entry) if base_entry == entry => None,
+                    Some(base_entry) => Some(CpuidTemplateEntry::from_entries(base_entry, entry)),
+                    // Added entries are built on top of an empty entry.
+                    None => Some(CpuidTemplateEntry::from_entries(&empty_entry, entry)),
+                }
+            })
+            .collect();
+
+        let removed = base
+            .as_slice()
+            .iter()
+            .filter(|entry| processed.get_entry(entry.function, entry.index).is_none())
+            .map(|entry| (entry.function, entry.index))
+            .collect();
+
+        CpuidTemplate {
+            entries,
+            removed,
+            mode: TemplateMode::Additive,
+        }
+    }
+
+    /// Applies the template to `cpuid`.
+    ///
+    /// Fails without adding any entry if there is no room for all the added entries.
+    pub fn apply(&self, cpuid: &mut CpuId) -> Result<(), Error> {
+        cpuid.retain(|entry| !self.removed.contains(&(entry.function, entry.index)));
+        if self.mode == TemplateMode::Exclusive {
+            cpuid.retain(|entry| {
+                self.entries.iter().any(|template_entry| {
+                    template_entry.function == entry.function && template_entry.index == entry.index
+                })
+            });
+        }
+
+        let entries: Vec<kvm_cpuid_entry2> = self
+            .entries
+            .iter()
+            .map(|template_entry| {
+                let mut entry = cpuid
+                    .get_entry(template_entry.function, template_entry.index)
+                    .copied()
+                    .unwrap_or(kvm_cpuid_entry2 {
+                        function: template_entry.function,
+                        index: template_entry.index,
+                        ..Default::default()
+                    });
+                template_entry.apply(&mut entry);
+                entry
+            })
+            .collect();
+
+        cpuid.set_entries(&entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_bindings::KVM_MAX_CPUID_ENTRIES;
+    use kvm_ioctls::Kvm;
+
+    use super::*;
+    use crate::transformer::VmSpec;
+
+    fn entry(function: u32, index: u32, eax: u32) -> kvm_cpuid_entry2 {
+        kvm_cpuid_entry2 {
+            function,
+            index,
+            eax,
+            ..Default::default()
+        }
+    }
+
+    fn round_trip(base: &CpuId, processed: &CpuId) {
+        let template = CpuidTemplate::from_processed(base, processed);
+        let json = serde_json::to_string(&template).unwrap();
+        let template: CpuidTemplate = serde_json::from_str(&json).unwrap();
+
+        let mut cpuid = base.clone();
+        template.apply(&mut cpuid).unwrap();
+
+        let mut expected = processed.clone();
+        cpuid.sort_entries();
+        expected.sort_entries();
+        assert_eq!(cpuid.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_register_masks() {
+        let masks = RegisterMasks::from_values(0b1100, 0b1010);
+        assert_eq!(masks.apply(0b1100), 0b1010);
+        // Bits which didn't change are taken from the value the masks are applied to.
+        assert_eq!(masks.apply(0b1101), 0b1011);
+    }
+
+    #[test]
+    fn test_from_processed() {
+        let base =
+            CpuId::from_entries(&[entry(0x0, 0, 1), entry(0x1, 0, 2), entry(0x2, 0, 3)]).unwrap();
+        let processed =
+            CpuId::from_entries(&[entry(0x0, 0, 1), entry(0x1, 0, 4), entry(0x7, 1, 5)]).unwrap();
+
+        let template = CpuidTemplate::from_processed(&base, &processed);
+        assert_eq!(template.removed, vec![(0x2, 0)]);
+        assert_eq!(
+            template
+                .entries
+                .iter()
+                .map(|entry| (entry.function, entry.index))
+                .collect::<Vec<_>>(),
+            vec![(0x1, 0), (0x7, 1)]
+        );
+
+        round_trip(&base, &processed);
+        round_trip(&processed, &base);
+        round_trip(&base, &base);
+    }
+
+    #[test]
+    fn test_apply_mode() {
+        let mut leaf_0x1 = entry(0x1, 0, 2);
+        leaf_0x1.ebx = 0x0100_0800;
+        leaf_0x1.flags = 1;
+        let base = CpuId::from_entries(&[entry(0x0, 0, 1), leaf_0x1, entry(0x7, 0, 3)]).unwrap();
+        let mut template = CpuidTemplate {
+            entries: vec![CpuidTemplateEntry {
+                function: 0x7,
+                index: 0,
+                flags: 0,
+                eax: RegisterMasks { and: 0, or: 0xf },
+                ebx: RegisterMasks::default(),
+                ecx: RegisterMasks::default(),
+                edx: RegisterMasks::default(),
+            }],
+            removed: vec![],
+            mode: TemplateMode::Additive,
+        };
+
+        // The entries which aren't listed are byte-identical.
+        let mut cpuid = base.clone();
+        template.apply(&mut cpuid).unwrap();
+        assert_eq!(cpuid.len(), 3);
+        assert_eq!(&cpuid.as_slice()[..2], &base.as_slice()[..2]);
+        assert_eq!(cpuid.get_entry(0x7, 0).unwrap().eax, 0xf);
+
+        template.mode = TemplateMode::Exclusive;
+        let mut cpuid = base.clone();
+        template.apply(&mut cpuid).unwrap();
+        assert_eq!(cpuid.as_slice(), &[entry(0x7, 0, 0xf)]);
+
+        // Templates are additive unless stated otherwise.
+        let template: CpuidTemplate =
+            serde_json::from_str(r#"{"entries": [], "removed": []}"#).unwrap();
+        assert_eq!(template.mode, TemplateMode::Additive);
+        let template: CpuidTemplate =
+            serde_json::from_str(r#"{"entries": [], "removed": [], "mode": "Exclusive"}"#).unwrap();
+        assert_eq!(template.mode, TemplateMode::Exclusive);
+    }
+
+    #[test]
+    fn test_apply_no_room() {
+        let base = CpuId::new(KVM_MAX_CPUID_ENTRIES - 1).unwrap();
+        let processed =
+            CpuId::from_entries(&[entry(0x4000_0000, 0, 1), entry(0x4000_0001, 0, 2)]).unwrap();
+        let template = CpuidTemplate {
+            entries: CpuidTemplate::from_processed(&base, &processed).entries,
+            removed: vec![],
+            mode: TemplateMode::Additive,
+        };
+
+        let mut cpuid = base.clone();
+        assert!(matches!(
+            template.apply(&mut cpuid),
+            Err(Error::Fam(utils::fam::Error::SizeLimitExceeded))
+        ));
+        assert_eq!(cpuid.as_slice(), base.as_slice());
+    }
+
+    #[test]
+    fn test_from_processed_host_cpuid() {
+        let kvm = Kvm::new().unwrap();
+        let base = kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES).unwrap();
+        let mut processed = base.clone();
+        crate::filter_cpuid(&mut processed, &VmSpec::new(1, 2, true).unwrap()).unwrap();
+
+        round_trip(&base, &processed);
+    }
+}
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use kvm_bindings::{CpuId, KVM_CPUID_FLAG_SIGNIFCANT_INDEX};
+use logger::warn;
 
 use super::*;
 use crate::bit_helper::BitHelper;
@@ -19,7 +20,7 @@ const NODES_PER_PROCESSOR: u32 = 0;
 
 pub fn update_structured_extended_entry(
     entry: &mut kvm_cpuid_entry2,
-    _vm_spec: &VmSpec,
+    vm_spec: &VmSpec,
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_0x7::index0::*;
 
@@ -30,7 +31,7 @@ pub fn update_structured_extended_entry(
         entry.edx.write_bit(edx::ARCH_CAPABILITIES_BITINDEX, false);
     }
 
-    Ok(())
+    common::update_optional_features_entry(entry, vm_spec)
 }
 
 pub fn update_largest_extended_fn_entry(
@@ -50,28 +51,124 @@ pub fn update_largest_extended_fn_entry(
 
 pub fn update_extended_feature_info_entry(
     entry: &mut kvm_cpuid_entry2,
-    _vm_spec: &VmSpec,
+    vm_spec: &VmSpec,
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_0x80000001::*;
 
     // set the Topology Extension bit since we use the Extended Cache Topology leaf
     entry.ecx.write_bit(ecx::TOPOEXT_INDEX, true);
 
+    common::update_optional_features_entry(entry, vm_spec)
+}
+
+/// Checks that the L1 data and instruction cache sizes reported by the host are valid.
+fn check_l1_cache_size(entry: &kvm_cpuid_entry2) -> Option<CpuidWarning> {
+    use crate::cpu_leaf::leaf_0x80000005::*;
+
+    if entry.ecx.read_bits_in_range(&ecx::L1_DC_SIZE_BITRANGE) == 0
+        || entry.edx.read_bits_in_range(&edx::L1_IC_SIZE_BITRANGE) == 0
+    {
+        return Some(CpuidWarning::ZeroL1CacheSize);
+    }
+
+    None
+}
+
+pub fn transform_entry_0x80000005(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    // The L1 cache and TLB information describes the physical hardware and is passed
+    // through as-is. A misconfigured host may report zeros though, which makes guests
+    // mis-detect the cache sizes, so let the user know.
+    if let Some(warning) = check_l1_cache_size(entry) {
+        warn!("{}", warning);
+    }
+
     Ok(())
 }
 
+pub fn transform_entry_0x8000001f(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    // Firecracker doesn't support confidential computing, so unless explicitly requested,
+    // hide SME/SEV/SEV-ES/SEV-SNP from the guest.
+    if !vm_spec.enable_amd_sev {
+        common::mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+    }
+
+    Ok(())
+}
+
+// The leaf 0x80000021 EAX bits which describe the behavior of the vCPU itself, as opposed to
+// the SMM, MSR and microcode facilities of the physical CPU which aren't exposed to the guest.
+const LEAF_0X80000021_EAX_KEPT_BITS: [u32; 10] = [
+    leaf_0x80000021::eax::NO_NESTED_DATA_BP_BITINDEX,
+    leaf_0x80000021::eax::FS_GS_BASE_NS_BITINDEX,
+    leaf_0x80000021::eax::LFENCE_ALWAYS_SERIALIZING_BITINDEX,
+    leaf_0x80000021::eax::NULL_SELECT_CLEARS_BASE_BITINDEX,
+    leaf_0x80000021::eax::AUTOMATIC_IBRS_BITINDEX,
+    leaf_0x80000021::eax::NO_SMM_CTL_MSR_BITINDEX,
+    leaf_0x80000021::eax::EPSF_BITINDEX,
+    leaf_0x80000021::eax::SBPB_BITINDEX,
+    leaf_0x80000021::eax::IBPB_BRTYPE_BITINDEX,
+    leaf_0x80000021::eax::SRSO_NO_BITINDEX,
+];
+
+/// Keeps the leaf 0x80000021 (Extended Feature Identification 2) features which apply to the
+/// guest, and clears the ones tied to the physical CPU.
+///
+/// The SMM paging lock, the prefetch control MSR, CPUID_USER_DIS and upper address ignore
+/// aren't virtualized, and the guest can't load microcode patches, so their size is cleared.
+pub fn transform_entry_0x80000021(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    let kept_eax_bits = LEAF_0X80000021_EAX_KEPT_BITS
+        .iter()
+        .fold(0, |mask, &bit_index| mask | (1 << bit_index));
+    common::mask_cpuid_entry(entry, kept_eax_bits, 0, 0, 0, 0, 0, 0, 0);
+
+    Ok(())
+}
+
+// Raises the largest extended function up to leaf 0x80000021 if the host supports it, since
+// `update_largest_extended_fn_entry` caps it to the leaves Firecracker always exposes.
+fn expose_extended_feature_2_leaf(cpuid: &mut CpuId) {
+    use crate::cpu_leaf::leaf_0x80000000::*;
+
+    if cpuid.get_entry(leaf_0x80000021::LEAF_NUM, 0).is_none() {
+        return;
+    }
+    if let Some(entry) = cpuid.get_entry_mut(LEAF_NUM, 0) {
+        entry.eax.write_bits_in_range(
+            &eax::LARGEST_EXTENDED_FN_BITRANGE,
+            leaf_0x80000021::LEAF_NUM,
+        );
+    }
+}
+
 pub fn update_amd_features_entry(
     entry: &mut kvm_cpuid_entry2,
     vm_spec: &VmSpec,
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_0x80000008::*;
 
-    // We don't support more then 128 threads right now.
-    // It's safe to put them all on the same processor.
+    // We don't support more then 128 threads per socket right now. With a single socket,
+    // it's safe to leave room for all of them. Otherwise the socket id must start right
+    // after the APIC IDs of the package.
+    let thread_id_size = match vm_spec.sockets() {
+        1 => THREAD_ID_MAX_SIZE,
+        _ => u32::from(vm_spec.apic_id_width()),
+    };
     entry
         .ecx
-        .write_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE, THREAD_ID_MAX_SIZE)
-        .write_bits_in_range(&ecx::NUM_THREADS_BITRANGE, u32::from(vm_spec.cpu_count - 1));
+        .write_bits_in_range(&ecx::THREAD_ID_SIZE_BITRANGE, thread_id_size)
+        .write_bits_in_range(
+            &ecx::NUM_THREADS_BITRANGE,
+            u32::from(vm_spec.cpus_per_socket() - 1),
+        );
 
     Ok(())
 }
@@ -98,15 +195,17 @@ pub fn update_extended_apic_id_entry(
     // logical CPU 1 -> core id: 0
     // logical CPU 2 -> core id: 1
     // logical CPU 3 -> core id: 1
-    let core_id = u32::from(vm_spec.cpu_index / vm_spec.cpus_per_core());
+    // Otherwise each logical CPU is a core of its own.
+    let core_id = if vm_spec.is_smt_enabled() {
+        u32::from(vm_spec.cpu_index / vm_spec.cpus_per_core())
+    } else {
+        u32::from(vm_spec.cpu_index)
+    };
 
     entry
         .eax
         // the Extended APIC ID is the id of the current logical CPU
-        .write_bits_in_range(
-            &eax::EXTENDED_APIC_ID_BITRANGE,
-            u32::from(vm_spec.cpu_index),
-        );
+        .write_bits_in_range(&eax::EXTENDED_APIC_ID_BITRANGE, vm_spec.x2apic_id());
 
     entry
         .ebx
@@ -119,8 +218,8 @@ pub fn update_extended_apic_id_entry(
     entry
         .ecx
         .write_bits_in_range(&ecx::NODES_PER_PROCESSOR_BITRANGE, NODES_PER_PROCESSOR)
-        // Put all the cpus in the same node.
-        .write_bits_in_range(&ecx::NODE_ID_BITRANGE, 0);
+        // Put all the cpus of a socket in the same node.
+        .write_bits_in_range(&ecx::NODE_ID_BITRANGE, u32::from(vm_spec.socket_id()));
 
     Ok(())
 }
@@ -134,19 +233,70 @@ impl CpuidTransformer for AmdCpuidTransformer {
         cpuid.retain(|entry| entry.function != leaf_0xb::LEAF_NUM);
         use_host_cpuid_function(cpuid, leaf_0x8000001e::LEAF_NUM, false)?;
         use_host_cpuid_function(cpuid, leaf_0x8000001d::LEAF_NUM, true)?;
-        self.process_entries(cpuid, vm_spec)
+        self.check_required_subleaves(cpuid)?;
+        self.process_entries(cpuid, vm_spec)?;
+        expose_extended_feature_2_leaf(cpuid);
+        common::update_xsave_user_state_entries(cpuid)?;
+        common::update_xsave_supervisor_state_entries(cpuid)?;
+        if vm_spec.sort_entries {
+            cpuid.sort_entries();
+        }
+
+        Ok(())
+    }
+
+    fn verify(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+        let mut violations = Vec::new();
+        common::verify_cpuid(cpuid, vm_spec, &mut violations);
+
+        for entry in cpuid
+            .as_slice()
+            .iter()
+            .filter(|entry| entry.function == leaf_0xb::LEAF_NUM)
+        {
+            violations.push(CpuidViolation::UnexpectedLeaf {
+                function: entry.function,
+                index: entry.index,
+            });
+        }
+
+        // The topology is described through the extended leaves, so TOPOEXT must be exposed.
+        match cpuid.get_entry(leaf_0x80000001::LEAF_NUM, 0) {
+            Some(entry) if entry.ecx.read_bit(leaf_0x80000001::ecx::TOPOEXT_INDEX) => {}
+            Some(_) => violations.push(CpuidViolation::FeatureBit {
+                function: leaf_0x80000001::LEAF_NUM,
+                index: 0,
+                register: "ecx",
+                bit: leaf_0x80000001::ecx::TOPOEXT_INDEX,
+                expected: true,
+            }),
+            None => violations.push(CpuidViolation::MissingLeaf {
+                function: leaf_0x80000001::LEAF_NUM,
+                index: 0,
+            }),
+        }
+
+        violations_to_result(violations)
     }
 
     fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
         match entry.function {
             leaf_0x1::LEAF_NUM => Some(common::update_feature_info_entry),
+            leaf_0x3::LEAF_NUM => Some(common::update_serial_number_entry),
             leaf_0x7::LEAF_NUM => Some(amd::update_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(common::update_optional_features_entry),
             leaf_0x80000000::LEAF_NUM => Some(amd::update_largest_extended_fn_entry),
             leaf_0x80000001::LEAF_NUM => Some(amd::update_extended_feature_info_entry),
+            leaf_0x80000005::LEAF_NUM => Some(amd::transform_entry_0x80000005),
             leaf_0x80000008::LEAF_NUM => Some(amd::update_amd_features_entry),
             leaf_0x8000001d::LEAF_NUM => Some(amd::update_extended_cache_topology_entry),
             leaf_0x8000001e::LEAF_NUM => Some(amd::update_extended_apic_id_entry),
-            0x8000_0002..=0x8000_0004 => Some(common::update_brand_string_entry),
+            leaf_0x8000001f::LEAF_NUM => Some(amd::transform_entry_0x8000001f),
+            // Only reported by KVM on the hosts which support it.
+            leaf_0x80000021::LEAF_NUM => Some(amd::transform_entry_0x80000021),
+            0x8000_0002 => Some(common::update_brand_string_entry_0x80000002),
+            0x8000_0003 => Some(common::update_brand_string_entry_0x80000003),
+            0x8000_0004 => Some(common::update_brand_string_entry_0x80000004),
             _ => None,
         }
     }
@@ -158,7 +308,7 @@ mod tests {
 
     #[test]
     fn test_process_cpuid() {
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let mut cpuid = CpuId::new(0).unwrap();
         let entry = kvm_cpuid_entry2 {
             function: leaf_0xb::LEAF_NUM,
@@ -178,12 +328,57 @@ mod tests {
         assert!(!cpuid.as_slice().contains(&entry));
     }
 
+    #[test]
+    fn test_verify() {
+        let vm_spec = VmSpec::default();
+        let transformer = AmdCpuidTransformer {};
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x1::LEAF_NUM,
+            ..Default::default()
+        };
+        entry.edx.write_bit(leaf_0x1::edx::APIC_BITINDEX, true);
+        entry
+            .ecx
+            .write_bit(leaf_0x1::ecx::HYPERVISOR_BITINDEX, true);
+        let mut cpuid = CpuId::from_entries(&[
+            entry,
+            kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            transformer.verify(&cpuid, &vm_spec).unwrap_err(),
+            vec![
+                CpuidViolation::UnexpectedLeaf {
+                    function: leaf_0xb::LEAF_NUM,
+                    index: 0,
+                },
+                CpuidViolation::MissingLeaf {
+                    function: leaf_0x80000001::LEAF_NUM,
+                    index: 0,
+                },
+            ]
+        );
+
+        cpuid
+            .push(kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        assert!(transformer.verify(&cpuid, &vm_spec).is_ok());
+    }
+
     #[test]
     fn test_update_structured_extended_entry() {
         use crate::cpu_leaf::leaf_0x7::index0::*;
 
         // Check that if index == 0 the entry is processed
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let mut entry = &mut kvm_cpuid_entry2 {
             function: leaf_0x7::LEAF_NUM,
             index: 0,
@@ -208,7 +403,7 @@ mod tests {
     fn test_update_largest_extended_fn_entry() {
         use crate::cpu_leaf::leaf_0x80000000::*;
 
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let entry = &mut kvm_cpuid_entry2 {
             function: LEAF_NUM,
             index: 0,
@@ -234,7 +429,7 @@ mod tests {
     fn test_update_extended_feature_info_entry() {
         use crate::cpu_leaf::leaf_0x80000001::*;
 
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let entry = &mut kvm_cpuid_entry2 {
             function: LEAF_NUM,
             index: 0,
@@ -254,6 +449,69 @@ mod tests {
         assert!(entry.ecx.read_bit(ecx::TOPOEXT_INDEX));
     }
 
+    #[test]
+    fn test_update_extended_feature_info_entry_paranoid_timing() {
+        use crate::cpu_leaf::leaf_0x80000001::*;
+
+        let mut vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            edx: 1 << edx::RDTSCP_BITINDEX,
+            ..Default::default()
+        };
+
+        assert!(update_extended_feature_info_entry(&mut entry, &vm_spec).is_ok());
+        assert!(entry.edx.read_bit(edx::RDTSCP_BITINDEX));
+
+        vm_spec.set_paranoid_timing(ParanoidTiming::Rdtscp);
+        assert!(update_extended_feature_info_entry(&mut entry, &vm_spec).is_ok());
+        assert!(!entry.edx.read_bit(edx::RDTSCP_BITINDEX));
+        assert!(entry.ecx.read_bit(ecx::TOPOEXT_INDEX));
+    }
+
+    #[test]
+    fn test_transform_entry_0x80000005() {
+        use crate::cpu_leaf::leaf_0x80000005::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0xff48_ff40,
+            ebx: 0xff48_ff40,
+            ecx: 0x2008_0140,
+            edx: 0x2008_0140,
+            padding: [0, 0, 0],
+        };
+        let expected = entry;
+
+        // Valid cache sizes are passed through untouched.
+        assert_eq!(check_l1_cache_size(&entry), None);
+        assert!(transform_entry_0x80000005(&mut entry, &vm_spec).is_ok());
+        assert_eq!(entry, expected);
+
+        // A zero L1 data cache size is reported.
+        entry.ecx.write_bits_in_range(&ecx::L1_DC_SIZE_BITRANGE, 0);
+        assert_eq!(
+            check_l1_cache_size(&entry),
+            Some(CpuidWarning::ZeroL1CacheSize)
+        );
+
+        // A zero L1 instruction cache size is reported.
+        let mut entry = expected;
+        entry.edx.write_bits_in_range(&edx::L1_IC_SIZE_BITRANGE, 0);
+        assert_eq!(
+            check_l1_cache_size(&entry),
+            Some(CpuidWarning::ZeroL1CacheSize)
+        );
+
+        // Zeros are still passed through.
+        let expected = entry;
+        assert!(transform_entry_0x80000005(&mut entry, &vm_spec).is_ok());
+        assert_eq!(entry, expected);
+    }
+
     fn check_update_amd_features_entry(cpu_count: u8, smt: bool) {
         use crate::cpu_leaf::leaf_0x80000008::*;
 
@@ -331,9 +589,104 @@ mod tests {
         assert_eq!(entry.ecx.read_bits_in_range(&ecx::NODE_ID_BITRANGE), 0);
     }
 
+    #[test]
+    fn test_transform_entry_0x8000001f() {
+        let mut vm_spec = VmSpec::default();
+        let entry = kvm_cpuid_entry2 {
+            function: leaf_0x8000001f::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0x0001_781f,
+            ebx: 0x0000_416f,
+            ecx: 0x0000_01fd,
+            edx: 0x0000_0001,
+            padding: [0, 0, 0],
+        };
+
+        let mut hidden_entry = entry;
+        assert!(transform_entry_0x8000001f(&mut hidden_entry, &vm_spec).is_ok());
+        assert_eq!(
+            hidden_entry,
+            kvm_cpuid_entry2 {
+                function: leaf_0x8000001f::LEAF_NUM,
+                ..Default::default()
+            }
+        );
+
+        vm_spec.set_enable_amd_sev(true);
+        let mut sev_entry = entry;
+        assert!(transform_entry_0x8000001f(&mut sev_entry, &vm_spec).is_ok());
+        assert_eq!(sev_entry, entry);
+    }
+
+    #[test]
+    fn test_transform_entry_0x80000021() {
+        use crate::cpu_leaf::leaf_0x80000021::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            index: 0,
+            flags: 0,
+            // SmmPgCfgLock, PrefetchCtlMsr and CpuidUserDis are set as well.
+            eax: 1 << eax::NO_NESTED_DATA_BP_BITINDEX
+                | 1 << eax::LFENCE_ALWAYS_SERIALIZING_BITINDEX
+                | 1 << 3
+                | 1 << eax::AUTOMATIC_IBRS_BITINDEX
+                | 1 << 13
+                | 1 << 17
+                | 1 << eax::SRSO_NO_BITINDEX,
+            // A microcode patch size of 4 KiB.
+            ebx: 0x0000_0100,
+            ecx: 0x1,
+            edx: 0x1,
+            padding: [0, 0, 0],
+        };
+
+        assert!(transform_entry_0x80000021(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry.eax,
+            1 << eax::NO_NESTED_DATA_BP_BITINDEX
+                | 1 << eax::LFENCE_ALWAYS_SERIALIZING_BITINDEX
+                | 1 << eax::AUTOMATIC_IBRS_BITINDEX
+                | 1 << eax::SRSO_NO_BITINDEX
+        );
+        assert_eq!((entry.ebx, entry.ecx, entry.edx), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_expose_extended_feature_2_leaf() {
+        let largest_extended_fn = |cpuid: &CpuId| {
+            cpuid
+                .get_entry(leaf_0x80000000::LEAF_NUM, 0)
+                .map(|entry| entry.eax)
+        };
+        let leaf_0x80000000 = kvm_cpuid_entry2 {
+            function: leaf_0x80000000::LEAF_NUM,
+            eax: LARGEST_EXTENDED_FN,
+            ..Default::default()
+        };
+
+        // Without the leaf on the host, the largest extended function is left untouched.
+        let mut cpuid = CpuId::from_entries(&[leaf_0x80000000]).unwrap();
+        expose_extended_feature_2_leaf(&mut cpuid);
+        assert_eq!(largest_extended_fn(&cpuid), Some(LARGEST_EXTENDED_FN));
+
+        let mut cpuid = CpuId::from_entries(&[
+            leaf_0x80000000,
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000021::LEAF_NUM,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        expose_extended_feature_2_leaf(&mut cpuid);
+        assert_eq!(largest_extended_fn(&cpuid), Some(leaf_0x80000021::LEAF_NUM));
+    }
+
     #[test]
     fn test_update_extended_cache_topology_entry() {
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let entry = &mut kvm_cpuid_entry2 {
             function: leaf_0x8000001d::LEAF_NUM,
             index: 0,
@@ -379,4 +732,55 @@ mod tests {
         check_update_extended_apic_id_entry(0, 2, true, 0, 1);
         check_update_extended_apic_id_entry(1, 2, true, 0, 1);
     }
+
+    #[test]
+    fn test_2socket_2core_ht_on() {
+        use crate::cpu_leaf::{leaf_0x80000008, leaf_0x8000001e};
+
+        // The second thread of the first core of the second socket.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(5)
+            .cpu_count(8)
+            .sockets(2)
+            .smt(true)
+            .build()
+            .unwrap();
+
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x80000008::LEAF_NUM,
+            ..Default::default()
+        };
+        assert!(update_amd_features_entry(&mut entry, &vm_spec).is_ok());
+        // The socket id starts after 1 thread bit and 1 core bit.
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x80000008::ecx::THREAD_ID_SIZE_BITRANGE),
+            2
+        );
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x80000008::ecx::NUM_THREADS_BITRANGE),
+            3
+        );
+
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x8000001e::LEAF_NUM,
+            ..Default::default()
+        };
+        assert!(update_extended_apic_id_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x8000001e::eax::EXTENDED_APIC_ID_BITRANGE),
+            5
+        );
+        assert_eq!(
+            entry
+                .ecx
+                .read_bits_in_range(&leaf_0x8000001e::ecx::NODE_ID_BITRANGE),
+            1
+        );
+    }
 }
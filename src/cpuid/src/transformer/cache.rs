@@ -0,0 +1,208 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt;
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+use super::synthetic::CacheGeometry;
+use crate::bit_helper::BitHelper;
+use crate::common::{get_vendor_id_from_cpuid, VENDOR_ID_AMD, VENDOR_ID_INTEL};
+use crate::cpu_leaf::*;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+
+impl CacheGeometry {
+    /// Decodes a deterministic cache parameters entry, returning `None` for the null cache
+    /// type ending the subleaves.
+    pub(crate) fn from_entry(entry: &kvm_cpuid_entry2) -> Option<Self> {
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+
+        let cache_type = entry.eax.read_bits_in_range(&eax::CACHE_TYPE_BITRANGE);
+        if cache_type == 0 {
+            return None;
+        }
+
+        Some(CacheGeometry {
+            level: entry.eax.read_bits_in_range(&eax::CACHE_LEVEL_BITRANGE),
+            cache_type,
+            ways: entry.ebx.read_bits_in_range(&ebx::WAYS_BITRANGE) + 1,
+            partitions: entry.ebx.read_bits_in_range(&ebx::PARTITIONS_BITRANGE) + 1,
+            line_size: entry.ebx.read_bits_in_range(&ebx::LINE_SIZE_BITRANGE) + 1,
+            sets: entry.ecx.wrapping_add(1),
+        })
+    }
+}
+
+/// The caches described to the guest, as decoded by `cache_summary`.
+///
+/// It is displayed as a human readable list of the cache sizes, e.g.
+/// `L1d: 32KB, L1i: 32KB, L2: 256KB, L3: 8MB`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheSummary {
+    /// The caches, in the order of the subleaves describing them.
+    pub caches: Vec<CacheGeometry>,
+}
+
+impl fmt::Display for CacheSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, cache) in self.caches.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+
+            let suffix = match cache.cache_type {
+                1 => "d",
+                2 => "i",
+                _ => "",
+            };
+            let size = cache.size();
+            if size >= MIB && size % MIB == 0 {
+                write!(f, "L{}{}: {}MB", cache.level, suffix, size / MIB)?;
+            } else {
+                write!(f, "L{}{}: {}KB", cache.level, suffix, size / KIB)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes the caches exposed by the deterministic cache parameters leaf of the CPUID vendor:
+/// leaf 0x4 on Intel and leaf 0x8000001D on AMD.
+///
+/// The summary is empty for the other vendors, or when the vendor leaf is missing.
+pub fn cache_summary(cpuid: &CpuId) -> CacheSummary {
+    let leaf = match get_vendor_id_from_cpuid(cpuid) {
+        Ok(ref vendor_id) if vendor_id == VENDOR_ID_INTEL => leaf_0x4::LEAF_NUM,
+        Ok(ref vendor_id) if vendor_id == VENDOR_ID_AMD => leaf_0x8000001d::LEAF_NUM,
+        _ => return CacheSummary::default(),
+    };
+
+    let mut entries: Vec<&kvm_cpuid_entry2> = cpuid
+        .as_slice()
+        .iter()
+        .filter(|entry| entry.function == leaf)
+        .collect();
+    entries.sort_by_key(|entry| entry.index);
+
+    CacheSummary {
+        caches: entries
+            .into_iter()
+            .map_while(CacheGeometry::from_entry)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vendor_entry(vendor_id: &[u8; 12]) -> kvm_cpuid_entry2 {
+        let reg = |i: usize| u32::from_le_bytes(vendor_id[i..i + 4].try_into().unwrap());
+        kvm_cpuid_entry2 {
+            function: 0,
+            ebx: reg(0),
+            edx: reg(4),
+            ecx: reg(8),
+            ..Default::default()
+        }
+    }
+
+    fn cache_entry(function: u32, index: u32, geometry: &CacheGeometry) -> kvm_cpuid_entry2 {
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+
+        let mut entry = kvm_cpuid_entry2 {
+            function,
+            index,
+            ecx: geometry.sets - 1,
+            ..Default::default()
+        };
+        entry
+            .eax
+            .write_bits_in_range(&eax::CACHE_LEVEL_BITRANGE, geometry.level)
+            .write_bits_in_range(&eax::CACHE_TYPE_BITRANGE, geometry.cache_type);
+        entry
+            .ebx
+            .write_bits_in_range(&ebx::WAYS_BITRANGE, geometry.ways - 1)
+            .write_bits_in_range(&ebx::PARTITIONS_BITRANGE, geometry.partitions - 1)
+            .write_bits_in_range(&ebx::LINE_SIZE_BITRANGE, geometry.line_size - 1);
+        entry
+    }
+
+    fn geometry(level: u32, cache_type: u32, ways: u32, sets: u32) -> CacheGeometry {
+        CacheGeometry {
+            level,
+            cache_type,
+            ways,
+            partitions: 1,
+            line_size: 64,
+            sets,
+        }
+    }
+
+    #[test]
+    fn test_cache_summary_intel() {
+        // The leaf 0x4 table of a Skylake client CPU.
+        let caches = [
+            geometry(1, 1, 8, 64),
+            geometry(1, 2, 8, 64),
+            geometry(2, 3, 4, 1024),
+            geometry(3, 3, 16, 8192),
+        ];
+        let mut entries = vec![vendor_entry(VENDOR_ID_INTEL)];
+        // The subleaves are not sorted, and the null cache type ends the table.
+        for (index, cache) in caches.iter().enumerate().rev() {
+            entries.push(cache_entry(leaf_0x4::LEAF_NUM, index as u32, cache));
+        }
+        entries.push(kvm_cpuid_entry2 {
+            function: leaf_0x4::LEAF_NUM,
+            index: 4,
+            ..Default::default()
+        });
+        // The AMD leaf is ignored on Intel.
+        entries.push(cache_entry(
+            leaf_0x8000001d::LEAF_NUM,
+            0,
+            &geometry(1, 1, 8, 128),
+        ));
+
+        let summary = cache_summary(&CpuId::from_entries(&entries).unwrap());
+        assert_eq!(summary.caches, caches);
+        assert_eq!(
+            summary.caches.iter().map(|c| c.size()).collect::<Vec<_>>(),
+            [32 * KIB, 32 * KIB, 256 * KIB, 8 * MIB]
+        );
+        assert_eq!(
+            summary.to_string(),
+            "L1d: 32KB, L1i: 32KB, L2: 256KB, L3: 8MB"
+        );
+    }
+
+    #[test]
+    fn test_cache_summary_amd() {
+        let entries = [
+            vendor_entry(VENDOR_ID_AMD),
+            cache_entry(leaf_0x4::LEAF_NUM, 0, &geometry(1, 1, 8, 128)),
+            cache_entry(leaf_0x8000001d::LEAF_NUM, 0, &geometry(2, 3, 8, 1024)),
+            cache_entry(leaf_0x8000001d::LEAF_NUM, 1, &geometry(3, 3, 16, 6144)),
+        ];
+
+        let summary = cache_summary(&CpuId::from_entries(&entries).unwrap());
+        assert_eq!(summary.to_string(), "L2: 512KB, L3: 6MB");
+    }
+
+    #[test]
+    fn test_cache_summary_unknown_vendor() {
+        let entries = [
+            vendor_entry(b"HygonGenuine"),
+            cache_entry(leaf_0x4::LEAF_NUM, 0, &geometry(1, 1, 8, 64)),
+        ];
+        assert_eq!(
+            cache_summary(&CpuId::from_entries(&entries).unwrap()),
+            CacheSummary::default()
+        );
+        assert_eq!(CacheSummary::default().to_string(), "");
+    }
+}
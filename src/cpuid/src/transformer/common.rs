@@ -6,6 +6,7 @@ use kvm_bindings::{kvm_cpuid_entry2, CpuId};
 use super::*;
 use crate::bit_helper::BitHelper;
 use crate::common::get_cpuid;
+use crate::cpuid_ext::CpuIdExt;
 
 // constants for setting the fields of kvm_cpuid2 structures
 // CPUID bits in ebx, ecx, and edx.
@@ -13,7 +14,7 @@ const EBX_CLFLUSH_CACHELINE: u32 = 8; // Flush a cache line size.
 
 /// The maximum number of logical processors per package is computed as the closest power of 2
 /// higher or equal to the CPU count configured by the user.
-fn get_max_cpus_per_package(cpu_count: u8) -> Result<u8, Error> {
+pub(crate) fn get_max_cpus_per_package(cpu_count: u8) -> Result<u8, Error> {
     let mut max_cpus_per_package: u8 = 1;
     while max_cpus_per_package < cpu_count {
         max_cpus_per_package <<= 1;
@@ -32,7 +33,8 @@ pub fn update_feature_info_entry(
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_0x1::*;
 
-    let max_cpus_per_package = u32::from(common::get_max_cpus_per_package(vm_spec.cpu_count)?);
+    let max_cpus_per_package =
+        u32::from(common::get_max_cpus_per_package(vm_spec.cpus_per_socket())?);
 
     // X86 hypervisor feature
     entry
@@ -42,7 +44,7 @@ pub fn update_feature_info_entry(
 
     entry
         .ebx
-        .write_bits_in_range(&ebx::APICID_BITRANGE, u32::from(vm_spec.cpu_index))
+        .write_bits_in_range(&ebx::APICID_BITRANGE, vm_spec.initial_apic_id())
         .write_bits_in_range(&ebx::CLFLUSH_SIZE_BITRANGE, EBX_CLFLUSH_CACHELINE)
         .write_bits_in_range(&ebx::CPU_COUNT_BITRANGE, max_cpus_per_package);
 
@@ -51,20 +53,73 @@ pub fn update_feature_info_entry(
     // is valid for the package
     entry
         .edx
-        .write_bit(edx::HTT_BITINDEX, vm_spec.cpu_count > 1);
+        .write_bit(edx::HTT_BITINDEX, vm_spec.cpus_per_socket() > 1)
+        // Never expose the processor serial number.
+        .write_bit(edx::PSN_BITINDEX, false);
 
     Ok(())
 }
 
-pub fn update_brand_string_entry(
+pub fn update_serial_number_entry(
     entry: &mut kvm_cpuid_entry2,
-    vm_spec: &VmSpec,
+    _vm_spec: &VmSpec,
 ) -> Result<(), Error> {
+    // No serial-number-like data should reach the guest.
+    mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+
+    Ok(())
+}
+
+/// Applies AND/OR masks to the registers of `entry`: `reg = (reg & reg_and) | reg_or`.
+#[allow(clippy::too_many_arguments)]
+pub fn mask_cpuid_entry(
+    entry: &mut kvm_cpuid_entry2,
+    eax_and: u32,
+    eax_or: u32,
+    ebx_and: u32,
+    ebx_or: u32,
+    ecx_and: u32,
+    ecx_or: u32,
+    edx_and: u32,
+    edx_or: u32,
+) {
+    entry.eax = (entry.eax & eax_and) | eax_or;
+    entry.ebx = (entry.ebx & ebx_and) | ebx_or;
+    entry.ecx = (entry.ecx & ecx_and) | ecx_or;
+    entry.edx = (entry.edx & edx_and) | edx_or;
+}
+
+fn write_brand_string_regs(entry: &mut kvm_cpuid_entry2, vm_spec: &VmSpec, leaf: u32) {
     let brand_string = &vm_spec.brand_string;
-    entry.eax = brand_string.get_reg_for_leaf(entry.function, BsReg::Eax);
-    entry.ebx = brand_string.get_reg_for_leaf(entry.function, BsReg::Ebx);
-    entry.ecx = brand_string.get_reg_for_leaf(entry.function, BsReg::Ecx);
-    entry.edx = brand_string.get_reg_for_leaf(entry.function, BsReg::Edx);
+    entry.eax = brand_string.get_reg_for_leaf(leaf, BsReg::Eax);
+    entry.ebx = brand_string.get_reg_for_leaf(leaf, BsReg::Ebx);
+    entry.ecx = brand_string.get_reg_for_leaf(leaf, BsReg::Ecx);
+    entry.edx = brand_string.get_reg_for_leaf(leaf, BsReg::Edx);
+}
+
+pub fn update_brand_string_entry_0x80000002(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    write_brand_string_regs(entry, vm_spec, 0x8000_0002);
+
+    Ok(())
+}
+
+pub fn update_brand_string_entry_0x80000003(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    write_brand_string_regs(entry, vm_spec, 0x8000_0003);
+
+    Ok(())
+}
+
+pub fn update_brand_string_entry_0x80000004(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    write_brand_string_regs(entry, vm_spec, 0x8000_0004);
 
     Ok(())
 }
@@ -75,6 +130,10 @@ pub fn update_cache_parameters_entry(
 ) -> Result<(), Error> {
     use crate::cpu_leaf::leaf_cache_parameters::*;
 
+    if vm_spec.cache_id_policy == CacheIdPolicy::PreserveHost {
+        return Ok(());
+    }
+
     match entry.eax.read_bits_in_range(&eax::CACHE_LEVEL_BITRANGE) {
         // L1 & L2 Cache
         1 | 2 => {
@@ -86,10 +145,10 @@ pub fn update_cache_parameters_entry(
         }
         // L3 Cache
         3 => {
-            // The L3 cache is shared among all the logical threads
+            // The L3 cache is shared among all the logical threads of the socket
             entry.eax.write_bits_in_range(
                 &eax::MAX_CPUS_PER_CORE_BITRANGE,
-                u32::from(vm_spec.cpu_count - 1),
+                u32::from(vm_spec.cpus_per_socket() - 1),
             );
         }
         _ => (),
@@ -98,6 +157,523 @@ pub fn update_cache_parameters_entry(
     Ok(())
 }
 
+/// Hides the optional features that were not enabled in the VM specification.
+pub fn update_optional_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::{leaf_0x1d, leaf_0x1e, leaf_0x7, leaf_0x80000001, leaf_0xd};
+
+    let amx_state = &leaf_0xd::index0::eax::AMX_STATE_BITRANGE;
+    let mpx_state = &leaf_0xd::index0::eax::MPX_STATE_BITRANGE;
+    match (entry.function, entry.index) {
+        (leaf_0x7::LEAF_NUM, 0) => {
+            use crate::cpu_leaf::leaf_0x7::index0::*;
+
+            if !vm_spec.enqcmd {
+                entry.ecx.write_bit(ecx::ENQCMD_BITINDEX, false);
+            }
+            if !vm_spec.pconfig {
+                entry.edx.write_bit(edx::PCONFIG_BITINDEX, false);
+            }
+            if !vm_spec.amx {
+                entry
+                    .edx
+                    .write_bit(edx::AMX_BF16_BITINDEX, false)
+                    .write_bit(edx::AMX_TILE_BITINDEX, false)
+                    .write_bit(edx::AMX_INT8_BITINDEX, false);
+            }
+            if vm_spec.paranoid_timing == ParanoidTiming::RdtscpAndRdpid {
+                entry.ecx.write_bit(ecx::RDPID_BITINDEX, false);
+            }
+            if !vm_spec.mpx {
+                entry.ebx.write_bit(ebx::MPX_BITINDEX, false);
+            }
+            if !vm_spec.fsgsbase {
+                entry.ebx.write_bit(ebx::FSGSBASE_BITINDEX, false);
+            }
+            if !vm_spec.serialize {
+                entry.edx.write_bit(edx::SERIALIZE_BITINDEX, false);
+            }
+        }
+        (leaf_0xd::LEAF_NUM, 1) => {
+            use crate::cpu_leaf::leaf_0xd::index1::*;
+
+            if !vm_spec.enqcmd {
+                entry.ecx.write_bit(ecx::PASID_STATE_BITINDEX, false);
+            }
+        }
+        (leaf_0xd::LEAF_NUM, 0) => {
+            if !vm_spec.amx {
+                entry.eax.write_bits_in_range(amx_state, 0);
+            }
+            if !vm_spec.mpx {
+                entry.eax.write_bits_in_range(mpx_state, 0);
+            }
+        }
+        (leaf_0xd::LEAF_NUM, index)
+            if !vm_spec.amx && (amx_state.lsb_index..=amx_state.msb_index).contains(&index) =>
+        {
+            // The XTILECFG and XTILEDATA state components.
+            mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+        }
+        (leaf_0xd::LEAF_NUM, index)
+            if !vm_spec.mpx && (mpx_state.lsb_index..=mpx_state.msb_index).contains(&index) =>
+        {
+            // The BNDREGS and BNDCSR state components.
+            mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+        }
+        (leaf_0x1d::LEAF_NUM, _) | (leaf_0x1e::LEAF_NUM, _) if !vm_spec.amx => {
+            mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+        }
+        (leaf_0x80000001::LEAF_NUM, 0) => {
+            use crate::cpu_leaf::leaf_0x80000001::*;
+
+            if vm_spec.paranoid_timing != ParanoidTiming::Disabled {
+                entry.edx.write_bit(edx::RDTSCP_BITINDEX, false);
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Checks the vendor independent invariants of a processed cpuid.
+pub fn verify_cpuid(cpuid: &CpuId, vm_spec: &VmSpec, violations: &mut Vec<CpuidViolation>) {
+    use crate::cpu_leaf::leaf_0x1::*;
+
+    let entry = match cpuid.get_entry(LEAF_NUM, 0) {
+        Some(entry) => entry,
+        None => {
+            violations.push(CpuidViolation::MissingLeaf {
+                function: LEAF_NUM,
+                index: 0,
+            });
+            return;
+        }
+    };
+
+    // The guest always runs with an xAPIC and must know it's running on a hypervisor.
+    for (register, value, bit) in [
+        ("edx", entry.edx, edx::APIC_BITINDEX),
+        ("ecx", entry.ecx, ecx::HYPERVISOR_BITINDEX),
+    ] {
+        if !value.read_bit(bit) {
+            violations.push(CpuidViolation::FeatureBit {
+                function: LEAF_NUM,
+                index: 0,
+                register,
+                bit,
+                expected: true,
+            });
+        }
+    }
+
+    let apic_id = entry.ebx.read_bits_in_range(&ebx::APICID_BITRANGE);
+    if apic_id != vm_spec.initial_apic_id() {
+        violations.push(CpuidViolation::FieldMismatch {
+            function: LEAF_NUM,
+            index: 0,
+            register: "ebx",
+            expected: vm_spec.initial_apic_id(),
+            actual: apic_id,
+        });
+    }
+}
+
+/// Returns the masks of the `eax`, `ebx`, `ecx` and `edx` bits identifying the vCPU within
+/// the topology.
+fn topology_masks(function: u32) -> [u32; 4] {
+    use crate::bit_helper::BitRangeExt;
+    use crate::cpu_leaf::{leaf_0x1, leaf_0x1f, leaf_0x8000001e, leaf_0xb};
+
+    match function {
+        leaf_0x1::LEAF_NUM => [0, leaf_0x1::ebx::APICID_BITRANGE.get_mask(), 0, 0],
+        // Leaf 0x1F is the V2 extended topology leaf, reporting the same x2APIC ID as leaf 0xB.
+        leaf_0xb::LEAF_NUM | leaf_0x1f::LEAF_NUM => [0, 0, 0, u32::MAX],
+        leaf_0x8000001e::LEAF_NUM => [
+            u32::MAX,
+            leaf_0x8000001e::ebx::CORE_ID_BITRANGE.get_mask(),
+            0,
+            0,
+        ],
+        _ => [0; 4],
+    }
+}
+
+/// Checks that the cpuids of two vCPUs only differ in the fields identifying each vCPU within
+/// the topology (the APIC IDs and the core IDs).
+///
+/// Meant for tests making sure that the transformers don't introduce per-vCPU differences
+/// outside of the topology leaves. All the other differences are returned, `a` being the
+/// expected side.
+pub fn assert_only_topology_differs(a: &CpuId, b: &CpuId) -> Result<(), Vec<CpuidViolation>> {
+    let mut violations = Vec::new();
+
+    for entry in a.as_slice() {
+        let other = match b.get_entry(entry.function, entry.index) {
+            Some(other) => other,
+            None => {
+                violations.push(CpuidViolation::MissingLeaf {
+                    function: entry.function,
+                    index: entry.index,
+                });
+                continue;
+            }
+        };
+
+        let masks = topology_masks(entry.function);
+        let registers = [
+            ("eax", entry.eax, other.eax),
+            ("ebx", entry.ebx, other.ebx),
+            ("ecx", entry.ecx, other.ecx),
+            ("edx", entry.edx, other.edx),
+        ];
+        for ((register, expected, actual), mask) in registers.into_iter().zip(masks) {
+            if expected & !mask != actual & !mask {
+                violations.push(CpuidViolation::FieldMismatch {
+                    function: entry.function,
+                    index: entry.index,
+                    register,
+                    expected: expected & !mask,
+                    actual: actual & !mask,
+                });
+            }
+        }
+    }
+
+    for entry in b.as_slice() {
+        if a.get_entry(entry.function, entry.index).is_none() {
+            violations.push(CpuidViolation::UnexpectedLeaf {
+                function: entry.function,
+                index: entry.index,
+            });
+        }
+    }
+
+    violations_to_result(violations)
+}
+
+/// Keeps the leaf 0xD sub-leaf 0 consistent with the state components that are still enabled.
+///
+/// The user state components (XCR0) whose sub-leaf was masked are removed from EAX/EDX and
+/// the maximum size of the XSAVE area (ECX) is recomputed accordingly.
+pub fn update_xsave_user_state_entries(cpuid: &mut CpuId) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+
+    let entries = cpuid.as_mut_slice();
+    let subleaf0_pos = match entries
+        .iter()
+        .position(|entry| entry.function == LEAF_NUM && entry.index == 0)
+    {
+        Some(pos) => pos,
+        None => return Ok(()),
+    };
+    let mut user_components =
+        u64::from(entries[subleaf0_pos].eax) | (u64::from(entries[subleaf0_pos].edx) << 32);
+
+    let mut size = component::LEGACY_AND_HEADER_SIZE;
+    // State components 0 and 1 (x87 and SSE) are part of the legacy region.
+    for component_idx in 2..64 {
+        let mask = 1_u64 << component_idx;
+        if user_components & mask == 0 {
+            continue;
+        }
+
+        let component = entries
+            .iter()
+            .find(|entry| entry.function == LEAF_NUM && entry.index == component_idx)
+            .filter(|entry| entry.eax != 0);
+        match component {
+            // In the standard format, EBX holds the offset of the state component.
+            Some(component) => size = size.max(component.ebx + component.eax),
+            None => user_components &= !mask,
+        }
+    }
+
+    let subleaf0 = &mut entries[subleaf0_pos];
+    subleaf0.eax = user_components as u32;
+    subleaf0.edx = (user_components >> 32) as u32;
+    subleaf0.ecx = size;
+
+    Ok(())
+}
+
+/// Keeps the leaf 0xD sub-leaf 1 consistent with the state components that are still enabled.
+///
+/// The sub-leaves of the PT and CET supervisor state components are masked when leaf 0x7
+/// doesn't expose their feature anymore. The supervisor state components (IA32_XSS) whose
+/// sub-leaf was masked are removed from ECX/EDX, XSAVES is masked if XSAVE is not exposed
+/// anymore and the size of the compacted XSAVE area (EBX) is recomputed accordingly.
+pub fn update_xsave_supervisor_state_entries(cpuid: &mut CpuId) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+    use crate::cpu_leaf::{leaf_0x1, leaf_0x7, leaf_0xd};
+
+    let entries = cpuid.as_mut_slice();
+    let find_entry = |entries: &[kvm_cpuid_entry2], function: u32, index: u32| {
+        entries
+            .iter()
+            .position(|entry| entry.function == function && entry.index == index)
+    };
+
+    let xsave = match find_entry(entries, leaf_0x1::LEAF_NUM, 0) {
+        Some(pos) => entries[pos].ecx.read_bit(leaf_0x1::ecx::XSAVE_BITINDEX),
+        None => true,
+    };
+    let user_components = match find_entry(entries, leaf_0xd::LEAF_NUM, 0) {
+        Some(pos) => u64::from(entries[pos].eax) | (u64::from(entries[pos].edx) << 32),
+        None => 0,
+    };
+    let subleaf1_pos = match find_entry(entries, leaf_0xd::LEAF_NUM, 1) {
+        Some(pos) => pos,
+        None => return Ok(()),
+    };
+
+    // The guest sizes its XSAVES area from the sub-leaves, so the state components of the
+    // masked features must go away with them.
+    if let Some(pos) = find_entry(entries, leaf_0x7::LEAF_NUM, 0) {
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let leaf_0x7 = entries[pos];
+        let mut disabled_components = 0_u32;
+        if !leaf_0x7.ebx.read_bit(ebx::PT_BITINDEX) {
+            disabled_components.write_bit(index1::ecx::PT_STATE_BITINDEX, true);
+        }
+        if !leaf_0x7.ecx.read_bit(ecx::CET_SS_BITINDEX)
+            && !leaf_0x7.edx.read_bit(edx::CET_IBT_BITINDEX)
+        {
+            disabled_components.write_bits_in_range(&index1::ecx::CET_STATE_BITRANGE, 0b11);
+        }
+
+        for entry in entries.iter_mut().filter(|entry| {
+            entry.function == leaf_0xd::LEAF_NUM
+                && entry.index < 32
+                && disabled_components.read_bit(entry.index)
+        }) {
+            mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
+        }
+    }
+
+    // XSAVES can't be used without XSAVE.
+    let subleaf1 = &mut entries[subleaf1_pos];
+    if !xsave {
+        subleaf1.eax.write_bit(index1::eax::XSAVES_SHIFT, false);
+    }
+    let xsaves = subleaf1.eax.read_bit(index1::eax::XSAVES_SHIFT);
+    let mut supervisor_components = u64::from(subleaf1.ecx) | (u64::from(subleaf1.edx) << 32);
+
+    // Without XSAVES, the IA32_XSS MSR is not available so no supervisor state can be enabled.
+    if !xsaves {
+        supervisor_components = 0;
+    }
+
+    let mut size = component::LEGACY_AND_HEADER_SIZE;
+    // State components 0 and 1 (x87 and SSE) are part of the legacy region.
+    for component_idx in 2..64 {
+        let mask = 1_u64 << component_idx;
+        if (user_components | supervisor_components) & mask == 0 {
+            continue;
+        }
+
+        let component = find_entry(entries, leaf_0xd::LEAF_NUM, component_idx)
+            .map(|pos| entries[pos])
+            .filter(|entry| entry.eax != 0);
+        match component {
+            Some(component) => {
+                if component.ecx.read_bit(component::ecx::ALIGNED_BITINDEX) {
+                    size = (size + 63) & !63;
+                }
+                size += component.eax;
+            }
+            None => {
+                // The state component sub-leaf was masked, so the component must not be
+                // advertised anymore.
+                supervisor_components &= !mask;
+            }
+        }
+    }
+
+    let subleaf1 = &mut entries[subleaf1_pos];
+    subleaf1.ebx = size;
+    subleaf1.ecx = supervisor_components as u32;
+    subleaf1.edx = (supervisor_components >> 32) as u32;
+
+    Ok(())
+}
+
+/// Checks that every state component enabled in XCR0 (leaf 0xD sub-leaf 0) belongs to an
+/// exposed feature.
+///
+/// Guests crash when they try to enable the state of a feature they can't use, so this is
+/// meant to catch the templates hiding a feature without its state components. The
+/// mismatches are reported one feature at a time.
+pub fn validate_xsave_consistency(cpuid: &CpuId) -> Result<(), XsaveError> {
+    use crate::bit_helper::{BitRange, BitRangeExt};
+    use crate::cpu_leaf::{leaf_0x1, leaf_0x7, leaf_0xd};
+
+    let xcr0 = match cpuid.get_entry(leaf_0xd::LEAF_NUM, 0) {
+        Some(entry) => u64::from(entry.eax) | (u64::from(entry.edx) << 32),
+        None => return Ok(()),
+    };
+    let leaf_0x1 = cpuid
+        .get_entry(leaf_0x1::LEAF_NUM, 0)
+        .copied()
+        .unwrap_or_default();
+    let leaf_0x7 = cpuid
+        .get_entry(leaf_0x7::LEAF_NUM, 0)
+        .copied()
+        .unwrap_or_default();
+
+    let state = |bit_range: &BitRange| u64::from(bit_range.get_mask());
+    let checks = [
+        // Only the x87 and SSE state can be managed without XSAVE.
+        (
+            "XSAVE",
+            !0b11,
+            leaf_0x1.ecx.read_bit(leaf_0x1::ecx::XSAVE_BITINDEX),
+        ),
+        (
+            "AVX",
+            1 << 2,
+            leaf_0x1.ecx.read_bit(leaf_0x1::ecx::AVX_BITINDEX),
+        ),
+        (
+            "MPX",
+            state(&leaf_0xd::index0::eax::MPX_STATE_BITRANGE),
+            leaf_0x7.ebx.read_bit(leaf_0x7::index0::ebx::MPX_BITINDEX),
+        ),
+        (
+            "AVX-512",
+            state(&leaf_0xd::index0::eax::AVX512_STATE_BITRANGE),
+            leaf_0x7
+                .ebx
+                .read_bit(leaf_0x7::index0::ebx::AVX512F_BITINDEX),
+        ),
+        (
+            "PKU",
+            1 << leaf_0xd::index0::eax::PKRU_BITINDEX,
+            leaf_0x7.ecx.read_bit(leaf_0x7::index0::ecx::PKU_BITINDEX),
+        ),
+        (
+            "AMX",
+            state(&leaf_0xd::index0::eax::AMX_STATE_BITRANGE),
+            leaf_0x7
+                .edx
+                .read_bit(leaf_0x7::index0::edx::AMX_TILE_BITINDEX),
+        ),
+    ];
+
+    for (feature, components, enabled) in checks {
+        if !enabled && xcr0 & components != 0 {
+            return Err(XsaveError::ComponentsWithoutFeature {
+                components: xcr0 & components,
+                feature,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the features a 64-bit guest can't boot without are exposed.
+///
+/// This is a cheap guard against templates accidentally masking long mode, the x87 FPU or
+/// SSE2, which would otherwise only show up as a guest failing to boot. When LA57 is set, the
+/// linear address width has to allow for 5-level paging, see `CpuIdExt::set_la57`. Clearing
+/// LA57 alone, as the templates do on LA57 hosts, is fine: the guest uses 4-level paging.
+pub fn validate_bootable(cpuid: &CpuId) -> Result<(), BootabilityError> {
+    use crate::cpu_leaf::{leaf_0x1, leaf_0x7, leaf_0x80000001, leaf_0x80000008};
+
+    let checks = [
+        (leaf_0x1::LEAF_NUM, "FPU", leaf_0x1::edx::FPU_BITINDEX),
+        (leaf_0x1::LEAF_NUM, "SSE2", leaf_0x1::edx::SSE2_BITINDEX),
+        (
+            leaf_0x80000001::LEAF_NUM,
+            "long mode",
+            leaf_0x80000001::edx::LM_BITINDEX,
+        ),
+    ];
+
+    for (function, feature, bit_index) in checks {
+        let entry = cpuid
+            .get_entry(function, 0)
+            .ok_or(BootabilityError::MissingLeaf(function))?;
+        if !entry.edx.read_bit(bit_index) {
+            return Err(BootabilityError::MissingFeature(feature));
+        }
+    }
+
+    // A guest set up for 5-level paging faults as soon as it enables paging if the linear
+    // addresses are too narrow for it.
+    if let (Some(leaf_0x7), Some(leaf_0x80000008)) = (
+        cpuid.get_entry(leaf_0x7::LEAF_NUM, 0),
+        cpuid.get_entry(leaf_0x80000008::LEAF_NUM, 0),
+    ) {
+        use crate::cpu_leaf::leaf_0x80000008::eax::*;
+
+        let la57 = leaf_0x7.ecx.read_bit(leaf_0x7::index0::ecx::LA57);
+        let linear_address_size = leaf_0x80000008
+            .eax
+            .read_bits_in_range(&LINEAR_ADDRESS_SIZE_BITRANGE);
+        if la57 && linear_address_size < LINEAR_ADDRESS_SIZE_5_LEVEL {
+            return Err(BootabilityError::La57Mismatch(linear_address_size));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the deprecated or risky features which are exposed by `cpuid`.
+///
+/// The findings are only advisory, see `Lint`. This is meant for template authors, to spot
+/// the features they forgot to mask.
+pub fn lint_cpuid(cpuid: &CpuId) -> Vec<Lint> {
+    use crate::cpu_leaf::{leaf_0x1, leaf_0x14, leaf_0x7};
+
+    let leaf_0x1 = cpuid
+        .get_entry(leaf_0x1::LEAF_NUM, 0)
+        .copied()
+        .unwrap_or_default();
+    let leaf_0x7 = cpuid
+        .get_entry(leaf_0x7::LEAF_NUM, 0)
+        .copied()
+        .unwrap_or_default();
+
+    let deprecated = [
+        (
+            "PSN",
+            "the processor serial number was dropped after the Pentium III",
+            leaf_0x1.edx.read_bit(leaf_0x1::edx::PSN_BITINDEX),
+        ),
+        (
+            "MPX",
+            "its support was removed from Linux and from recent CPUs",
+            leaf_0x7.ebx.read_bit(leaf_0x7::index0::ebx::MPX_BITINDEX),
+        ),
+    ];
+    let mut lints: Vec<Lint> = deprecated
+        .iter()
+        .filter(|(_, _, enabled)| *enabled)
+        .map(|&(feature, reason, _)| Lint::DeprecatedFeature { feature, reason })
+        .collect();
+
+    // The guest drives Intel PT according to the capabilities of leaf 0x14, which KVM only
+    // reports when it can virtualize PT.
+    let pt_capabilities = matches!(
+        cpuid.get_entry(leaf_0x14::LEAF_NUM, 0),
+        Some(entry) if entry.ebx != 0 || entry.ecx != 0
+    );
+    if leaf_0x7.ebx.read_bit(leaf_0x7::index0::ebx::PT_BITINDEX) && !pt_capabilities {
+        lints.push(Lint::MissingCapabilities {
+            feature: "Intel PT",
+            function: leaf_0x14::LEAF_NUM,
+        });
+    }
+
+    lints
+}
+
 /// Replaces the `cpuid` entries corresponding to `function` with the entries from the host's cpuid.
 pub fn use_host_cpuid_function(
     cpuid: &mut CpuId,
@@ -141,6 +717,118 @@ mod tests {
     use crate::common::tests::get_topoext_fn;
     use crate::transformer::VmSpec;
 
+    fn check_update_brand_string_entry(transformer_fn: EntryTransformerFn, leaf: u32) {
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf,
+            eax: u32::MAX,
+            ebx: u32::MAX,
+            ecx: u32::MAX,
+            edx: u32::MAX,
+            ..Default::default()
+        };
+
+        assert!(transformer_fn(&mut entry, &vm_spec).is_ok());
+
+        let brand_string = &vm_spec.brand_string;
+        assert_eq!(entry.eax, brand_string.get_reg_for_leaf(leaf, BsReg::Eax));
+        assert_eq!(entry.ebx, brand_string.get_reg_for_leaf(leaf, BsReg::Ebx));
+        assert_eq!(entry.ecx, brand_string.get_reg_for_leaf(leaf, BsReg::Ecx));
+        assert_eq!(entry.edx, brand_string.get_reg_for_leaf(leaf, BsReg::Edx));
+    }
+
+    #[test]
+    fn test_update_brand_string_entry_0x80000002() {
+        check_update_brand_string_entry(update_brand_string_entry_0x80000002, 0x8000_0002);
+
+        // The first leaf starts with the brand string itself.
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2::default();
+        assert!(update_brand_string_entry_0x80000002(&mut entry, &vm_spec).is_ok());
+        let expected_prefix: &[u8] = match vm_spec.cpu_vendor_id() {
+            crate::common::VENDOR_ID_INTEL => b"Inte",
+            crate::common::VENDOR_ID_AMD => b"AMD ",
+            _ => b"\0\0\0\0",
+        };
+        assert_eq!(entry.eax.to_le_bytes(), expected_prefix);
+    }
+
+    #[test]
+    fn test_update_brand_string_entry_0x80000003() {
+        check_update_brand_string_entry(update_brand_string_entry_0x80000003, 0x8000_0003);
+    }
+
+    #[test]
+    fn test_update_brand_string_entry_0x80000004() {
+        check_update_brand_string_entry(update_brand_string_entry_0x80000004, 0x8000_0004);
+    }
+
+    #[test]
+    fn test_processor_serial_number() {
+        use crate::cpu_leaf::leaf_0x1::edx::PSN_BITINDEX;
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x3};
+
+        let vm_spec = VmSpec::default();
+        let mut leaf_0x1 = kvm_cpuid_entry2 {
+            function: leaf_0x1::LEAF_NUM,
+            edx: 1 << PSN_BITINDEX,
+            ..Default::default()
+        };
+        assert!(update_feature_info_entry(&mut leaf_0x1, &vm_spec).is_ok());
+        assert!(!leaf_0x1.edx.read_bit(PSN_BITINDEX));
+
+        let mut leaf_0x3 = kvm_cpuid_entry2 {
+            function: leaf_0x3::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0,
+            ebx: 0,
+            ecx: 0x1234_5678,
+            edx: 0x9abc_def0,
+            padding: [0, 0, 0],
+        };
+        assert!(update_serial_number_entry(&mut leaf_0x3, &vm_spec).is_ok());
+        assert_eq!(
+            leaf_0x3,
+            kvm_cpuid_entry2 {
+                function: leaf_0x3::LEAF_NUM,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_mask_cpuid_entry() {
+        let mut entry = kvm_cpuid_entry2 {
+            function: 0x1,
+            index: 0,
+            flags: 0,
+            eax: 0xffff_ffff,
+            ebx: 0x0000_0000,
+            ecx: 0x0f0f_0f0f,
+            edx: 0x1234_5678,
+            padding: [0, 0, 0],
+        };
+
+        mask_cpuid_entry(
+            &mut entry,
+            0x0000_ffff,
+            0,
+            0,
+            0x8000_0001,
+            0xffff_0000,
+            0x0000_00f0,
+            u32::MAX,
+            0,
+        );
+
+        assert_eq!(entry.eax, 0x0000_ffff);
+        assert_eq!(entry.ebx, 0x8000_0001);
+        assert_eq!(entry.ecx, 0x0f0f_00f0);
+        assert_eq!(entry.edx, 0x1234_5678);
+        assert_eq!(entry.function, 0x1);
+    }
+
     #[test]
     fn test_get_max_cpus_per_package() {
         assert_eq!(get_max_cpus_per_package(1).unwrap(), 1);
@@ -172,6 +860,40 @@ mod tests {
         assert!(entry.ecx.read_bit(ecx::TSC_DEADLINE_TIMER_BITINDEX));
     }
 
+    #[test]
+    fn test_update_feature_info_entry_cpu_count() {
+        use crate::cpu_leaf::leaf_0x1::*;
+
+        // (cpu_count, expected maximum number of addressable logical processor IDs)
+        let cases = [
+            (1, 1),
+            (2, 2),
+            (3, 4),
+            (4, 4),
+            (5, 8),
+            (8, 8),
+            (12, 16),
+            (32, 32),
+        ];
+        for (cpu_count, expected) in cases {
+            let vm_spec = VmSpec::new(0, cpu_count, true).unwrap();
+            // The host value must not leak into the guest.
+            let mut entry = kvm_cpuid_entry2 {
+                function: LEAF_NUM,
+                ebx: *0.write_bits_in_range(&ebx::CPU_COUNT_BITRANGE, 0xff),
+                ..Default::default()
+            };
+
+            update_feature_info_entry(&mut entry, &vm_spec).unwrap();
+            assert_eq!(
+                entry.ebx.read_bits_in_range(&ebx::CPU_COUNT_BITRANGE),
+                expected,
+                "cpu_count: {}",
+                cpu_count
+            );
+        }
+    }
+
     fn check_update_cache_parameters_entry(
         cpu_count: u8,
         smt: bool,
@@ -254,6 +976,747 @@ mod tests {
         check_update_cache_parameters_entry(2, true, 3, 1);
     }
 
+    #[test]
+    fn test_assert_only_topology_differs() {
+        use kvm_bindings::KVM_MAX_CPUID_ENTRIES;
+        use kvm_ioctls::Kvm;
+
+        use crate::bit_helper::BitRangeExt;
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x7};
+
+        let kvm = Kvm::new().unwrap();
+        let base = kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES).unwrap();
+        let processed = |cpu_index: u8| {
+            let mut cpuid = base.clone();
+            crate::filter_cpuid(&mut cpuid, &VmSpec::new(cpu_index, 4, true).unwrap()).unwrap();
+            cpuid
+        };
+        let cpuid_0 = processed(0);
+        let mut cpuid_3 = processed(3);
+
+        assert_eq!(assert_only_topology_differs(&cpuid_0, &cpuid_0), Ok(()));
+        assert_eq!(assert_only_topology_differs(&cpuid_0, &cpuid_3), Ok(()));
+        // The APIC ID is allowed to differ, but not the rest of the register.
+        let leaf_1 = cpuid_3.get_entry_mut(leaf_0x1::LEAF_NUM, 0).unwrap();
+        assert_ne!(
+            leaf_1
+                .ebx
+                .read_bits_in_range(&leaf_0x1::ebx::APICID_BITRANGE),
+            0
+        );
+        leaf_1
+            .ebx
+            .write_bits_in_range(&leaf_0x1::ebx::CLFLUSH_SIZE_BITRANGE, 0);
+        let expected = cpuid_0.get_entry(leaf_0x1::LEAF_NUM, 0).unwrap().ebx
+            & !leaf_0x1::ebx::APICID_BITRANGE.get_mask();
+
+        cpuid_3.get_entry_mut(leaf_0x7::LEAF_NUM, 0).unwrap().ebx ^= 1;
+        cpuid_3.retain(|entry| entry.function != 0x8000_0002);
+        cpuid_3
+            .push(kvm_cpuid_entry2 {
+                function: 0x4fff_ffff,
+                ..Default::default()
+            })
+            .unwrap();
+
+        let violations = assert_only_topology_differs(&cpuid_0, &cpuid_3).unwrap_err();
+        assert_eq!(violations.len(), 4);
+        assert!(violations.contains(&CpuidViolation::FieldMismatch {
+            function: leaf_0x1::LEAF_NUM,
+            index: 0,
+            register: "ebx",
+            expected,
+            actual: expected & !leaf_0x1::ebx::CLFLUSH_SIZE_BITRANGE.get_mask(),
+        }));
+        assert!(violations.iter().any(|violation| matches!(
+            violation,
+            CpuidViolation::FieldMismatch {
+                function: leaf_0x7::LEAF_NUM,
+                register: "ebx",
+                ..
+            }
+        )));
+        assert!(violations.contains(&CpuidViolation::MissingLeaf {
+            function: 0x8000_0002,
+            index: 0,
+        }));
+        assert!(violations.contains(&CpuidViolation::UnexpectedLeaf {
+            function: 0x4fff_ffff,
+            index: 0,
+        }));
+    }
+
+    fn xsave_state_cpuid() -> CpuId {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0xd};
+
+        let entry = |index: u32, eax: u32, ecx: u32| kvm_cpuid_entry2 {
+            function: leaf_0xd::LEAF_NUM,
+            index,
+            eax,
+            ecx,
+            ..Default::default()
+        };
+
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                ecx: 1 << leaf_0x1::ecx::XSAVE_BITINDEX,
+                ..Default::default()
+            },
+            // x87, SSE and AVX user state components.
+            entry(0, 0b111, 0),
+            // XSAVEC and XSAVES with the PT and CET_U supervisor state components.
+            entry(
+                1,
+                (1 << leaf_0xd::index1::eax::XSAVEC_SHIFT)
+                    | (1 << leaf_0xd::index1::eax::XSAVES_SHIFT),
+                (1 << 8) | (1 << 11),
+            ),
+            // AVX
+            entry(2, 256, 0),
+            // PT
+            entry(8, 128, 1),
+            // CET_U, 64-byte aligned
+            entry(11, 16, 0b11),
+        ])
+        .unwrap()
+    }
+
+    fn xsave_subleaf1(cpuid: &CpuId) -> kvm_cpuid_entry2 {
+        *cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 0xd && entry.index == 1)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_update_xsave_supervisor_state_entries() {
+        use crate::cpu_leaf::leaf_0xd::index1::eax::XSAVES_SHIFT;
+
+        let mut cpuid = xsave_state_cpuid();
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        let subleaf1 = xsave_subleaf1(&cpuid);
+        assert!(subleaf1.eax.read_bit(XSAVES_SHIFT));
+        assert_eq!(subleaf1.ecx, (1 << 8) | (1 << 11));
+        // 576 + AVX (256) + PT (128) + CET_U (16, already aligned)
+        assert_eq!(subleaf1.ebx, 976);
+
+        // Mask the PT state component.
+        for entry in cpuid.as_mut_slice() {
+            if entry.function == 0xd && entry.index == 8 {
+                entry.eax = 0;
+                entry.ecx = 0;
+            }
+        }
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        let subleaf1 = xsave_subleaf1(&cpuid);
+        assert!(subleaf1.eax.read_bit(XSAVES_SHIFT));
+        assert_eq!(subleaf1.ecx, 1 << 11);
+        assert_eq!(subleaf1.edx, 0);
+        // 576 + AVX (256) + CET_U (16, already aligned)
+        assert_eq!(subleaf1.ebx, 848);
+    }
+
+    #[test]
+    fn test_update_xsave_supervisor_state_entries_masked_features() {
+        use crate::cpu_leaf::leaf_0x7;
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let xsave_subleaf = |cpuid: &CpuId, index: u32| {
+            *cpuid
+                .as_slice()
+                .iter()
+                .find(|entry| entry.function == 0xd && entry.index == index)
+                .unwrap()
+        };
+        let mut cpuid = xsave_state_cpuid();
+        let mut leaf_0x7 = kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            ..Default::default()
+        };
+        leaf_0x7.ebx.write_bit(ebx::PT_BITINDEX, true);
+        leaf_0x7.edx.write_bit(edx::CET_IBT_BITINDEX, true);
+        cpuid.push(leaf_0x7).unwrap();
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        assert_eq!(xsave_subleaf1(&cpuid).ecx, (1 << 8) | (1 << 11));
+        assert_eq!(xsave_subleaf1(&cpuid).ebx, 976);
+
+        // Masking PT removes its state component.
+        let leaf_0x7 = cpuid.get_entry_mut(leaf_0x7::LEAF_NUM, 0).unwrap();
+        leaf_0x7.ebx.write_bit(ebx::PT_BITINDEX, false);
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        assert_eq!(xsave_subleaf(&cpuid, 8).eax, 0);
+        assert_eq!(xsave_subleaf(&cpuid, 8).ecx, 0);
+        assert_eq!(xsave_subleaf1(&cpuid).ecx, 1 << 11);
+        // 576 + AVX (256) + CET_U (16, already aligned)
+        assert_eq!(xsave_subleaf1(&cpuid).ebx, 848);
+
+        // The CET state components stay as long as one of the CET features is exposed.
+        let leaf_0x7 = cpuid.get_entry_mut(leaf_0x7::LEAF_NUM, 0).unwrap();
+        leaf_0x7.edx.write_bit(edx::CET_IBT_BITINDEX, false);
+        leaf_0x7.ecx.write_bit(ecx::CET_SS_BITINDEX, true);
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        assert_eq!(xsave_subleaf1(&cpuid).ecx, 1 << 11);
+
+        let leaf_0x7 = cpuid.get_entry_mut(leaf_0x7::LEAF_NUM, 0).unwrap();
+        leaf_0x7.ecx.write_bit(ecx::CET_SS_BITINDEX, false);
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        assert_eq!(xsave_subleaf(&cpuid, 11).eax, 0);
+        assert_eq!(xsave_subleaf1(&cpuid).ecx, 0);
+        // 576 + AVX (256)
+        assert_eq!(xsave_subleaf1(&cpuid).ebx, 832);
+    }
+
+    #[test]
+    fn test_update_xsave_supervisor_state_entries_no_xsave() {
+        use crate::cpu_leaf::leaf_0x1;
+        use crate::cpu_leaf::leaf_0xd::index1::eax::{XSAVEC_SHIFT, XSAVES_SHIFT};
+
+        // Masking XSAVE also masks XSAVES and all the supervisor state components.
+        let mut cpuid = xsave_state_cpuid();
+        cpuid.as_mut_slice()[0]
+            .ecx
+            .write_bit(leaf_0x1::ecx::XSAVE_BITINDEX, false);
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        let subleaf1 = xsave_subleaf1(&cpuid);
+        assert!(!subleaf1.eax.read_bit(XSAVES_SHIFT));
+        assert!(subleaf1.eax.read_bit(XSAVEC_SHIFT));
+        assert_eq!(subleaf1.ecx, 0);
+        assert_eq!(subleaf1.edx, 0);
+        // 576 + AVX (256)
+        assert_eq!(subleaf1.ebx, 832);
+    }
+
+    #[test]
+    fn test_update_xsave_supervisor_state_entries_alignment() {
+        // The CET_U component is 64-byte aligned in the compacted format.
+        let mut cpuid = xsave_state_cpuid();
+        for entry in cpuid.as_mut_slice() {
+            if entry.function == 0xd && entry.index == 2 {
+                entry.eax = 260;
+            }
+        }
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        // 576 + AVX (260) + PT (128) = 964, aligned to 1024 + CET_U (16)
+        assert_eq!(xsave_subleaf1(&cpuid).ebx, 1040);
+
+        // Nothing to do if there's no sub-leaf 1.
+        let mut cpuid = CpuId::new(1).unwrap();
+        assert!(update_xsave_supervisor_state_entries(&mut cpuid).is_ok());
+        assert_eq!(cpuid.as_slice()[0], kvm_cpuid_entry2::default());
+    }
+
+    #[test]
+    fn test_validate_bootable() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x80000001};
+
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                edx: (1 << leaf_0x1::edx::FPU_BITINDEX) | (1 << leaf_0x1::edx::SSE2_BITINDEX),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                edx: 1 << leaf_0x80000001::edx::LM_BITINDEX,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        assert_eq!(validate_bootable(&cpuid), Ok(()));
+
+        // A template clearing long mode.
+        cpuid.as_mut_slice()[1]
+            .edx
+            .write_bit(leaf_0x80000001::edx::LM_BITINDEX, false);
+        assert_eq!(
+            validate_bootable(&cpuid),
+            Err(BootabilityError::MissingFeature("long mode"))
+        );
+
+        cpuid.as_mut_slice()[0]
+            .edx
+            .write_bit(leaf_0x1::edx::SSE2_BITINDEX, false);
+        assert_eq!(
+            validate_bootable(&cpuid),
+            Err(BootabilityError::MissingFeature("SSE2"))
+        );
+
+        cpuid.retain(|entry| entry.function != leaf_0x1::LEAF_NUM);
+        assert_eq!(
+            validate_bootable(&cpuid),
+            Err(BootabilityError::MissingLeaf(leaf_0x1::LEAF_NUM))
+        );
+    }
+
+    #[test]
+    fn test_validate_bootable_la57() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x7, leaf_0x80000001, leaf_0x80000008};
+        use crate::template::intel::t2::T2CpuidTransformer;
+        use crate::transformer::CpuidTransformer;
+
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                edx: (1 << leaf_0x1::edx::FPU_BITINDEX) | (1 << leaf_0x1::edx::SSE2_BITINDEX),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                edx: 1 << leaf_0x80000001::edx::LM_BITINDEX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000008::LEAF_NUM,
+                eax: 0x302e,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        assert_eq!(validate_bootable(&cpuid), Ok(()));
+        cpuid.set_la57(true);
+        assert_eq!(validate_bootable(&cpuid), Ok(()));
+
+        // The T2 template clears LA57 on an LA57 host without touching the linear address
+        // width, the guest then uses 4-level paging.
+        let mut t2_cpuid = cpuid.clone();
+        T2CpuidTransformer {}
+            .process_cpuid(&mut t2_cpuid, &VmSpec::new(0, 1, false).unwrap())
+            .unwrap();
+        assert!(!t2_cpuid
+            .get_entry(leaf_0x7::LEAF_NUM, 0)
+            .unwrap()
+            .ecx
+            .read_bit(leaf_0x7::index0::ecx::LA57));
+        assert_eq!(validate_bootable(&t2_cpuid), Ok(()));
+
+        cpuid.set_la57(false);
+        cpuid
+            .get_entry_mut(leaf_0x7::LEAF_NUM, 0)
+            .unwrap()
+            .ecx
+            .write_bit(leaf_0x7::index0::ecx::LA57, true);
+        assert_eq!(
+            validate_bootable(&cpuid),
+            Err(BootabilityError::La57Mismatch(48))
+        );
+
+        // The check is skipped without the linear address width.
+        cpuid.retain(|entry| entry.function != leaf_0x80000008::LEAF_NUM);
+        assert_eq!(validate_bootable(&cpuid), Ok(()));
+    }
+
+    #[test]
+    fn test_lint_cpuid() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x14, leaf_0x7};
+
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                edx: 1 << leaf_0x1::edx::FPU_BITINDEX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                ebx: 1 << leaf_0x7::index0::ebx::MPX_BITINDEX,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        assert_eq!(
+            lint_cpuid(&cpuid),
+            vec![Lint::DeprecatedFeature {
+                feature: "MPX",
+                reason: "its support was removed from Linux and from recent CPUs",
+            }]
+        );
+
+        cpuid.as_mut_slice()[0]
+            .edx
+            .write_bit(leaf_0x1::edx::PSN_BITINDEX, true);
+        cpuid.as_mut_slice()[1]
+            .ebx
+            .write_bit(leaf_0x7::index0::ebx::MPX_BITINDEX, false)
+            .write_bit(leaf_0x7::index0::ebx::PT_BITINDEX, true);
+        let lints = lint_cpuid(&cpuid);
+        assert_eq!(lints.len(), 2);
+        assert!(matches!(
+            lints[0],
+            Lint::DeprecatedFeature { feature: "PSN", .. }
+        ));
+        assert_eq!(
+            lints[1],
+            Lint::MissingCapabilities {
+                feature: "Intel PT",
+                function: leaf_0x14::LEAF_NUM,
+            }
+        );
+
+        // Intel PT is fine once its capabilities are enumerated.
+        cpuid.as_mut_slice()[0]
+            .edx
+            .write_bit(leaf_0x1::edx::PSN_BITINDEX, false);
+        cpuid
+            .push(kvm_cpuid_entry2 {
+                function: leaf_0x14::LEAF_NUM,
+                ebx: 0b1,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(lint_cpuid(&cpuid).is_empty());
+    }
+
+    #[test]
+    fn test_validate_xsave_consistency() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x7};
+
+        // x87, SSE, AVX, the AVX-512 and the PKRU state components.
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                ecx: (1 << leaf_0x1::ecx::XSAVE_BITINDEX) | (1 << leaf_0x1::ecx::AVX_BITINDEX),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x7::LEAF_NUM,
+                ebx: 1 << leaf_0x7::index0::ebx::AVX512F_BITINDEX,
+                ecx: 1 << leaf_0x7::index0::ecx::PKU_BITINDEX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0xd,
+                eax: 0x2e7,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        assert_eq!(validate_xsave_consistency(&cpuid), Ok(()));
+
+        // The AVX state is still enabled once AVX is hidden.
+        cpuid.as_mut_slice()[0]
+            .ecx
+            .write_bit(leaf_0x1::ecx::AVX_BITINDEX, false);
+        assert_eq!(
+            validate_xsave_consistency(&cpuid),
+            Err(XsaveError::ComponentsWithoutFeature {
+                components: 1 << 2,
+                feature: "AVX",
+            })
+        );
+
+        // Same for the AVX-512 state, once the AVX state is consistent again.
+        cpuid.as_mut_slice()[2].eax = 0x2e3;
+        cpuid.as_mut_slice()[1].ebx = 0;
+        assert_eq!(
+            validate_xsave_consistency(&cpuid),
+            Err(XsaveError::ComponentsWithoutFeature {
+                components: 0xe0,
+                feature: "AVX-512",
+            })
+        );
+
+        // Nothing to check without leaf 0xD.
+        let cpuid = CpuId::new(1).unwrap();
+        assert_eq!(validate_xsave_consistency(&cpuid), Ok(()));
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_enqcmd() {
+        use crate::cpu_leaf::leaf_0x7::index0::ecx::ENQCMD_BITINDEX;
+        use crate::cpu_leaf::leaf_0xd::index1::ecx::PASID_STATE_BITINDEX;
+
+        let mut vm_spec = VmSpec::default();
+        let mut leaf_0x7 = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            ecx: 1 << ENQCMD_BITINDEX,
+            ..Default::default()
+        };
+        let mut leaf_0xd = kvm_cpuid_entry2 {
+            function: 0xd,
+            index: 1,
+            ecx: 1 << PASID_STATE_BITINDEX,
+            ..Default::default()
+        };
+
+        vm_spec.set_enqcmd(true);
+        update_optional_features_entry(&mut leaf_0x7, &vm_spec).unwrap();
+        update_optional_features_entry(&mut leaf_0xd, &vm_spec).unwrap();
+        assert!(leaf_0x7.ecx.read_bit(ENQCMD_BITINDEX));
+        assert!(leaf_0xd.ecx.read_bit(PASID_STATE_BITINDEX));
+
+        vm_spec.set_enqcmd(false);
+        update_optional_features_entry(&mut leaf_0x7, &vm_spec).unwrap();
+        update_optional_features_entry(&mut leaf_0xd, &vm_spec).unwrap();
+        assert!(!leaf_0x7.ecx.read_bit(ENQCMD_BITINDEX));
+        assert!(!leaf_0xd.ecx.read_bit(PASID_STATE_BITINDEX));
+
+        // Other subleaves are left untouched.
+        let mut leaf_0x7_1 = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 1,
+            ecx: 1 << ENQCMD_BITINDEX,
+            ..Default::default()
+        };
+        update_optional_features_entry(&mut leaf_0x7_1, &vm_spec).unwrap();
+        assert!(leaf_0x7_1.ecx.read_bit(ENQCMD_BITINDEX));
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_pconfig() {
+        use crate::cpu_leaf::leaf_0x7::index0::edx::PCONFIG_BITINDEX;
+
+        let mut vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            edx: 1 << PCONFIG_BITINDEX,
+            ..Default::default()
+        };
+
+        vm_spec.set_pconfig(true);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert!(entry.edx.read_bit(PCONFIG_BITINDEX));
+
+        vm_spec.set_pconfig(false);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert!(!entry.edx.read_bit(PCONFIG_BITINDEX));
+
+        // Hidden by default.
+        let mut entry = kvm_cpuid_entry2 {
+            edx: 1 << PCONFIG_BITINDEX,
+            ..entry
+        };
+        update_optional_features_entry(&mut entry, &VmSpec::default()).unwrap();
+        assert!(!entry.edx.read_bit(PCONFIG_BITINDEX));
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_amx() {
+        use crate::cpu_leaf::leaf_0x7::index0::edx::*;
+        use crate::cpu_leaf::{leaf_0x1d, leaf_0xd};
+
+        let amx_bits =
+            (1 << AMX_BF16_BITINDEX) | (1 << AMX_TILE_BITINDEX) | (1 << AMX_INT8_BITINDEX);
+        let entry =
+            |function: u32, index: u32, eax: u32, ebx: u32, ecx: u32, edx: u32| kvm_cpuid_entry2 {
+                function,
+                index,
+                eax,
+                ebx,
+                ecx,
+                edx,
+                ..Default::default()
+            };
+        // The AMX leaves of a Sapphire Rapids host, with the x87, SSE, AVX, XTILECFG and
+        // XTILEDATA user state components.
+        let amx_cpuid = CpuId::from_entries(&[
+            entry(0x7, 0, 0, 0, 0, amx_bits | (1 << PCONFIG_BITINDEX)),
+            entry(leaf_0xd::LEAF_NUM, 0, 0x6_0007, 0, 11008, 0),
+            entry(leaf_0xd::LEAF_NUM, 2, 256, 576, 0, 0),
+            entry(leaf_0xd::LEAF_NUM, 17, 64, 2752, 0, 0),
+            entry(leaf_0xd::LEAF_NUM, 18, 8192, 2816, 6, 0),
+            entry(leaf_0x1d::LEAF_NUM, 0, 1, 0, 0, 0),
+            entry(leaf_0x1d::LEAF_NUM, 1, 0x400_2000, 0x8_0040, 16, 0),
+            entry(0x1e, 0, 0, 0x4_0010, 0, 0),
+        ])
+        .unwrap();
+        let process = |vm_spec: &VmSpec| {
+            let mut cpuid = amx_cpuid.clone();
+            for entry in cpuid.as_mut_slice() {
+                update_optional_features_entry(entry, vm_spec).unwrap();
+            }
+            update_xsave_user_state_entries(&mut cpuid).unwrap();
+            cpuid
+        };
+
+        let vm_spec = VmSpec::builder().amx(true).pconfig(true).build().unwrap();
+        assert_eq!(process(&vm_spec).as_slice(), amx_cpuid.as_slice());
+
+        // Disabled by default, which hides all the AMX leaves and bits together.
+        let cpuid = process(&VmSpec::builder().pconfig(true).build().unwrap());
+        let entries = cpuid.as_slice();
+        assert_eq!(entries[0].edx, 1 << PCONFIG_BITINDEX);
+        // Only the x87, SSE and AVX state components are left.
+        assert_eq!(entries[1].eax, 0b111);
+        assert_eq!(entries[1].ecx, 576 + 256);
+        assert_eq!(entries[2], amx_cpuid.as_slice()[2]);
+        for entry in &entries[3..] {
+            assert_eq!(
+                (entry.eax, entry.ebx, entry.ecx, entry.edx),
+                (0, 0, 0, 0),
+                "leaf 0x{:x}, subleaf {} is not masked",
+                entry.function,
+                entry.index
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_mpx() {
+        use crate::cpu_leaf::leaf_0x7::index0::ebx::*;
+        use crate::cpu_leaf::leaf_0xd;
+
+        let entry = |function: u32, index: u32, eax: u32, ebx: u32, ecx: u32| kvm_cpuid_entry2 {
+            function,
+            index,
+            eax,
+            ebx,
+            ecx,
+            ..Default::default()
+        };
+        // The MPX leaves of a Skylake host, with the x87, SSE, AVX, BNDREGS and BNDCSR user
+        // state components.
+        let mpx_cpuid = CpuId::from_entries(&[
+            entry(0x7, 0, 0, (1 << MPX_BITINDEX) | (1 << AVX2_BITINDEX), 0),
+            entry(leaf_0xd::LEAF_NUM, 0, 0x1f, 0, 1088),
+            entry(leaf_0xd::LEAF_NUM, 2, 256, 576, 0),
+            entry(leaf_0xd::LEAF_NUM, 3, 64, 960, 0),
+            entry(leaf_0xd::LEAF_NUM, 4, 64, 1024, 0),
+        ])
+        .unwrap();
+        let process = |vm_spec: &VmSpec| {
+            let mut cpuid = mpx_cpuid.clone();
+            for entry in cpuid.as_mut_slice() {
+                update_optional_features_entry(entry, vm_spec).unwrap();
+            }
+            update_xsave_user_state_entries(&mut cpuid).unwrap();
+            cpuid
+        };
+
+        let vm_spec = VmSpec::builder().mpx(true).build().unwrap();
+        assert_eq!(process(&vm_spec).as_slice(), mpx_cpuid.as_slice());
+
+        // Disabled by default, which hides the feature bit and its state components together.
+        let cpuid = process(&VmSpec::default());
+        let entries = cpuid.as_slice();
+        assert_eq!(entries[0].ebx, 1 << AVX2_BITINDEX);
+        // Only the x87, SSE and AVX state components are left.
+        assert_eq!(entries[1].eax, 0b111);
+        assert_eq!(entries[1].ecx, 576 + 256);
+        assert_eq!(entries[2], mpx_cpuid.as_slice()[2]);
+        for entry in &entries[3..] {
+            assert_eq!(
+                (entry.eax, entry.ebx, entry.ecx, entry.edx),
+                (0, 0, 0, 0),
+                "leaf 0x{:x}, subleaf {} is not masked",
+                entry.function,
+                entry.index
+            );
+        }
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_fsgsbase() {
+        use crate::cpu_leaf::leaf_0x7::index0::ebx::*;
+
+        let host_entry = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            ebx: (1 << FSGSBASE_BITINDEX) | (1 << AVX2_BITINDEX),
+            ..Default::default()
+        };
+
+        // The host value is preserved by default.
+        let mut vm_spec = VmSpec::default();
+        let mut entry = host_entry;
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert_eq!(entry, host_entry);
+
+        vm_spec.set_fsgsbase(false);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert_eq!(entry.ebx, 1 << AVX2_BITINDEX);
+
+        // Enabling it doesn't expose it on a host which doesn't support it.
+        vm_spec.set_fsgsbase(true);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert_eq!(entry.ebx, 1 << AVX2_BITINDEX);
+    }
+
+    #[test]
+    fn test_update_optional_features_entry_rdpid() {
+        use crate::cpu_leaf::leaf_0x7::index0::ecx::RDPID_BITINDEX;
+
+        let mut vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            ecx: 1 << RDPID_BITINDEX,
+            ..Default::default()
+        };
+
+        // RDPID is only hidden when explicitly requested.
+        vm_spec.set_paranoid_timing(ParanoidTiming::Rdtscp);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert!(entry.ecx.read_bit(RDPID_BITINDEX));
+
+        vm_spec.set_paranoid_timing(ParanoidTiming::RdtscpAndRdpid);
+        update_optional_features_entry(&mut entry, &vm_spec).unwrap();
+        assert!(!entry.ecx.read_bit(RDPID_BITINDEX));
+    }
+
+    #[test]
+    fn test_verify_cpuid() {
+        use crate::cpu_leaf::leaf_0x1::*;
+
+        let vm_spec = VmSpec::new(1, 2, false).expect("Error creating vm_spec");
+        let mut violations = Vec::new();
+
+        let mut cpuid = CpuId::new(0).unwrap();
+        verify_cpuid(&cpuid, &vm_spec, &mut violations);
+        assert_eq!(
+            violations,
+            vec![CpuidViolation::MissingLeaf {
+                function: LEAF_NUM,
+                index: 0
+            }]
+        );
+
+        cpuid
+            .push(kvm_cpuid_entry2 {
+                function: LEAF_NUM,
+                ..Default::default()
+            })
+            .unwrap();
+        violations.clear();
+        verify_cpuid(&cpuid, &vm_spec, &mut violations);
+        assert_eq!(violations.len(), 3);
+        assert!(violations.contains(&CpuidViolation::FeatureBit {
+            function: LEAF_NUM,
+            index: 0,
+            register: "edx",
+            bit: edx::APIC_BITINDEX,
+            expected: true,
+        }));
+        assert!(violations.contains(&CpuidViolation::FeatureBit {
+            function: LEAF_NUM,
+            index: 0,
+            register: "ecx",
+            bit: ecx::HYPERVISOR_BITINDEX,
+            expected: true,
+        }));
+        assert!(violations.contains(&CpuidViolation::FieldMismatch {
+            function: LEAF_NUM,
+            index: 0,
+            register: "ebx",
+            expected: 1,
+            actual: 0,
+        }));
+
+        // Processing the leaf fixes the hypervisor bit and the APIC ID.
+        let entry = cpuid.get_entry_mut(LEAF_NUM, 0).unwrap();
+        entry.edx.write_bit(edx::APIC_BITINDEX, true);
+        update_feature_info_entry(entry, &vm_spec).unwrap();
+        violations.clear();
+        verify_cpuid(&cpuid, &vm_spec, &mut violations);
+        assert!(violations.is_empty());
+    }
+
     #[test]
     #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
     fn test_use_host_cpuid_function_with_count() {
@@ -0,0 +1,192 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+
+use super::*;
+
+/// The base of the hypervisor leaves, reporting the highest hypervisor leaf in EAX.
+const HYPERVISOR_BASE_LEAF: u32 = 0x4000_0000;
+/// The hypervisor leaf carrying the topology of the current vCPU. It is not used by KVM, so
+/// the guests only read it when looking for it explicitly.
+pub const DEBUG_TOPOLOGY_LEAF: u32 = 0x4000_0010;
+
+/// Cpuid transformer wrapper that exposes the index of the current vCPU and the number of
+/// vCPUs of the VM in `DEBUG_TOPOLOGY_LEAF`, in EAX and EBX respectively.
+///
+/// This is meant for debugging the topology seen by a guest, which otherwise can't tell which
+/// vCPU of the VM it runs on, so the leaf is only added when `enabled` is set.
+pub struct DebugTopologyTransformer<T: CpuidTransformer> {
+    /// The transformer doing the actual work.
+    pub inner: T,
+    /// Whether the topology leaf is added to the cpuid.
+    pub enabled: bool,
+}
+
+impl<T: CpuidTransformer> DebugTopologyTransformer<T> {
+    fn add_topology_leaf(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        cpuid.retain(|entry| entry.function != DEBUG_TOPOLOGY_LEAF);
+        cpuid
+            .push(kvm_cpuid_entry2 {
+                function: DEBUG_TOPOLOGY_LEAF,
+                eax: u32::from(vm_spec.cpu_index),
+                ebx: u32::from(vm_spec.cpu_count),
+                ..Default::default()
+            })
+            .map_err(Error::Fam)?;
+
+        // KVM answers the leaves above the highest hypervisor leaf with the highest basic
+        // leaf, so the new leaf must be in range.
+        for entry in cpuid.as_mut_slice() {
+            if entry.function == HYPERVISOR_BASE_LEAF {
+                entry.eax = entry.eax.max(DEBUG_TOPOLOGY_LEAF);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: CpuidTransformer> CpuidTransformer for DebugTopologyTransformer<T> {
+    fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        self.inner.process_cpuid(cpuid, vm_spec)?;
+        self.add_topology_leaf(cpuid, vm_spec)
+    }
+
+    fn transformed(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<CpuId, Error> {
+        let mut transformed = self.inner.transformed(cpuid, vm_spec)?;
+        self.add_topology_leaf(&mut transformed, vm_spec)?;
+
+        Ok(transformed)
+    }
+
+    fn required_subleaves(&self) -> &[(u32, u32)] {
+        self.inner.required_subleaves()
+    }
+
+    fn check_required_subleaves(&self, cpuid: &CpuId) -> Result<(), Error> {
+        self.inner.check_required_subleaves(cpuid)
+    }
+
+    fn process_entries(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        self.inner.process_entries(cpuid, vm_spec)
+    }
+
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        self.inner.process_slice(entries, vm_spec)
+    }
+
+    fn verify(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+        self.inner.verify(cpuid, vm_spec)
+    }
+
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        self.inner.entry_transformer_fn(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockCpuidTransformer {}
+
+    impl CpuidTransformer for MockCpuidTransformer {
+        fn entry_transformer_fn(
+            &self,
+            _entry: &mut kvm_cpuid_entry2,
+        ) -> Option<EntryTransformerFn> {
+            None
+        }
+    }
+
+    fn debug_topology_entry(cpuid: &CpuId) -> Option<&kvm_cpuid_entry2> {
+        cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == DEBUG_TOPOLOGY_LEAF)
+    }
+
+    #[test]
+    fn test_debug_topology_transformer() {
+        let vm_spec = VmSpec::new(2, 4, false).unwrap();
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: HYPERVISOR_BASE_LEAF,
+            eax: 0x4000_0001,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        // Disabled, the cpuid is left untouched.
+        let mut transformer = DebugTopologyTransformer {
+            inner: MockCpuidTransformer {},
+            enabled: false,
+        };
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        assert!(debug_topology_entry(&cpuid).is_none());
+        assert_eq!(cpuid.as_slice()[0].eax, 0x4000_0001);
+
+        transformer.enabled = true;
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        let entry = debug_topology_entry(&cpuid).unwrap();
+        assert_eq!((entry.eax, entry.ebx), (2, 4));
+        assert_eq!(cpuid.as_slice()[0].eax, DEBUG_TOPOLOGY_LEAF);
+
+        // Processing again for another vCPU replaces the leaf.
+        let vm_spec = VmSpec::new(3, 4, false).unwrap();
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        assert_eq!(cpuid.as_slice().len(), 2);
+        let entry = debug_topology_entry(&cpuid).unwrap();
+        assert_eq!((entry.eax, entry.ebx), (3, 4));
+
+        // The copy gets the leaf as well, the input is left untouched.
+        let vm_spec = VmSpec::new(1, 4, false).unwrap();
+        let transformed = transformer.transformed(&cpuid, &vm_spec).unwrap();
+        let entry = debug_topology_entry(&transformed).unwrap();
+        assert_eq!((entry.eax, entry.ebx), (1, 4));
+        assert_eq!(debug_topology_entry(&cpuid).unwrap().eax, 3);
+    }
+
+    #[test]
+    fn test_debug_topology_transformer_verify() {
+        struct FailingCpuidTransformer {}
+
+        impl CpuidTransformer for FailingCpuidTransformer {
+            fn verify(&self, _cpuid: &CpuId, _vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+                Err(vec![CpuidViolation::MissingLeaf {
+                    function: 0x1,
+                    index: 0,
+                }])
+            }
+
+            fn entry_transformer_fn(
+                &self,
+                _entry: &mut kvm_cpuid_entry2,
+            ) -> Option<EntryTransformerFn> {
+                None
+            }
+        }
+
+        let vm_spec = VmSpec::new(0, 1, false).unwrap();
+        let cpuid = CpuId::new(0).unwrap();
+        let transformer = DebugTopologyTransformer {
+            inner: FailingCpuidTransformer {},
+            enabled: true,
+        };
+        assert_eq!(
+            transformer.verify(&cpuid, &vm_spec).unwrap_err(),
+            vec![CpuidViolation::MissingLeaf {
+                function: 0x1,
+                index: 0,
+            }]
+        );
+    }
+}
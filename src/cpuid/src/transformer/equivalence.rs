@@ -0,0 +1,249 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::CpuId;
+
+use super::record::CpuidRegister;
+use super::*;
+
+/// Cpuid transformer leaving the cpuid untouched.
+///
+/// Meant as the reference side of `transformers_equivalent`, e.g. to check that a transformer
+/// doesn't modify a table it isn't expected to touch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassthroughTransformer;
+
+impl CpuidTransformer for PassthroughTransformer {
+    fn process_cpuid(&self, _cpuid: &mut CpuId, _vm_spec: &VmSpec) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// A difference between the outputs of two transformers, as reported by
+/// `transformers_equivalent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuidDiff {
+    /// The entry is only in the output of the first transformer.
+    OnlyInFirst {
+        /// The leaf of the entry.
+        function: u32,
+        /// The subleaf of the entry.
+        index: u32,
+    },
+    /// The entry is only in the output of the second transformer.
+    OnlyInSecond {
+        /// The leaf of the entry.
+        function: u32,
+        /// The subleaf of the entry.
+        index: u32,
+    },
+    /// A register of the entry differs.
+    Register {
+        /// The leaf of the entry.
+        function: u32,
+        /// The subleaf of the entry.
+        index: u32,
+        /// The differing register.
+        reg: CpuidRegister,
+        /// The value of the register in the output of the first transformer.
+        first: u32,
+        /// The value of the register in the output of the second transformer.
+        second: u32,
+    },
+    /// The transformers didn't fail in the same way. The errors are `None` for the transformers
+    /// which succeeded.
+    Outcome {
+        /// The error of the first transformer.
+        first: Option<String>,
+        /// The error of the second transformer.
+        second: Option<String>,
+    },
+}
+
+fn cpuid_diffs(first: &CpuId, second: &CpuId) -> Vec<CpuidDiff> {
+    let mut diffs = Vec::new();
+
+    for entry in first.as_slice() {
+        let other = match second.get_entry(entry.function, entry.index) {
+            Some(other) => other,
+            None => {
+                diffs.push(CpuidDiff::OnlyInFirst {
+                    function: entry.function,
+                    index: entry.index,
+                });
+                continue;
+            }
+        };
+
+        let registers = [
+            (CpuidRegister::Eax, entry.eax, other.eax),
+            (CpuidRegister::Ebx, entry.ebx, other.ebx),
+            (CpuidRegister::Ecx, entry.ecx, other.ecx),
+            (CpuidRegister::Edx, entry.edx, other.edx),
+        ];
+        for (reg, first, second) in registers {
+            if first != second {
+                diffs.push(CpuidDiff::Register {
+                    function: entry.function,
+                    index: entry.index,
+                    reg,
+                    first,
+                    second,
+                });
+            }
+        }
+    }
+
+    for entry in second.as_slice() {
+        if first.get_entry(entry.function, entry.index).is_none() {
+            diffs.push(CpuidDiff::OnlyInSecond {
+                function: entry.function,
+                index: entry.index,
+            });
+        }
+    }
+
+    diffs
+}
+
+/// Checks that two transformers produce the same output when processing `base` for each of
+/// `specs`, e.g. to make sure that a refactored transformer behaves like the one it replaces.
+///
+/// The entries are compared by leaf and subleaf, so their order doesn't matter. Two
+/// transformers failing with the same error are equivalent. On failure, the differences are
+/// returned along with the index of the spec they were found with.
+pub fn transformers_equivalent(
+    a: &dyn CpuidTransformer,
+    b: &dyn CpuidTransformer,
+    base: &CpuId,
+    specs: &[VmSpec],
+) -> Result<(), Vec<(usize, Vec<CpuidDiff>)>> {
+    let mut differences = Vec::new();
+
+    for (i, vm_spec) in specs.iter().enumerate() {
+        let diffs = match (a.transformed(base, vm_spec), b.transformed(base, vm_spec)) {
+            (Ok(first), Ok(second)) => cpuid_diffs(&first, &second),
+            (Err(first), Err(second)) if first.to_string() == second.to_string() => Vec::new(),
+            (first, second) => vec![CpuidDiff::Outcome {
+                first: first.err().map(|err| err.to_string()),
+                second: second.err().map(|err| err.to_string()),
+            }],
+        };
+        if !diffs.is_empty() {
+            differences.push((i, diffs));
+        }
+    }
+
+    if differences.is_empty() {
+        Ok(())
+    } else {
+        Err(differences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_bindings::kvm_cpuid_entry2;
+
+    use super::*;
+    use crate::transformer::synthetic::UniformSteppingTransformer;
+
+    fn base_cpuid() -> CpuId {
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x0,
+                eax: 0xd,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                eax: 0x0005_0657,
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_transformers_equivalent() {
+        let specs = [
+            VmSpec::new(0, 1, false).unwrap(),
+            VmSpec::new(1, 2, true).unwrap(),
+        ];
+        assert_eq!(
+            transformers_equivalent(
+                &PassthroughTransformer,
+                &PassthroughTransformer,
+                &base_cpuid(),
+                &specs
+            ),
+            Ok(())
+        );
+
+        // The differences are reported for every spec.
+        let stepping = UniformSteppingTransformer { stepping: 0x4 };
+        let diff = CpuidDiff::Register {
+            function: 0x1,
+            index: 0,
+            reg: CpuidRegister::Eax,
+            first: 0x0005_0657,
+            second: 0x0005_0654,
+        };
+        assert_eq!(
+            transformers_equivalent(&PassthroughTransformer, &stepping, &base_cpuid(), &specs),
+            Err(vec![(0, vec![diff.clone()]), (1, vec![diff])])
+        );
+
+        // So are the failures of a single transformer.
+        let invalid = UniformSteppingTransformer { stepping: 0x10 };
+        let errors = transformers_equivalent(
+            &PassthroughTransformer,
+            &invalid,
+            &base_cpuid(),
+            &specs[..1],
+        )
+        .unwrap_err();
+        assert!(matches!(
+            errors[0].1[..],
+            [CpuidDiff::Outcome {
+                first: None,
+                second: Some(_)
+            }]
+        ));
+        assert_eq!(
+            transformers_equivalent(&invalid, &invalid, &base_cpuid(), &specs),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_cpuid_diffs() {
+        let first = base_cpuid();
+        let second = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                eax: 0x0005_0657,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            cpuid_diffs(&first, &second),
+            [
+                CpuidDiff::OnlyInFirst {
+                    function: 0x0,
+                    index: 0
+                },
+                CpuidDiff::OnlyInSecond {
+                    function: 0x7,
+                    index: 0
+                },
+            ]
+        );
+    }
+}
@@ -1,8 +1,10 @@
 // Copyright 2019 Amazon.com, Inc. or its affiliates. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+use kvm_bindings::KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
+
 use super::*;
-use crate::bit_helper::BitHelper;
+use crate::bit_helper::{BitHelper, BitRange, BitRangeExt};
 use crate::cpu_leaf::*;
 
 // The APIC ID shift in leaf 0xBh specifies the number of bits to shit the x2APIC ID to get a
@@ -16,11 +18,14 @@ fn update_deterministic_cache_entry(
     use crate::cpu_leaf::leaf_0x4::*;
 
     common::update_cache_parameters_entry(entry, vm_spec)?;
+    if vm_spec.cache_id_policy == CacheIdPolicy::PreserveHost {
+        return Ok(());
+    }
 
-    // Put all the cores in the same socket
+    // Spread the cores evenly across the sockets
     entry.eax.write_bits_in_range(
         &eax::MAX_CORES_PER_PACKAGE_BITRANGE,
-        u32::from(vm_spec.cpu_count / vm_spec.cpus_per_core()) - 1,
+        u32::from(vm_spec.cpus_per_socket() / vm_spec.cpus_per_core()) - 1,
     );
 
     Ok(())
@@ -42,10 +47,7 @@ fn update_power_management_entry(
 fn update_perf_mon_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
     // Architectural Performance Monitor Leaf
     // Disable PMU
-    entry.eax = 0;
-    entry.ebx = 0;
-    entry.ecx = 0;
-    entry.edx = 0;
+    common::mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, 0);
 
     Ok(())
 }
@@ -57,12 +59,9 @@ fn update_extended_topology_entry(
     use crate::cpu_leaf::leaf_0xb::*;
 
     // reset eax, ebx, ecx
-    entry.eax = 0_u32;
-    entry.ebx = 0_u32;
-    entry.ecx = 0_u32;
     // EDX bits 31..0 contain x2APIC ID of current logical processor
     // x2APIC increases the size of the APIC ID from 8 bits to 32 bits
-    entry.edx = u32::from(vm_spec.cpu_index);
+    common::mask_cpuid_entry(entry, 0, 0, 0, 0, 0, 0, 0, vm_spec.x2apic_id());
 
     // "If SMT is not present in a processor implementation but CPUID leaf 0BH is supported,
     // CPUID.EAX=0BH, ECX=0 will return EAX = 0, EBX = 1 and level type = 1.
@@ -71,11 +70,11 @@ fn update_extended_topology_entry(
     match entry.index {
         // Thread Level Topology; index = 0
         0 => {
-            // To get the next level APIC ID, shift right with at most 1 because we have
-            // maximum 2 hyperthreads per core that can be represented by 1 bit.
+            // To get the next level APIC ID, shift right with 1 only when SMT is enabled because
+            // we have maximum 2 hyperthreads per core that can be represented by 1 bit.
             entry
                 .eax
-                .write_bits_in_range(&eax::APICID_BITRANGE, u32::from(vm_spec.cpu_bits));
+                .write_bits_in_range(&eax::APICID_BITRANGE, u32::from(vm_spec.is_smt_enabled()));
             // When cpu_count == 1 or HT is disabled, there is 1 logical core at this level
             // Otherwise there are 2
             entry.ebx.write_bits_in_range(
@@ -89,12 +88,18 @@ fn update_extended_topology_entry(
         }
         // Core Level Processor Topology; index = 1
         1 => {
+            // Shifting by the width of the package APIC IDs gives the socket id. A single
+            // socket keeps the historical shift.
+            let apicid_shift = match vm_spec.sockets() {
+                1 => LEAFBH_INDEX1_APICID,
+                _ => u32::from(vm_spec.apic_id_width()),
+            };
             entry
                 .eax
-                .write_bits_in_range(&eax::APICID_BITRANGE, LEAFBH_INDEX1_APICID);
+                .write_bits_in_range(&eax::APICID_BITRANGE, apicid_shift);
             entry.ebx.write_bits_in_range(
                 &ebx::NUM_LOGICAL_PROCESSORS_BITRANGE,
-                u32::from(vm_spec.cpu_count),
+                u32::from(vm_spec.cpus_per_socket()),
             );
             entry
                 .ecx
@@ -115,17 +120,173 @@ fn update_extended_topology_entry(
     Ok(())
 }
 
+pub fn update_extended_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x7::index0::*;
+
+    common::update_optional_features_entry(entry, vm_spec)?;
+
+    // Like leaf 0x1A, report a non-hybrid processor since every vCPU gets the same topology,
+    // whatever the core type it runs on.
+    if entry.index == 0 {
+        entry.edx.write_bit(edx::HYBRID_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+pub fn transform_entry_0x1a(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x1a::*;
+
+    // The vCPUs are not pinned to a specific host core type, so report a non-hybrid
+    // processor rather than the core type the vCPU happened to be created on.
+    entry
+        .eax
+        .write_bits_in_range(&eax::CORE_TYPE_BITRANGE, 0)
+        .write_bits_in_range(&eax::NATIVE_MODEL_ID_BITRANGE, 0);
+
+    Ok(())
+}
+
+fn tlb_entry(
+    index: u32,
+    geometry: &TlbGeometry,
+    vm_spec: &VmSpec,
+) -> Result<kvm_cpuid_entry2, Error> {
+    use crate::cpu_leaf::leaf_0x18::*;
+
+    let fits = |value: u32, bit_range: &BitRange| {
+        value > 0 && value <= bit_range.get_mask() >> bit_range.lsb_index
+    };
+    if !fits(geometry.level, &edx::TLB_LEVEL_BITRANGE) || !(1..=5).contains(&geometry.tlb_type) {
+        return Err(Error::InvalidTlbGeometry("invalid level or type"));
+    }
+    if !fits(geometry.page_sizes, &ebx::PAGE_SIZES_BITRANGE)
+        || !fits(geometry.ways, &ebx::WAYS_BITRANGE)
+        || geometry.sets == 0
+    {
+        return Err(Error::InvalidTlbGeometry(
+            "the page sizes, ways and sets can't be empty or out of range",
+        ));
+    }
+
+    let mut entry = kvm_cpuid_entry2 {
+        function: LEAF_NUM,
+        index,
+        flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+        ecx: geometry.sets,
+        ..Default::default()
+    };
+    entry
+        .ebx
+        .write_bits_in_range(&ebx::PAGE_SIZES_BITRANGE, geometry.page_sizes)
+        .write_bits_in_range(&ebx::WAYS_BITRANGE, geometry.ways);
+    // Like the L1 caches, the TLBs are shared by the threads of a core.
+    entry
+        .edx
+        .write_bits_in_range(&edx::TLB_TYPE_BITRANGE, geometry.tlb_type)
+        .write_bits_in_range(&edx::TLB_LEVEL_BITRANGE, geometry.level)
+        .write_bit(edx::FULLY_ASSOCIATIVE_BITINDEX, geometry.fully_associative)
+        .write_bits_in_range(
+            &edx::MAX_SHARING_CPUS_BITRANGE,
+            u32::from(vm_spec.cpus_per_core()) - 1,
+        );
+
+    Ok(entry)
+}
+
+// Replaces the host TLBs of leaf 0x18 with the ones of `vm_spec`. Sub-leaf 0 reports the
+// highest sub-leaf in EAX, and the enumeration ends with a null TLB type, so the described
+// TLBs are followed by a null sub-leaf. Without any TLB, sub-leaf 0 is the null one.
+fn update_tlb_entries(cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x18::*;
+
+    if cpuid.get_entry(LEAF_NUM, 0).is_none() {
+        return Ok(());
+    }
+
+    let mut entries = vm_spec
+        .tlb_geometry
+        .iter()
+        .enumerate()
+        .map(|(index, geometry)| tlb_entry(index as u32, geometry, vm_spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    let count = entries.len() as u32;
+    entries.push(kvm_cpuid_entry2 {
+        function: LEAF_NUM,
+        index: count,
+        flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+        ..Default::default()
+    });
+    entries[0].eax = count.saturating_sub(1);
+
+    cpuid.retain(|entry| entry.function != LEAF_NUM);
+    for entry in entries {
+        cpuid.push(entry).map_err(Error::Fam)?;
+    }
+
+    Ok(())
+}
+
 pub struct IntelCpuidTransformer {}
 
 impl CpuidTransformer for IntelCpuidTransformer {
+    fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        self.check_required_subleaves(cpuid)?;
+        update_tlb_entries(cpuid, vm_spec)?;
+        self.process_entries(cpuid, vm_spec)?;
+        common::update_xsave_user_state_entries(cpuid)?;
+        common::update_xsave_supervisor_state_entries(cpuid)?;
+        if vm_spec.sort_entries {
+            cpuid.sort_entries();
+        }
+
+        Ok(())
+    }
+
+    fn verify(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+        let mut violations = Vec::new();
+        common::verify_cpuid(cpuid, vm_spec, &mut violations);
+
+        // Every leaf 0xB and 0x1F sub-leaf reports the x2APIC ID of the current vCPU.
+        for entry in cpuid.as_slice().iter().filter(|entry| {
+            entry.function == leaf_0xb::LEAF_NUM || entry.function == leaf_0x1f::LEAF_NUM
+        }) {
+            if entry.edx != vm_spec.x2apic_id() {
+                violations.push(CpuidViolation::FieldMismatch {
+                    function: entry.function,
+                    index: entry.index,
+                    register: "edx",
+                    expected: vm_spec.x2apic_id(),
+                    actual: entry.edx,
+                });
+            }
+        }
+
+        violations_to_result(violations)
+    }
+
     fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
         match entry.function {
             leaf_0x1::LEAF_NUM => Some(common::update_feature_info_entry),
+            leaf_0x3::LEAF_NUM => Some(common::update_serial_number_entry),
             leaf_0x4::LEAF_NUM => Some(intel::update_deterministic_cache_entry),
             leaf_0x6::LEAF_NUM => Some(intel::update_power_management_entry),
+            leaf_0x7::LEAF_NUM => Some(intel::update_extended_features_entry),
             leaf_0xa::LEAF_NUM => Some(intel::update_perf_mon_entry),
-            leaf_0xb::LEAF_NUM => Some(intel::update_extended_topology_entry),
-            0x8000_0002..=0x8000_0004 => Some(common::update_brand_string_entry),
+            // Leaf 0x1F takes precedence over leaf 0xB in the guest, so both describe the
+            // topology of the VM rather than the one of the host.
+            leaf_0xb::LEAF_NUM | leaf_0x1f::LEAF_NUM => Some(intel::update_extended_topology_entry),
+            leaf_0xd::LEAF_NUM => Some(common::update_optional_features_entry),
+            leaf_0x1a::LEAF_NUM => Some(intel::transform_entry_0x1a),
+            leaf_0x1d::LEAF_NUM => Some(common::update_optional_features_entry),
+            leaf_0x1e::LEAF_NUM => Some(common::update_optional_features_entry),
+            leaf_0x80000001::LEAF_NUM => Some(common::update_optional_features_entry),
+            0x8000_0002 => Some(common::update_brand_string_entry_0x80000002),
+            0x8000_0003 => Some(common::update_brand_string_entry_0x80000003),
+            0x8000_0004 => Some(common::update_brand_string_entry_0x80000004),
             _ => None,
         }
     }
@@ -139,9 +300,254 @@ mod tests {
     use crate::cpu_leaf::leaf_0xb::{LEVEL_TYPE_CORE, LEVEL_TYPE_THREAD};
     use crate::transformer::VmSpec;
 
+    #[test]
+    fn test_verify() {
+        let vm_spec = VmSpec::new(3, 4, false).expect("Error creating vm_spec");
+        let transformer = IntelCpuidTransformer {};
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                index: 0,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                index: 1,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+        cpuid.as_mut_slice()[0]
+            .edx
+            .write_bit(leaf_0x1::edx::APIC_BITINDEX, true);
+
+        let violations = transformer.verify(&cpuid, &vm_spec).unwrap_err();
+        assert_eq!(violations.len(), 4);
+        assert!(violations.contains(&CpuidViolation::FieldMismatch {
+            function: leaf_0xb::LEAF_NUM,
+            index: 1,
+            register: "edx",
+            expected: 3,
+            actual: 0,
+        }));
+
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        assert!(transformer.verify(&cpuid, &vm_spec).is_ok());
+    }
+
+    fn host_tlb_cpuid() -> CpuId {
+        // The leaf 0x18 of an Ice Lake server host: a 4KB instruction TLB, two data TLBs
+        // for loads and stores, and a unified STLB, with an invalid sub-leaf in between.
+        let tlb = |index: u32, eax: u32, ebx: u32, ecx: u32, edx: u32| kvm_cpuid_entry2 {
+            function: leaf_0x18::LEAF_NUM,
+            index,
+            flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+            eax,
+            ebx,
+            ecx,
+            edx,
+            ..Default::default()
+        };
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x0,
+                eax: leaf_0x18::LEAF_NUM,
+                ..Default::default()
+            },
+            tlb(0, 5, 0, 0, 0),
+            tlb(1, 0, 0x0008_0001, 0x10, 0x0000_4022),
+            tlb(2, 0, 0x0004_0001, 0x10, 0x0000_4021),
+            tlb(3, 0, 0, 0, 0),
+            tlb(4, 0, 0x0010_0001, 0x80, 0x0000_4043),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_update_tlb_entries_default() {
+        let vm_spec = VmSpec::default();
+        let mut cpuid = host_tlb_cpuid();
+        update_tlb_entries(&mut cpuid, &vm_spec).unwrap();
+
+        // Only the null sub-leaf 0 is left, reporting itself as the last sub-leaf.
+        let entries: Vec<&kvm_cpuid_entry2> = cpuid
+            .as_slice()
+            .iter()
+            .filter(|entry| entry.function == leaf_0x18::LEAF_NUM)
+            .collect();
+        assert_eq!(
+            entries,
+            [&kvm_cpuid_entry2 {
+                function: leaf_0x18::LEAF_NUM,
+                index: 0,
+                flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                ..Default::default()
+            }]
+        );
+        assert!(cpuid.get_entry(0x0, 0).is_some());
+
+        // The leaf is not added if the host doesn't report it.
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2::default()]).unwrap();
+        update_tlb_entries(&mut cpuid, &vm_spec).unwrap();
+        assert_eq!(cpuid.as_slice().len(), 1);
+    }
+
+    #[test]
+    fn test_update_tlb_entries_geometry() {
+        use crate::cpu_leaf::leaf_0x18::*;
+
+        let geometry = [
+            TlbGeometry {
+                level: 1,
+                tlb_type: 3,
+                page_sizes: 0x3,
+                ways: 4,
+                sets: 16,
+                fully_associative: false,
+            },
+            TlbGeometry {
+                level: 2,
+                tlb_type: 3,
+                page_sizes: 0xb,
+                ways: 8,
+                sets: 128,
+                fully_associative: true,
+            },
+        ];
+        let vm_spec = VmSpec::builder()
+            .cpu_count(2)
+            .smt(true)
+            .tlb_geometry(geometry.to_vec())
+            .build()
+            .unwrap();
+        let mut cpuid = host_tlb_cpuid();
+        IntelCpuidTransformer {}
+            .process_cpuid(&mut cpuid, &vm_spec)
+            .unwrap();
+
+        let mut entries: Vec<kvm_cpuid_entry2> = cpuid
+            .as_slice()
+            .iter()
+            .filter(|entry| entry.function == LEAF_NUM)
+            .copied()
+            .collect();
+        entries.sort_by_key(|entry| entry.index);
+        assert_eq!(entries.len(), 3);
+        // Sub-leaf 0 reports the last TLB sub-leaf.
+        assert_eq!(entries[0].eax, 1);
+        assert_eq!(entries[1].eax, 0);
+        for (entry, geometry) in entries.iter().zip(geometry.iter()) {
+            assert_eq!(
+                entry.ebx.read_bits_in_range(&ebx::PAGE_SIZES_BITRANGE),
+                geometry.page_sizes
+            );
+            assert_eq!(
+                entry.ebx.read_bits_in_range(&ebx::WAYS_BITRANGE),
+                geometry.ways
+            );
+            assert_eq!(entry.ecx, geometry.sets);
+            assert_eq!(
+                entry.edx.read_bits_in_range(&edx::TLB_TYPE_BITRANGE),
+                geometry.tlb_type
+            );
+            assert_eq!(
+                entry.edx.read_bits_in_range(&edx::TLB_LEVEL_BITRANGE),
+                geometry.level
+            );
+            assert_eq!(
+                entry.edx.read_bit(edx::FULLY_ASSOCIATIVE_BITINDEX),
+                geometry.fully_associative
+            );
+            // The TLBs are shared by the 2 threads of the core.
+            assert_eq!(
+                entry
+                    .edx
+                    .read_bits_in_range(&edx::MAX_SHARING_CPUS_BITRANGE),
+                1
+            );
+        }
+        // The enumeration ends with a null TLB type.
+        assert_eq!(entries[2].index, 2);
+        assert_eq!(
+            entries[2].edx.read_bits_in_range(&edx::TLB_TYPE_BITRANGE),
+            0
+        );
+
+        let vm_spec = VmSpec::builder()
+            .tlb_geometry(vec![TlbGeometry {
+                tlb_type: 6,
+                ..geometry[0]
+            }])
+            .build()
+            .unwrap();
+        assert!(matches!(
+            update_tlb_entries(&mut host_tlb_cpuid(), &vm_spec),
+            Err(Error::InvalidTlbGeometry(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_extended_features_entry() {
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let mut vm_spec = VmSpec::default();
+        // The SERIALIZE and HYBRID bits of an Alder Lake host.
+        let host_edx = (1 << edx::SERIALIZE_BITINDEX) | (1 << edx::HYBRID_BITINDEX);
+        let entry = &mut kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            index: 0,
+            edx: host_edx,
+            ..Default::default()
+        };
+
+        assert!(update_extended_features_entry(entry, &vm_spec).is_ok());
+        assert!(entry.edx.read_bit(edx::SERIALIZE_BITINDEX));
+        assert!(!entry.edx.read_bit(edx::HYBRID_BITINDEX));
+
+        vm_spec.set_serialize(false);
+        entry.edx = host_edx;
+        assert!(update_extended_features_entry(entry, &vm_spec).is_ok());
+        assert_eq!(entry.edx, 0);
+
+        // The other subleaves are left untouched.
+        let entry = &mut kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            index: 1,
+            edx: host_edx,
+            ..Default::default()
+        };
+        assert!(update_extended_features_entry(entry, &vm_spec).is_ok());
+        assert_eq!(entry.edx, host_edx);
+    }
+
+    #[test]
+    fn test_transform_entry_0x1a() {
+        let vm_spec = VmSpec::default();
+        // A P-core of an Alder Lake host.
+        let entry = &mut kvm_cpuid_entry2 {
+            function: leaf_0x1a::LEAF_NUM,
+            index: 0,
+            flags: 0,
+            eax: 0x4000_0001,
+            ebx: 0,
+            ecx: 0,
+            edx: 0,
+            padding: [0, 0, 0],
+        };
+
+        assert!(transform_entry_0x1a(entry, &vm_spec).is_ok());
+
+        assert_eq!(entry.eax, 0);
+        assert_eq!(entry.function, leaf_0x1a::LEAF_NUM);
+    }
+
     #[test]
     fn test_update_perf_mon_entry() {
-        let vm_spec = VmSpec::new(0, 1, false).expect("Error creating vm_spec");
+        let vm_spec = VmSpec::default();
         let entry = &mut kvm_cpuid_entry2 {
             function: leaf_0xa::LEAF_NUM,
             index: 0,
@@ -226,6 +632,76 @@ mod tests {
         assert!(entry.ecx.read_bits_in_range(&ecx::LEVEL_NUMBER_BITRANGE) == index);
     }
 
+    fn host_cache_table() -> CpuId {
+        use crate::cpu_leaf::leaf_0x4::*;
+
+        // L1d, L1i, L2 and L3 of a 2 socket host with 2 threads per core and 16 cores per
+        // package.
+        let entry = |index: u32, cache_level: u32, max_cpus_per_core: u32| {
+            let mut eax = 0;
+            eax.write_bits_in_range(&eax::CACHE_LEVEL_BITRANGE, cache_level)
+                .write_bits_in_range(&eax::MAX_CPUS_PER_CORE_BITRANGE, max_cpus_per_core)
+                .write_bits_in_range(&eax::MAX_CORES_PER_PACKAGE_BITRANGE, 15);
+            kvm_cpuid_entry2 {
+                function: LEAF_NUM,
+                index,
+                eax,
+                ..Default::default()
+            }
+        };
+
+        CpuId::from_entries(&[
+            entry(0, 1, 1),
+            entry(1, 1, 1),
+            entry(2, 2, 1),
+            entry(3, 3, 31),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_cache_id_policy() {
+        use crate::cpu_leaf::leaf_0x4::*;
+
+        let sharing = |cpuid: &CpuId| {
+            cpuid
+                .as_slice()
+                .iter()
+                .map(|entry| {
+                    (
+                        entry
+                            .eax
+                            .read_bits_in_range(&eax::MAX_CPUS_PER_CORE_BITRANGE),
+                        entry
+                            .eax
+                            .read_bits_in_range(&eax::MAX_CORES_PER_PACKAGE_BITRANGE),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The caches are remapped to the guest topology by default.
+        let vm_spec = VmSpec::new(0, 4, true).unwrap();
+        let mut cpuid = host_cache_table();
+        IntelCpuidTransformer {}
+            .process_entries(&mut cpuid, &vm_spec)
+            .unwrap();
+        assert_eq!(sharing(&cpuid), vec![(1, 1), (1, 1), (1, 1), (3, 1)]);
+
+        let vm_spec = VmSpec::builder()
+            .cpu_count(4)
+            .smt(true)
+            .cache_id_policy(CacheIdPolicy::PreserveHost)
+            .build()
+            .unwrap();
+        let mut cpuid = host_cache_table();
+        IntelCpuidTransformer {}
+            .process_entries(&mut cpuid, &vm_spec)
+            .unwrap();
+        assert_eq!(cpuid.as_slice(), host_cache_table().as_slice());
+        assert_eq!(sharing(&cpuid), vec![(1, 15), (1, 15), (1, 15), (31, 15)]);
+    }
+
     #[test]
     fn test_1vcpu_ht_off() {
         // test update_deterministic_cache_entry
@@ -293,4 +769,173 @@ mod tests {
         // index 1
         check_update_extended_topology_entry(2, true, 1, LEAFBH_INDEX1_APICID, 2, LEVEL_TYPE_CORE);
     }
+
+    #[test]
+    fn test_apic_id_above_255() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0xb};
+
+        // The last cpu of the third socket, whose x2APIC ID doesn't fit in 8 bits.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(254)
+            .cpu_count(255)
+            .sockets(3)
+            .build()
+            .unwrap();
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                edx: 1 << leaf_0x1::edx::APIC_BITINDEX,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                index: 0,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                index: 1,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let entries = cpuid.as_mut_slice();
+        assert!(common::update_feature_info_entry(&mut entries[0], &vm_spec).is_ok());
+        assert!(update_extended_topology_entry(&mut entries[1], &vm_spec).is_ok());
+        assert!(update_extended_topology_entry(&mut entries[2], &vm_spec).is_ok());
+
+        // Leaf 0x1 holds the low 8 bits of the full ID reported by leaf 0xB.
+        assert_eq!(
+            entries[0]
+                .ebx
+                .read_bits_in_range(&leaf_0x1::ebx::APICID_BITRANGE),
+            84
+        );
+        assert_eq!(entries[1].edx, 340);
+        assert_eq!(entries[2].edx, 340);
+        assert_eq!(IntelCpuidTransformer {}.verify(&cpuid, &vm_spec), Ok(()));
+    }
+
+    #[test]
+    fn test_2socket_2core_ht_on() {
+        use crate::cpu_leaf::{leaf_0x4, leaf_0xb};
+
+        // The second thread of the first core of the second socket.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(5)
+            .cpu_count(8)
+            .sockets(2)
+            .smt(true)
+            .build()
+            .unwrap();
+
+        let topology = |index: u32| {
+            let mut entry = kvm_cpuid_entry2 {
+                function: leaf_0xb::LEAF_NUM,
+                index,
+                ..Default::default()
+            };
+            assert!(update_extended_topology_entry(&mut entry, &vm_spec).is_ok());
+            (
+                entry
+                    .eax
+                    .read_bits_in_range(&leaf_0xb::eax::APICID_BITRANGE),
+                entry
+                    .ebx
+                    .read_bits_in_range(&leaf_0xb::ebx::NUM_LOGICAL_PROCESSORS_BITRANGE),
+                entry
+                    .ecx
+                    .read_bits_in_range(&leaf_0xb::ecx::LEVEL_TYPE_BITRANGE),
+                entry.edx,
+            )
+        };
+        // 1 bit for the threads, 1 more for the cores, the rest is the socket id.
+        assert_eq!(topology(0), (1, 2, leaf_0xb::LEVEL_TYPE_THREAD, 5));
+        assert_eq!(topology(1), (2, 4, leaf_0xb::LEVEL_TYPE_CORE, 5));
+
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x4::LEAF_NUM,
+            eax: *(0_u32).write_bits_in_range(&leaf_0x4::eax::CACHE_LEVEL_BITRANGE, 3),
+            ..Default::default()
+        };
+        assert!(update_deterministic_cache_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x4::eax::MAX_CORES_PER_PACKAGE_BITRANGE),
+            1
+        );
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&leaf_0x4::eax::MAX_CPUS_PER_CORE_BITRANGE),
+            3
+        );
+    }
+
+    #[test]
+    fn test_leaf_0x1f() {
+        use crate::cpu_leaf::{leaf_0x1, leaf_0x1f, leaf_0xb};
+
+        // The first thread of the second socket, on a host whose leaf 0x1F reports a die
+        // level on top of the core level.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(4)
+            .cpu_count(8)
+            .sockets(2)
+            .smt(true)
+            .build()
+            .unwrap();
+        let host_entry = |function: u32, index: u32, level_type: u32| kvm_cpuid_entry2 {
+            function,
+            index,
+            flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+            eax: 6,
+            ebx: 64,
+            ecx: *{ index }.write_bits_in_range(&leaf_0xb::ecx::LEVEL_TYPE_BITRANGE, level_type),
+            edx: 17,
+            ..Default::default()
+        };
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x0,
+                eax: leaf_0x1f::LEAF_NUM,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                edx: 1 << leaf_0x1::edx::APIC_BITINDEX,
+                ..Default::default()
+            },
+            host_entry(leaf_0xb::LEAF_NUM, 0, leaf_0xb::LEVEL_TYPE_THREAD),
+            host_entry(leaf_0xb::LEAF_NUM, 1, leaf_0xb::LEVEL_TYPE_CORE),
+            host_entry(leaf_0x1f::LEAF_NUM, 0, leaf_0xb::LEVEL_TYPE_THREAD),
+            host_entry(leaf_0x1f::LEAF_NUM, 1, leaf_0xb::LEVEL_TYPE_CORE),
+            // The die level type.
+            host_entry(leaf_0x1f::LEAF_NUM, 2, 5),
+        ])
+        .unwrap();
+
+        let transformer = IntelCpuidTransformer {};
+        assert!(transformer.verify(&cpuid, &vm_spec).is_err());
+        transformer.process_entries(&mut cpuid, &vm_spec).unwrap();
+        assert_eq!(transformer.verify(&cpuid, &vm_spec), Ok(()));
+
+        let topology = |function: u32| {
+            cpuid
+                .as_slice()
+                .iter()
+                .filter(|entry| entry.function == function)
+                .map(|entry| (entry.eax, entry.ebx, entry.ecx, entry.edx))
+                .collect::<Vec<_>>()
+        };
+        // Leaf 0x1F describes the same topology as leaf 0xB, and the host die level is
+        // reported as invalid.
+        let expected = vec![(1, 2, 0x100, 4), (2, 4, 0x201, 4)];
+        assert_eq!(topology(leaf_0xb::LEAF_NUM), expected);
+        assert_eq!(topology(leaf_0x1f::LEAF_NUM)[..2], expected[..]);
+        assert_eq!(topology(leaf_0x1f::LEAF_NUM)[2], (0, 0, 2, 4));
+        assert_eq!(vm_spec.x2apic_id(), 4);
+    }
 }
@@ -0,0 +1,147 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use logger::{log, Level};
+
+use super::*;
+use crate::common::CpuidEntryDisplay;
+
+/// Cpuid transformer wrapper that logs every entry modified by the inner transformer.
+pub struct LoggingTransformer<T: CpuidTransformer> {
+    /// The transformer doing the actual work.
+    pub inner: T,
+    /// The level at which the modified entries are logged.
+    pub log_level: Level,
+}
+
+impl<T: CpuidTransformer> LoggingTransformer<T> {
+    fn log_entry_diff(&self, before: &kvm_cpuid_entry2, after: &kvm_cpuid_entry2) {
+        if before != after {
+            log!(
+                self.log_level,
+                "CPUID entry modified: [{}] -> [{}]",
+                CpuidEntryDisplay(before),
+                CpuidEntryDisplay(after)
+            );
+        }
+    }
+}
+
+impl<T: CpuidTransformer> CpuidTransformer for LoggingTransformer<T> {
+    fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        // The inner transformer may add or remove entries before processing them, so
+        // the whole table is compared instead of relying on `process_entries`.
+        let before = cpuid.clone();
+        self.inner.process_cpuid(cpuid, vm_spec)?;
+
+        for entry in cpuid.as_slice() {
+            match before
+                .as_slice()
+                .iter()
+                .find(|old| old.function == entry.function && old.index == entry.index)
+            {
+                Some(old) => self.log_entry_diff(old, entry),
+                None => log!(
+                    self.log_level,
+                    "CPUID entry added: [{}]",
+                    CpuidEntryDisplay(entry)
+                ),
+            }
+        }
+
+        for old in before.as_slice() {
+            if !cpuid
+                .as_slice()
+                .iter()
+                .any(|entry| entry.function == old.function && entry.index == old.index)
+            {
+                log!(
+                    self.log_level,
+                    "CPUID entry removed: [{}]",
+                    CpuidEntryDisplay(old)
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        for entry in entries.iter_mut() {
+            let maybe_transformer_fn = self.inner.entry_transformer_fn(entry);
+
+            if let Some(transformer_fn) = maybe_transformer_fn {
+                let before = *entry;
+                transformer_fn(entry, vm_spec)?;
+                self.log_entry_diff(&before, entry);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn required_subleaves(&self) -> &[(u32, u32)] {
+        self.inner.required_subleaves()
+    }
+
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        self.inner.entry_transformer_fn(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROCESSED_FN: u32 = 1;
+    const EXPECTED_EAX: u32 = 0xabcd;
+
+    fn transform_entry(entry: &mut kvm_cpuid_entry2, _vm_spec: &VmSpec) -> Result<(), Error> {
+        entry.eax = EXPECTED_EAX;
+
+        Ok(())
+    }
+
+    struct MockCpuidTransformer {}
+
+    impl CpuidTransformer for MockCpuidTransformer {
+        fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+            match entry.function {
+                PROCESSED_FN => Some(transform_entry),
+                _ => None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_logging_transformer() {
+        let vm_spec = VmSpec::default();
+        let transformer = LoggingTransformer {
+            inner: MockCpuidTransformer {},
+            log_level: Level::Debug,
+        };
+
+        let mut cpuid = CpuId::new(3).unwrap();
+        cpuid.as_mut_slice()[0].function = PROCESSED_FN;
+        cpuid.as_mut_slice()[1].function = PROCESSED_FN + 1;
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+
+        // The wrapper must not alter the effect of the inner transformer.
+        let entries = cpuid.as_slice();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].eax, EXPECTED_EAX);
+        assert_eq!(entries[1].eax, 0);
+        assert_eq!(entries[2].eax, 0);
+
+        let mut cpuid = CpuId::new(2).unwrap();
+        cpuid.as_mut_slice()[0].function = PROCESSED_FN;
+        assert!(transformer.process_entries(&mut cpuid, &vm_spec).is_ok());
+        assert_eq!(cpuid.as_slice()[0].eax, EXPECTED_EAX);
+        assert_eq!(cpuid.as_slice()[1].eax, 0);
+    }
+}
@@ -2,13 +2,94 @@
 // SPDX-License-Identifier: Apache-2.0
 
 pub mod amd;
+pub mod cache;
 pub mod common;
+pub mod debug;
+pub mod equivalence;
 pub mod intel;
+pub mod logging;
+pub mod record;
+pub mod synthetic;
+pub mod templates;
 
 pub use kvm_bindings::{kvm_cpuid_entry2, CpuId};
 
+use logger::warn;
+
 use crate::brand_string::{BrandString, Reg as BsReg};
-use crate::common::get_vendor_id_from_host;
+use crate::common::{get_host_physical_cpu_count, get_vendor_id_from_host};
+use crate::cpuid_ext::CpuIdExt;
+
+/// The high resolution timing features hidden from side-channel sensitive guests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParanoidTiming {
+    /// The timing features are exposed as supported by the host.
+    Disabled,
+    /// RDTSCP is hidden. Guests fall back to RDTSC or coarser timers.
+    Rdtscp,
+    /// Both RDTSCP and RDPID (which reads the same IA32_TSC_AUX MSR) are hidden.
+    RdtscpAndRdpid,
+}
+
+/// How the cache sharing information of the cache leaves (0x4 and 0x8000001D) is exposed.
+///
+/// The guest derives the ID of each cache from the number of logical cpus sharing it, so
+/// this decides which topology the cache IDs map to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheIdPolicy {
+    /// The sharing information is rebuilt from the guest topology.
+    Remap,
+    /// The sharing information is passed through as reported by the host. This can mislead
+    /// the guest NUMA/scheduler logic when the guest topology differs from the host one.
+    PreserveHost,
+}
+
+/// The geometry of one of the TLBs described by the deterministic address translation leaf
+/// (0x18), see `VmSpec::set_tlb_geometry`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlbGeometry {
+    /// The TLB level, starting at 1.
+    pub level: u32,
+    /// The TLB type, as encoded in EDX: 1 for data, 2 for instruction, 3 for unified, 4 for
+    /// load only and 5 for store only TLBs.
+    pub tlb_type: u32,
+    /// The supported page sizes, as a mask of 4KB (bit 0), 2MB (bit 1), 4MB (bit 2) and 1GB
+    /// (bit 3).
+    pub page_sizes: u32,
+    /// The number of ways of associativity.
+    pub ways: u32,
+    /// The number of sets.
+    pub sets: u32,
+    /// Whether the TLB is fully associative.
+    pub fully_associative: bool,
+}
+
+/// How the brand string of the leaves 0x80000002 through 0x80000004 is built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrandStringMode {
+    /// A generic brand string for the vendor, keeping only the frequency of the host on Intel.
+    Emulated,
+    /// The brand string of the host, with the frequency blanked so that the guest sees the
+    /// model but not the exact SKU clock.
+    HostScrubbedFrequency,
+}
+
+/// The predefined CPU templates, masking the guest features down to a common baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuTemplate {
+    /// The features are exposed as supported by the host.
+    None,
+    /// Intel Skylake/Cascade Lake masked down to the features of a C3 instance.
+    C3,
+    /// Intel Skylake/Cascade Lake masked down to the features of a T2 instance.
+    T2,
+    /// AMD Milan masked down to the features of a T2 instance.
+    T2A,
+    /// Intel Cascade Lake masked down to the features of a T2 instance, keeping AVX-512.
+    T2CL,
+    /// Intel Skylake masked down to the features of a T2 instance, with the Skylake MSRs.
+    T2S,
+}
 
 /// Structure containing the specifications of the VM
 pub struct VmSpec {
@@ -21,24 +102,173 @@ pub struct VmSpec {
     cpu_index: u8,
     /// The total number of logical cpus.
     cpu_count: u8,
+    /// The number of sockets (packages) the logical cpus are evenly spread across.
+    sockets: u8,
 
     /// The number of bits needed to enumerate logical CPUs per core.
     cpu_bits: u8,
+
+    /// Whether the entries should be sorted by (function, index) after processing.
+    sort_entries: bool,
+
+    /// Whether ENQCMD (and the PASID state it relies on) is exposed, if supported by the host.
+    enqcmd: bool,
+
+    /// Whether PCONFIG (used to program the MKTME keys) is exposed, if supported by the host.
+    pconfig: bool,
+
+    /// Whether AMX (the tile instructions and their XSAVE state) is exposed, if supported by
+    /// the host.
+    amx: bool,
+
+    /// Whether MPX (the bound instructions and their XSAVE state) is exposed, if supported by
+    /// the host.
+    mpx: bool,
+
+    /// Whether FSGSBASE (the instructions accessing the FS and GS bases) is exposed, if
+    /// supported by the host.
+    fsgsbase: bool,
+
+    /// Whether SERIALIZE is exposed, if supported by the host.
+    serialize: bool,
+
+    /// Whether the AMD encrypted memory capabilities (SEV and friends) are exposed.
+    enable_amd_sev: bool,
+
+    /// The timing features hidden from the guest.
+    paranoid_timing: ParanoidTiming,
+
+    /// How the cache IDs of the cache leaves are exposed.
+    cache_id_policy: CacheIdPolicy,
+
+    /// The TLBs described by leaf 0x18, none if empty.
+    tlb_geometry: Vec<TlbGeometry>,
+
+    /// The CPU template applied on top of the vendor specific transformations.
+    cpu_template: CpuTemplate,
+}
+
+#[cfg(test)]
+impl Default for VmSpec {
+    /// A single vCPU spec for the host vendor, for tests that don't care about the topology.
+    fn default() -> Self {
+        VmSpec::new(0, 1, false).expect("Error creating vm_spec")
+    }
 }
 
 impl VmSpec {
     /// Creates a new instance of VmSpec with the specified parameters
     /// The brand string is deduced from the vendor_id
     pub fn new(cpu_index: u8, cpu_count: u8, smt: bool) -> Result<VmSpec, Error> {
-        let cpu_vendor_id = get_vendor_id_from_host()?;
+        VmSpec::builder()
+            .cpu_index(cpu_index)
+            .cpu_count(cpu_count)
+            .smt(smt)
+            .build()
+    }
 
-        Ok(VmSpec {
-            cpu_vendor_id,
-            cpu_index,
-            cpu_count,
-            cpu_bits: (cpu_count > 1 && smt) as u8,
-            brand_string: BrandString::from_vendor_id(&cpu_vendor_id),
-        })
+    /// Returns a builder for a single vCPU spec, without SMT and with all the optional
+    /// features disabled.
+    pub fn builder() -> VmSpecBuilder {
+        VmSpecBuilder::default()
+    }
+
+    /// Sets whether the processed CPUID entries are sorted in a deterministic order.
+    pub fn set_sort_entries(&mut self, sort_entries: bool) {
+        self.sort_entries = sort_entries;
+    }
+
+    /// Sets whether ENQCMD is exposed to the guest, if supported by the host.
+    ///
+    /// Disabled by default since Firecracker doesn't provide the shared virtual memory support
+    /// needed by the guest to actually use it.
+    pub fn set_enqcmd(&mut self, enabled: bool) {
+        self.enqcmd = enabled;
+    }
+
+    /// Sets whether PCONFIG is exposed to the guest, if supported by the host.
+    ///
+    /// Disabled by default since Firecracker doesn't expose MKTME, so the guest would only
+    /// fault when attempting to use it.
+    pub fn set_pconfig(&mut self, enabled: bool) {
+        self.pconfig = enabled;
+    }
+
+    /// Sets whether AMX is exposed to the guest, if supported by the host.
+    ///
+    /// AMX is enumerated across leaf 0x7 (EDX), the tile leaves 0x1D and 0x1E and the
+    /// XTILECFG/XTILEDATA state components of leaf 0xD, so disabling it hides all of them
+    /// together. Disabled by default since Firecracker doesn't request the permission to use
+    /// the XTILEDATA state for its vCPUs, so KVM may only report a part of it.
+    pub fn set_amx(&mut self, enabled: bool) {
+        self.amx = enabled;
+    }
+
+    /// Sets whether MPX is exposed to the guest, if supported by the host.
+    ///
+    /// MPX is enumerated in leaf 0x7 (EBX) and the BNDREGS/BNDCSR state components of leaf
+    /// 0xD, so disabling it hides both together and keeps the XSAVE area sizes consistent.
+    /// Disabled by default since MPX is deprecated and its support was removed from Linux.
+    pub fn set_mpx(&mut self, enabled: bool) {
+        self.mpx = enabled;
+    }
+
+    /// Sets whether FSGSBASE is exposed to the guest, if supported by the host.
+    ///
+    /// Enabled by default, so the host value is preserved. Modern guest kernels rely on it
+    /// for their context switches once they set CR4.FSGSBASE, so hiding it mostly makes sense
+    /// to keep the guest compatible with older hosts.
+    pub fn set_fsgsbase(&mut self, enabled: bool) {
+        self.fsgsbase = enabled;
+    }
+
+    /// Sets whether SERIALIZE is exposed to the guest, if supported by the host.
+    ///
+    /// Enabled by default, so the host value is preserved. Hiding it keeps the guest
+    /// compatible with the hosts predating the instruction.
+    pub fn set_serialize(&mut self, enabled: bool) {
+        self.serialize = enabled;
+    }
+
+    /// Sets whether the AMD encrypted memory capabilities (leaf 0x8000001F) are exposed.
+    ///
+    /// Disabled by default, so that guests don't attempt to initialize SEV.
+    pub fn set_enable_amd_sev(&mut self, enable_amd_sev: bool) {
+        self.enable_amd_sev = enable_amd_sev;
+    }
+
+    /// Sets which high resolution timing features are hidden from the guest.
+    pub fn set_paranoid_timing(&mut self, paranoid_timing: ParanoidTiming) {
+        self.paranoid_timing = paranoid_timing;
+    }
+
+    /// Sets how the cache IDs reported in the cache leaves map to the topology.
+    ///
+    /// Defaults to `CacheIdPolicy::Remap`, so that the caches match the guest topology.
+    pub fn set_cache_id_policy(&mut self, cache_id_policy: CacheIdPolicy) {
+        self.cache_id_policy = cache_id_policy;
+    }
+
+    /// Sets the TLBs described by the deterministic address translation leaf (0x18) on Intel.
+    ///
+    /// Empty by default, in which case the leaf describes no TLB at all, since the host TLBs
+    /// identify the host microarchitecture. The leaf is left out if the host doesn't report
+    /// it.
+    pub fn set_tlb_geometry(&mut self, tlb_geometry: Vec<TlbGeometry>) {
+        self.tlb_geometry = tlb_geometry;
+    }
+
+    /// Sets the CPU template masking the features exposed to the guest.
+    ///
+    /// The template pass runs after the vendor specific transformations (see `filter_cpuid`)
+    /// and fails with `Error::InvalidVendor` if the template is meant for another vendor.
+    pub fn set_cpu_template(&mut self, cpu_template: CpuTemplate) {
+        self.cpu_template = cpu_template;
+    }
+
+    /// Returns the CPU template masking the features exposed to the guest.
+    pub fn cpu_template(&self) -> CpuTemplate {
+        self.cpu_template
     }
 
     /// Returns an immutable reference to cpu_vendor_id
@@ -50,6 +280,302 @@ impl VmSpec {
     pub fn cpus_per_core(&self) -> u8 {
         1 << self.cpu_bits
     }
+
+    /// Returns the number of sockets.
+    pub fn sockets(&self) -> u8 {
+        self.sockets
+    }
+
+    /// Returns the number of logical cpus per socket.
+    pub fn cpus_per_socket(&self) -> u8 {
+        self.cpu_count / self.sockets
+    }
+
+    /// Returns the index of the socket of the current logical cpu.
+    pub fn socket_id(&self) -> u8 {
+        self.cpu_index / self.cpus_per_socket()
+    }
+
+    /// Returns the x2APIC ID of the current logical cpu, as exposed in leaf 0xB.
+    ///
+    /// The socket id takes the bits above `apic_id_width`, so with more than one socket the
+    /// IDs are not contiguous unless the number of cpus per socket is a power of 2.
+    pub fn x2apic_id(&self) -> u32 {
+        self.x2apic_id_of(self.cpu_index)
+    }
+
+    fn x2apic_id_of(&self, cpu_index: u8) -> u32 {
+        let socket_id = u32::from(cpu_index / self.cpus_per_socket());
+        let index_in_socket = u32::from(cpu_index % self.cpus_per_socket());
+        (socket_id << self.apic_id_width()) | index_in_socket
+    }
+
+    /// Returns the initial APIC ID of the current logical cpu, as exposed in leaf 0x1.
+    ///
+    /// The legacy field only has 8 bits, so this is the low byte of `x2apic_id`. The full
+    /// value is only reported by leaf 0xB.
+    pub fn initial_apic_id(&self) -> u32 {
+        self.x2apic_id() & 0xff
+    }
+
+    /// Returns the x2APIC IDs of `cpu_count` logical cpus in a single socket, in the same layout
+    /// used by the topology leaves, so that they can be used when building the ACPI/MP tables.
+    ///
+    /// The thread id (if SMT is enabled) takes the least significant bits and the core id the
+    /// next ones, so consecutive cpus are siblings in the same core.
+    pub fn apic_ids(cpu_count: u8, smt: bool) -> Vec<u32> {
+        let cpu_bits = u32::from(cpu_count > 1 && smt);
+        (0..u32::from(cpu_count))
+            .map(|cpu_index| {
+                let thread_id = cpu_index & ((1 << cpu_bits) - 1);
+                let core_id = cpu_index >> cpu_bits;
+                (core_id << cpu_bits) | thread_id
+            })
+            .collect()
+    }
+
+    /// Returns the x2APIC IDs of all the logical cpus of the VM, see `apic_ids`.
+    ///
+    /// The socket id takes the bits above `apic_id_width`, on top of the IDs of the cpus in
+    /// the socket.
+    pub fn x2apic_ids(&self) -> Vec<u32> {
+        let socket_apic_ids = VmSpec::apic_ids(self.cpus_per_socket(), self.is_smt_enabled());
+        (0..u32::from(self.sockets))
+            .flat_map(|socket_id| {
+                socket_apic_ids
+                    .iter()
+                    .map(move |apic_id| (socket_id << self.apic_id_width()) | apic_id)
+            })
+            .collect()
+    }
+
+    /// Returns true if more than one logical CPU is exposed per core
+    pub fn is_smt_enabled(&self) -> bool {
+        self.cpu_bits > 0
+    }
+
+    /// Returns the number of bits needed to enumerate logical CPUs per core (the SMT width)
+    pub fn cpu_bits(&self) -> u8 {
+        self.cpu_bits
+    }
+
+    /// Returns the number of bits needed to enumerate all the APIC IDs in the package.
+    ///
+    /// This is the SMT width plus the number of bits needed to enumerate the cores of a
+    /// socket.
+    pub fn apic_id_width(&self) -> u8 {
+        let cpus_per_core = u32::from(self.cpus_per_core());
+        let core_count = (u32::from(self.cpus_per_socket()) + cpus_per_core - 1) / cpus_per_core;
+        let core_bits = u32::BITS - core_count.saturating_sub(1).leading_zeros();
+
+        self.cpu_bits + core_bits as u8
+    }
+}
+
+/// Builder for `VmSpec`, so that the optional settings don't have to be passed to `new`.
+#[derive(Debug, Clone)]
+pub struct VmSpecBuilder {
+    cpu_index: u8,
+    cpu_count: u8,
+    sockets: u8,
+    smt: bool,
+    sort_entries: bool,
+    enqcmd: bool,
+    pconfig: bool,
+    amx: bool,
+    mpx: bool,
+    fsgsbase: bool,
+    serialize: bool,
+    enable_amd_sev: bool,
+    paranoid_timing: ParanoidTiming,
+    cache_id_policy: CacheIdPolicy,
+    tlb_geometry: Vec<TlbGeometry>,
+    brand_string_mode: BrandStringMode,
+    cpu_template: CpuTemplate,
+}
+
+impl Default for VmSpecBuilder {
+    fn default() -> Self {
+        VmSpecBuilder {
+            cpu_index: 0,
+            cpu_count: 1,
+            sockets: 1,
+            smt: false,
+            sort_entries: false,
+            enqcmd: false,
+            pconfig: false,
+            amx: false,
+            mpx: false,
+            fsgsbase: true,
+            serialize: true,
+            enable_amd_sev: false,
+            paranoid_timing: ParanoidTiming::Disabled,
+            cache_id_policy: CacheIdPolicy::Remap,
+            tlb_geometry: Vec::new(),
+            brand_string_mode: BrandStringMode::Emulated,
+            cpu_template: CpuTemplate::None,
+        }
+    }
+}
+
+impl VmSpecBuilder {
+    /// Sets the index of the logical cpu the spec is built for.
+    pub fn cpu_index(mut self, cpu_index: u8) -> Self {
+        self.cpu_index = cpu_index;
+        self
+    }
+
+    /// Sets the total number of logical cpus.
+    pub fn cpu_count(mut self, cpu_count: u8) -> Self {
+        self.cpu_count = cpu_count;
+        self
+    }
+
+    /// Sets the number of sockets. The cpu count must be a multiple of it.
+    pub fn sockets(mut self, sockets: u8) -> Self {
+        self.sockets = sockets;
+        self
+    }
+
+    /// Sets whether 2 logical cpus are exposed per core (when there is more than one cpu).
+    pub fn smt(mut self, smt: bool) -> Self {
+        self.smt = smt;
+        self
+    }
+
+    /// See `VmSpec::set_sort_entries`.
+    pub fn sort_entries(mut self, sort_entries: bool) -> Self {
+        self.sort_entries = sort_entries;
+        self
+    }
+
+    /// See `VmSpec::set_enqcmd`.
+    pub fn enqcmd(mut self, enabled: bool) -> Self {
+        self.enqcmd = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_pconfig`.
+    pub fn pconfig(mut self, enabled: bool) -> Self {
+        self.pconfig = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_amx`.
+    pub fn amx(mut self, enabled: bool) -> Self {
+        self.amx = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_mpx`.
+    pub fn mpx(mut self, enabled: bool) -> Self {
+        self.mpx = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_fsgsbase`.
+    pub fn fsgsbase(mut self, enabled: bool) -> Self {
+        self.fsgsbase = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_serialize`.
+    pub fn serialize(mut self, enabled: bool) -> Self {
+        self.serialize = enabled;
+        self
+    }
+
+    /// See `VmSpec::set_enable_amd_sev`.
+    pub fn enable_amd_sev(mut self, enable_amd_sev: bool) -> Self {
+        self.enable_amd_sev = enable_amd_sev;
+        self
+    }
+
+    /// See `VmSpec::set_paranoid_timing`.
+    pub fn paranoid_timing(mut self, paranoid_timing: ParanoidTiming) -> Self {
+        self.paranoid_timing = paranoid_timing;
+        self
+    }
+
+    /// See `VmSpec::set_cache_id_policy`.
+    pub fn cache_id_policy(mut self, cache_id_policy: CacheIdPolicy) -> Self {
+        self.cache_id_policy = cache_id_policy;
+        self
+    }
+
+    /// See `VmSpec::set_tlb_geometry`.
+    pub fn tlb_geometry(mut self, tlb_geometry: Vec<TlbGeometry>) -> Self {
+        self.tlb_geometry = tlb_geometry;
+        self
+    }
+
+    /// Sets how the brand string is built. Defaults to `BrandStringMode::Emulated`.
+    ///
+    /// If the host doesn't report a brand string, the emulated one is used.
+    pub fn brand_string_mode(mut self, brand_string_mode: BrandStringMode) -> Self {
+        self.brand_string_mode = brand_string_mode;
+        self
+    }
+
+    /// See `VmSpec::set_cpu_template`.
+    pub fn cpu_template(mut self, cpu_template: CpuTemplate) -> Self {
+        self.cpu_template = cpu_template;
+        self
+    }
+
+    /// Builds the `VmSpec`. The vendor id and the brand string are deduced from the host.
+    pub fn build(self) -> Result<VmSpec, Error> {
+        let cpu_vendor_id = get_vendor_id_from_host()?;
+        let cpu_count = self.cpu_count;
+        if cpu_count == 0 {
+            return Err(Error::InvalidCpuCount);
+        }
+        // The index is the basis of all the APIC IDs.
+        if self.cpu_index >= cpu_count {
+            return Err(Error::InvalidCpuIndex(self.cpu_index));
+        }
+        let sockets = self.sockets;
+        if sockets == 0 || cpu_count % sockets != 0 {
+            return Err(Error::InvalidSocketCount(sockets));
+        }
+        // The topology leaves advertise a power of 2 number of logical cpus per package.
+        common::get_max_cpus_per_package(cpu_count / sockets)?;
+
+        // Oversubscribing the host is allowed, but it's usually not intended.
+        match get_host_physical_cpu_count() {
+            Ok(host_cpu_count) if cpu_count > host_cpu_count => warn!(
+                "The VM has {} vCPUs, but the host only has {} CPUs online.",
+                cpu_count, host_cpu_count
+            ),
+            Ok(_) => {}
+            Err(err) => warn!("Cannot validate the vCPU count: {}", err),
+        }
+
+        Ok(VmSpec {
+            cpu_vendor_id,
+            cpu_index: self.cpu_index,
+            cpu_count,
+            sockets,
+            cpu_bits: (cpu_count / sockets > 1 && self.smt) as u8,
+            brand_string: match self.brand_string_mode {
+                BrandStringMode::Emulated => BrandString::from_vendor_id(&cpu_vendor_id),
+                BrandStringMode::HostScrubbedFrequency => BrandString::from_host_cpuid_scrubbed()
+                    .unwrap_or_else(|_| BrandString::from_vendor_id(&cpu_vendor_id)),
+            },
+            sort_entries: self.sort_entries,
+            enqcmd: self.enqcmd,
+            pconfig: self.pconfig,
+            amx: self.amx,
+            mpx: self.mpx,
+            fsgsbase: self.fsgsbase,
+            serialize: self.serialize,
+            enable_amd_sev: self.enable_amd_sev,
+            paranoid_timing: self.paranoid_timing,
+            cache_id_policy: self.cache_id_policy,
+            tlb_geometry: self.tlb_geometry,
+            cpu_template: self.cpu_template,
+        })
+    }
 }
 
 /// Errors associated with processing the CPUID leaves.
@@ -64,11 +590,175 @@ pub enum Error {
     /// The operation is not permitted for the current vendor
     #[error("The operation is not permitted for the current vendor.")]
     InvalidVendor,
+    /// The VM must have at least one vCPU.
+    #[error("The vCPU count must be at least 1.")]
+    InvalidCpuCount,
+    /// The index of the current vCPU is not lower than the vCPU count.
+    #[error("Invalid vCPU index {0}: it must be lower than the vCPU count.")]
+    InvalidCpuIndex(u8),
+    /// The vCPUs can't be evenly spread across the sockets.
+    #[error("Invalid socket count {0}: the vCPUs must be evenly spread across the sockets.")]
+    InvalidSocketCount(u8),
     /// The maximum number of addressable logical CPUs cannot be stored in an `u8`.
     #[error("The maximum number of addressable logical CPUs cannot be stored in an `u8`.")]
     VcpuCountOverflow,
+    /// The number of host CPUs could not be determined.
+    #[error("Failed to get the number of host CPUs: {0}")]
+    HostCpuCount(String),
+    /// The synthetic CPU model cannot be encoded in the cpuid.
+    #[error("Invalid synthetic CPU model: {0}.")]
+    InvalidSyntheticModel(&'static str),
+    /// A TLB geometry cannot be encoded in leaf 0x18.
+    #[error("Invalid TLB geometry: {0}.")]
+    InvalidTlbGeometry(&'static str),
+    /// The binary representation of a cpuid is malformed.
+    #[error("Invalid cpuid binary representation: {0}.")]
+    InvalidBytes(&'static str),
+    /// A subleaf required by the transformer is not present in the cpuid.
+    #[error("Leaf 0x{function:x} subleaf 0x{index:x} is required but missing.")]
+    MissingSubleaf {
+        /// The leaf function.
+        function: u32,
+        /// The subleaf index.
+        index: u32,
+    },
+}
+
+/// Invariants that don't hold on a processed CPUID.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CpuidViolation {
+    /// A mandatory leaf is missing.
+    #[error("Leaf 0x{function:x} index 0x{index:x} is missing.")]
+    MissingLeaf {
+        /// The leaf function.
+        function: u32,
+        /// The leaf index.
+        index: u32,
+    },
+    /// A leaf that must not be exposed is present.
+    #[error("Leaf 0x{function:x} index 0x{index:x} must not be present.")]
+    UnexpectedLeaf {
+        /// The leaf function.
+        function: u32,
+        /// The leaf index.
+        index: u32,
+    },
+    /// A feature bit doesn't have the expected value.
+    #[error(
+        "Bit {bit} of {register} in leaf 0x{function:x} index 0x{index:x} should be {expected}."
+    )]
+    FeatureBit {
+        /// The leaf function.
+        function: u32,
+        /// The leaf index.
+        index: u32,
+        /// The register holding the feature bit.
+        register: &'static str,
+        /// The index of the feature bit.
+        bit: u32,
+        /// The expected value of the feature bit.
+        expected: bool,
+    },
+    /// A field doesn't have the value expected for the VM specification.
+    #[error(
+        "The {register} field of leaf 0x{function:x} index 0x{index:x} is {actual}, expected \
+         {expected}."
+    )]
+    FieldMismatch {
+        /// The leaf function.
+        function: u32,
+        /// The leaf index.
+        index: u32,
+        /// The register holding the field.
+        register: &'static str,
+        /// The expected value.
+        expected: u32,
+        /// The actual value.
+        actual: u32,
+    },
+}
+
+/// Inconsistencies between the XSAVE state components enabled in XCR0 and the features
+/// enabling them.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum XsaveError {
+    /// State components are enabled in XCR0 (leaf 0xD sub-leaf 0) while the feature they
+    /// belong to is hidden.
+    #[error("XCR0 state components 0x{components:x} are enabled without the {feature} feature.")]
+    ComponentsWithoutFeature {
+        /// The mask of the offending state components.
+        components: u64,
+        /// The name of the hidden feature.
+        feature: &'static str,
+    },
 }
 
+/// Must-have features hidden from the guest, which can't boot without them.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BootabilityError {
+    /// The leaf reporting must-have features is missing.
+    #[error("Leaf 0x{0:x} is missing.")]
+    MissingLeaf(u32),
+    /// A must-have feature is hidden.
+    #[error("The {0} feature is hidden, the guest can't boot without it.")]
+    MissingFeature(&'static str),
+    /// LA57 is set, but the linear address width of leaf 0x80000008 is too narrow for 5-level
+    /// paging.
+    #[error("LA57 is set, but the linear address width is only {0} bits.")]
+    La57Mismatch(u32),
+}
+
+/// Advisory findings about the features exposed by a CPUID, as returned by `lint_cpuid`.
+///
+/// Unlike the errors of the `validate_*` functions, these don't prevent the guest from
+/// booting, but usually point to a template exposing more than intended.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Lint {
+    /// A deprecated feature is exposed.
+    #[error("The deprecated {feature} feature is exposed: {reason}.")]
+    DeprecatedFeature {
+        /// The name of the feature.
+        feature: &'static str,
+        /// Why the feature shouldn't be exposed.
+        reason: &'static str,
+    },
+    /// A feature is exposed without the leaf enumerating its capabilities.
+    #[error("The {feature} feature is exposed without its capabilities in leaf 0x{function:x}.")]
+    MissingCapabilities {
+        /// The name of the feature.
+        feature: &'static str,
+        /// The leaf enumerating the capabilities of the feature.
+        function: u32,
+    },
+}
+
+fn violations_to_result(violations: Vec<CpuidViolation>) -> Result<(), Vec<CpuidViolation>> {
+    match violations.is_empty() {
+        true => Ok(()),
+        false => Err(violations),
+    }
+}
+
+/// Non-fatal issues found while processing the CPUID leaves.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CpuidWarning {
+    /// The host reports an L1 cache size of 0.
+    #[error("The host reports a zero L1 cache size (leaf 0x80000005), passing it through.")]
+    ZeroL1CacheSize,
+}
+
+/// Function transforming a single cpuid entry, as returned by
+/// `CpuidTransformer::entry_transformer_fn`.
+///
+/// The function is called once per entry whose leaf it was registered for, in the order in
+/// which the entries appear in the cpuid. It only gets to see the entry it was called for,
+/// which may or may not have already been modified by the transformers of previous entries.
+///
+/// The function is called for every index of the leaf, so filtering the indexes that it
+/// doesn't handle (most of the time anything other than 0) is its own responsibility.
+///
+/// Returning an `Err` aborts the whole `process_cpuid` call, leaving the cpuid partially
+/// transformed.
 pub type EntryTransformerFn =
     fn(entry: &mut kvm_cpuid_entry2, vm_spec: &VmSpec) -> Result<(), Error>;
 
@@ -77,12 +767,57 @@ pub trait CpuidTransformer {
     /// Trait main function. It processes the cpuid and makes the desired transformations.
     /// The default logic can be overwritten if needed. For example see `AmdCpuidTransformer`.
     fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
-        self.process_entries(cpuid, vm_spec)
+        self.check_required_subleaves(cpuid)?;
+        self.process_entries(cpuid, vm_spec)?;
+        common::update_xsave_user_state_entries(cpuid)?;
+        common::update_xsave_supervisor_state_entries(cpuid)?;
+        if vm_spec.sort_entries {
+            cpuid.sort_entries();
+        }
+
+        Ok(())
+    }
+
+    /// Same as `process_cpuid`, but the transformation is applied on a copy of `cpuid`, which is
+    /// returned. The input is left untouched.
+    fn transformed(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<CpuId, Error> {
+        let mut transformed = cpuid.clone();
+        self.process_cpuid(&mut transformed, vm_spec)?;
+
+        Ok(transformed)
+    }
+
+    /// The `(function, index)` pairs that must be present for the transformation to be complete.
+    fn required_subleaves(&self) -> &[(u32, u32)] {
+        &[]
+    }
+
+    /// Checks that all the subleaves returned by `required_subleaves` are present.
+    fn check_required_subleaves(&self, cpuid: &CpuId) -> Result<(), Error> {
+        match self
+            .required_subleaves()
+            .iter()
+            .find(|(function, index)| cpuid.get_entry(*function, *index).is_none())
+        {
+            Some(&(function, index)) => Err(Error::MissingSubleaf { function, index }),
+            None => Ok(()),
+        }
     }
 
     /// Iterates through all the cpuid entries and calls the associated transformer for each one.
     fn process_entries(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
-        for entry in cpuid.as_mut_slice().iter_mut() {
+        self.process_slice(cpuid.as_mut_slice(), vm_spec)
+    }
+
+    /// Calls the associated transformer for each one of the raw cpuid entries.
+    ///
+    /// This is useful for callers already holding the KVM entries outside of a `CpuId`.
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        for entry in entries.iter_mut() {
             let maybe_transformer_fn = self.entry_transformer_fn(entry);
 
             if let Some(transformer_fn) = maybe_transformer_fn {
@@ -93,12 +828,83 @@ pub trait CpuidTransformer {
         Ok(())
     }
 
+    /// Same as `process_entries`, but the entries are transformed concurrently.
+    ///
+    /// The entry transformers are independent of each other, so this only pays off for very
+    /// large tables (e.g. when restoring many vCPUs from a snapshot). Note that any vendor
+    /// specific pre-processing done by `process_cpuid` is not applied.
+    #[cfg(feature = "rayon")]
+    fn process_cpuid_parallel(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error>
+    where
+        Self: Sync,
+    {
+        use rayon::prelude::*;
+
+        cpuid.as_mut_slice().par_iter_mut().try_for_each(|entry| {
+            match self.entry_transformer_fn(entry) {
+                Some(transformer_fn) => transformer_fn(entry, vm_spec),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Checks the invariants that must hold after the cpuid was processed.
+    ///
+    /// All the broken invariants are returned. Transformers can override this in order to
+    /// check vendor specific invariants on top of the ones from `common::verify_cpuid`.
+    fn verify(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+        let mut violations = Vec::new();
+        common::verify_cpuid(cpuid, vm_spec, &mut violations);
+
+        violations_to_result(violations)
+    }
+
     /// Gets the associated transformer for a cpuid entry
     fn entry_transformer_fn(&self, _entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
         None
     }
 }
 
+/// Transformers can be used through references (e.g. `&dyn CpuidTransformer`) wherever an
+/// owned transformer is expected, without boxing them.
+impl<T: CpuidTransformer + ?Sized> CpuidTransformer for &T {
+    fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        (**self).process_cpuid(cpuid, vm_spec)
+    }
+
+    fn transformed(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<CpuId, Error> {
+        (**self).transformed(cpuid, vm_spec)
+    }
+
+    fn required_subleaves(&self) -> &[(u32, u32)] {
+        (**self).required_subleaves()
+    }
+
+    fn check_required_subleaves(&self, cpuid: &CpuId) -> Result<(), Error> {
+        (**self).check_required_subleaves(cpuid)
+    }
+
+    fn process_entries(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        (**self).process_entries(cpuid, vm_spec)
+    }
+
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        (**self).process_slice(entries, vm_spec)
+    }
+
+    fn verify(&self, cpuid: &CpuId, vm_spec: &VmSpec) -> Result<(), Vec<CpuidViolation>> {
+        (**self).verify(cpuid, vm_spec)
+    }
+
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        (**self).entry_transformer_fn(entry)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -108,18 +914,265 @@ mod tests {
         let vm_spec = VmSpec::new(0, 1, true).unwrap();
         assert_eq!(vm_spec.cpu_bits, 0);
         assert_eq!(vm_spec.cpus_per_core(), 1);
+        assert!(!vm_spec.is_smt_enabled());
 
         let vm_spec = VmSpec::new(0, 1, false).unwrap();
         assert_eq!(vm_spec.cpu_bits, 0);
         assert_eq!(vm_spec.cpus_per_core(), 1);
+        assert!(!vm_spec.is_smt_enabled());
 
         let vm_spec = VmSpec::new(0, 2, false).unwrap();
         assert_eq!(vm_spec.cpu_bits, 0);
         assert_eq!(vm_spec.cpus_per_core(), 1);
+        assert!(!vm_spec.is_smt_enabled());
 
         let vm_spec = VmSpec::new(0, 2, true).unwrap();
         assert_eq!(vm_spec.cpu_bits, 1);
         assert_eq!(vm_spec.cpus_per_core(), 2);
+        assert!(vm_spec.is_smt_enabled());
+    }
+
+    #[test]
+    fn test_invalid_cpu_count() {
+        assert!(matches!(
+            VmSpec::new(0, 0, false),
+            Err(Error::InvalidCpuCount)
+        ));
+        assert!(matches!(
+            VmSpec::new(0, 0, true),
+            Err(Error::InvalidCpuCount)
+        ));
+
+        // 128 logical cpus per package is the largest power of 2 that fits in an `u8`.
+        let vm_spec = VmSpec::new(127, 128, false).unwrap();
+        assert_eq!(vm_spec.cpus_per_core(), 1);
+        let vm_spec = VmSpec::new(127, 128, true).unwrap();
+        assert_eq!(vm_spec.cpus_per_core(), 2);
+        assert_eq!(vm_spec.apic_id_width(), 7);
+
+        assert!(matches!(
+            VmSpec::new(0, 255, true),
+            Err(Error::VcpuCountOverflow)
+        ));
+        assert!(matches!(
+            VmSpec::new(0, 129, false),
+            Err(Error::VcpuCountOverflow)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_cpu_index() {
+        assert!(matches!(
+            VmSpec::new(1, 1, false),
+            Err(Error::InvalidCpuIndex(1))
+        ));
+        assert!(matches!(
+            VmSpec::new(255, 128, true),
+            Err(Error::InvalidCpuIndex(255))
+        ));
+        assert!(matches!(
+            VmSpec::builder().cpu_index(4).cpu_count(4).build(),
+            Err(Error::InvalidCpuIndex(4))
+        ));
+        // An empty VM is rejected by the vCPU count check first.
+        assert!(matches!(
+            VmSpec::new(0, 0, false),
+            Err(Error::InvalidCpuCount)
+        ));
+
+        assert!(VmSpec::new(0, 1, false).is_ok());
+        assert!(VmSpec::new(3, 4, true).is_ok());
+    }
+
+    #[test]
+    fn test_vmspec_builder() {
+        let vm_spec = VmSpec::builder().build().unwrap();
+        assert_eq!(vm_spec.cpu_index, 0);
+        assert_eq!(vm_spec.cpu_count, 1);
+        assert!(!vm_spec.is_smt_enabled());
+        assert!(!vm_spec.sort_entries);
+        assert!(!vm_spec.enqcmd);
+        assert!(vm_spec.fsgsbase);
+        assert!(vm_spec.serialize);
+        assert!(!vm_spec.enable_amd_sev);
+        assert_eq!(vm_spec.paranoid_timing, ParanoidTiming::Disabled);
+        assert_eq!(vm_spec.cache_id_policy, CacheIdPolicy::Remap);
+
+        let vm_spec = VmSpec::builder()
+            .cpu_index(3)
+            .cpu_count(4)
+            .smt(true)
+            .sort_entries(true)
+            .enqcmd(true)
+            .fsgsbase(false)
+            .serialize(false)
+            .enable_amd_sev(true)
+            .paranoid_timing(ParanoidTiming::Rdtscp)
+            .cache_id_policy(CacheIdPolicy::PreserveHost)
+            .build()
+            .unwrap();
+        assert_eq!(vm_spec.x2apic_id(), 3);
+        assert_eq!(vm_spec.cpu_count, 4);
+        assert_eq!(vm_spec.cpus_per_core(), 2);
+        assert!(vm_spec.sort_entries);
+        assert!(vm_spec.enqcmd);
+        assert!(!vm_spec.fsgsbase);
+        assert!(!vm_spec.serialize);
+        assert!(vm_spec.enable_amd_sev);
+        assert_eq!(vm_spec.paranoid_timing, ParanoidTiming::Rdtscp);
+        assert_eq!(vm_spec.cache_id_policy, CacheIdPolicy::PreserveHost);
+
+        // The host brand string keeps its model, but not its frequency.
+        let vm_spec = VmSpec::builder()
+            .brand_string_mode(BrandStringMode::HostScrubbedFrequency)
+            .build()
+            .unwrap();
+        assert!(vm_spec.brand_string.find_freq().is_none());
+    }
+
+    #[test]
+    fn test_vmspec_default() {
+        let vm_spec = VmSpec::default();
+        assert_eq!(vm_spec.cpu_vendor_id(), &get_vendor_id_from_host().unwrap());
+        assert_eq!(vm_spec.cpu_index, 0);
+        assert_eq!(vm_spec.cpu_count, 1);
+        assert!(!vm_spec.is_smt_enabled());
+    }
+
+    #[test]
+    fn test_vmspec_apic_id_width() {
+        let vm_spec = VmSpec::default();
+        assert_eq!(vm_spec.cpu_bits(), 0);
+        assert_eq!(vm_spec.apic_id_width(), 0);
+
+        let vm_spec = VmSpec::new(0, 2, false).unwrap();
+        assert_eq!(vm_spec.cpu_bits(), 0);
+        assert_eq!(vm_spec.apic_id_width(), 1);
+
+        let vm_spec = VmSpec::new(0, 2, true).unwrap();
+        assert_eq!(vm_spec.cpu_bits(), 1);
+        assert_eq!(vm_spec.apic_id_width(), 1);
+
+        // 3 vCPUs with SMT need 2 cores, so 1 SMT bit + 1 core bit.
+        let vm_spec = VmSpec::new(0, 3, true).unwrap();
+        assert_eq!(vm_spec.cpu_bits(), 1);
+        assert_eq!(vm_spec.apic_id_width(), 2);
+
+        // 6 vCPUs without SMT need 6 cores, so 3 core bits.
+        let vm_spec = VmSpec::new(0, 6, false).unwrap();
+        assert_eq!(vm_spec.cpu_bits(), 0);
+        assert_eq!(vm_spec.apic_id_width(), 3);
+
+        let vm_spec = VmSpec::new(0, 32, true).unwrap();
+        assert_eq!(vm_spec.cpu_bits(), 1);
+        assert_eq!(vm_spec.apic_id_width(), 5);
+    }
+
+    #[test]
+    fn test_vmspec_sockets() {
+        let x2apic_ids = |cpu_count: u8, sockets: u8, smt: bool| -> Vec<u32> {
+            (0..cpu_count)
+                .map(|cpu_index| {
+                    VmSpec::builder()
+                        .cpu_index(cpu_index)
+                        .cpu_count(cpu_count)
+                        .sockets(sockets)
+                        .smt(smt)
+                        .build()
+                        .unwrap()
+                        .x2apic_id()
+                })
+                .collect()
+        };
+
+        // 2 sockets of 2 cores with 2 threads each.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(5)
+            .cpu_count(8)
+            .sockets(2)
+            .smt(true)
+            .build()
+            .unwrap();
+        assert_eq!(vm_spec.sockets(), 2);
+        assert_eq!(vm_spec.cpus_per_socket(), 4);
+        assert_eq!(vm_spec.socket_id(), 1);
+        assert_eq!(vm_spec.cpu_bits(), 1);
+        assert_eq!(vm_spec.apic_id_width(), 2);
+        assert_eq!(x2apic_ids(8, 2, true), vec![0, 1, 2, 3, 4, 5, 6, 7]);
+
+        // 3 cpus per socket need 2 bits, so the second socket starts at 4.
+        assert_eq!(x2apic_ids(6, 2, false), vec![0, 1, 2, 4, 5, 6]);
+        // A single cpu per socket has no SMT sibling.
+        let vm_spec = VmSpec::builder()
+            .cpu_count(2)
+            .sockets(2)
+            .smt(true)
+            .build()
+            .unwrap();
+        assert!(!vm_spec.is_smt_enabled());
+        assert_eq!(x2apic_ids(2, 2, true), vec![0, 1]);
+
+        assert!(matches!(
+            VmSpec::builder().cpu_count(3).sockets(2).build(),
+            Err(Error::InvalidSocketCount(2))
+        ));
+        assert!(matches!(
+            VmSpec::builder().sockets(0).build(),
+            Err(Error::InvalidSocketCount(0))
+        ));
+    }
+
+    #[test]
+    fn test_vmspec_initial_apic_id() {
+        let vm_spec = VmSpec::new(3, 4, true).unwrap();
+        assert_eq!(vm_spec.initial_apic_id(), vm_spec.x2apic_id());
+
+        // 3 sockets of 85 cpus need 7 bits each, so the last socket starts at 256.
+        let vm_spec = VmSpec::builder()
+            .cpu_index(254)
+            .cpu_count(255)
+            .sockets(3)
+            .build()
+            .unwrap();
+        assert_eq!(vm_spec.x2apic_id(), 340);
+        assert_eq!(vm_spec.initial_apic_id(), 84);
+    }
+
+    #[test]
+    fn test_vmspec_apic_ids() {
+        let cpu_count = 4;
+        let apic_ids = VmSpec::apic_ids(cpu_count, true);
+        assert_eq!(apic_ids, vec![0, 1, 2, 3]);
+
+        let x2apic_ids: Vec<u32> = (0..cpu_count)
+            .map(|cpu_index| VmSpec::new(cpu_index, cpu_count, true).unwrap().x2apic_id())
+            .collect();
+        assert_eq!(apic_ids, x2apic_ids);
+        assert_eq!(VmSpec::apic_ids(3, false), vec![0, 1, 2]);
+        assert_eq!(VmSpec::apic_ids(1, true), vec![0]);
+
+        // The APIC IDs of the VM don't depend on the cpu the spec is built for.
+        assert_eq!(
+            VmSpec::new(3, cpu_count, true).unwrap().x2apic_ids(),
+            apic_ids
+        );
+        assert_eq!(VmSpec::new(0, 1, false).unwrap().x2apic_ids(), vec![0]);
+
+        // 3 cpus per socket need 2 bits, so the second socket starts at 4.
+        let vm_spec = VmSpec::builder().cpu_count(6).sockets(2).build().unwrap();
+        let x2apic_ids: Vec<u32> = (0..6)
+            .map(|cpu_index| {
+                VmSpec::builder()
+                    .cpu_index(cpu_index)
+                    .cpu_count(6)
+                    .sockets(2)
+                    .build()
+                    .unwrap()
+                    .x2apic_id()
+            })
+            .collect();
+        assert_eq!(vm_spec.x2apic_ids(), vec![0, 1, 2, 4, 5, 6]);
+        assert_eq!(vm_spec.x2apic_ids(), x2apic_ids);
     }
 
     const PROCESSED_FN: u32 = 1;
@@ -142,15 +1195,113 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_process_slice() {
+        let vm_spec = VmSpec::default();
+        let mut entries = [
+            kvm_cpuid_entry2 {
+                function: PROCESSED_FN,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: PROCESSED_FN + 1,
+                ..Default::default()
+            },
+        ];
+
+        assert!(MockCpuidTransformer {}
+            .process_slice(&mut entries, &vm_spec)
+            .is_ok());
+
+        assert_eq!(entries[0].index, EXPECTED_INDEX);
+        assert_ne!(entries[1].index, EXPECTED_INDEX);
+    }
+
+    #[test]
+    fn test_transformer_reference() {
+        fn process<T: CpuidTransformer>(transformer: T, entries: &mut [kvm_cpuid_entry2]) {
+            assert!(transformer
+                .process_slice(entries, &VmSpec::default())
+                .is_ok());
+        }
+
+        let transformer = MockCpuidTransformer {};
+        let dyn_transformer: &dyn CpuidTransformer = &transformer;
+        let new_entries = || {
+            [kvm_cpuid_entry2 {
+                function: PROCESSED_FN,
+                ..Default::default()
+            }]
+        };
+
+        let mut entries = new_entries();
+        process(&transformer, &mut entries);
+        assert_eq!(entries[0].index, EXPECTED_INDEX);
+
+        let mut entries = new_entries();
+        process(dyn_transformer, &mut entries);
+        assert_eq!(entries[0].index, EXPECTED_INDEX);
+
+        // The overridden methods of the referenced transformer are used.
+        let logging_transformer = logging::LoggingTransformer {
+            inner: dyn_transformer,
+            log_level: logger::Level::Debug,
+        };
+        let mut entries = new_entries();
+        process(&logging_transformer, &mut entries);
+        assert_eq!(entries[0].index, EXPECTED_INDEX);
+    }
+
+    #[test]
+    #[cfg(feature = "rayon")]
+    fn test_process_cpuid_parallel() {
+        let num_entries = 64;
+
+        let mut cpuid = CpuId::new(num_entries).unwrap();
+        let vm_spec = VmSpec::default();
+        for entry in cpuid.as_mut_slice().iter_mut().step_by(2) {
+            entry.function = PROCESSED_FN;
+        }
+        assert!(MockCpuidTransformer {}
+            .process_cpuid_parallel(&mut cpuid, &vm_spec)
+            .is_ok());
+
+        assert_eq!(cpuid.as_slice().len(), num_entries);
+        for entry in cpuid.as_slice().iter() {
+            match entry.function {
+                PROCESSED_FN => assert_eq!(entry.index, EXPECTED_INDEX),
+                _ => assert_ne!(entry.index, EXPECTED_INDEX),
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_cpuid_sort_entries() {
+        let mut cpuid = CpuId::new(3).unwrap();
+        cpuid.as_mut_slice()[0].function = 0x8000_0000;
+        cpuid.as_mut_slice()[1].function = PROCESSED_FN;
+        cpuid.as_mut_slice()[2].function = 0x4000_0000;
+
+        let mut vm_spec = VmSpec::default();
+        let transformer = MockCpuidTransformer {};
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        assert_eq!(cpuid.as_slice()[0].function, 0x8000_0000);
+
+        vm_spec.set_sort_entries(true);
+        assert!(transformer.process_cpuid(&mut cpuid, &vm_spec).is_ok());
+        let functions: Vec<u32> = cpuid.as_slice().iter().map(|e| e.function).collect();
+        assert_eq!(functions, vec![PROCESSED_FN, 0x4000_0000, 0x8000_0000]);
+    }
+
     #[test]
     fn test_process_cpuid() {
         let num_entries = 5;
 
         let mut cpuid = CpuId::new(num_entries).unwrap();
-        let vm_spec = VmSpec::new(0, 1, false);
+        let vm_spec = VmSpec::default();
         cpuid.as_mut_slice()[0].function = PROCESSED_FN;
         assert!(MockCpuidTransformer {}
-            .process_cpuid(&mut cpuid, &vm_spec.unwrap())
+            .process_cpuid(&mut cpuid, &vm_spec)
             .is_ok());
 
         assert!(cpuid.as_mut_slice().len() == num_entries);
@@ -165,4 +1316,59 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_transformed() {
+        let cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: PROCESSED_FN,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        let transformed = MockCpuidTransformer {}
+            .transformed(&cpuid, &VmSpec::default())
+            .unwrap();
+
+        assert_eq!(transformed.as_slice()[0].index, EXPECTED_INDEX);
+        // The input is not modified.
+        assert_eq!(cpuid.as_slice()[0].index, 0);
+    }
+
+    struct SubleafCpuidTransformer {}
+
+    impl CpuidTransformer for SubleafCpuidTransformer {
+        fn required_subleaves(&self) -> &[(u32, u32)] {
+            &[(0x7, 0), (0x7, 1)]
+        }
+    }
+
+    #[test]
+    fn test_process_cpuid_missing_subleaf() {
+        let vm_spec = VmSpec::default();
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: 0x7,
+            index: 0,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        assert!(matches!(
+            SubleafCpuidTransformer {}.process_cpuid(&mut cpuid, &vm_spec),
+            Err(Error::MissingSubleaf {
+                function: 0x7,
+                index: 1
+            })
+        ));
+
+        cpuid
+            .push(kvm_cpuid_entry2 {
+                function: 0x7,
+                index: 1,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(SubleafCpuidTransformer {}
+            .process_cpuid(&mut cpuid, &vm_spec)
+            .is_ok());
+    }
 }
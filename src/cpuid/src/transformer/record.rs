@@ -0,0 +1,443 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::sync::Mutex;
+
+use kvm_bindings::{kvm_cpuid_entry2, CpuId};
+use serde::{Deserialize, Serialize};
+
+use super::*;
+
+/// A register of a cpuid entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpuidRegister {
+    /// EAX.
+    Eax,
+    /// EBX.
+    Ebx,
+    /// ECX.
+    Ecx,
+    /// EDX.
+    Edx,
+}
+
+impl CpuidRegister {
+    const ALL: [CpuidRegister; 4] = [
+        CpuidRegister::Eax,
+        CpuidRegister::Ebx,
+        CpuidRegister::Ecx,
+        CpuidRegister::Edx,
+    ];
+
+    fn get(&self, entry: &kvm_cpuid_entry2) -> u32 {
+        match self {
+            CpuidRegister::Eax => entry.eax,
+            CpuidRegister::Ebx => entry.ebx,
+            CpuidRegister::Ecx => entry.ecx,
+            CpuidRegister::Edx => entry.edx,
+        }
+    }
+
+    fn get_mut<'a>(&self, entry: &'a mut kvm_cpuid_entry2) -> &'a mut u32 {
+        match self {
+            CpuidRegister::Eax => &mut entry.eax,
+            CpuidRegister::Ebx => &mut entry.ebx,
+            CpuidRegister::Ecx => &mut entry.ecx,
+            CpuidRegister::Edx => &mut entry.edx,
+        }
+    }
+}
+
+/// A register write done by a transformer, as recorded by `RecordingTransformer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegisterWrite {
+    /// The leaf of the written entry.
+    pub function: u32,
+    /// The subleaf of the written entry.
+    pub index: u32,
+    /// The written register.
+    pub reg: CpuidRegister,
+    /// The value of the register before the write.
+    pub old: u32,
+    /// The value of the register after the write.
+    pub new: u32,
+}
+
+impl RegisterWrite {
+    /// Applies the bits set and cleared by the write to `value`, keeping the other bits.
+    fn apply(&self, value: u32) -> u32 {
+        let set = self.new & !self.old;
+        let cleared = self.old & !self.new;
+        (value | set) & !cleared
+    }
+}
+
+/// A cpuid entry added by a transformer, as recorded by `RecordingTransformer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedEntry {
+    /// The leaf of the entry.
+    pub function: u32,
+    /// The subleaf of the entry.
+    pub index: u32,
+    /// The KVM flags of the entry.
+    pub flags: u32,
+    /// The value of EAX.
+    pub eax: u32,
+    /// The value of EBX.
+    pub ebx: u32,
+    /// The value of ECX.
+    pub ecx: u32,
+    /// The value of EDX.
+    pub edx: u32,
+}
+
+impl From<&kvm_cpuid_entry2> for RecordedEntry {
+    fn from(entry: &kvm_cpuid_entry2) -> Self {
+        RecordedEntry {
+            function: entry.function,
+            index: entry.index,
+            flags: entry.flags,
+            eax: entry.eax,
+            ebx: entry.ebx,
+            ecx: entry.ecx,
+            edx: entry.edx,
+        }
+    }
+}
+
+impl From<&RecordedEntry> for kvm_cpuid_entry2 {
+    fn from(entry: &RecordedEntry) -> Self {
+        kvm_cpuid_entry2 {
+            function: entry.function,
+            index: entry.index,
+            flags: entry.flags,
+            eax: entry.eax,
+            ebx: entry.ebx,
+            ecx: entry.ecx,
+            edx: entry.edx,
+            ..Default::default()
+        }
+    }
+}
+
+/// A change done by a transformer to a cpuid, as recorded by `RecordingTransformer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CpuidChange {
+    /// An entry was added. The entry is recorded whole, so the entries whose registers are all
+    /// 0 are kept as well.
+    Added(RecordedEntry),
+    /// The entry of the given leaf and subleaf was removed.
+    Removed {
+        /// The leaf of the removed entry.
+        function: u32,
+        /// The subleaf of the removed entry.
+        index: u32,
+    },
+    /// The flags of an existing entry were changed.
+    Flags {
+        /// The leaf of the entry.
+        function: u32,
+        /// The subleaf of the entry.
+        index: u32,
+        /// The new flags of the entry.
+        flags: u32,
+    },
+    /// A register of an existing entry was written.
+    Write(RegisterWrite),
+}
+
+/// Cpuid transformer wrapper that records every change done by the inner transformer.
+///
+/// The log captures the exact effect of a pipeline as a portable artifact, which can be
+/// serialized and replayed onto another cpuid with `ReplayTransformer`. Unlike the template
+/// masks, the log also holds the values of the non feature fields (e.g. the topology), along
+/// with the entries added and removed by the inner transformer.
+pub struct RecordingTransformer<T: CpuidTransformer> {
+    /// The transformer doing the actual work.
+    pub inner: T,
+    log: Mutex<Vec<CpuidChange>>,
+}
+
+impl<T: CpuidTransformer> RecordingTransformer<T> {
+    /// Creates a transformer recording the writes of `inner`.
+    pub fn new(inner: T) -> Self {
+        RecordingTransformer {
+            inner,
+            log: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns the changes recorded so far and clears the log. For each processed cpuid, the
+    /// removed entries come first, followed by the added and written entries in table order.
+    pub fn take_log(&self) -> Vec<CpuidChange> {
+        std::mem::take(&mut *self.log.lock().expect("Poisoned lock"))
+    }
+}
+
+impl<T: CpuidTransformer> CpuidTransformer for RecordingTransformer<T> {
+    fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+        // The inner transformer may add or remove entries before processing them, so
+        // the whole table is compared instead of relying on `process_entries`.
+        let before = cpuid.clone();
+        self.inner.process_cpuid(cpuid, vm_spec)?;
+
+        let mut log = self.log.lock().expect("Poisoned lock");
+        for old in before.as_slice() {
+            if cpuid.get_entry(old.function, old.index).is_none() {
+                log.push(CpuidChange::Removed {
+                    function: old.function,
+                    index: old.index,
+                });
+            }
+        }
+        for entry in cpuid.as_slice() {
+            let old = match before.get_entry(entry.function, entry.index) {
+                Some(old) => old,
+                None => {
+                    log.push(CpuidChange::Added(RecordedEntry::from(entry)));
+                    continue;
+                }
+            };
+            if old.flags != entry.flags {
+                log.push(CpuidChange::Flags {
+                    function: entry.function,
+                    index: entry.index,
+                    flags: entry.flags,
+                });
+            }
+            for reg in CpuidRegister::ALL {
+                if reg.get(old) != reg.get(entry) {
+                    log.push(CpuidChange::Write(RegisterWrite {
+                        function: entry.function,
+                        index: entry.index,
+                        reg,
+                        old: reg.get(old),
+                        new: reg.get(entry),
+                    }));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn required_subleaves(&self) -> &[(u32, u32)] {
+        self.inner.required_subleaves()
+    }
+
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        self.inner.entry_transformer_fn(entry)
+    }
+}
+
+/// Cpuid transformer replaying the changes recorded by `RecordingTransformer`.
+///
+/// The bits set and cleared by each write are replayed, so onto the base table of the
+/// recording the output of the recorded pipeline is reproduced exactly, while onto another
+/// table the bits the pipeline didn't touch keep their base values. The added entries are
+/// inserted with their recorded flags and values, replacing the entry already in the table if
+/// any, and the removed ones are removed. The writes and flags of the entries missing from the
+/// table are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayTransformer {
+    /// The changes to replay, in order.
+    pub log: Vec<CpuidChange>,
+}
+
+impl CpuidTransformer for ReplayTransformer {
+    fn process_cpuid(&self, cpuid: &mut CpuId, _vm_spec: &VmSpec) -> Result<(), Error> {
+        for change in &self.log {
+            match change {
+                CpuidChange::Added(added) => {
+                    match cpuid.get_entry_mut(added.function, added.index) {
+                        Some(entry) => *entry = kvm_cpuid_entry2::from(added),
+                        None => cpuid
+                            .push(kvm_cpuid_entry2::from(added))
+                            .map_err(Error::Fam)?,
+                    }
+                }
+                CpuidChange::Removed { function, index } => {
+                    cpuid.retain(|entry| entry.function != *function || entry.index != *index)
+                }
+                CpuidChange::Flags {
+                    function,
+                    index,
+                    flags,
+                } => {
+                    if let Some(entry) = cpuid.get_entry_mut(*function, *index) {
+                        entry.flags = *flags;
+                    }
+                }
+                CpuidChange::Write(write) => {
+                    if let Some(entry) = cpuid.get_entry_mut(write.function, write.index) {
+                        let value = write.reg.get_mut(entry);
+                        *value = write.apply(*value);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry_transformer_fn(&self, _entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvm_bindings::KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
+
+    use super::*;
+
+    fn transform_leaf_0x1(entry: &mut kvm_cpuid_entry2, vm_spec: &VmSpec) -> Result<(), Error> {
+        entry.eax = 0x0005_0657;
+        entry.ebx = (vm_spec.initial_apic_id() << 24) | (entry.ebx & 0x00ff_ffff);
+        entry.ecx = (entry.ecx | (1 << 31)) & !1;
+
+        Ok(())
+    }
+
+    struct MockCpuidTransformer {}
+
+    impl CpuidTransformer for MockCpuidTransformer {
+        fn process_cpuid(&self, cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+            self.process_entries(cpuid, vm_spec)?;
+            cpuid.retain(|entry| entry.function != 0x6);
+            if let Some(entry) = cpuid.get_entry_mut(0x7, 0) {
+                entry.flags = KVM_CPUID_FLAG_SIGNIFCANT_INDEX;
+            }
+            cpuid
+                .push(kvm_cpuid_entry2 {
+                    function: 0xd,
+                    index: 1,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    eax: 0xf,
+                    ..Default::default()
+                })
+                .map_err(Error::Fam)?;
+            // An entry with a significant index 0 and all its registers cleared.
+            cpuid
+                .push(kvm_cpuid_entry2 {
+                    function: 0xd,
+                    index: 0,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    ..Default::default()
+                })
+                .map_err(Error::Fam)
+        }
+
+        fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+            match entry.function {
+                0x1 => Some(transform_leaf_0x1),
+                _ => None,
+            }
+        }
+    }
+
+    fn base_cpuid() -> CpuId {
+        CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: 0x0,
+                eax: 0xd,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x1,
+                eax: 0x0006_06a6,
+                ebx: 0x0100_0800,
+                ecx: 0x0000_0201,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x6,
+                eax: 0x4,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x7,
+                ebx: 0x1,
+                ..Default::default()
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_record_replay() {
+        let vm_spec = VmSpec::new(2, 4, false).unwrap();
+        let transformer = RecordingTransformer::new(MockCpuidTransformer {});
+        let recorded = transformer.transformed(&base_cpuid(), &vm_spec).unwrap();
+
+        let log = transformer.take_log();
+        assert_eq!(
+            log,
+            [
+                CpuidChange::Removed {
+                    function: 0x6,
+                    index: 0,
+                },
+                CpuidChange::Write(RegisterWrite {
+                    function: 0x1,
+                    index: 0,
+                    reg: CpuidRegister::Eax,
+                    old: 0x0006_06a6,
+                    new: 0x0005_0657,
+                }),
+                CpuidChange::Write(RegisterWrite {
+                    function: 0x1,
+                    index: 0,
+                    reg: CpuidRegister::Ebx,
+                    old: 0x0100_0800,
+                    new: 0x0200_0800,
+                }),
+                CpuidChange::Write(RegisterWrite {
+                    function: 0x1,
+                    index: 0,
+                    reg: CpuidRegister::Ecx,
+                    old: 0x0000_0201,
+                    new: 0x8000_0200,
+                }),
+                CpuidChange::Flags {
+                    function: 0x7,
+                    index: 0,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                },
+                CpuidChange::Added(RecordedEntry {
+                    function: 0xd,
+                    index: 1,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    eax: 0xf,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                }),
+                CpuidChange::Added(RecordedEntry {
+                    function: 0xd,
+                    index: 0,
+                    flags: KVM_CPUID_FLAG_SIGNIFCANT_INDEX,
+                    eax: 0,
+                    ebx: 0,
+                    ecx: 0,
+                    edx: 0,
+                }),
+            ]
+        );
+        assert!(transformer.take_log().is_empty());
+
+        // The log survives a serialization round trip and reproduces the recorded output.
+        let replay: ReplayTransformer =
+            serde_json::from_str(&serde_json::to_string(&ReplayTransformer { log }).unwrap())
+                .unwrap();
+        let replayed = replay.transformed(&base_cpuid(), &vm_spec).unwrap();
+        assert_eq!(replayed.as_slice(), recorded.as_slice());
+
+        // Onto another table, the bits the pipeline didn't touch are kept.
+        let mut other = base_cpuid();
+        other.as_mut_slice()[1].ecx |= 1 << 8;
+        let replayed = replay.transformed(&other, &vm_spec).unwrap();
+        assert_eq!(replayed.as_slice()[1].ecx, 0x8000_0300);
+        assert_eq!(replayed.keys(), recorded.keys());
+    }
+}
@@ -0,0 +1,439 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::kvm_cpuid_entry2;
+
+use super::*;
+use crate::bit_helper::{BitHelper, BitRange, BitRangeExt};
+use crate::cpu_leaf::*;
+
+/// The highest family that can be encoded in leaf 0x1: 0xF plus the 8 bit extended family.
+const MAX_FAMILY: u16 = 0xF + 0xFF;
+/// The brand string leaves hold 48 bytes, including the NULL terminator.
+const MAX_BRAND_LEN: usize = 47;
+
+/// The geometry of one of the caches described by the deterministic cache parameters leaves
+/// (0x4 on Intel, 0x8000001D on AMD).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheGeometry {
+    /// The cache level, starting at 1.
+    pub level: u32,
+    /// The cache type, as encoded in EAX: 1 for data, 2 for instruction and 3 for unified caches.
+    pub cache_type: u32,
+    /// The number of ways of associativity.
+    pub ways: u32,
+    /// The number of physical line partitions.
+    pub partitions: u32,
+    /// The size of a cache line, in bytes.
+    pub line_size: u32,
+    /// The number of sets.
+    pub sets: u32,
+}
+
+impl CacheGeometry {
+    /// Returns the size of the cache, in bytes.
+    pub fn size(&self) -> u64 {
+        u64::from(self.ways)
+            * u64::from(self.partitions)
+            * u64::from(self.line_size)
+            * u64::from(self.sets)
+    }
+
+    fn is_valid(&self) -> bool {
+        // Every field is stored as its value - 1.
+        let fits = |value: u32, bit_range: &BitRange| {
+            value > 0 && value - 1 <= bit_range.get_mask() >> bit_range.lsb_index
+        };
+
+        fits(self.ways, &leaf_cache_parameters::ebx::WAYS_BITRANGE)
+            && fits(
+                self.partitions,
+                &leaf_cache_parameters::ebx::PARTITIONS_BITRANGE,
+            )
+            && fits(
+                self.line_size,
+                &leaf_cache_parameters::ebx::LINE_SIZE_BITRANGE,
+            )
+            && self.sets > 0
+    }
+}
+
+/// Writes the family/model/stepping fields, as laid out in leaf 0x1 EAX.
+///
+/// The values are not validated, see `SyntheticModelTransformer` for the supported ranges.
+pub(crate) fn write_signature(eax: &mut u32, family: u16, model: u8, stepping: u8) {
+    use crate::cpu_leaf::leaf_0x1::eax::*;
+
+    let (family, extended_family) = match family {
+        family if family < 0xF => (family, 0),
+        family => (0xF, family - 0xF),
+    };
+
+    eax.write_bits_in_range(&STEPPING_BITRANGE, u32::from(stepping))
+        .write_bits_in_range(&PROCESSOR_MODEL_BITRANGE, u32::from(model & 0xF))
+        .write_bits_in_range(&PROCESSOR_FAMILY_BITRANGE, u32::from(family))
+        .write_bits_in_range(&EXTENDED_PROCESSOR_MODEL_BITRANGE, u32::from(model >> 4))
+        .write_bits_in_range(&EXTENDED_FAMILY_ID_BITRANGE, u32::from(extended_family));
+}
+
+/// Cpuid transformer exposing the same synthetic CPU model to every guest, regardless of the
+/// host it runs on.
+///
+/// The family/model/stepping signature (leaves 0x1 and 0x80000001), the brand string and the
+/// geometry of the caches described by the deterministic cache parameters leaves are all set
+/// from the same model. The cache sharing information is left to the vendor transformer, so
+/// this is meant to run after `filter_cpuid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyntheticModelTransformer {
+    /// The displayed family, up to 0x10E.
+    pub family: u16,
+    /// The displayed model. Models above 0xF can only be encoded for the family 0x6 and the
+    /// families starting at 0xF.
+    pub model: u8,
+    /// The stepping, up to 0xF.
+    pub stepping: u8,
+    /// The ASCII brand string, up to 47 characters.
+    pub brand: String,
+    /// The caches to describe. The host caches without a matching level and type are left
+    /// untouched.
+    pub cache_geometry: Vec<CacheGeometry>,
+}
+
+impl SyntheticModelTransformer {
+    fn validate(&self) -> Result<(), Error> {
+        if self.family > MAX_FAMILY {
+            return Err(Error::InvalidSyntheticModel("family out of range"));
+        }
+        if self.model > 0xF && self.family != 0x6 && self.family < 0xF {
+            return Err(Error::InvalidSyntheticModel(
+                "extended model used with a family that doesn't support it",
+            ));
+        }
+        if self.stepping > 0xF {
+            return Err(Error::InvalidSyntheticModel("stepping out of range"));
+        }
+        if !self.brand.is_ascii() || self.brand.len() > MAX_BRAND_LEN {
+            return Err(Error::InvalidSyntheticModel(
+                "the brand string must be ASCII and at most 47 characters long",
+            ));
+        }
+        if !self.cache_geometry.iter().all(CacheGeometry::is_valid) {
+            return Err(Error::InvalidSyntheticModel("cache geometry out of range"));
+        }
+
+        Ok(())
+    }
+
+    fn write_signature(&self, eax: &mut u32) {
+        write_signature(eax, self.family, self.model, self.stepping);
+    }
+
+    fn update_cache_entry(&self, entry: &mut kvm_cpuid_entry2) {
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+
+        let level = entry.eax.read_bits_in_range(&eax::CACHE_LEVEL_BITRANGE);
+        let cache_type = entry.eax.read_bits_in_range(&eax::CACHE_TYPE_BITRANGE);
+
+        if let Some(geometry) = self
+            .cache_geometry
+            .iter()
+            .find(|geometry| geometry.level == level && geometry.cache_type == cache_type)
+        {
+            entry
+                .ebx
+                .write_bits_in_range(&ebx::WAYS_BITRANGE, geometry.ways - 1)
+                .write_bits_in_range(&ebx::PARTITIONS_BITRANGE, geometry.partitions - 1)
+                .write_bits_in_range(&ebx::LINE_SIZE_BITRANGE, geometry.line_size - 1);
+            entry.ecx = geometry.sets - 1;
+        }
+    }
+}
+
+impl CpuidTransformer for SyntheticModelTransformer {
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        _vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        self.validate()?;
+        let brand_string = BrandString::from_bytes_unchecked(self.brand.as_bytes());
+
+        for entry in entries.iter_mut() {
+            match entry.function {
+                leaf_0x1::LEAF_NUM => self.write_signature(&mut entry.eax),
+                // AMD mirrors the signature in leaf 0x80000001, while it's reserved on Intel.
+                leaf_0x80000001::LEAF_NUM if entry.eax != 0 => self.write_signature(&mut entry.eax),
+                leaf_0x4::LEAF_NUM | leaf_0x8000001d::LEAF_NUM => self.update_cache_entry(entry),
+                leaf @ 0x8000_0002..=0x8000_0004 => {
+                    entry.eax = brand_string.get_reg_for_leaf(leaf, BsReg::Eax);
+                    entry.ebx = brand_string.get_reg_for_leaf(leaf, BsReg::Ebx);
+                    entry.ecx = brand_string.get_reg_for_leaf(leaf, BsReg::Ecx);
+                    entry.edx = brand_string.get_reg_for_leaf(leaf, BsReg::Edx);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Cpuid transformer exposing the same stepping to every guest of a fleet mixing the steppings
+/// of a CPU generation, e.g. so that the microcode detection of the guest doesn't change after
+/// a migration.
+///
+/// Unlike `SyntheticModelTransformer`, only the stepping of the signature (leaves 0x1 and
+/// 0x80000001) is set, the host family and model are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniformSteppingTransformer {
+    /// The stepping to expose, up to 0xF. This is usually the lowest stepping of the fleet.
+    pub stepping: u8,
+}
+
+impl UniformSteppingTransformer {
+    fn write_stepping(&self, eax: &mut u32) {
+        eax.write_bits_in_range(&leaf_0x1::eax::STEPPING_BITRANGE, u32::from(self.stepping));
+    }
+}
+
+impl CpuidTransformer for UniformSteppingTransformer {
+    fn process_slice(
+        &self,
+        entries: &mut [kvm_cpuid_entry2],
+        _vm_spec: &VmSpec,
+    ) -> Result<(), Error> {
+        if self.stepping > 0xF {
+            return Err(Error::InvalidSyntheticModel("stepping out of range"));
+        }
+
+        for entry in entries.iter_mut() {
+            match entry.function {
+                leaf_0x1::LEAF_NUM => self.write_stepping(&mut entry.eax),
+                leaf_0x80000001::LEAF_NUM if entry.eax != 0 => self.write_stepping(&mut entry.eax),
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const L1D: CacheGeometry = CacheGeometry {
+        level: 1,
+        cache_type: 1,
+        ways: 8,
+        partitions: 1,
+        line_size: 64,
+        sets: 64,
+    };
+    const L2: CacheGeometry = CacheGeometry {
+        level: 2,
+        cache_type: 3,
+        ways: 16,
+        partitions: 1,
+        line_size: 64,
+        sets: 1024,
+    };
+
+    fn synthetic_model() -> SyntheticModelTransformer {
+        SyntheticModelTransformer {
+            family: 0x19,
+            model: 0x1,
+            stepping: 0x1,
+            brand: "Firecracker Virtual CPU".to_string(),
+            cache_geometry: vec![L1D, L2],
+        }
+    }
+
+    fn cache_entry(index: u32, level: u32, cache_type: u32) -> kvm_cpuid_entry2 {
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+
+        let mut eax = 0;
+        eax.write_bits_in_range(&eax::CACHE_LEVEL_BITRANGE, level)
+            .write_bits_in_range(&eax::CACHE_TYPE_BITRANGE, cache_type)
+            .write_bits_in_range(&eax::MAX_CPUS_PER_CORE_BITRANGE, 1);
+        kvm_cpuid_entry2 {
+            function: leaf_0x8000001d::LEAF_NUM,
+            index,
+            eax,
+            ebx: u32::MAX,
+            ecx: u32::MAX,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_synthetic_model() {
+        use crate::cpu_leaf::leaf_cache_parameters::*;
+
+        let vm_spec = VmSpec::default();
+        let l1i = cache_entry(1, 1, 2);
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                eax: 0x0006_06a6 | (1 << 12),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                eax: 0x0006_06a6,
+                ..Default::default()
+            },
+            cache_entry(0, 1, 1),
+            l1i,
+            cache_entry(2, 2, 3),
+            kvm_cpuid_entry2 {
+                function: 0x8000_0002,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x8000_0003,
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: 0x8000_0004,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let transformer = synthetic_model();
+        transformer.process_cpuid(&mut cpuid, &vm_spec).unwrap();
+
+        // Family 0x19 is encoded as 0xF + 0xA, the processor type is left untouched.
+        assert_eq!(
+            cpuid.get_entry(0x1, 0).unwrap().eax,
+            0x00a0_0f11 | (1 << 12)
+        );
+        assert_eq!(cpuid.get_entry(0x8000_0001, 0).unwrap().eax, 0x00a0_0f11);
+
+        let geometry = |entry: &kvm_cpuid_entry2| CacheGeometry {
+            level: entry.eax.read_bits_in_range(&eax::CACHE_LEVEL_BITRANGE),
+            cache_type: entry.eax.read_bits_in_range(&eax::CACHE_TYPE_BITRANGE),
+            ways: entry.ebx.read_bits_in_range(&ebx::WAYS_BITRANGE) + 1,
+            partitions: entry.ebx.read_bits_in_range(&ebx::PARTITIONS_BITRANGE) + 1,
+            line_size: entry.ebx.read_bits_in_range(&ebx::LINE_SIZE_BITRANGE) + 1,
+            sets: entry.ecx + 1,
+        };
+        let l1d = cpuid.get_entry(leaf_0x8000001d::LEAF_NUM, 0).unwrap();
+        assert_eq!(geometry(l1d), L1D);
+        assert_eq!(geometry(l1d).size(), 32 * 1024);
+        // The sharing information is not part of the model.
+        assert_eq!(
+            l1d.eax.read_bits_in_range(&eax::MAX_CPUS_PER_CORE_BITRANGE),
+            1
+        );
+        assert_eq!(
+            geometry(cpuid.get_entry(leaf_0x8000001d::LEAF_NUM, 2).unwrap()).size(),
+            1024 * 1024
+        );
+        // There is no geometry for the L1 instruction cache.
+        assert_eq!(cpuid.get_entry(leaf_0x8000001d::LEAF_NUM, 1), Some(&l1i));
+
+        let mut brand = Vec::new();
+        for leaf in 0x8000_0002..=0x8000_0004 {
+            let entry = cpuid.get_entry(leaf, 0).unwrap();
+            for reg in [entry.eax, entry.ebx, entry.ecx, entry.edx] {
+                brand.extend_from_slice(&reg.to_le_bytes());
+            }
+        }
+        assert!(brand.starts_with(transformer.brand.as_bytes()));
+        assert!(brand[transformer.brand.len()..]
+            .iter()
+            .all(|&byte| byte == 0));
+
+        // Family 6 models use the extended model field.
+        let transformer = SyntheticModelTransformer {
+            family: 0x6,
+            model: 0x55,
+            stepping: 0x7,
+            ..synthetic_model()
+        };
+        transformer.process_cpuid(&mut cpuid, &vm_spec).unwrap();
+        assert_eq!(
+            cpuid.get_entry(0x1, 0).unwrap().eax,
+            0x0005_0657 | (1 << 12)
+        );
+    }
+
+    #[test]
+    fn test_invalid_synthetic_model() {
+        let vm_spec = VmSpec::default();
+        let mut cpuid = CpuId::new(1).unwrap();
+        let invalid_models = [
+            SyntheticModelTransformer {
+                family: 0x10F,
+                ..synthetic_model()
+            },
+            SyntheticModelTransformer {
+                family: 0x5,
+                model: 0x10,
+                ..synthetic_model()
+            },
+            SyntheticModelTransformer {
+                stepping: 0x10,
+                ..synthetic_model()
+            },
+            SyntheticModelTransformer {
+                brand: "x".repeat(48),
+                ..synthetic_model()
+            },
+            SyntheticModelTransformer {
+                cache_geometry: vec![CacheGeometry { ways: 0, ..L1D }],
+                ..synthetic_model()
+            },
+        ];
+
+        for transformer in invalid_models.iter() {
+            assert!(matches!(
+                transformer.process_cpuid(&mut cpuid, &vm_spec),
+                Err(Error::InvalidSyntheticModel(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_uniform_stepping() {
+        let vm_spec = VmSpec::default();
+        let mut cpuid = CpuId::from_entries(&[
+            kvm_cpuid_entry2 {
+                function: leaf_0x1::LEAF_NUM,
+                eax: 0x0005_0657 | (1 << 12),
+                ..Default::default()
+            },
+            kvm_cpuid_entry2 {
+                function: leaf_0x80000001::LEAF_NUM,
+                eax: 0x00a0_0f11,
+                ..Default::default()
+            },
+        ])
+        .unwrap();
+
+        let transformer = UniformSteppingTransformer { stepping: 0x4 };
+        transformer.process_cpuid(&mut cpuid, &vm_spec).unwrap();
+        // The family, model and processor type are kept.
+        assert_eq!(
+            cpuid.get_entry(0x1, 0).unwrap().eax,
+            0x0005_0654 | (1 << 12)
+        );
+        assert_eq!(cpuid.get_entry(0x8000_0001, 0).unwrap().eax, 0x00a0_0f14);
+
+        // The leaf 0x80000001 signature is reserved on Intel.
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: leaf_0x80000001::LEAF_NUM,
+            ..Default::default()
+        }])
+        .unwrap();
+        transformer.process_cpuid(&mut cpuid, &vm_spec).unwrap();
+        assert_eq!(cpuid.get_entry(0x8000_0001, 0).unwrap().eax, 0);
+
+        assert!(matches!(
+            UniformSteppingTransformer { stepping: 0x10 }.process_cpuid(&mut cpuid, &vm_spec),
+            Err(Error::InvalidSyntheticModel(_))
+        ));
+    }
+}
@@ -0,0 +1,395 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use kvm_bindings::kvm_cpuid_entry2;
+
+use super::*;
+use crate::bit_helper::BitHelper;
+use crate::common::{VENDOR_ID_AMD, VENDOR_ID_INTEL};
+use crate::cpu_leaf::*;
+use crate::template::intel::c3::C3CpuidTransformer;
+use crate::template::intel::t2::{self, T2CpuidTransformer};
+use crate::template::intel::t2s::T2SCpuidTransformer;
+
+// The leaf 0x7 EBX features of Cascade Lake that T2CL keeps on top of T2.
+const T2CL_KEPT_EBX_BITS: [u32; 9] = [
+    leaf_0x7::index0::ebx::AVX512F_BITINDEX,
+    leaf_0x7::index0::ebx::AVX512DQ_BITINDEX,
+    leaf_0x7::index0::ebx::RDSEED_BITINDEX,
+    leaf_0x7::index0::ebx::ADX_BITINDEX,
+    leaf_0x7::index0::ebx::CLFLUSHOPT_BITINDEX,
+    leaf_0x7::index0::ebx::CLWB_BITINDEX,
+    leaf_0x7::index0::ebx::AVX512CD_BITINDEX,
+    leaf_0x7::index0::ebx::AVX512BW_BITINDEX,
+    leaf_0x7::index0::ebx::AVX512VL_BITINDEX,
+];
+
+// The leaf 0x7 ECX features of Cascade Lake that T2CL keeps on top of T2.
+const T2CL_KEPT_ECX_BITS: [u32; 1] = [leaf_0x7::index0::ecx::AVX512_VNNI_BITINDEX];
+
+fn kept_bits(value: u32, bit_indexes: &[u32]) -> u32 {
+    bit_indexes
+        .iter()
+        .filter(|&&bit_index| value.read_bit(bit_index))
+        .fold(0, |kept, &bit_index| kept | (1 << bit_index))
+}
+
+// Same features as T2, but the family, model and stepping of the host are kept.
+fn update_feature_info_entry(entry: &mut kvm_cpuid_entry2, vm_spec: &VmSpec) -> Result<(), Error> {
+    let eax = entry.eax;
+    t2::update_feature_info_entry(entry, vm_spec)?;
+    entry.eax = eax;
+
+    Ok(())
+}
+
+fn update_t2cl_structured_extended_entry(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    let (ebx, ecx) = (entry.ebx, entry.ecx);
+    t2::update_structured_extended_entry(entry, vm_spec)?;
+
+    if entry.index == 0 {
+        entry.ebx |= kept_bits(ebx, &T2CL_KEPT_EBX_BITS);
+        entry.ecx |= kept_bits(ecx, &T2CL_KEPT_ECX_BITS);
+    }
+
+    Ok(())
+}
+
+fn update_t2cl_xsave_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+
+    let avx512_state = entry
+        .eax
+        .read_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE);
+    t2::update_xsave_features_entry(entry, vm_spec)?;
+
+    // AVX-512 is kept, so its XSAVE state components are as well.
+    if entry.index == 0 {
+        entry
+            .eax
+            .write_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE, avx512_state);
+    }
+
+    Ok(())
+}
+
+/// Masks the cpuid of an Intel Cascade Lake host down to the T2 features, keeping AVX-512.
+struct T2CLCpuidTransformer {}
+
+impl CpuidTransformer for T2CLCpuidTransformer {
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        match entry.function {
+            leaf_0x1::LEAF_NUM => Some(update_feature_info_entry),
+            leaf_0x7::LEAF_NUM => Some(update_t2cl_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(update_t2cl_xsave_features_entry),
+            leaf_0x80000001::LEAF_NUM => Some(t2::update_extended_feature_info_entry),
+            _ => None,
+        }
+    }
+}
+
+// The T2A template masks the features of a Milan host that a Haswell host doesn't have. The
+// signature of the host is kept, since the guest needs it to pick the AMD specific code paths.
+fn update_t2a_feature_info_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x1::*;
+
+    entry
+        .ecx
+        .write_bit(ecx::MONITOR_BITINDEX, false)
+        .write_bit(ecx::OSXSAVE_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_t2a_structured_extended_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x7::index0::*;
+
+    if entry.index == 0 {
+        entry
+            .ebx
+            .write_bit(ebx::RDT_M_BITINDEX, false)
+            .write_bit(ebx::RDT_A_BITINDEX, false)
+            .write_bit(ebx::RDSEED_BITINDEX, false)
+            .write_bit(ebx::ADX_BITINDEX, false)
+            .write_bit(ebx::CLFLUSHOPT_BITINDEX, false)
+            .write_bit(ebx::CLWB_BITINDEX, false)
+            .write_bit(ebx::SHA_BITINDEX, false);
+
+        entry
+            .ecx
+            .write_bit(ecx::UMIP_BITINDEX, false)
+            .write_bit(ecx::PKU_BITINDEX, false)
+            .write_bit(ecx::OSPKE_BITINDEX, false)
+            .write_bit(ecx::CET_SS_BITINDEX, false)
+            .write_bit(ecx::VAES_BITINDEX, false)
+            .write_bit(ecx::VPCLMULQDQ_BITINDEX, false)
+            .write_bit(ecx::RDPID_BITINDEX, false);
+    }
+
+    Ok(())
+}
+
+fn update_t2a_xsave_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0xd::*;
+
+    // Milan has neither MPX nor AVX-512, only the PKRU state component has to be masked since
+    // OSPKE is masked in leaf_0x7 index 0.
+    if entry.index == 0 {
+        entry.eax.write_bit(index0::eax::PKRU_BITINDEX, false);
+    }
+
+    if entry.index == 1 {
+        entry
+            .eax
+            .write_bit(index1::eax::XSAVEC_SHIFT, false)
+            .write_bit(index1::eax::XGETBV_SHIFT, false)
+            .write_bit(index1::eax::XSAVES_SHIFT, false);
+    }
+
+    Ok(())
+}
+
+fn update_t2a_extended_feature_info_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x80000001::*;
+
+    // TOPOEXT is left alone, the extended cache topology leaf is still exposed.
+    entry
+        .ecx
+        .write_bit(ecx::SSE4A_BITINDEX, false)
+        .write_bit(ecx::MISALIGN_SSE_BITINDEX, false)
+        .write_bit(ecx::PREFETCH_BITINDEX, false)
+        .write_bit(ecx::OSVW_BITINDEX, false)
+        .write_bit(ecx::IBS_BITINDEX, false)
+        .write_bit(ecx::PERFCTR_CORE_BITINDEX, false)
+        .write_bit(ecx::MONITORX_BITINDEX, false);
+
+    entry
+        .edx
+        .write_bit(edx::MMXEXT_BITINDEX, false)
+        .write_bit(edx::FFXSR_BITINDEX, false)
+        .write_bit(edx::PDPE1GB_BITINDEX, false);
+
+    Ok(())
+}
+
+fn update_t2a_amd_features_entry(
+    entry: &mut kvm_cpuid_entry2,
+    _vm_spec: &VmSpec,
+) -> Result<(), Error> {
+    use crate::cpu_leaf::leaf_0x80000008::*;
+
+    entry
+        .ebx
+        .write_bit(ebx::CLZERO_BITINDEX, false)
+        .write_bit(ebx::RSTR_FP_ERR_PTRS_BITINDEX, false)
+        .write_bit(ebx::WBNOINVD_BITINDEX, false);
+
+    Ok(())
+}
+
+/// Masks the cpuid of an AMD Milan host down to the T2 features.
+struct T2ACpuidTransformer {}
+
+impl CpuidTransformer for T2ACpuidTransformer {
+    fn entry_transformer_fn(&self, entry: &mut kvm_cpuid_entry2) -> Option<EntryTransformerFn> {
+        match entry.function {
+            leaf_0x1::LEAF_NUM => Some(update_t2a_feature_info_entry),
+            leaf_0x7::LEAF_NUM => Some(update_t2a_structured_extended_entry),
+            leaf_0xd::LEAF_NUM => Some(update_t2a_xsave_features_entry),
+            leaf_0x80000001::LEAF_NUM => Some(update_t2a_extended_feature_info_entry),
+            leaf_0x80000008::LEAF_NUM => Some(update_t2a_amd_features_entry),
+            _ => None,
+        }
+    }
+}
+
+/// Applies the CPU template selected in `vm_spec` on a cpuid already processed by the vendor
+/// specific transformer.
+///
+/// Fails with `Error::InvalidVendor` if the template is meant for another vendor than the one
+/// of `vm_spec`.
+pub fn apply_cpu_template(cpuid: &mut CpuId, vm_spec: &VmSpec) -> Result<(), Error> {
+    let (vendor_id, transformer): (&[u8; 12], &dyn CpuidTransformer) = match vm_spec.cpu_template {
+        CpuTemplate::None => return Ok(()),
+        CpuTemplate::C3 => (VENDOR_ID_INTEL, &C3CpuidTransformer {}),
+        CpuTemplate::T2 => (VENDOR_ID_INTEL, &T2CpuidTransformer {}),
+        CpuTemplate::T2A => (VENDOR_ID_AMD, &T2ACpuidTransformer {}),
+        CpuTemplate::T2CL => (VENDOR_ID_INTEL, &T2CLCpuidTransformer {}),
+        CpuTemplate::T2S => (VENDOR_ID_INTEL, &T2SCpuidTransformer {}),
+    };
+
+    if vm_spec.cpu_vendor_id() != vendor_id {
+        return Err(Error::InvalidVendor);
+    }
+    transformer.process_cpuid(cpuid, vm_spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_feature_info_entry() {
+        use crate::cpu_leaf::leaf_0x1::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            eax: 0x0005_0657,
+            ecx: 1 << ecx::VMX_BITINDEX | 1 << ecx::FMA_BITINDEX,
+            ..Default::default()
+        };
+
+        assert!(update_feature_info_entry(&mut entry, &vm_spec).is_ok());
+        // The signature of the host is kept, the features are masked as for T2.
+        assert_eq!(entry.eax, 0x0005_0657);
+        assert!(!entry.ecx.read_bit(ecx::VMX_BITINDEX));
+        assert!(entry.ecx.read_bit(ecx::FMA_BITINDEX));
+    }
+
+    #[test]
+    fn test_update_t2cl_structured_extended_entry() {
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            index: 0,
+            ebx: 1 << ebx::AVX512F_BITINDEX | 1 << ebx::MPX_BITINDEX | 1 << ebx::CLWB_BITINDEX,
+            ecx: 1 << ecx::AVX512_VNNI_BITINDEX | 1 << ecx::PKU_BITINDEX,
+            ..Default::default()
+        };
+
+        assert!(update_t2cl_structured_extended_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(
+            entry.ebx,
+            1 << ebx::AVX512F_BITINDEX | 1 << ebx::CLWB_BITINDEX
+        );
+        assert_eq!(entry.ecx, 1 << ecx::AVX512_VNNI_BITINDEX);
+
+        // Features missing on the host aren't added.
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            index: 0,
+            ..Default::default()
+        };
+        assert!(update_t2cl_structured_extended_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!((entry.ebx, entry.ecx), (0, 0));
+    }
+
+    #[test]
+    fn test_update_t2cl_xsave_features_entry() {
+        use crate::cpu_leaf::leaf_0xd::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            index: 0,
+            eax: 0x2ff,
+            ..Default::default()
+        };
+
+        assert!(update_t2cl_xsave_features_entry(&mut entry, &vm_spec).is_ok());
+        // The MPX and PKRU state components are masked, the AVX-512 ones are kept.
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&index0::eax::MPX_STATE_BITRANGE),
+            0
+        );
+        assert!(!entry.eax.read_bit(index0::eax::PKRU_BITINDEX));
+        assert_eq!(
+            entry
+                .eax
+                .read_bits_in_range(&index0::eax::AVX512_STATE_BITRANGE),
+            0b111
+        );
+    }
+
+    #[test]
+    fn test_update_t2a_extended_feature_info_entry() {
+        use crate::cpu_leaf::leaf_0x80000001::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: LEAF_NUM,
+            ecx: 1 << ecx::SSE4A_BITINDEX | 1 << ecx::TOPOEXT_INDEX | 1 << ecx::LZCNT_BITINDEX,
+            edx: 1 << edx::MMXEXT_BITINDEX | 1 << edx::PDPE1GB_BITINDEX | 1 << edx::LM_BITINDEX,
+            ..Default::default()
+        };
+
+        assert!(update_t2a_extended_feature_info_entry(&mut entry, &vm_spec).is_ok());
+        // The AMD only features are masked, the ones T2 shares with Milan are kept.
+        assert_eq!(
+            entry.ecx,
+            1 << ecx::TOPOEXT_INDEX | 1 << ecx::LZCNT_BITINDEX
+        );
+        assert_eq!(entry.edx, 1 << edx::LM_BITINDEX);
+    }
+
+    #[test]
+    fn test_update_t2a_structured_extended_entry() {
+        use crate::cpu_leaf::leaf_0x7::index0::*;
+
+        let vm_spec = VmSpec::default();
+        let mut entry = kvm_cpuid_entry2 {
+            function: leaf_0x7::LEAF_NUM,
+            index: 0,
+            ebx: 1 << ebx::AVX2_BITINDEX | 1 << ebx::SHA_BITINDEX,
+            ecx: 1 << ecx::VAES_BITINDEX | 1 << ecx::PKU_BITINDEX,
+            ..Default::default()
+        };
+
+        assert!(update_t2a_structured_extended_entry(&mut entry, &vm_spec).is_ok());
+        assert_eq!(entry.ebx, 1 << ebx::AVX2_BITINDEX);
+        assert_eq!(entry.ecx, 0);
+    }
+
+    #[test]
+    fn test_apply_cpu_template() {
+        let mut vm_spec = VmSpec::default();
+        let mut cpuid = CpuId::from_entries(&[kvm_cpuid_entry2 {
+            function: leaf_0x80000001::LEAF_NUM,
+            edx: 1 << leaf_0x80000001::edx::PDPE1GB_BITINDEX,
+            ..Default::default()
+        }])
+        .unwrap();
+
+        // Without a template, the cpuid is left untouched.
+        assert!(apply_cpu_template(&mut cpuid, &vm_spec).is_ok());
+        assert_ne!(cpuid.as_slice()[0].edx, 0);
+
+        let (supported, unsupported) = match vm_spec.cpu_vendor_id() {
+            VENDOR_ID_INTEL => (CpuTemplate::T2, CpuTemplate::T2A),
+            _ => (CpuTemplate::T2A, CpuTemplate::T2),
+        };
+
+        vm_spec.set_cpu_template(unsupported);
+        assert!(matches!(
+            apply_cpu_template(&mut cpuid.clone(), &vm_spec),
+            Err(Error::InvalidVendor)
+        ));
+
+        vm_spec.set_cpu_template(supported);
+        assert!(apply_cpu_template(&mut cpuid, &vm_spec).is_ok());
+        assert_eq!(cpuid.as_slice()[0].edx, 0);
+    }
+}
@@ -0,0 +1,26 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![cfg(target_arch = "x86_64")]
+
+use cpuid::cpuid_ext::CpuIdExt;
+use cpuid::{filter_cpuid, verify_cpuid, VmSpec};
+use kvm_bindings::KVM_MAX_CPUID_ENTRIES;
+use kvm_ioctls::Kvm;
+
+#[test]
+fn test_filter_cpuid_invariants() {
+    let kvm = Kvm::new().unwrap();
+    let supported_cpuid = kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES).unwrap();
+
+    for (cpu_count, smt) in [(1, false), (2, true), (4, false)] {
+        for cpu_index in 0..cpu_count {
+            let vm_spec = VmSpec::new(cpu_index, cpu_count, smt).unwrap();
+            let mut cpuid = supported_cpuid.clone();
+
+            filter_cpuid(&mut cpuid, &vm_spec).unwrap();
+            cpuid.intersect_with_host(&supported_cpuid);
+            assert_eq!(verify_cpuid(&cpuid, &vm_spec), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,371 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal 82093AA IOAPIC, used when KVM only emulates the local APICs (split irqchip).
+//!
+//! The pins are not wired to the device models. Instead, every unmasked redirection entry is
+//! turned into an MSI route for the GSI with the same number, so that the devices can keep
+//! signaling their interrupts through irqfds.
+
+use std::convert::TryInto;
+use std::io;
+
+use logger::{error, warn};
+
+use crate::bus::BusDevice;
+
+/// The number of interrupt input pins.
+pub const IOAPIC_NUM_PINS: usize = 24;
+/// The guest physical address of the IOAPIC registers, as advertised in the MP table.
+pub const IOAPIC_START: u64 = 0xfec0_0000;
+/// The size of the IOAPIC MMIO window.
+pub const IOAPIC_SIZE: u64 = 0x1000;
+
+/// Offset of the register selecting the indirectly accessed register.
+const IOREGSEL_OFFSET: u64 = 0x00;
+/// Offset of the window to the selected register.
+const IOWIN_OFFSET: u64 = 0x10;
+
+/// Indirectly accessed registers.
+const IOAPICID: u32 = 0x00;
+const IOAPICVER: u32 = 0x01;
+const IOAPICARB: u32 = 0x02;
+const IOREDTBL_BASE: u32 = 0x10;
+
+/// Version of the 82093AA, with the index of the last redirection entry in bits 23:16.
+const IOAPIC_VERSION: u32 = 0x11 | ((IOAPIC_NUM_PINS as u32 - 1) << 16);
+
+/// Redirection entry fields.
+const VECTOR_MASK: u64 = 0xff;
+const DELIVERY_MODE_SHIFT: u64 = 8;
+const DELIVERY_MODE_MASK: u64 = 0x7;
+const DELIVERY_MODE_LOWEST_PRIORITY: u64 = 0x1;
+const DEST_MODE_LOGICAL: u64 = 1 << 11;
+const REMOTE_IRR: u64 = 1 << 14;
+const TRIGGER_MODE_LEVEL: u64 = 1 << 15;
+const MASKED: u64 = 1 << 16;
+const DESTINATION_SHIFT: u64 = 56;
+/// The bits that are read-only for the guest: the delivery status and the remote IRR.
+const READ_ONLY_BITS: u64 = (1 << 12) | REMOTE_IRR;
+
+/// MSI address and data fields.
+const MSI_ADDRESS_BASE: u32 = 0xfee0_0000;
+const MSI_DESTINATION_SHIFT: u32 = 12;
+const MSI_REDIRECTION_HINT: u32 = 1 << 3;
+const MSI_DEST_MODE_LOGICAL: u32 = 1 << 2;
+const MSI_LEVEL_ASSERT: u32 = 1 << 14;
+const MSI_TRIGGER_MODE_LEVEL: u32 = 1 << 15;
+
+/// An MSI message, as programmed in the route of a GSI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MsiMessage {
+    /// The low 32 bits of the message address.
+    pub address_lo: u32,
+    /// The high 32 bits of the message address.
+    pub address_hi: u32,
+    /// The message data.
+    pub data: u32,
+}
+
+/// The route of one of the IOAPIC pins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoapicRoute {
+    /// The pin, which is also the GSI signaled by the devices.
+    pub pin: u32,
+    /// The MSI the pin is routed to, or `None` if the pin is masked.
+    pub msi: Option<MsiMessage>,
+}
+
+/// Programs the routes of the IOAPIC pins into the hypervisor.
+pub trait IoapicRouting: Send {
+    /// Replaces the routes of all the IOAPIC pins.
+    fn set_routes(&mut self, routes: &[IoapicRoute]) -> io::Result<()>;
+}
+
+/// A userspace IOAPIC, exposing the standard 24 redirection entries.
+pub struct Ioapic {
+    id: u32,
+    ioregsel: u32,
+    redirection_table: [u64; IOAPIC_NUM_PINS],
+    routing: Box<dyn IoapicRouting>,
+}
+
+impl Ioapic {
+    /// Creates an IOAPIC with all the pins masked.
+    pub fn new(routing: Box<dyn IoapicRouting>) -> Self {
+        Ioapic {
+            id: 0,
+            ioregsel: 0,
+            redirection_table: [MASKED; IOAPIC_NUM_PINS],
+            routing,
+        }
+    }
+
+    /// Returns the current route of every pin.
+    pub fn routes(&self) -> Vec<IoapicRoute> {
+        self.redirection_table
+            .iter()
+            .enumerate()
+            .map(|(pin, &entry)| IoapicRoute {
+                pin: pin as u32,
+                msi: Self::msi_message(entry),
+            })
+            .collect()
+    }
+
+    /// Handles the EOI broadcast by the local APICs for a level triggered `vector`.
+    ///
+    /// The pins aren't driven by the devices, so there is no line level to sample and no
+    /// interrupt to deliver again: only the remote IRR of the matching entries is cleared.
+    pub fn end_of_interrupt(&mut self, vector: u8) {
+        for entry in self.redirection_table.iter_mut() {
+            if *entry & VECTOR_MASK == u64::from(vector) && *entry & TRIGGER_MODE_LEVEL != 0 {
+                *entry &= !REMOTE_IRR;
+            }
+        }
+    }
+
+    fn msi_message(entry: u64) -> Option<MsiMessage> {
+        if entry & MASKED != 0 {
+            return None;
+        }
+
+        let delivery_mode = (entry >> DELIVERY_MODE_SHIFT) & DELIVERY_MODE_MASK;
+        let mut address_lo =
+            MSI_ADDRESS_BASE | (((entry >> DESTINATION_SHIFT) as u32) << MSI_DESTINATION_SHIFT);
+        if entry & DEST_MODE_LOGICAL != 0 {
+            address_lo |= MSI_DEST_MODE_LOGICAL;
+        }
+        if delivery_mode == DELIVERY_MODE_LOWEST_PRIORITY {
+            address_lo |= MSI_REDIRECTION_HINT;
+        }
+
+        let mut data = (entry & VECTOR_MASK) as u32 | ((delivery_mode as u32) << 8);
+        if entry & TRIGGER_MODE_LEVEL != 0 {
+            data |= MSI_TRIGGER_MODE_LEVEL | MSI_LEVEL_ASSERT;
+        }
+
+        Some(MsiMessage {
+            address_lo,
+            address_hi: 0,
+            data,
+        })
+    }
+
+    fn update_routes(&mut self) {
+        let routes = self.routes();
+        if let Err(err) = self.routing.set_routes(&routes) {
+            error!("Failed to update the IOAPIC routes: {}", err);
+        }
+    }
+
+    fn read_register(&self) -> u32 {
+        match self.ioregsel {
+            IOAPICID => self.id,
+            IOAPICVER => IOAPIC_VERSION,
+            IOAPICARB => self.id,
+            reg => match Self::redirection_entry_index(reg) {
+                Some((pin, true)) => (self.redirection_table[pin] >> 32) as u32,
+                Some((pin, false)) => self.redirection_table[pin] as u32,
+                None => {
+                    warn!("Read from unknown IOAPIC register {:#x}", reg);
+                    0
+                }
+            },
+        }
+    }
+
+    fn write_register(&mut self, value: u32) {
+        match self.ioregsel {
+            // Only the 4 bits of the ID are writable.
+            IOAPICID => self.id = value & (0xf << 24),
+            IOAPICVER | IOAPICARB => (),
+            reg => match Self::redirection_entry_index(reg) {
+                Some((pin, high)) => {
+                    let entry = &mut self.redirection_table[pin];
+                    let (mask, value) = match high {
+                        true => (0xffff_ffff_0000_0000, u64::from(value) << 32),
+                        false => (0x0000_0000_ffff_ffff, u64::from(value)),
+                    };
+                    let mask = mask & !READ_ONLY_BITS;
+                    *entry = (*entry & !mask) | (value & mask);
+
+                    self.update_routes();
+                }
+                None => warn!("Write to unknown IOAPIC register {:#x}", reg),
+            },
+        }
+    }
+
+    /// Returns the pin of a redirection table register and whether it's the high half.
+    fn redirection_entry_index(reg: u32) -> Option<(usize, bool)> {
+        let index = reg.checked_sub(IOREDTBL_BASE)? as usize;
+        match index / 2 {
+            pin if pin < IOAPIC_NUM_PINS => Some((pin, index % 2 == 1)),
+            _ => None,
+        }
+    }
+}
+
+impl BusDevice for Ioapic {
+    fn read(&mut self, offset: u64, data: &mut [u8]) {
+        // The registers are 32 bits wide and have to be accessed as such.
+        if data.len() != 4 {
+            warn!("Invalid IOAPIC read of {} bytes", data.len());
+            return;
+        }
+
+        let value = match offset {
+            IOREGSEL_OFFSET => self.ioregsel,
+            IOWIN_OFFSET => self.read_register(),
+            _ => {
+                warn!("Read from unknown IOAPIC offset {:#x}", offset);
+                0
+            }
+        };
+        data.copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8]) {
+        let value = match data.try_into() {
+            Ok(bytes) => u32::from_le_bytes(bytes),
+            Err(_) => {
+                warn!("Invalid IOAPIC write of {} bytes", data.len());
+                return;
+            }
+        };
+
+        match offset {
+            IOREGSEL_OFFSET => self.ioregsel = value & 0xff,
+            IOWIN_OFFSET => self.write_register(value),
+            _ => warn!("Write to unknown IOAPIC offset {:#x}", offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MockRouting {
+        routes: Arc<Mutex<Vec<IoapicRoute>>>,
+    }
+
+    impl IoapicRouting for MockRouting {
+        fn set_routes(&mut self, routes: &[IoapicRoute]) -> io::Result<()> {
+            *self.routes.lock().unwrap() = routes.to_vec();
+            Ok(())
+        }
+    }
+
+    fn read_register(ioapic: &mut Ioapic, reg: u32) -> u32 {
+        let mut data = [0; 4];
+        ioapic.write(IOREGSEL_OFFSET, &reg.to_le_bytes());
+        ioapic.read(IOWIN_OFFSET, &mut data);
+        u32::from_le_bytes(data)
+    }
+
+    fn write_register(ioapic: &mut Ioapic, reg: u32, value: u32) {
+        ioapic.write(IOREGSEL_OFFSET, &reg.to_le_bytes());
+        ioapic.write(IOWIN_OFFSET, &value.to_le_bytes());
+    }
+
+    #[test]
+    fn test_ioapic_registers() {
+        let mut ioapic = Ioapic::new(Box::new(MockRouting::default()));
+
+        assert_eq!(read_register(&mut ioapic, IOAPICVER), 0x0017_0011);
+        write_register(&mut ioapic, IOAPICVER, 0);
+        assert_eq!(read_register(&mut ioapic, IOAPICVER), 0x0017_0011);
+
+        write_register(&mut ioapic, IOAPICID, 0xffff_ffff);
+        assert_eq!(read_register(&mut ioapic, IOAPICID), 0x0f00_0000);
+
+        // All the pins start masked.
+        for pin in 0..IOAPIC_NUM_PINS as u32 {
+            assert_eq!(
+                read_register(&mut ioapic, IOREDTBL_BASE + 2 * pin),
+                MASKED as u32
+            );
+        }
+        // There is nothing after the last redirection entry.
+        let last = IOREDTBL_BASE + 2 * IOAPIC_NUM_PINS as u32;
+        write_register(&mut ioapic, last, 0xffff_ffff);
+        assert_eq!(read_register(&mut ioapic, last), 0);
+
+        // Accesses that are not 32 bits wide are ignored.
+        let mut data = [0xff; 2];
+        ioapic.read(IOREGSEL_OFFSET, &mut data);
+        assert_eq!(data, [0xff; 2]);
+        ioapic.write(IOREGSEL_OFFSET, &[IOAPICID as u8]);
+        assert_eq!(read_register(&mut ioapic, IOAPICVER), 0x0017_0011);
+    }
+
+    #[test]
+    fn test_ioapic_routes() {
+        let routing = MockRouting::default();
+        let routes = routing.routes.clone();
+        let mut ioapic = Ioapic::new(Box::new(routing));
+
+        // Pin 4: edge triggered vector 0x24 for the physical APIC ID 3.
+        write_register(&mut ioapic, IOREDTBL_BASE + 9, 3 << 24);
+        write_register(&mut ioapic, IOREDTBL_BASE + 8, 0x24);
+        // Pin 5: level triggered, lowest priority vector 0x25 for the logical destination 1.
+        write_register(&mut ioapic, IOREDTBL_BASE + 11, 1 << 24);
+        write_register(
+            &mut ioapic,
+            IOREDTBL_BASE + 10,
+            0x25 | (1 << 8) | DEST_MODE_LOGICAL as u32 | TRIGGER_MODE_LEVEL as u32,
+        );
+
+        let routes = routes.lock().unwrap().clone();
+        assert_eq!(routes, ioapic.routes());
+        assert_eq!(routes.len(), IOAPIC_NUM_PINS);
+        assert_eq!(
+            routes[4].msi,
+            Some(MsiMessage {
+                address_lo: 0xfee0_3000,
+                address_hi: 0,
+                data: 0x24,
+            })
+        );
+        assert_eq!(
+            routes[5].msi,
+            Some(MsiMessage {
+                address_lo: 0xfee0_100c,
+                address_hi: 0,
+                data: 0xc125,
+            })
+        );
+        assert!(routes
+            .iter()
+            .filter(|route| route.pin != 4 && route.pin != 5)
+            .all(|route| route.msi.is_none()));
+
+        // Masking the pin removes its route.
+        write_register(&mut ioapic, IOREDTBL_BASE + 8, 0x24 | MASKED as u32);
+        assert_eq!(ioapic.routes()[4].msi, None);
+    }
+
+    #[test]
+    fn test_ioapic_end_of_interrupt() {
+        let mut ioapic = Ioapic::new(Box::new(MockRouting::default()));
+        ioapic.redirection_table[5] = 0x25 | TRIGGER_MODE_LEVEL | REMOTE_IRR;
+        ioapic.redirection_table[6] = 0x25 | REMOTE_IRR;
+
+        // The remote IRR is read-only for the guest.
+        write_register(&mut ioapic, IOREDTBL_BASE + 10, 0x25);
+        assert_eq!(ioapic.redirection_table[5], 0x25 | REMOTE_IRR);
+        ioapic.redirection_table[5] |= TRIGGER_MODE_LEVEL;
+
+        ioapic.end_of_interrupt(0x26);
+        assert_ne!(ioapic.redirection_table[5] & REMOTE_IRR, 0);
+        ioapic.end_of_interrupt(0x25);
+        assert_eq!(ioapic.redirection_table[5] & REMOTE_IRR, 0);
+        // Edge triggered entries don't take part in the EOI.
+        assert_ne!(ioapic.redirection_table[6] & REMOTE_IRR, 0);
+    }
+}
@@ -6,6 +6,8 @@
 // found in the THIRD-PARTY file.
 
 mod i8042;
+#[cfg(target_arch = "x86_64")]
+mod ioapic;
 #[cfg(target_arch = "aarch64")]
 mod rtc_pl031;
 pub mod serial;
@@ -17,6 +19,10 @@ use utils::eventfd::EventFd;
 use vm_superio::Trigger;
 
 pub use self::i8042::{Error as I8042DeviceError, I8042Device};
+#[cfg(target_arch = "x86_64")]
+pub use self::ioapic::{
+    Ioapic, IoapicRoute, IoapicRouting, MsiMessage, IOAPIC_NUM_PINS, IOAPIC_SIZE, IOAPIC_START,
+};
 #[cfg(target_arch = "aarch64")]
 pub use self::rtc_pl031::RTCDevice;
 pub use self::serial::{SerialDevice, SerialEventsWrapper, SerialWrapper};
@@ -16,7 +16,7 @@ use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
 use vm_memory::{Address, ByteValued, Bytes, GuestAddress, GuestMemoryMmap};
 
 use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BALLOON};
-use super::utils::{compact_page_frame_numbers, remove_range};
+use super::utils::{compact_page_frame_numbers, prefault_range, remove_range};
 use super::{
     BALLOON_DEV_ID, DEFLATE_INDEX, INFLATE_INDEX, MAX_PAGES_IN_DESC, MAX_PAGE_COMPACT_BUFFER,
     MIB_TO_4K_PAGES, NUM_QUEUES, QUEUE_SIZES, STATS_INDEX, VIRTIO_BALLOON_F_DEFLATE_ON_OOM,
@@ -331,6 +331,43 @@ impl Balloon {
         let mut needs_interrupt = false;
 
         while let Some(head) = queue.pop(mem) {
+            let len = head.len as usize;
+            let page_count = len / SIZE_OF_U32;
+
+            // The pages given back to the guest are only prefaulted on a best effort basis, so
+            // the descriptors which can't be parsed are still acknowledged.
+            if !head.is_write_only() && len % SIZE_OF_U32 == 0 && page_count <= MAX_PAGES_IN_DESC {
+                let pfns = &mut self.pfn_buffer[..page_count];
+                let read_result = pfns.iter_mut().enumerate().try_for_each(|(index, pfn)| {
+                    let addr = head
+                        .addr
+                        .checked_add((index * SIZE_OF_U32) as u64)
+                        .ok_or(BalloonError::MalformedDescriptor)?;
+                    *pfn = mem
+                        .read_obj::<u32>(addr)
+                        .map_err(|_| BalloonError::MalformedDescriptor)?;
+                    Ok(())
+                });
+
+                match read_result {
+                    Ok(()) => {
+                        for (page_frame_number, range_len) in compact_page_frame_numbers(pfns) {
+                            let guest_addr = GuestAddress(
+                                (page_frame_number as u64) << VIRTIO_BALLOON_PFN_SHIFT,
+                            );
+
+                            if let Err(err) = prefault_range(
+                                mem,
+                                (guest_addr, u64::from(range_len) << VIRTIO_BALLOON_PFN_SHIFT),
+                            ) {
+                                error!("Error prefaulting memory range: {:?}", err);
+                            }
+                        }
+                    }
+                    Err(err) => error!("Error reading deflate descriptor: {:?}", err),
+                }
+            }
+
             queue
                 .add_used(mem, head.index, 0)
                 .map_err(BalloonError::Queue)?;
@@ -898,6 +935,11 @@ pub(crate) mod tests {
 
         // Happy case.
         {
+            // Fill the second page with non-zero bytes.
+            for i in 0..0x1000 {
+                assert!(mem.write_obj::<u8>(1, GuestAddress((1 << 12) + i)).is_ok());
+            }
+            mem.write_obj::<u32>(0x1, GuestAddress(page_addr)).unwrap();
             set_request(&defq, 1, page_addr, SIZE_OF_U32 as u32, VIRTQ_DESC_F_NEXT);
             check_metric_after_block!(
                 METRICS.balloon.deflate_count,
@@ -905,6 +947,24 @@ pub(crate) mod tests {
                 invoke_handler_for_queue_event(&mut balloon, DEFLATE_INDEX)
             );
             check_request_completion(&defq, 1);
+
+            // Prefaulting the deflated page doesn't change its content.
+            for i in 0..0x1000 {
+                assert_eq!(mem.read_obj::<u8>(GuestAddress((1 << 12) + i)).unwrap(), 1);
+            }
+        }
+
+        // Descriptors with a bogus length are still acknowledged.
+        {
+            set_request(
+                &defq,
+                2,
+                page_addr,
+                SIZE_OF_U32 as u32 + 1,
+                VIRTQ_DESC_F_NEXT,
+            );
+            invoke_handler_for_queue_event(&mut balloon, DEFLATE_INDEX);
+            check_request_completion(&defq, 2);
         }
     }
 
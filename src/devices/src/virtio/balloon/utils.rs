@@ -116,6 +116,37 @@ pub(crate) fn remove_range(
     }
 }
 
+/// Advises the host that a range given back to the guest will be used soon, so that it's
+/// faulted back in ahead of the guest accesses where possible.
+pub(crate) fn prefault_range(
+    guest_memory: &GuestMemoryMmap,
+    range: (GuestAddress, u64),
+) -> std::result::Result<(), RemoveRegionError> {
+    let (guest_address, range_len) = range;
+
+    if let Some(region) = guest_memory.find_region(guest_address) {
+        if guest_address.0 + range_len > region.start_addr().0 + region.len() {
+            return Err(RemoveRegionError::MalformedRange);
+        }
+        let phys_address = guest_memory
+            .get_host_address(guest_address)
+            .map_err(|_| RemoveRegionError::AddressTranslation)?;
+
+        // SAFETY: The address and length are known to be valid.
+        let ret = unsafe {
+            let range_len = range_len as usize;
+            libc::madvise(phys_address.cast(), range_len, libc::MADV_WILLNEED)
+        };
+        if ret < 0 {
+            return Err(RemoveRegionError::MadviseFail(io::Error::last_os_error()));
+        }
+
+        Ok(())
+    } else {
+        Err(RemoveRegionError::RegionNotFound)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use vm_memory::Bytes;
@@ -220,6 +251,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_prefault_range() {
+        let page_size: usize = 0x1000;
+        let mem = vm_memory::test_utils::create_anon_guest_memory(
+            &[(GuestAddress(0), 2 * page_size)],
+            false,
+        )
+        .unwrap();
+
+        // Fill the memory with ones.
+        let ones = vec![1u8; 2 * page_size];
+        mem.write(&ones[..], GuestAddress(0)).unwrap();
+
+        // Prefaulting a removed page gives back a zeroed page.
+        assert!(remove_range(&mem, (GuestAddress(0), page_size as u64), false).is_ok());
+        assert!(prefault_range(&mem, (GuestAddress(0), page_size as u64)).is_ok());
+        let mut actual_page = vec![0u8; page_size];
+        mem.read(actual_page.as_mut_slice(), GuestAddress(0))
+            .unwrap();
+        assert_eq!(vec![0u8; page_size], actual_page);
+
+        // Prefaulting a page in use leaves its content untouched.
+        assert!(prefault_range(&mem, (GuestAddress(page_size as u64), page_size as u64)).is_ok());
+        mem.read(actual_page.as_mut_slice(), GuestAddress(page_size as u64))
+            .unwrap();
+        assert_eq!(vec![1u8; page_size], actual_page);
+
+        // Malformed range: the len is too big.
+        assert_match!(
+            prefault_range(&mem, (GuestAddress(0), 0x10000)).unwrap_err(),
+            RemoveRegionError::MalformedRange
+        );
+
+        // Region not mapped.
+        assert_match!(
+            prefault_range(&mem, (GuestAddress(0x10000), 0x10)).unwrap_err(),
+            RemoveRegionError::RegionNotFound
+        );
+
+        // Madvise fail: the guest address is not aligned to the page size.
+        assert_match!(
+            prefault_range(&mem, (GuestAddress(0x20), page_size as u64)).unwrap_err(),
+            RemoveRegionError::MadviseFail(_)
+        );
+    }
+
     #[test]
     fn test_remove_range_on_restored() {
         let page_size: usize = 0x1000;
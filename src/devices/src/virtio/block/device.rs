@@ -28,10 +28,14 @@ use vm_memory::GuestMemoryMmap;
 
 use super::super::{ActivateResult, DeviceState, Queue, VirtioDevice, TYPE_BLOCK};
 use super::io::async_io;
+use super::metrics::{BlockDeviceMetrics, BlockDeviceStats};
 use super::request::*;
 use super::{io as block_io, Error, CONFIG_SPACE_SIZE, QUEUE_SIZES, SECTOR_SHIFT, SECTOR_SIZE};
 use crate::virtio::{IrqTrigger, IrqType};
 
+// The zoned block device feature, which is missing from the virtio_gen bindings.
+pub const VIRTIO_BLK_F_ZONED: u32 = 17;
+
 /// Configuration options for disk caching.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum CacheType {
@@ -73,6 +77,27 @@ impl FileEngineType {
     }
 }
 
+/// The zoned characteristics of a block device, following the zoned storage model of the
+/// SMR and ZNS drives.
+///
+/// Zoned block devices are not emulated yet, and this is unreachable on purpose: it isn't
+/// part of `BlockDeviceConfig`, so no block device of a microVM has it set and
+/// `VIRTIO_BLK_F_ZONED` is never offered to a guest. The config space doesn't expose the
+/// zoned characteristics either, and all the zone management requests fail with
+/// `VIRTIO_BLK_S_UNSUPP`, so providing it through the API first needs both to be
+/// implemented.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ZonedConfig {
+    /// The size of a zone, in sectors.
+    pub zone_sectors: u32,
+    /// The maximum number of zones that can be open at once.
+    pub max_open_zones: u32,
+    /// The maximum number of zones that can be active (open or closed) at once.
+    pub max_active_zones: u32,
+    /// The maximum size of a zone append request, in sectors.
+    pub max_append_sectors: u32,
+}
+
 /// Helper object for setting up all `Block` fields derived from its backing file.
 pub(crate) struct DiskProperties {
     cache_type: CacheType,
@@ -213,6 +238,8 @@ pub struct Block {
     pub(crate) root_device: bool,
     pub(crate) rate_limiter: RateLimiter,
     is_io_engine_throttled: bool,
+    pub(crate) metrics: BlockDeviceMetrics,
+    pub(crate) zoned: Option<ZonedConfig>,
 }
 
 macro_rules! unwrap_async_file_engine_or_return {
@@ -278,6 +305,8 @@ impl Block {
             irq_trigger: IrqTrigger::new().map_err(Error::IrqTrigger)?,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             is_io_engine_throttled: false,
+            metrics: BlockDeviceMetrics::default(),
+            zoned: None,
         })
     }
 
@@ -346,7 +375,7 @@ impl Block {
                     }
 
                     used_any = true;
-                    request.process(&mut self.disk, head.index, mem)
+                    request.process(&mut self.disk, head.index, mem, &self.metrics)
                 }
                 Err(err) => {
                     error!("Failed to parse available descriptor chain: {:?}", err);
@@ -415,7 +444,7 @@ impl Block {
                             ))),
                         ),
                     };
-                    let finished = pending.finish(mem, res);
+                    let finished = pending.finish(mem, res, &self.metrics);
 
                     Self::add_used_descriptor(
                         queue,
@@ -502,6 +531,27 @@ impl Block {
         &self.rate_limiter
     }
 
+    /// Provides the zoned characteristics of this block device, if it is zoned.
+    pub fn zoned_config(&self) -> Option<ZonedConfig> {
+        self.zoned
+    }
+
+    /// Sets the zoned characteristics of this block device. `VIRTIO_BLK_F_ZONED` is only
+    /// offered to the driver when they are set, which isn't the case by default. See
+    /// `ZonedConfig` for why the VMM never sets them.
+    pub fn set_zoned_config(&mut self, zoned: Option<ZonedConfig>) {
+        match zoned {
+            Some(_) => self.avail_features |= 1u64 << VIRTIO_BLK_F_ZONED,
+            None => self.avail_features &= !(1u64 << VIRTIO_BLK_F_ZONED),
+        }
+        self.zoned = zoned;
+    }
+
+    /// Provides the I/O counters of this block device.
+    pub fn stats(&self) -> BlockDeviceStats {
+        self.metrics.stats()
+    }
+
     pub fn file_engine_type(&self) -> FileEngineType {
         match self.disk.file_engine() {
             FileEngine::Sync(_) => FileEngineType::Sync,
@@ -632,6 +682,7 @@ pub(crate) mod tests {
     use std::fs::metadata;
     use std::io::Read;
     use std::os::unix::ffi::OsStrExt;
+    use std::sync::atomic::Ordering;
     use std::time::Duration;
     use std::{thread, u32};
 
@@ -643,13 +694,13 @@ pub(crate) mod tests {
     use super::*;
     use crate::check_metric_after_block;
     use crate::virtio::block::test_utils::{
-        default_block, default_engine_type_for_kv, set_queue, set_rate_limiter,
-        simulate_async_completion_event, simulate_queue_and_async_completion_events,
-        simulate_queue_event,
+        default_block, default_block_with_path, default_engine_type_for_kv, set_queue,
+        set_rate_limiter, simulate_async_completion_event,
+        simulate_queue_and_async_completion_events, simulate_queue_event,
     };
     use crate::virtio::queue::tests::*;
     use crate::virtio::test_utils::{default_mem, initialize_virtqueue, VirtQueue};
-    use crate::virtio::IO_URING_NUM_ENTRIES;
+    use crate::virtio::{IO_URING_NUM_ENTRIES, VIRTIO_MMIO_INT_CONFIG};
 
     #[test]
     fn test_disk_backing_file_helper() {
@@ -706,6 +757,39 @@ pub(crate) mod tests {
         assert_eq!(block.acked_features, features);
     }
 
+    #[test]
+    fn test_zoned_features() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let features: u64 = (1u64 << VIRTIO_F_VERSION_1) | (1u64 << VIRTIO_RING_F_EVENT_IDX);
+
+        // Not offered by default.
+        assert!(block.zoned_config().is_none());
+        assert_eq!(block.avail_features(), features);
+
+        let zoned = ZonedConfig {
+            zone_sectors: 0x8_0000,
+            max_open_zones: 14,
+            max_active_zones: 14,
+            max_append_sectors: 0x100,
+        };
+        block.set_zoned_config(Some(zoned));
+        assert_eq!(block.zoned_config(), Some(zoned));
+        assert_eq!(
+            block.avail_features(),
+            features | (1u64 << VIRTIO_BLK_F_ZONED)
+        );
+
+        block.ack_features_by_page(0, u32::MAX);
+        assert_eq!(
+            block.acked_features(),
+            (1u64 << VIRTIO_RING_F_EVENT_IDX) | (1u64 << VIRTIO_BLK_F_ZONED)
+        );
+
+        block.set_zoned_config(None);
+        assert!(block.zoned_config().is_none());
+        assert_eq!(block.avail_features(), features);
+    }
+
     #[test]
     fn test_virtio_read_config() {
         let block = default_block(default_engine_type_for_kv());
@@ -1294,6 +1378,55 @@ pub(crate) mod tests {
             assert_eq!(vq.used.ring[0].get().len, 1);
             assert_eq!(mem.read_obj::<u32>(status_addr).unwrap(), VIRTIO_BLK_S_OK);
         }
+
+        assert_eq!(block.stats().flush_ops, 2);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut block = default_block(default_engine_type_for_kv());
+        let mem = default_mem();
+        let vq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        set_queue(&mut block, 0, vq.create_queue());
+        block.activate(mem.clone()).unwrap();
+        initialize_virtqueue(&vq);
+
+        let request_type_addr = GuestAddress(vq.dtable[0].addr.get());
+        let data_addr = GuestAddress(vq.dtable[1].addr.get());
+        let rand_data = utils::rand::rand_alphanumerics(512).as_bytes().to_vec();
+        assert_eq!(block.stats(), BlockDeviceStats::default());
+
+        // Write.
+        mem.write_obj::<u32>(VIRTIO_BLK_T_OUT, request_type_addr)
+            .unwrap();
+        vq.dtable[1].flags.set(VIRTQ_DESC_F_NEXT);
+        vq.dtable[1].len.set(512);
+        mem.write_slice(&rand_data, data_addr).unwrap();
+        simulate_queue_and_async_completion_events(&mut block, true);
+
+        // Read, twice.
+        for _ in 0..2 {
+            vq.used.idx.set(0);
+            set_queue(&mut block, 0, vq.create_queue());
+            mem.write_obj::<u32>(VIRTIO_BLK_T_IN, request_type_addr)
+                .unwrap();
+            vq.dtable[1]
+                .flags
+                .set(VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+            simulate_queue_and_async_completion_events(&mut block, true);
+        }
+
+        // Invalid requests aren't accounted.
+        vq.used.idx.set(0);
+        set_queue(&mut block, 0, vq.create_queue());
+        vq.dtable[1].len.set(511);
+        simulate_queue_and_async_completion_events(&mut block, true);
+
+        let stats = block.stats();
+        assert_eq!((stats.write_ops, stats.write_bytes), (1, 512));
+        assert_eq!((stats.read_ops, stats.read_bytes), (2, 1024));
+        assert_eq!(stats.flush_ops, 0);
+        assert_ne!(stats.io_latency_us_p99, 0);
     }
 
     #[test]
@@ -1669,4 +1802,25 @@ pub(crate) mod tests {
         );
         assert_eq!(block.disk.image_id, id.as_slice());
     }
+
+    #[test]
+    fn test_update_disk_image_resized() {
+        // Updating the disk image with its current path picks up the new size of the file.
+        let f = TempFile::new().unwrap();
+        f.as_file().set_len(0x1000).unwrap();
+        let path = f.as_path().to_str().unwrap().to_string();
+        let mut block = default_block_with_path(path.clone(), default_engine_type_for_kv());
+        let interrupt_status = block.interrupt_status();
+
+        f.as_file().set_len(0x3000).unwrap();
+        block.update_disk_image(path).unwrap();
+        assert_eq!(block.disk.nsectors(), 0x3000 >> SECTOR_SHIFT);
+        let mut capacity = [0u8; CONFIG_SPACE_SIZE];
+        block.read_config(0, &mut capacity);
+        assert_eq!(u64::from_le_bytes(capacity), 0x3000 >> SECTOR_SHIFT);
+        assert_eq!(
+            interrupt_status.load(Ordering::SeqCst),
+            VIRTIO_MMIO_INT_CONFIG as usize
+        );
+    }
 }
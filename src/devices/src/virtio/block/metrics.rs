@@ -0,0 +1,165 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! I/O counters of a single block device.
+//!
+//! Unlike the `block` metrics of the logger, which aggregate all the drives and are reset on
+//! every flush, these counters are kept per device and are read through the API.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// The number of latency buckets. Bucket `i` counts the requests which took less than `2^i`
+/// microseconds (and at least `2^(i-1)`), the last one also counts all the slower requests.
+const LATENCY_BUCKETS: usize = 32;
+
+/// The I/O counters of a block device.
+#[derive(Debug, Default)]
+pub struct BlockDeviceMetrics {
+    /// Number of bytes read by the guest.
+    pub read_bytes: AtomicU64,
+    /// Number of bytes written by the guest.
+    pub write_bytes: AtomicU64,
+    /// Number of successful read requests.
+    pub read_ops: AtomicU64,
+    /// Number of successful write requests.
+    pub write_ops: AtomicU64,
+    /// Number of flush requests.
+    pub flush_ops: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS],
+}
+
+impl BlockDeviceMetrics {
+    /// Accounts a successful read request of `len` bytes.
+    pub fn add_read(&self, len: u32) {
+        self.read_bytes.fetch_add(u64::from(len), Ordering::Relaxed);
+        self.read_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts a successful write request of `len` bytes.
+    pub fn add_write(&self, len: u32) {
+        self.write_bytes
+            .fetch_add(u64::from(len), Ordering::Relaxed);
+        self.write_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts a flush request.
+    pub fn add_flush(&self) {
+        self.flush_ops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts the latency of a request, from the moment it was popped from the queue to its
+    /// completion.
+    pub fn add_latency(&self, latency_us: u64) {
+        let bucket = (u64::BITS - latency_us.leading_zeros()) as usize;
+        self.latency_buckets[bucket.min(LATENCY_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Returns the upper bound of the bucket holding the given percentile of the latencies.
+    // This is only computed when the stats are read, so that the I/O path only increments a
+    // bucket.
+    fn latency_percentile(&self, percentile: u64) -> u64 {
+        let mut counts = [0; LATENCY_BUCKETS];
+        for (count, bucket) in counts.iter_mut().zip(self.latency_buckets.iter()) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        let total: u64 = counts.iter().sum();
+        // The rank of the percentile, rounded up.
+        let rank = (total * percentile + 99) / 100;
+
+        let mut seen = 0;
+        for (bucket, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank && seen > 0 {
+                return 1 << bucket;
+            }
+        }
+        0
+    }
+
+    /// Returns the current values of the counters.
+    pub fn stats(&self) -> BlockDeviceStats {
+        BlockDeviceStats {
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            write_bytes: self.write_bytes.load(Ordering::Relaxed),
+            read_ops: self.read_ops.load(Ordering::Relaxed),
+            write_ops: self.write_ops.load(Ordering::Relaxed),
+            flush_ops: self.flush_ops.load(Ordering::Relaxed),
+            io_latency_us_p99: self.latency_percentile(99),
+        }
+    }
+}
+
+/// The values of the I/O counters of a block device, as returned by the API.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct BlockDeviceStats {
+    /// Number of bytes read by the guest.
+    pub read_bytes: u64,
+    /// Number of bytes written by the guest.
+    pub write_bytes: u64,
+    /// Number of successful read requests.
+    pub read_ops: u64,
+    /// Number of successful write requests.
+    pub write_ops: u64,
+    /// Number of flush requests.
+    pub flush_ops: u64,
+    /// The 99th percentile of the request latencies, in microseconds. This is the upper bound
+    /// of a power of 2 bucket, so it can be up to twice the actual latency.
+    pub io_latency_us_p99: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let metrics = BlockDeviceMetrics::default();
+        assert_eq!(metrics.stats(), BlockDeviceStats::default());
+
+        metrics.add_read(512);
+        metrics.add_read(4096);
+        metrics.add_write(1024);
+        metrics.add_flush();
+
+        assert_eq!(
+            metrics.stats(),
+            BlockDeviceStats {
+                read_bytes: 4608,
+                write_bytes: 1024,
+                read_ops: 2,
+                write_ops: 1,
+                flush_ops: 1,
+                io_latency_us_p99: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_latency_p99() {
+        let metrics = BlockDeviceMetrics::default();
+        // 99 requests completed in [64, 128) us.
+        for _ in 0..99 {
+            metrics.add_latency(100);
+        }
+        assert_eq!(metrics.stats().io_latency_us_p99, 128);
+
+        // A single slow request is past the 99th percentile.
+        metrics.add_latency(5000);
+        assert_eq!(metrics.stats().io_latency_us_p99, 128);
+        // But not anymore once there are 2 out of 101.
+        metrics.add_latency(5000);
+        assert_eq!(metrics.stats().io_latency_us_p99, 8192);
+
+        // Immediate requests and huge latencies don't overflow the buckets.
+        let metrics = BlockDeviceMetrics::default();
+        metrics.add_latency(0);
+        assert_eq!(metrics.stats().io_latency_us_p99, 1);
+        metrics.add_latency(u64::MAX);
+        assert_eq!(
+            metrics.stats().io_latency_us_p99,
+            1 << (LATENCY_BUCKETS - 1)
+        );
+    }
+}
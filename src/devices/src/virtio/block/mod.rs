@@ -4,14 +4,16 @@
 pub mod device;
 pub mod event_handler;
 mod io;
+pub mod metrics;
 pub mod persist;
 pub mod request;
 pub mod test_utils;
 
 use vm_memory::GuestMemoryError;
 
-pub use self::device::{Block, CacheType};
+pub use self::device::{Block, CacheType, ZonedConfig};
 pub use self::event_handler::*;
+pub use self::metrics::{BlockDeviceMetrics, BlockDeviceStats};
 pub use self::request::*;
 
 pub const CONFIG_SPACE_SIZE: usize = 8;
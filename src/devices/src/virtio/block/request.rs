@@ -10,15 +10,23 @@ use std::result;
 
 use logger::{error, IncMetric, METRICS};
 use rate_limiter::{RateLimiter, TokenType};
+use utils::time::{get_time_us, ClockType};
 pub use virtio_gen::virtio_blk::{
     VIRTIO_BLK_ID_BYTES, VIRTIO_BLK_S_IOERR, VIRTIO_BLK_S_OK, VIRTIO_BLK_S_UNSUPP,
     VIRTIO_BLK_T_FLUSH, VIRTIO_BLK_T_GET_ID, VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
 };
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
+// The zone management requests of the zoned block devices, which are missing from the
+// virtio_gen bindings.
+pub const VIRTIO_BLK_T_ZONE_REPORT: u32 = 16;
+pub const VIRTIO_BLK_T_ZONE_OPEN: u32 = 18;
+pub const VIRTIO_BLK_T_ZONE_CLOSE: u32 = 20;
+
 use super::super::DescriptorChain;
 use super::{io as block_io, Error, SECTOR_SHIFT};
 use crate::virtio::block::device::DiskProperties;
+use crate::virtio::block::metrics::BlockDeviceMetrics;
 use crate::virtio::SECTOR_SIZE;
 
 #[derive(Debug, derive_more::From)]
@@ -34,6 +42,10 @@ pub enum RequestType {
     Out,
     Flush,
     GetDeviceID,
+    // The zone management requests, not implemented yet.
+    ZoneReport,
+    ZoneOpen,
+    ZoneClose,
     Unsupported(u32),
 }
 
@@ -44,6 +56,9 @@ impl From<u32> for RequestType {
             VIRTIO_BLK_T_OUT => RequestType::Out,
             VIRTIO_BLK_T_FLUSH => RequestType::Flush,
             VIRTIO_BLK_T_GET_ID => RequestType::GetDeviceID,
+            VIRTIO_BLK_T_ZONE_REPORT => RequestType::ZoneReport,
+            VIRTIO_BLK_T_ZONE_OPEN => RequestType::ZoneOpen,
+            VIRTIO_BLK_T_ZONE_CLOSE => RequestType::ZoneClose,
             t => RequestType::Unsupported(t),
         }
     }
@@ -91,6 +106,8 @@ pub struct PendingRequest {
     data_len: u32,
     status_addr: GuestAddress,
     desc_idx: u16,
+    // When the request was popped from the queue, for the latency metrics.
+    start_us: u64,
 }
 
 impl PendingRequest {
@@ -133,13 +150,21 @@ impl PendingRequest {
         }
     }
 
-    pub fn finish(self, mem: &GuestMemoryMmap, res: Result<u32, IoErr>) -> FinishedRequest {
+    pub fn finish(
+        self,
+        mem: &GuestMemoryMmap,
+        res: Result<u32, IoErr>,
+        metrics: &BlockDeviceMetrics,
+    ) -> FinishedRequest {
+        metrics.add_latency(get_time_us(ClockType::Monotonic).saturating_sub(self.start_us));
+
         let status = match (res, self.r#type) {
             (Ok(transferred_data_len), RequestType::In) => {
                 let status = Status::from_data(self.data_len, transferred_data_len, true);
                 METRICS.block.read_bytes.add(transferred_data_len as usize);
                 if let Status::Ok { .. } = status {
                     METRICS.block.read_count.inc();
+                    metrics.add_read(transferred_data_len);
                 }
                 status
             }
@@ -148,11 +173,13 @@ impl PendingRequest {
                 METRICS.block.write_bytes.add(transferred_data_len as usize);
                 if let Status::Ok { .. } = status {
                     METRICS.block.write_count.inc();
+                    metrics.add_write(transferred_data_len);
                 }
                 status
             }
             (Ok(_), RequestType::Flush) => {
                 METRICS.block.flush_count.inc();
+                metrics.add_flush();
                 Status::Ok {
                     num_bytes_to_mem: 0,
                 }
@@ -160,6 +187,15 @@ impl PendingRequest {
             (Ok(transferred_data_len), RequestType::GetDeviceID) => {
                 Status::from_data(self.data_len, transferred_data_len, true)
             }
+            (_, RequestType::ZoneReport) => Status::Unsupported {
+                op: VIRTIO_BLK_T_ZONE_REPORT,
+            },
+            (_, RequestType::ZoneOpen) => Status::Unsupported {
+                op: VIRTIO_BLK_T_ZONE_OPEN,
+            },
+            (_, RequestType::ZoneClose) => Status::Unsupported {
+                op: VIRTIO_BLK_T_ZONE_CLOSE,
+            },
             (_, RequestType::Unsupported(op)) => Status::Unsupported { op },
             (Err(err), _) => Status::IoErr {
                 num_bytes_to_mem: 0,
@@ -250,8 +286,11 @@ impl Request {
 
         if !desc.has_next() {
             status_desc = desc;
-            // Only flush requests are allowed to skip the data descriptor.
-            if req.r#type != RequestType::Flush {
+            // Only flush and zone open/close requests are allowed to skip the data descriptor.
+            if !matches!(
+                req.r#type,
+                RequestType::Flush | RequestType::ZoneOpen | RequestType::ZoneClose
+            ) {
                 return Err(Error::DescriptorChainTooShort);
             }
         } else {
@@ -342,6 +381,7 @@ impl Request {
             data_len: self.data_len,
             status_addr: self.status_addr,
             desc_idx,
+            start_us: get_time_us(ClockType::Monotonic),
         }
     }
 
@@ -350,6 +390,7 @@ impl Request {
         disk: &mut DiskProperties,
         desc_idx: u16,
         mem: &GuestMemoryMmap,
+        metrics: &BlockDeviceMetrics,
     ) -> ProcessingResult {
         let pending = self.to_pending_request(desc_idx);
         let res = match self.r#type {
@@ -373,25 +414,30 @@ impl Request {
                     .write_slice(disk.image_id(), self.data_addr)
                     .map(|_| VIRTIO_BLK_ID_BYTES)
                     .map_err(IoErr::GetId);
-                return ProcessingResult::Executed(pending.finish(mem, res));
+                return ProcessingResult::Executed(pending.finish(mem, res, metrics));
             }
-            RequestType::Unsupported(_) => {
-                return ProcessingResult::Executed(pending.finish(mem, Ok(0)));
+            RequestType::ZoneReport
+            | RequestType::ZoneOpen
+            | RequestType::ZoneClose
+            | RequestType::Unsupported(_) => {
+                return ProcessingResult::Executed(pending.finish(mem, Ok(0), metrics));
             }
         };
 
         match res {
             Ok(block_io::FileEngineOk::Submitted) => ProcessingResult::Submitted,
             Ok(block_io::FileEngineOk::Executed(res)) => {
-                ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count)))
+                ProcessingResult::Executed(res.user_data.finish(mem, Ok(res.count), metrics))
             }
             Err(err) => {
                 if err.error.is_throttling_err() {
                     ProcessingResult::Throttled
                 } else {
-                    ProcessingResult::Executed(
-                        err.user_data.finish(mem, Err(IoErr::FileEngine(err.error))),
-                    )
+                    ProcessingResult::Executed(err.user_data.finish(
+                        mem,
+                        Err(IoErr::FileEngine(err.error)),
+                        metrics,
+                    ))
                 }
             }
         }
@@ -449,6 +495,18 @@ mod tests {
             RequestType::from(VIRTIO_BLK_T_GET_ID),
             RequestType::GetDeviceID
         );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_ZONE_REPORT),
+            RequestType::ZoneReport
+        );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_ZONE_OPEN),
+            RequestType::ZoneOpen
+        );
+        assert_eq!(
+            RequestType::from(VIRTIO_BLK_T_ZONE_CLOSE),
+            RequestType::ZoneClose
+        );
         assert_eq!(RequestType::from(42), RequestType::Unsupported(42));
     }
 
@@ -696,6 +754,32 @@ mod tests {
         queue.check_parse(true);
     }
 
+    #[test]
+    fn test_parse_zone() {
+        let mem = &create_anon_guest_memory(&[(GuestAddress(0), 0x10000)], false).unwrap();
+        let mut queue = RequestVirtQueue::new(GuestAddress(0), mem);
+
+        // Zone report request with a data descriptor.
+        let request_header = RequestHeader::new(VIRTIO_BLK_T_ZONE_REPORT, 0);
+        queue.set_hdr_desc(0x1000, 0x1000, VIRTQ_DESC_F_NEXT, request_header);
+        queue.set_data_desc(0x2000, 0x1000, VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE);
+        queue.set_status_desc(0x3000, 0x1000, VIRTQ_DESC_F_WRITE);
+        queue.check_parse(true);
+
+        // Zone report request without a data descriptor.
+        queue
+            .mut_hdr_desc()
+            .next
+            .set(RequestVirtQueue::STATUS_DESC as u16);
+        queue.check_parse_err(Error::DescriptorChainTooShort);
+
+        // Zone open and close requests don't need one.
+        queue.mut_hdr().request_type = VIRTIO_BLK_T_ZONE_OPEN;
+        queue.check_parse(false);
+        queue.mut_hdr().request_type = VIRTIO_BLK_T_ZONE_CLOSE;
+        queue.check_parse(false);
+    }
+
     use std::convert::TryInto;
 
     /// -------------------------------------
@@ -738,9 +822,9 @@ mod tests {
                     1u32,
                     std::sync::Arc::new(Strategy::prop_map(any::<u32>(), |id| {
                         // Random unsupported requests for our implementation start at
-                        // VIRTIO_BLK_T_GET_ID + 1 = 9.
-                        // This can be further refined to include unsupported requests ids < 9.
-                        RequestType::Unsupported(id.checked_add(9).unwrap_or(9))
+                        // VIRTIO_BLK_T_ZONE_CLOSE + 1 = 21.
+                        // This can be further refined to include unsupported requests ids < 21.
+                        RequestType::Unsupported(id.checked_add(21).unwrap_or(21))
                     })),
                 ),
             ))
@@ -754,6 +838,9 @@ mod tests {
                 RequestType::Out => VIRTIO_BLK_T_OUT,
                 RequestType::Flush => VIRTIO_BLK_T_FLUSH,
                 RequestType::GetDeviceID => VIRTIO_BLK_T_GET_ID,
+                RequestType::ZoneReport => VIRTIO_BLK_T_ZONE_REPORT,
+                RequestType::ZoneOpen => VIRTIO_BLK_T_ZONE_OPEN,
+                RequestType::ZoneClose => VIRTIO_BLK_T_ZONE_CLOSE,
                 RequestType::Unsupported(id) => id,
             }
         }
@@ -766,6 +853,8 @@ mod tests {
             RequestType::Out => VIRTQ_DESC_F_NEXT,
             RequestType::Flush => VIRTQ_DESC_F_NEXT,
             RequestType::GetDeviceID => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+            RequestType::ZoneReport => VIRTQ_DESC_F_NEXT | VIRTQ_DESC_F_WRITE,
+            RequestType::ZoneOpen | RequestType::ZoneClose => VIRTQ_DESC_F_NEXT,
             RequestType::Unsupported(_) => VIRTQ_DESC_F_NEXT,
         }
     }
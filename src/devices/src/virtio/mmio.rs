@@ -8,8 +8,9 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 
-use logger::warn;
+use logger::{error, warn};
 use utils::byte_order;
+use virtio_gen::virtio_blk::VIRTIO_F_VERSION_1;
 use vm_memory::{GuestAddress, GuestMemoryMmap};
 
 use super::{device_status, *};
@@ -135,6 +136,16 @@ impl MmioTransport {
         }
     }
 
+    // Returns the features page offered to the driver, as read from the device features
+    // register.
+    fn offered_features_by_page(&self, page: u32) -> u32 {
+        let mut features = self.locked_device().avail_features_by_page(page);
+        if page == 1 {
+            features |= 0x1; // enable support of VirtIO Version 1
+        }
+        features
+    }
+
     fn reset(&mut self) {
         if self.locked_device().is_activated() {
             warn!("reset device while it's still in active state");
@@ -172,7 +183,20 @@ impl MmioTransport {
                 self.device_status = status;
             }
             FEATURES_OK if self.device_status == (ACKNOWLEDGE | DRIVER) => {
-                self.device_status = status;
+                // Only the VirtIO 1.0 interface is implemented, so the legacy drivers which
+                // don't accept VIRTIO_F_VERSION_1 are refused by leaving FEATURES_OK cleared,
+                // as specified in section 3.1.1.
+                if self
+                    .locked_device()
+                    .has_feature(u64::from(VIRTIO_F_VERSION_1))
+                {
+                    self.device_status = status;
+                } else {
+                    error!(
+                        "DeviceFeatureNegotiationError: the driver didn't accept \
+                         VIRTIO_F_VERSION_1"
+                    );
+                }
             }
             DRIVER_OK if self.device_status == (ACKNOWLEDGE | DRIVER | FEATURES_OK) => {
                 self.device_status = status;
@@ -225,15 +249,7 @@ impl BusDevice for MmioTransport {
                     0x04 => MMIO_VERSION,
                     0x08 => self.locked_device().device_type(),
                     0x0c => VENDOR_ID, // vendor id
-                    0x10 => {
-                        let mut features = self
-                            .locked_device()
-                            .avail_features_by_page(self.features_select);
-                        if self.features_select == 1 {
-                            features |= 0x1; // enable support of VirtIO Version 1
-                        }
-                        features
-                    }
+                    0x10 => self.offered_features_by_page(self.features_select),
                     0x34 => self.with_queue(0, |q| u32::from(q.get_max_size())),
                     0x44 => self.with_queue(0, |q| q.ready as u32),
                     0x60 => self.interrupt_status.load(Ordering::SeqCst) as u32,
@@ -276,8 +292,20 @@ impl BusDevice for MmioTransport {
                             device_status::DRIVER,
                             device_status::FEATURES_OK | device_status::FAILED,
                         ) {
-                            self.locked_device()
-                                .ack_features_by_page(self.acked_features_select, v);
+                            let page = self.acked_features_select;
+                            let unoffered_features = v & !self.offered_features_by_page(page);
+                            if unoffered_features != 0 {
+                                // The driver doesn't follow the negotiation, so the device
+                                // is given up on, as if the driver had set FAILED itself.
+                                error!(
+                                    "DeviceFeatureNegotiationError: the driver acked the \
+                                     features 0x{:x} of page {} which were not offered",
+                                    unoffered_features, page
+                                );
+                                self.device_status |= device_status::FAILED;
+                            } else {
+                                self.locked_device().ack_features_by_page(page, v);
+                            }
                         } else {
                             warn!(
                                 "ack virtio features in invalid state 0x{:x}",
@@ -348,7 +376,7 @@ pub(crate) mod tests {
         pub(crate) fn new() -> Self {
             DummyDevice {
                 acked_features: 0,
-                avail_features: 0,
+                avail_features: 1 << VIRTIO_F_VERSION_1,
                 interrupt_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
                 interrupt_status: Arc::new(AtomicUsize::new(0)),
                 queue_evts: vec![
@@ -429,6 +457,13 @@ pub(crate) mod tests {
         d.write(0x70, &buf[..]);
     }
 
+    fn ack_version_1(d: &mut MmioTransport) {
+        let mut buf = vec![0; 4];
+        d.acked_features_select = 1;
+        write_le_u32(&mut buf[..], 0x1);
+        d.write(0x20, &buf[..]);
+    }
+
     #[test]
     fn test_new() {
         let m =
@@ -601,9 +636,17 @@ pub(crate) mod tests {
         write_le_u32(&mut buf[..], 0x124);
 
         // Set the device available features in order to make acknowledging possible.
-        dummy_dev.lock().unwrap().set_avail_features(0x124);
+        dummy_dev
+            .lock()
+            .unwrap()
+            .set_avail_features(0x124 | (1 << VIRTIO_F_VERSION_1));
         d.write(0x20, &buf[..]);
         assert_eq!(d.locked_device().acked_features(), 0x124);
+        ack_version_1(&mut d);
+        assert_eq!(
+            d.locked_device().acked_features(),
+            0x124 | (1 << VIRTIO_F_VERSION_1)
+        );
 
         d.acked_features_select = 0;
         write_le_u32(&mut buf[..], 2);
@@ -615,11 +658,17 @@ pub(crate) mod tests {
         );
 
         // Acking features in invalid state shouldn't take effect.
-        assert_eq!(d.locked_device().acked_features(), 0x124);
+        assert_eq!(
+            d.locked_device().acked_features(),
+            0x124 | (1 << VIRTIO_F_VERSION_1)
+        );
         d.acked_features_select = 0x0;
         write_le_u32(&mut buf[..], 1);
         d.write(0x20, &buf[..]);
-        assert_eq!(d.locked_device().acked_features(), 0x124);
+        assert_eq!(
+            d.locked_device().acked_features(),
+            0x124 | (1 << VIRTIO_F_VERSION_1)
+        );
 
         // Setup queues
         d.queue_select = 0;
@@ -732,6 +781,17 @@ pub(crate) mod tests {
             device_status::ACKNOWLEDGE | device_status::DRIVER
         );
 
+        // The features are refused without VIRTIO_F_VERSION_1.
+        set_device_status(
+            &mut d,
+            device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FEATURES_OK,
+        );
+        assert_eq!(
+            d.device_status,
+            device_status::ACKNOWLEDGE | device_status::DRIVER
+        );
+
+        ack_version_1(&mut d);
         set_device_status(
             &mut d,
             device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FEATURES_OK,
@@ -788,6 +848,7 @@ pub(crate) mod tests {
     fn activate_device(d: &mut MmioTransport) {
         set_device_status(d, device_status::ACKNOWLEDGE);
         set_device_status(d, device_status::ACKNOWLEDGE | device_status::DRIVER);
+        ack_version_1(d);
         set_device_status(
             d,
             device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FEATURES_OK,
@@ -824,6 +885,50 @@ pub(crate) mod tests {
         assert!(d.locked_device().is_activated());
     }
 
+    #[test]
+    fn test_ack_unoffered_features() {
+        let m =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x1000)], false)
+                .unwrap();
+        let dummy_dev = Arc::new(Mutex::new(DummyDevice::new()));
+        dummy_dev.lock().unwrap().set_avail_features(0x124);
+        let mut d = MmioTransport::new(m, dummy_dev);
+        let mut buf = vec![0; 4];
+
+        set_device_status(&mut d, device_status::ACKNOWLEDGE);
+        set_device_status(&mut d, device_status::ACKNOWLEDGE | device_status::DRIVER);
+
+        // A subset of the offered features is accepted.
+        d.acked_features_select = 0;
+        write_le_u32(&mut buf[..], 0x24);
+        d.write(0x20, &buf[..]);
+        assert_eq!(d.locked_device().acked_features(), 0x24);
+        // VirtIO Version 1 is always offered by the transport.
+        d.acked_features_select = 1;
+        write_le_u32(&mut buf[..], 0x1);
+        d.write(0x20, &buf[..]);
+        assert_eq!(d.locked_device().acked_features(), 0x24);
+        assert_eq!(
+            d.device_status,
+            device_status::ACKNOWLEDGE | device_status::DRIVER
+        );
+
+        // A feature which wasn't offered fails the device and nothing is acked.
+        d.acked_features_select = 0;
+        write_le_u32(&mut buf[..], 0x101);
+        d.write(0x20, &buf[..]);
+        assert_eq!(d.locked_device().acked_features(), 0x24);
+        assert_eq!(
+            d.device_status,
+            device_status::ACKNOWLEDGE | device_status::DRIVER | device_status::FAILED
+        );
+
+        // The failed device can't negotiate anymore.
+        write_le_u32(&mut buf[..], 0x100);
+        d.write(0x20, &buf[..]);
+        assert_eq!(d.locked_device().acked_features(), 0x24);
+    }
+
     #[test]
     fn test_bus_device_reset() {
         let m =
@@ -8,6 +8,7 @@ use std::io::{Read, Write};
 use std::net::Ipv4Addr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{cmp, mem, result};
 
 use dumbo::pdu::ethernet::EthernetFrame;
@@ -20,18 +21,21 @@ use utils::eventfd::EventFd;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use utils::rand_bytes;
 use virtio_gen::virtio_net::{
-    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_GUEST_CSUM,
-    VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO, VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO,
-    VIRTIO_NET_F_MAC,
+    virtio_net_hdr_v1, VIRTIO_F_VERSION_1, VIRTIO_NET_CTRL_MAC, VIRTIO_NET_CTRL_MAC_ADDR_SET,
+    VIRTIO_NET_ERR, VIRTIO_NET_F_CSUM, VIRTIO_NET_F_CTRL_MAC_ADDR, VIRTIO_NET_F_CTRL_VQ,
+    VIRTIO_NET_F_GUEST_CSUM, VIRTIO_NET_F_GUEST_TSO4, VIRTIO_NET_F_GUEST_UFO,
+    VIRTIO_NET_F_HOST_TSO4, VIRTIO_NET_F_HOST_UFO, VIRTIO_NET_F_MAC, VIRTIO_NET_OK,
 };
 use virtio_gen::virtio_ring::VIRTIO_RING_F_EVENT_IDX;
 use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
 
+use crate::virtio::net::metrics::{NetDeviceMetrics, NetDeviceStats};
 use crate::virtio::net::tap::Tap;
 #[cfg(test)]
 use crate::virtio::net::test_utils::Mocks;
 use crate::virtio::net::{
-    Error, NetQueue, Result, MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX, TX_INDEX,
+    Error, NetQueue, Result, CTRL_INDEX, MAX_BUFFER_SIZE, QUEUE_SIZE, QUEUE_SIZES, RX_INDEX,
+    TX_INDEX,
 };
 use crate::virtio::{
     ActivateResult, DescriptorChain, DeviceState, IrqTrigger, IrqType, Queue, VirtioDevice,
@@ -50,6 +54,10 @@ enum FrontendError {
 // KVM OUI MAC address is 52:54:00:xx:xx:xx
 const KVM_OUI: [u8; 3] = [0x52, 0x54, 0x00];
 
+// The size of the largest supported control command: the class and command bytes, followed by
+// a MAC address.
+const CTRL_COMMAND_MAX_LEN: usize = 2 + MAC_ADDR_LEN;
+
 pub(crate) fn vnet_hdr_len() -> usize {
     mem::size_of::<virtio_net_hdr_v1>()
 }
@@ -105,6 +113,33 @@ impl Default for ConfigSpace {
 // SAFETY: `ConfigSpace` contains only PODs.
 unsafe impl ByteValued for ConfigSpace {}
 
+// Bounds the time spent processing the TX queue in a single event loop iteration.
+struct TxBudget {
+    budget: Duration,
+    // Set when the TX queue processing starts, the budget is exhausted once it is reached.
+    deadline: Instant,
+}
+
+impl TxBudget {
+    fn new(budget: Duration) -> Self {
+        TxBudget {
+            budget,
+            deadline: Instant::now(),
+        }
+    }
+
+    // Computes the deadline when the TX queue processing starts.
+    fn start(&mut self) {
+        self.deadline = Instant::now() + self.budget;
+    }
+
+    // Returns whether the deadline computed by `start` is reached. With a zero budget, it is
+    // reached right away.
+    fn exceeded(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
 pub struct Net {
     pub(crate) id: String,
 
@@ -126,6 +161,7 @@ pub struct Net {
 
     tx_iovec: Vec<(GuestAddress, usize)>,
     tx_frame_buf: [u8; MAX_BUFFER_SIZE],
+    tx_budget: Option<TxBudget>,
 
     pub(crate) irq_trigger: IrqTrigger,
 
@@ -137,6 +173,9 @@ pub struct Net {
 
     pub mmds_ns: Option<MmdsNetworkStack>,
 
+    pub(crate) metrics: NetDeviceMetrics,
+    pub(crate) stats_reset_on_read: bool,
+
     #[cfg(test)]
     pub(crate) mocks: Mocks,
 }
@@ -206,12 +245,15 @@ impl Net {
             rx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_frame_buf: [0u8; MAX_BUFFER_SIZE],
             tx_iovec: Vec::with_capacity(QUEUE_SIZE as usize),
+            tx_budget: None,
             irq_trigger: IrqTrigger::new().map_err(Error::EventFd)?,
             device_state: DeviceState::Inactive,
             activate_evt: EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?,
             config_space,
             mmds_ns: None,
             guest_mac: mac_addr,
+            metrics: NetDeviceMetrics::default(),
+            stats_reset_on_read: false,
 
             #[cfg(test)]
             mocks: Mocks::default(),
@@ -263,6 +305,56 @@ impl Net {
         &self.tx_rate_limiter
     }
 
+    /// Enables the control virtqueue, through which the guest can change its MAC address at
+    /// runtime. It must be called before the device is attached to the MMIO bus.
+    pub fn enable_ctrl_vq(&mut self) -> Result<()> {
+        if self.ctrl_vq_enabled() {
+            return Ok(());
+        }
+
+        self.queue_evts
+            .push(EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?);
+        self.queues.push(Queue::new(QUEUE_SIZE));
+        self.avail_features |= 1 << VIRTIO_NET_F_CTRL_VQ | 1 << VIRTIO_NET_F_CTRL_MAC_ADDR;
+
+        Ok(())
+    }
+
+    /// Returns whether the control virtqueue is enabled.
+    pub fn ctrl_vq_enabled(&self) -> bool {
+        self.avail_features & (1 << VIRTIO_NET_F_CTRL_VQ) != 0
+    }
+
+    /// Returns whether the packet counters are reset every time they are read.
+    pub fn stats_reset_on_read(&self) -> bool {
+        self.stats_reset_on_read
+    }
+
+    /// Sets whether the packet counters are reset every time they are read.
+    pub fn set_stats_reset_on_read(&mut self, reset_on_read: bool) {
+        self.stats_reset_on_read = reset_on_read;
+    }
+
+    /// Returns the maximum time, in microseconds, spent processing the TX queue at once.
+    pub fn tx_budget_us(&self) -> Option<u64> {
+        self.tx_budget
+            .as_ref()
+            .map(|tx_budget| tx_budget.budget.as_micros() as u64)
+    }
+
+    /// Bounds the time spent processing the TX queue in a single event loop iteration, so
+    /// that a guest flooding the queue doesn't starve the other event handlers. Once the
+    /// budget is exhausted, the remaining frames are left for the next iteration. A budget of
+    /// 0 transmits a single frame per iteration.
+    pub fn set_tx_budget_us(&mut self, budget_us: Option<u64>) {
+        self.tx_budget = budget_us.map(|budget_us| TxBudget::new(Duration::from_micros(budget_us)));
+    }
+
+    /// Provides the packet counters of this net device.
+    pub fn stats(&self) -> NetDeviceStats {
+        self.metrics.stats(self.stats_reset_on_read)
+    }
+
     fn signal_used_queue(&mut self, queue_type: NetQueue) -> result::Result<(), DeviceError> {
         // This is safe since we checked in the event handler that the device is activated.
         let mem = self.device_state.mem().unwrap();
@@ -270,6 +362,7 @@ impl Net {
         let queue = match queue_type {
             NetQueue::Rx => &mut self.queues[RX_INDEX],
             NetQueue::Tx => &mut self.queues[TX_INDEX],
+            NetQueue::Ctrl => &mut self.queues[CTRL_INDEX],
         };
 
         if queue.prepare_kick(mem) {
@@ -388,8 +481,10 @@ impl Net {
         // Mark the descriptor chain as used. If an error occurred, skip the descriptor chain.
         let used_len = if result.is_err() {
             METRICS.net.rx_fails.inc();
+            self.metrics.add_rx_drop();
             0
         } else {
+            self.metrics.add_rx_packet(self.rx_bytes_read);
             self.rx_bytes_read as u32
         };
         queue.add_used(mem, head_index, used_len).map_err(|err| {
@@ -430,6 +525,7 @@ impl Net {
         frame_buf: &[u8],
         tap: &mut Tap,
         guest_mac: MacAddr,
+        metrics: &NetDeviceMetrics,
     ) -> Result<bool> {
         let checked_frame = |frame_buf| {
             frame_bytes_from_buf(frame_buf).map_err(|err| {
@@ -465,6 +561,7 @@ impl Net {
                 METRICS.net.tx_bytes_count.add(frame_buf.len());
                 METRICS.net.tx_packets_count.inc();
                 METRICS.net.tx_count.inc();
+                metrics.add_tx_packet(frame_buf.len());
             }
             Err(err) => {
                 error!("Failed to write to tap: {:?}", err);
@@ -559,6 +656,10 @@ impl Net {
         let mut used_any = false;
         let tx_queue = &mut self.queues[TX_INDEX];
 
+        if let Some(tx_budget) = self.tx_budget.as_mut() {
+            tx_budget.start();
+        }
+
         while let Some(head) = tx_queue.pop_or_enable_notification(mem) {
             // If limiter.consume() fails it means there is no more TokenType::Ops
             // budget and rate limiting is in effect.
@@ -635,6 +736,7 @@ impl Net {
                 &self.tx_frame_buf[..read_count],
                 &mut self.tap,
                 self.guest_mac,
+                &self.metrics,
             )
             .unwrap_or(false);
             if frame_consumed_by_mmds && !self.rx_deferred_frame {
@@ -646,6 +748,21 @@ impl Net {
                 .add_used(mem, head_index, 0)
                 .map_err(DeviceError::QueueError)?;
             used_any = true;
+
+            // Without any frame left, the loop goes on so that the notifications are enabled
+            // again, instead of waking up the event loop for nothing.
+            if matches!(&self.tx_budget, Some(tx_budget) if tx_budget.exceeded())
+                && !tx_queue.is_empty(mem)
+            {
+                // Let the other event handlers run, and get back to the remaining frames on
+                // the next event loop iteration.
+                METRICS.net.tx_budget_exceeded.inc();
+                if let Err(err) = self.queue_evts[TX_INDEX].write(1) {
+                    error!("Failed to re-arm the tx queue event: {:?}", err);
+                    METRICS.net.event_fails.inc();
+                }
+                break;
+            }
         }
 
         if !used_any {
@@ -662,6 +779,103 @@ impl Net {
         }
     }
 
+    // Executes a control command, made of the class and command bytes followed by the command
+    // specific data, and returns the ack to send back to the guest.
+    //
+    // Only `VIRTIO_NET_CTRL_MAC_ADDR_SET` is supported.
+    fn handle_ctrl_command(
+        tap: &Tap,
+        config_space: &mut ConfigSpace,
+        guest_mac: &mut MacAddr,
+        command: &[u8],
+    ) -> u8 {
+        match command {
+            [class, cmd, mac @ ..]
+                if u32::from(*class) == VIRTIO_NET_CTRL_MAC
+                    && u32::from(*cmd) == VIRTIO_NET_CTRL_MAC_ADDR_SET
+                    && mac.len() == MAC_ADDR_LEN =>
+            {
+                let new_mac = MacAddr::from_bytes_unchecked(mac);
+                if let Err(err) = tap.set_mac_addr(&new_mac) {
+                    error!("Failed to set the tap MAC address: {:?}", err);
+                    return VIRTIO_NET_ERR as u8;
+                }
+
+                config_space.guest_mac.copy_from_slice(mac);
+                *guest_mac = new_mac;
+                METRICS.net.mac_address_updates.inc();
+                VIRTIO_NET_OK as u8
+            }
+            [class, cmd, ..] => {
+                warn!("Net: unsupported control command {}:{}", class, cmd);
+                VIRTIO_NET_ERR as u8
+            }
+            _ => {
+                warn!("Net: malformed control command");
+                VIRTIO_NET_ERR as u8
+            }
+        }
+    }
+
+    fn process_ctrl_queue(&mut self) -> result::Result<(), DeviceError> {
+        // This is safe since we checked in the event handler that the device is activated.
+        let mem = self.device_state.mem().unwrap();
+        let ctrl_queue = &mut self.queues[CTRL_INDEX];
+
+        while let Some(head) = ctrl_queue.pop_or_enable_notification(mem) {
+            let head_index = head.index;
+            let mut command = [0u8; CTRL_COMMAND_MAX_LEN];
+            let mut command_len = 0;
+            let mut read_ok = true;
+            let mut ack_addr = None;
+
+            // The command is in the device-readable descriptors, followed by a device-writable
+            // descriptor holding the ack.
+            let mut next_desc = Some(head);
+            while let Some(desc) = next_desc {
+                if desc.is_write_only() {
+                    ack_addr = Some(desc.addr);
+                } else {
+                    let len = desc.len as usize;
+                    // Commands longer than the supported ones are rejected.
+                    read_ok &= match command.get_mut(command_len..command_len + len) {
+                        Some(buf) => mem.read_slice(buf, desc.addr).is_ok(),
+                        None => false,
+                    };
+                    command_len += len;
+                }
+                next_desc = desc.next_descriptor();
+            }
+
+            let ack = if read_ok {
+                // `read_ok` guarantees that the command fits in the buffer.
+                Self::handle_ctrl_command(
+                    &self.tap,
+                    &mut self.config_space,
+                    &mut self.guest_mac,
+                    &command[..command_len],
+                )
+            } else {
+                warn!("Net: malformed control command");
+                VIRTIO_NET_ERR as u8
+            };
+
+            let used_len = match ack_addr {
+                Some(addr) if mem.write_obj(ack, addr).is_ok() => 1,
+                _ => {
+                    error!("Net: failed to write the control command ack");
+                    METRICS.net.event_fails.inc();
+                    0
+                }
+            };
+            ctrl_queue
+                .add_used(mem, head_index, used_len)
+                .map_err(DeviceError::QueueError)?;
+        }
+
+        self.signal_used_queue(NetQueue::Ctrl)
+    }
+
     /// Updates the parameters for the rate limiters
     pub fn patch_rate_limiters(
         &mut self,
@@ -739,6 +953,16 @@ impl Net {
         }
     }
 
+    pub fn process_ctrl_queue_event(&mut self) {
+        if let Err(err) = self.queue_evts[CTRL_INDEX].read() {
+            error!("Failed to get ctrl queue event: {:?}", err);
+            METRICS.net.event_fails.inc();
+        } else {
+            self.process_ctrl_queue()
+                .unwrap_or_else(report_net_event_fail);
+        }
+    }
+
     pub fn process_rx_rate_limiter_event(&mut self) {
         METRICS.net.rx_event_rate_limiter_count.inc();
         // Upon rate limiter event, call the rate limiter handler
@@ -776,6 +1000,9 @@ impl Net {
     pub fn process_virtio_queues(&mut self) {
         let _ = self.resume_rx();
         let _ = self.process_tx();
+        if self.ctrl_vq_enabled() {
+            let _ = self.process_ctrl_queue();
+        }
     }
 }
 
@@ -895,12 +1122,14 @@ pub mod tests {
     };
     use crate::virtio::net::test_utils::test::TestHelper;
     use crate::virtio::net::test_utils::{
-        default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent, NetQueue, ReadTapMock,
-        TapTrafficSimulator,
+        default_guest_memory, default_net, if_index, inject_tap_tx_frame, set_mac, NetEvent,
+        NetQueue, ReadTapMock, TapTrafficSimulator,
     };
     use crate::virtio::net::QUEUE_SIZES;
+    use crate::virtio::test_utils::VirtQueue;
     use crate::virtio::{
-        Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTQ_DESC_F_WRITE,
+        Net, VirtioDevice, MAX_BUFFER_SIZE, RX_INDEX, TX_INDEX, TYPE_NET, VIRTQ_DESC_F_NEXT,
+        VIRTQ_DESC_F_WRITE,
     };
 
     impl Net {
@@ -1070,6 +1299,7 @@ pub mod tests {
         th.rxq.check_used_elem(0, 0, 0);
 
         th.check_rx_queue_resume(&frame);
+        assert_eq!(th.net().stats().rx_drops, 1);
     }
 
     #[test]
@@ -1235,6 +1465,12 @@ pub mod tests {
         th.rxq.check_used_elem(1, 2, frame_2.len() as u32);
         th.rxq.dtable[2].check_data(&frame_2);
         th.rxq.dtable[3].check_data(&[0; 500]);
+
+        // Check the interface counters.
+        let stats = th.net().stats();
+        assert_eq!(stats.rx_packets, 2);
+        assert_eq!(stats.rx_bytes, (frame_1.len() + frame_2.len()) as u64);
+        assert_eq!(stats.rx_drops, 0);
     }
 
     #[test]
@@ -1428,6 +1664,75 @@ pub mod tests {
         let mut buf = vec![0; 600];
         assert!(tap_traffic_simulator.pop_rx_packet(&mut buf[vnet_hdr_len()..]));
         assert_eq!(&buf[..600], &frame_2[..600]);
+
+        // Check the interface counters, which are reset on read if configured so.
+        th.net().set_stats_reset_on_read(true);
+        let stats = th.net().stats();
+        assert_eq!(stats.tx_packets, 2);
+        assert_eq!(stats.tx_bytes, 900);
+        assert_eq!(th.net().stats(), NetDeviceStats::default());
+    }
+
+    #[test]
+    fn test_tx_budget() {
+        let mut th = TestHelper::default();
+        assert_eq!(th.net().tx_budget_us(), None);
+        // With a zero budget, a single frame is transmitted per event loop iteration.
+        th.net().set_tx_budget_us(Some(0));
+        assert_eq!(th.net().tx_budget_us(), Some(0));
+        th.activate_net();
+
+        let desc_list = [(0, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 0, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        let desc_list = [(1, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 200, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+
+        check_metric_after_block!(
+            METRICS.net.tx_budget_exceeded,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 1);
+        th.txq.check_used_elem(0, 0, 0);
+
+        // The TX queue event was re-armed for the remaining frame.
+        check_metric_after_block!(
+            METRICS.net.tx_packets_count,
+            1,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 2);
+        th.txq.check_used_elem(1, 1, 0);
+
+        // A large budget doesn't defer the processing.
+        th.net().set_tx_budget_us(Some(1_000_000));
+        let desc_list = [(2, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 400, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        let desc_list = [(3, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 600, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        check_metric_after_block!(
+            METRICS.net.tx_budget_exceeded,
+            0,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 4);
+
+        // The TX queue event isn't re-armed when the budget runs out on the last frame.
+        th.net().set_tx_budget_us(Some(0));
+        let desc_list = [(4, 100, 0)];
+        th.add_desc_chain(NetQueue::Tx, 800, &desc_list);
+        th.write_tx_frame(&desc_list, 100);
+        check_metric_after_block!(
+            METRICS.net.tx_budget_exceeded,
+            0,
+            th.event_manager.run_with_timeout(100).unwrap()
+        );
+        assert_eq!(th.txq.used.idx.get(), 5);
+        assert!(th.net().queue_evts[TX_INDEX].read().is_err());
     }
 
     fn create_arp_request(
@@ -1484,6 +1789,7 @@ pub mod tests {
                 &frame_buf[..frame_len],
                 &mut net.tap,
                 src_mac,
+                &net.metrics,
             )
             .unwrap())
         );
@@ -1518,6 +1824,7 @@ pub mod tests {
                 &frame_buf[..frame_len],
                 &mut net.tap,
                 guest_mac,
+                &net.metrics,
             )
         );
 
@@ -1531,10 +1838,71 @@ pub mod tests {
                 &frame_buf[..frame_len],
                 &mut net.tap,
                 not_guest_mac,
+                &net.metrics,
             )
         );
     }
 
+    #[test]
+    fn test_ctrl_queue() {
+        let mut net = default_net();
+        assert!(!net.ctrl_vq_enabled());
+        net.enable_ctrl_vq().unwrap();
+        assert!(net.ctrl_vq_enabled());
+        assert_ne!(net.avail_features() & (1 << VIRTIO_NET_F_CTRL_MAC_ADDR), 0);
+        assert_eq!(net.queues().len(), CTRL_INDEX + 1);
+        assert_eq!(net.queue_events().len(), CTRL_INDEX + 1);
+
+        let mem = default_guest_memory();
+        let ctrlq = VirtQueue::new(GuestAddress(0), &mem, 16);
+        net.queues[CTRL_INDEX] = ctrlq.create_queue();
+        net.activate(mem.clone()).unwrap();
+
+        let command_addr = ctrlq.end().unchecked_align_up(8);
+        let ack_addr = command_addr.unchecked_add(0x100);
+        // Sends a command in a 2 descriptor chain and returns the ack.
+        let send_command = |net: &mut Net, command: &[u8]| -> u8 {
+            mem.write_slice(command, command_addr).unwrap();
+            ctrlq.dtable[0].set(
+                command_addr.raw_value(),
+                command.len() as u32,
+                VIRTQ_DESC_F_NEXT,
+                1,
+            );
+            ctrlq.dtable[1].set(ack_addr.raw_value(), 1, VIRTQ_DESC_F_WRITE, 0);
+
+            let avail_idx = ctrlq.avail.idx.get();
+            ctrlq.avail.ring[avail_idx as usize].set(0);
+            ctrlq.avail.idx.set(avail_idx + 1);
+            net.queue_evts[CTRL_INDEX].write(1).unwrap();
+            net.process_ctrl_queue_event();
+
+            ctrlq.check_used_elem(avail_idx, 0, 1);
+            mem.read_obj::<u8>(ack_addr).unwrap()
+        };
+
+        let old_mac = *net.guest_mac();
+        let new_mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+
+        // Unsupported commands are rejected.
+        let mut command = vec![
+            VIRTIO_NET_CTRL_MAC as u8 + 1,
+            VIRTIO_NET_CTRL_MAC_ADDR_SET as u8,
+        ];
+        command.extend_from_slice(new_mac.get_bytes());
+        assert_eq!(send_command(&mut net, &command), VIRTIO_NET_ERR as u8);
+        // So are the truncated ones.
+        command[0] = VIRTIO_NET_CTRL_MAC as u8;
+        assert_eq!(send_command(&mut net, &command[..4]), VIRTIO_NET_ERR as u8);
+        assert_eq!(net.guest_mac(), &old_mac);
+
+        // The MAC address is updated.
+        assert_eq!(send_command(&mut net, &command), VIRTIO_NET_OK as u8);
+        assert_eq!(net.guest_mac(), &new_mac);
+        assert_eq!(&net.config_space.guest_mac, new_mac.get_bytes());
+        assert!(net.irq_trigger.has_pending_irq(IrqType::Vring));
+    }
+
     #[test]
     fn test_process_error_cases() {
         let mut th = TestHelper::default();
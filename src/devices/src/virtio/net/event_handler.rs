@@ -8,7 +8,7 @@ use logger::{debug, error, warn, IncMetric, METRICS};
 use utils::epoll::EventSet;
 
 use crate::virtio::net::device::Net;
-use crate::virtio::{VirtioDevice, RX_INDEX, TX_INDEX};
+use crate::virtio::{VirtioDevice, CTRL_INDEX, RX_INDEX, TX_INDEX};
 
 impl Net {
     fn register_runtime_events(&self, ops: &mut EventOps) {
@@ -18,6 +18,11 @@ impl Net {
         if let Err(err) = ops.add(Events::new(&self.queue_evts[TX_INDEX], EventSet::IN)) {
             error!("Failed to register tx queue event: {}", err);
         }
+        if let Some(ctrl_queue_evt) = self.queue_evts.get(CTRL_INDEX) {
+            if let Err(err) = ops.add(Events::new(ctrl_queue_evt, EventSet::IN)) {
+                error!("Failed to register ctrl queue event: {}", err);
+            }
+        }
         if let Err(err) = ops.add(Events::new(&self.rx_rate_limiter, EventSet::IN)) {
             error!("Failed to register rx queue event: {}", err);
         }
@@ -69,6 +74,7 @@ impl MutEventSubscriber for Net {
         if self.is_activated() {
             let virtq_rx_ev_fd = self.queue_evts[RX_INDEX].as_raw_fd();
             let virtq_tx_ev_fd = self.queue_evts[TX_INDEX].as_raw_fd();
+            let virtq_ctrl_ev_fd = self.queue_evts.get(CTRL_INDEX).map(|evt| evt.as_raw_fd());
             let rx_rate_limiter_fd = self.rx_rate_limiter.as_raw_fd();
             let tx_rate_limiter_fd = self.tx_rate_limiter.as_raw_fd();
             let tap_fd = self.tap.as_raw_fd();
@@ -79,6 +85,7 @@ impl MutEventSubscriber for Net {
                 _ if source == virtq_rx_ev_fd => self.process_rx_queue_event(),
                 _ if source == tap_fd => self.process_tap_rx_event(),
                 _ if source == virtq_tx_ev_fd => self.process_tx_queue_event(),
+                _ if Some(source) == virtq_ctrl_ev_fd => self.process_ctrl_queue_event(),
                 _ if source == rx_rate_limiter_fd => self.process_rx_rate_limiter_event(),
                 _ if source == tx_rate_limiter_fd => self.process_tx_rate_limiter_event(),
                 _ if activate_fd == source => self.process_activate_event(ops),
@@ -0,0 +1,105 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Packet counters of a single network interface.
+//!
+//! Unlike the `net` metrics of the logger, which aggregate all the interfaces and are reset on
+//! every flush, these counters are kept per device and are read through the API.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// The packet counters of a net device.
+#[derive(Debug, Default)]
+pub struct NetDeviceMetrics {
+    /// Number of bytes received by the guest.
+    pub rx_bytes: AtomicU64,
+    /// Number of bytes transmitted by the guest.
+    pub tx_bytes: AtomicU64,
+    /// Number of packets received by the guest.
+    pub rx_packets: AtomicU64,
+    /// Number of packets transmitted by the guest.
+    pub tx_packets: AtomicU64,
+    /// Number of received frames that could not be written in a guest buffer.
+    pub rx_drops: AtomicU64,
+}
+
+impl NetDeviceMetrics {
+    /// Accounts a frame of `len` bytes received by the guest.
+    pub fn add_rx_packet(&self, len: usize) {
+        self.rx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts a frame of `len` bytes transmitted by the guest.
+    pub fn add_tx_packet(&self, len: usize) {
+        self.tx_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Accounts a received frame that could not be written in a guest buffer.
+    pub fn add_rx_drop(&self) {
+        self.rx_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the current values of the counters, resetting them to 0 if `reset` is set.
+    pub fn stats(&self, reset: bool) -> NetDeviceStats {
+        let read = |counter: &AtomicU64| {
+            if reset {
+                counter.swap(0, Ordering::Relaxed)
+            } else {
+                counter.load(Ordering::Relaxed)
+            }
+        };
+
+        NetDeviceStats {
+            rx_bytes: read(&self.rx_bytes),
+            tx_bytes: read(&self.tx_bytes),
+            rx_packets: read(&self.rx_packets),
+            tx_packets: read(&self.tx_packets),
+            rx_drops: read(&self.rx_drops),
+        }
+    }
+}
+
+/// The values of the packet counters of a net device, as returned by the API.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NetDeviceStats {
+    /// Number of bytes received by the guest.
+    pub rx_bytes: u64,
+    /// Number of bytes transmitted by the guest.
+    pub tx_bytes: u64,
+    /// Number of packets received by the guest.
+    pub rx_packets: u64,
+    /// Number of packets transmitted by the guest.
+    pub tx_packets: u64,
+    /// Number of received frames that could not be written in a guest buffer.
+    pub rx_drops: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let metrics = NetDeviceMetrics::default();
+        metrics.add_rx_packet(100);
+        metrics.add_rx_packet(20);
+        metrics.add_tx_packet(64);
+        metrics.add_rx_drop();
+
+        let expected = NetDeviceStats {
+            rx_bytes: 120,
+            tx_bytes: 64,
+            rx_packets: 2,
+            tx_packets: 1,
+            rx_drops: 1,
+        };
+        assert_eq!(metrics.stats(false), expected);
+        // Reading without reset leaves the counters untouched.
+        assert_eq!(metrics.stats(true), expected);
+        assert_eq!(metrics.stats(false), NetDeviceStats::default());
+    }
+}
@@ -11,9 +11,12 @@ pub const QUEUE_SIZES: &[u16] = &[QUEUE_SIZE; NUM_QUEUES];
 pub const RX_INDEX: usize = 0;
 // The index of the tx queue from Net device queues/queues_evts vector.
 pub const TX_INDEX: usize = 1;
+// The index of the control queue from Net device queues/queues_evts vector, when enabled.
+pub const CTRL_INDEX: usize = 2;
 
 pub mod device;
 pub mod event_handler;
+pub mod metrics;
 pub mod persist;
 mod tap;
 pub mod test_utils;
@@ -22,6 +25,7 @@ pub use tap::Error as TapError;
 
 pub use self::device::Net;
 pub use self::event_handler::*;
+pub use self::metrics::{NetDeviceMetrics, NetDeviceStats};
 
 /// Enum representing the Net device queue types
 pub enum NetQueue {
@@ -29,6 +33,8 @@ pub enum NetQueue {
     Rx,
     /// The TX queue
     Tx,
+    /// The control queue
+    Ctrl,
 }
 
 #[derive(Debug)]
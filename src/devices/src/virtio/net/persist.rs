@@ -7,6 +7,7 @@ use std::io;
 use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 
+use logger::warn;
 use mmds::data_store::Mmds;
 use mmds::ns::MmdsNetworkStack;
 use mmds::persist::MmdsNetworkStackState;
@@ -16,6 +17,7 @@ use snapshot::Persist;
 use utils::net::mac::{MacAddr, MAC_ADDR_LEN};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
+use virtio_gen::virtio_net::VIRTIO_NET_F_CTRL_VQ;
 use vm_memory::GuestMemoryMmap;
 
 use super::device::Net;
@@ -39,6 +41,18 @@ pub struct NetState {
     pub mmds_ns: Option<MmdsNetworkStackState>,
     config_space: NetConfigSpaceState,
     virtio_state: VirtioDeviceState,
+    #[version(start = 2, ser_fn = "ser_tx_budget_us")]
+    tx_budget_us: Option<u64>,
+}
+
+impl NetState {
+    fn ser_tx_budget_us(&mut self, target_version: u16) -> VersionizeResult<()> {
+        if target_version < 2 && self.tx_budget_us.is_some() {
+            warn!("Saving to older snapshot version, the TX budget will not be saved.");
+        }
+
+        Ok(())
+    }
 }
 
 pub struct NetConstructorArgs {
@@ -70,6 +84,7 @@ impl Persist<'_> for Net {
                 guest_mac: self.config_space.guest_mac,
             },
             virtio_state: VirtioDeviceState::from_device(self),
+            tx_budget_us: self.tx_budget_us(),
         }
     }
 
@@ -90,6 +105,7 @@ impl Persist<'_> for Net {
             rx_rate_limiter,
             tx_rate_limiter,
         )?;
+        net.set_tx_budget_us(state.tx_budget_us);
 
         // We trust the MMIODeviceManager::restore to pass us an MMDS data store reference if
         // there is at least one net device having the MMDS NS present and/or the mmds version was
@@ -108,10 +124,17 @@ impl Persist<'_> for Net {
             );
         }
 
+        // The control queue is only present if it was enabled when the device was created.
+        let num_queues = if state.virtio_state.avail_features & (1 << VIRTIO_NET_F_CTRL_VQ) != 0 {
+            net.enable_ctrl_vq()?;
+            NUM_QUEUES + 1
+        } else {
+            NUM_QUEUES
+        };
         net.queues = state.virtio_state.build_queues_checked(
             &constructor_args.mem,
             TYPE_NET,
-            NUM_QUEUES,
+            num_queues,
             QUEUE_SIZE,
         )?;
         net.irq_trigger.irq_status =
@@ -182,6 +205,7 @@ mod tests {
                         virtio_state.interrupt_status
                     );
                     assert_eq!(restored_net.is_activated(), virtio_state.activated);
+                    assert_eq!(restored_net.queues.len(), virtio_state.queues.len());
 
                     // Test that net specific fields are the same.
                     assert_eq!(&restored_net.id, &id);
@@ -210,5 +234,41 @@ mod tests {
         // Check what happens if the MMIODeviceManager does not give us the reference to the MMDS
         // data store. This will return an error.
         validate_save_and_restore(default_net(), None);
+
+        // The control queue is restored if it was enabled.
+        let mut net = default_net_no_mmds();
+        net.enable_ctrl_vq().unwrap();
+        validate_save_and_restore(net, None);
+    }
+
+    #[test]
+    fn test_persist_tx_budget() {
+        let guest_mem = default_guest_memory();
+        let mut mem = vec![0; 4096];
+        let mut version_map = VersionMap::new();
+        version_map
+            .new_version()
+            .set_type_version(NetState::type_id(), 2);
+
+        let mut net = default_net_no_mmds();
+        net.set_tx_budget_us(Some(500));
+        let state = <Net as Persist>::save(&net);
+        drop(net);
+
+        // The TX budget is only saved from version 2 on.
+        for (version, expected) in [(1, None), (2, Some(500))] {
+            state
+                .serialize(&mut mem.as_mut_slice(), &version_map, version)
+                .unwrap();
+            let restored_net = Net::restore(
+                NetConstructorArgs {
+                    mem: guest_mem.clone(),
+                    mmds: None,
+                },
+                &NetState::deserialize(&mut mem.as_slice(), &version_map, version).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(restored_net.tx_budget_us(), expected);
+        }
     }
 }
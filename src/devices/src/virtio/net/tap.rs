@@ -10,8 +10,9 @@ use std::io::{Error as IoError, Read, Result as IoResult, Write};
 use std::os::raw::*;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 
-use net_gen::ifreq;
+use net_gen::{ifreq, sockaddr};
 use utils::ioctl::{ioctl_with_mut_ref, ioctl_with_ref, ioctl_with_val};
+use utils::net::mac::MacAddr;
 use utils::{ioctl_ioc_nr, ioctl_iow_nr};
 
 // As defined in the Linux UAPI:
@@ -87,6 +88,18 @@ impl IfReqBuilder {
         self
     }
 
+    pub(crate) fn hw_addr(mut self, mac: &MacAddr) -> Self {
+        let mut hw_addr = sockaddr {
+            sa_family: libc::ARPHRD_ETHER,
+            ..Default::default()
+        };
+        for (dst, src) in hw_addr.sa_data.iter_mut().zip(mac.get_bytes()) {
+            *dst = *src as c_char;
+        }
+        self.0.ifr_ifru.ifru_hwaddr = hw_addr;
+        self
+    }
+
     pub(crate) fn execute<F: AsRawFd>(mut self, socket: &F, ioctl: u64) -> Result<ifreq> {
         // SAFETY: ioctl is safe. Called with a valid socket fd, and we check the return.
         let ret = unsafe { ioctl_with_mut_ref(socket, ioctl, &mut self.0) };
@@ -152,6 +165,19 @@ impl Tap {
         Ok(())
     }
 
+    /// Set the hardware address of the tap interface.
+    pub fn set_mac_addr(&self, mac: &MacAddr) -> Result<()> {
+        IfReqBuilder::new()
+            .if_name(&self.if_name)
+            .hw_addr(mac)
+            .execute(
+                &self.tap_file,
+                c_ulong::from(net_gen::sockios::SIOCSIFHWADDR),
+            )?;
+
+        Ok(())
+    }
+
     /// Set the size of the vnet hdr.
     pub fn set_vnet_hdr_size(&self, size: c_int) -> Result<()> {
         // SAFETY: ioctl is safe. Called with a valid tap fd, and we check the return.
@@ -242,6 +268,8 @@ pub mod tests {
         let tap = Tap::open_named("").unwrap();
         tap.set_vnet_hdr_size(16).unwrap();
         tap.set_offload(0).unwrap();
+        let mac = MacAddr::parse_str("12:34:56:78:9a:bc").unwrap();
+        tap.set_mac_addr(&mac).unwrap();
 
         let faulty_tap = Tap {
             tap_file: unsafe { File::from_raw_fd(-2) },
@@ -249,6 +277,7 @@ pub mod tests {
         };
         assert!(faulty_tap.set_vnet_hdr_size(16).is_err());
         assert!(faulty_tap.set_offload(0).is_err());
+        assert!(faulty_tap.set_mac_addr(&mac).is_err());
     }
 
     #[test]
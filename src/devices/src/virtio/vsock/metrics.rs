@@ -0,0 +1,161 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Connection counters of the vsock device.
+//!
+//! Unlike the `vsock` metrics of the logger, which are reset on every flush, these counters are
+//! cumulative since the device was created and are read through the API.
+
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// The connection counters of the vsock device.
+#[derive(Debug, Default)]
+pub struct VsockMetrics {
+    connections_established: u64,
+    connections_closed: u64,
+    tx_bytes: u64,
+    rx_bytes: u64,
+    rx_queue_full_drops: u64,
+    /// The counters of the active connections, by local and peer port.
+    connections: BTreeMap<(u32, u32), VsockConnectionStats>,
+}
+
+impl VsockMetrics {
+    /// Starts accounting the connection between `local_port` and `peer_port`.
+    pub fn add_connection(&mut self, local_port: u32, peer_port: u32) {
+        self.connections.insert(
+            (local_port, peer_port),
+            VsockConnectionStats {
+                local_port,
+                peer_port,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Accounts a connection that just completed its handshake.
+    pub fn add_established(&mut self) {
+        self.connections_established += 1;
+    }
+
+    /// Accounts the removal of the connection between `local_port` and `peer_port`.
+    pub fn remove_connection(&mut self, local_port: u32, peer_port: u32) {
+        self.connections.remove(&(local_port, peer_port));
+        self.connections_closed += 1;
+    }
+
+    /// Accounts `len` bytes transmitted by the guest on a connection.
+    pub fn add_tx_bytes(&mut self, local_port: u32, peer_port: u32, len: u32) {
+        self.tx_bytes += u64::from(len);
+        if let Some(conn) = self.connections.get_mut(&(local_port, peer_port)) {
+            conn.tx_bytes += u64::from(len);
+        }
+    }
+
+    /// Accounts `len` bytes received by the guest on a connection.
+    pub fn add_rx_bytes(&mut self, local_port: u32, peer_port: u32, len: u32) {
+        self.rx_bytes += u64::from(len);
+        if let Some(conn) = self.connections.get_mut(&(local_port, peer_port)) {
+            conn.rx_bytes += u64::from(len);
+        }
+    }
+
+    /// Accounts a packet dropped because the RX queue of the muxer was full.
+    pub fn add_rx_queue_full_drop(&mut self) {
+        self.rx_queue_full_drops += 1;
+    }
+
+    /// Returns the current values of the counters.
+    pub fn stats(&self) -> VsockStats {
+        VsockStats {
+            connections_established: self.connections_established,
+            connections_closed: self.connections_closed,
+            tx_bytes: self.tx_bytes,
+            rx_bytes: self.rx_bytes,
+            rx_queue_full_drops: self.rx_queue_full_drops,
+            connections: self.connections.values().copied().collect(),
+        }
+    }
+}
+
+/// The byte counters of an active vsock connection, as returned by the API.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VsockConnectionStats {
+    /// The host side port of the connection.
+    pub local_port: u32,
+    /// The guest side port of the connection.
+    pub peer_port: u32,
+    /// Number of bytes transmitted by the guest.
+    pub tx_bytes: u64,
+    /// Number of bytes received by the guest.
+    pub rx_bytes: u64,
+}
+
+/// The values of the connection counters of the vsock device, as returned by the API.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct VsockStats {
+    /// Number of connections which completed their handshake.
+    pub connections_established: u64,
+    /// Number of connections which were removed.
+    pub connections_closed: u64,
+    /// Number of bytes transmitted by the guest, on all the connections.
+    pub tx_bytes: u64,
+    /// Number of bytes received by the guest, on all the connections.
+    pub rx_bytes: u64,
+    /// Number of packets dropped because the RX queue was full.
+    pub rx_queue_full_drops: u64,
+    /// The counters of the active connections, sorted by local and peer port.
+    pub connections: Vec<VsockConnectionStats>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut metrics = VsockMetrics::default();
+        metrics.add_connection(1026, 1025);
+        metrics.add_connection(1024, 1025);
+        metrics.add_established();
+        metrics.add_tx_bytes(1026, 1025, 4);
+        metrics.add_rx_bytes(1026, 1025, 6);
+        metrics.add_rx_bytes(1024, 1025, 1);
+        metrics.add_rx_queue_full_drop();
+
+        assert_eq!(
+            metrics.stats(),
+            VsockStats {
+                connections_established: 1,
+                connections_closed: 0,
+                tx_bytes: 4,
+                rx_bytes: 7,
+                rx_queue_full_drops: 1,
+                connections: vec![
+                    VsockConnectionStats {
+                        local_port: 1024,
+                        peer_port: 1025,
+                        tx_bytes: 0,
+                        rx_bytes: 1,
+                    },
+                    VsockConnectionStats {
+                        local_port: 1026,
+                        peer_port: 1025,
+                        tx_bytes: 4,
+                        rx_bytes: 6,
+                    },
+                ],
+            }
+        );
+
+        // The aggregate counters survive the removal of the connections.
+        metrics.remove_connection(1026, 1025);
+        let stats = metrics.stats();
+        assert_eq!(stats.connections_closed, 1);
+        assert_eq!(stats.tx_bytes, 4);
+        assert_eq!(stats.rx_bytes, 7);
+        assert_eq!(stats.connections.len(), 1);
+    }
+}
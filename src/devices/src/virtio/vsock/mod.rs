@@ -8,6 +8,7 @@
 mod csm;
 mod device;
 mod event_handler;
+pub mod metrics;
 mod packet;
 pub mod persist;
 pub mod test_utils;
@@ -22,6 +23,7 @@ use vm_memory::{GuestMemoryError, GuestMemoryMmap};
 pub use self::defs::uapi::VIRTIO_ID_VSOCK as TYPE_VSOCK;
 pub use self::defs::VSOCK_DEV_ID;
 pub use self::device::Vsock;
+pub use self::metrics::{VsockConnectionStats, VsockMetrics, VsockStats};
 pub use self::unix::{Error as VsockUnixBackendError, VsockUnixBackend};
 use crate::virtio::persist::Error as VirtioStateError;
 
@@ -41,6 +41,7 @@ use vm_memory::GuestMemoryMmap;
 
 use super::super::csm::ConnState;
 use super::super::defs::uapi;
+use super::super::metrics::{VsockMetrics, VsockStats};
 use super::super::packet::VsockPacket;
 use super::super::{
     Result as VsockResult, VsockBackend, VsockChannel, VsockEpollListener, VsockError,
@@ -106,6 +107,8 @@ pub struct VsockMuxer {
     local_port_set: HashSet<u32>,
     /// The last used host-side port.
     local_port_last: u32,
+    /// The connection counters, read through the API.
+    metrics: VsockMetrics,
 }
 
 impl VsockChannel for VsockMuxer {
@@ -169,6 +172,9 @@ impl VsockChannel for VsockMuxer {
                         local_port: pkt.src_port(),
                         peer_port: pkt.dst_port(),
                     });
+                } else if pkt.op() == uapi::VSOCK_OP_RW {
+                    self.metrics
+                        .add_rx_bytes(pkt.src_port(), pkt.dst_port(), pkt.len());
                 }
 
                 debug!("vsock muxer: RX pkt: {:?}", pkt.hdr());
@@ -243,6 +249,10 @@ impl VsockChannel for VsockMuxer {
         self.apply_conn_mutation(conn_key, |conn| {
             res = conn.send_pkt(pkt, mem);
         });
+        if res.is_ok() && pkt.op() == uapi::VSOCK_OP_RW {
+            self.metrics
+                .add_tx_bytes(conn_key.local_port, conn_key.peer_port, pkt.len());
+        }
 
         res
     }
@@ -320,6 +330,7 @@ impl VsockMuxer {
             killq: MuxerKillQ::new(),
             local_port_last: (1u32 << 30) - 1,
             local_port_set: HashSet::with_capacity(defs::MAX_CONNECTIONS),
+            metrics: VsockMetrics::default(),
         };
 
         // Listen on the host initiated socket, for incoming connections.
@@ -331,6 +342,11 @@ impl VsockMuxer {
         &self.host_sock_path
     }
 
+    /// Returns the connection counters of the muxer.
+    pub fn stats(&self) -> VsockStats {
+        self.metrics.stats()
+    }
+
     /// Handle/dispatch an epoll event to its listener.
     fn handle_event(&mut self, fd: RawFd, event_set: EventSet) {
         debug!(
@@ -493,6 +509,7 @@ impl VsockMuxer {
                 self.rxq.push(MuxerRx::ConnRx(key));
             }
             self.conn_map.insert(key, conn);
+            self.metrics.add_connection(key.local_port, key.peer_port);
             METRICS.vsock.conns_added.inc();
         })
     }
@@ -501,6 +518,8 @@ impl VsockMuxer {
     fn remove_connection(&mut self, key: ConnMapKey) {
         if let Some(conn) = self.conn_map.remove(&key) {
             self.remove_listener(conn.as_raw_fd());
+            self.metrics
+                .remove_connection(key.local_port, key.peer_port);
             METRICS.vsock.conns_removed.inc();
         }
         self.free_local_port(key.local_port);
@@ -632,6 +651,10 @@ impl VsockMuxer {
 
             mut_fn(conn);
 
+            if prev_state != ConnState::Established && conn.state() == ConnState::Established {
+                self.metrics.add_established();
+            }
+
             // If this is a host-initiated connection that has just become established, we'll have
             // to send an ack message to the host end.
             if prev_state == ConnState::LocalInit && conn.state() == ConnState::Established {
@@ -757,6 +780,7 @@ impl VsockMuxer {
             peer_port,
         });
         if !pushed {
+            self.metrics.add_rx_queue_full_drop();
             warn!(
                 "vsock: muxer.rxq full; dropping RST packet for lp={}, pp={}",
                 local_port, peer_port
@@ -775,6 +799,7 @@ mod tests {
     use utils::tempfile::TempFile;
 
     use super::super::super::csm::defs as csm_defs;
+    use super::super::super::metrics::VsockConnectionStats;
     use super::*;
     use crate::virtio::vsock::device::RXQ_INDEX;
     use crate::virtio::vsock::test_utils::TestContext as VsockTestContext;
@@ -1524,4 +1549,51 @@ mod tests {
         // Check that the connection was removed.
         assert_eq!(METRICS.vsock.conns_removed.count(), conns_removed + 1);
     }
+
+    #[test]
+    fn test_vsock_stats() {
+        let mut ctx = MuxerTestContext::new("vsock_stats");
+        let peer_port = 1025;
+        let (mut stream, local_port) = ctx.local_connect(peer_port);
+
+        let stats = ctx.muxer.stats();
+        assert_eq!(stats.connections_established, 1);
+        assert_eq!(
+            stats.connections,
+            [VsockConnectionStats {
+                local_port,
+                peer_port,
+                ..Default::default()
+            }]
+        );
+
+        let data = [1, 2, 3, 4];
+        ctx.init_data_pkt(local_port, peer_port, &data);
+        ctx.send();
+        let data = [1, 2, 3, 4, 5, 6];
+        stream.write_all(&data).unwrap();
+        ctx.notify_muxer();
+        ctx.recv();
+
+        let stats = ctx.muxer.stats();
+        assert_eq!(stats.tx_bytes, 4);
+        assert_eq!(stats.rx_bytes, 6);
+        assert_eq!(stats.connections[0].tx_bytes, 4);
+        assert_eq!(stats.connections[0].rx_bytes, 6);
+
+        // The aggregate counters are kept once the connection is closed.
+        ctx.init_pkt(local_port, peer_port, uapi::VSOCK_OP_RST);
+        ctx.send();
+        assert_eq!(
+            ctx.muxer.stats(),
+            VsockStats {
+                connections_established: 1,
+                connections_closed: 1,
+                tx_bytes: 4,
+                rx_bytes: 6,
+                rx_queue_full_drops: 0,
+                connections: vec![],
+            }
+        );
+    }
 }
@@ -0,0 +1,319 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+#![warn(clippy::ptr_as_ptr)]
+#![warn(clippy::undocumented_unsafe_blocks)]
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::process;
+
+use serde::Deserialize;
+use serde_json::Value;
+use utils::arg_parser::{ArgParser, Argument, Arguments};
+
+const REPLAY_VERSION: &str = env!("FIRECRACKER_VERSION");
+const EXIT_CODE_SUCCESS: i32 = 0;
+const API_SOCK: &str = "api-sock";
+const JOURNAL: &str = "journal";
+
+#[derive(Debug)]
+enum Error {
+    OpenJournal(std::io::Error),
+    ReadJournal(std::io::Error),
+    // The line of the journal and the parsing error.
+    InvalidEntry(usize, serde_json::Error),
+    Connect(std::io::Error),
+    SendRequest(std::io::Error),
+    ReadResponse(std::io::Error),
+    InvalidResponse(String),
+    // The line of the journal, the status code and the body of the response.
+    FailedRequest(usize, u16, String),
+}
+
+// An entry of the journal written by the API server.
+#[derive(Debug, Deserialize, PartialEq)]
+struct JournalEntry {
+    method: String,
+    path: String,
+    body: Option<Value>,
+}
+
+fn build_arg_parser<'a>() -> ArgParser<'a> {
+    ArgParser::new()
+        .arg(
+            Argument::new(API_SOCK)
+                .required(true)
+                .takes_value(true)
+                .help("Path to the unix domain socket of the Firecracker API."),
+        )
+        .arg(
+            Argument::new(JOURNAL)
+                .required(true)
+                .takes_value(true)
+                .help("File path of the API journal to replay."),
+        )
+}
+
+fn extract_args<'a>(arg_parser: &'a mut ArgParser<'a>) -> &'a Arguments<'a> {
+    arg_parser.parse_from_cmdline().unwrap_or_else(|err| {
+        panic!(
+            "Arguments parsing error: {} \n\nFor more information try --help.",
+            err
+        );
+    });
+
+    if arg_parser.arguments().flag_present("help") {
+        println!("Firecracker-replay v{}", REPLAY_VERSION);
+        println!(
+            "Tool that issues the requests recorded in an API journal, in the same order, \
+             stopping at the first failure\n"
+        );
+        println!("{}", arg_parser.formatted_help());
+        process::exit(EXIT_CODE_SUCCESS);
+    }
+    if arg_parser.arguments().flag_present("version") {
+        println!("Firecracker-replay v{}\n", REPLAY_VERSION);
+        process::exit(EXIT_CODE_SUCCESS);
+    }
+
+    arg_parser.arguments()
+}
+
+fn write_request<W: Write>(writer: &mut W, entry: &JournalEntry) -> std::io::Result<()> {
+    let request = match entry.body {
+        Some(ref body) => {
+            let body = body.to_string();
+            format!(
+                "{} {} HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+                entry.method,
+                entry.path,
+                body.len(),
+                body
+            )
+        }
+        None => format!("{} {} HTTP/1.1\r\n\r\n", entry.method, entry.path),
+    };
+    writer.write_all(request.as_bytes())
+}
+
+// Returns the status code and the body of the response.
+fn read_response<R: BufRead>(reader: &mut R) -> Result<(u16, String), Error> {
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(Error::ReadResponse)?;
+    // The status line is "HTTP/1.1 <status code> <reason phrase>".
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| Error::InvalidResponse(line.clone()))?;
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(Error::ReadResponse)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| Error::InvalidResponse(header.to_string()))?;
+            }
+        }
+    }
+
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).map_err(Error::ReadResponse)?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+// Issues the requests of the journal, one at a time, and returns how many were replayed.
+fn replay<R: BufRead>(journal: R, stream: &UnixStream) -> Result<usize, Error> {
+    let mut reader = BufReader::new(stream);
+    let mut writer = stream;
+    let mut count = 0;
+
+    for (index, line) in journal.lines().enumerate() {
+        let line = line.map_err(Error::ReadJournal)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: JournalEntry =
+            serde_json::from_str(&line).map_err(|err| Error::InvalidEntry(index + 1, err))?;
+
+        write_request(&mut writer, &entry).map_err(Error::SendRequest)?;
+        let (status, body) = read_response(&mut reader)?;
+        println!("{} {}: {}", entry.method, entry.path, status);
+        if !(200..300).contains(&status) {
+            return Err(Error::FailedRequest(index + 1, status, body));
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+fn main() {
+    let mut arg_parser = build_arg_parser();
+    let args = extract_args(&mut arg_parser);
+    // Safe to unwrap since the required arguments are checked as part of
+    // `arg_parser.parse_from_cmdline()`
+    let journal = File::open(args.single_value(JOURNAL).unwrap())
+        .map_err(Error::OpenJournal)
+        .unwrap_or_else(|err| panic!("Error opening the journal: {:?}", err));
+    let stream = UnixStream::connect(args.single_value(API_SOCK).unwrap())
+        .map_err(Error::Connect)
+        .unwrap_or_else(|err| panic!("Error connecting to the API socket: {:?}", err));
+
+    let count = replay(BufReader::new(journal), &stream)
+        .unwrap_or_else(|err| panic!("Error replaying the journal: {:?}", err));
+    println!("Replayed {} requests.", count);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+    use std::thread;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_write_request() {
+        let mut request = Vec::new();
+        let entry = JournalEntry {
+            method: "PUT".to_string(),
+            path: "/machine-config".to_string(),
+            body: Some(json!({ "vcpu_count": 2 })),
+        };
+        write_request(&mut request, &entry).unwrap();
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            "PUT /machine-config HTTP/1.1\r\nContent-Type: application/json\r\n\
+             Content-Length: 16\r\n\r\n{\"vcpu_count\":2}"
+        );
+
+        let mut request = Vec::new();
+        let entry = JournalEntry {
+            method: "PUT".to_string(),
+            path: "/actions".to_string(),
+            body: None,
+        };
+        write_request(&mut request, &entry).unwrap();
+        assert_eq!(
+            String::from_utf8(request).unwrap(),
+            "PUT /actions HTTP/1.1\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_read_response() {
+        let mut response = Cursor::new(
+            "HTTP/1.1 400 \r\nServer: Firecracker API\r\nContent-Length: 7\r\n\r\n\
+             invalidHTTP/1.1 204 \r\nServer: Firecracker API\r\n\r\n",
+        );
+        assert_eq!(
+            read_response(&mut response).unwrap(),
+            (400, "invalid".to_string())
+        );
+        assert_eq!(read_response(&mut response).unwrap(), (204, String::new()));
+
+        let mut response = Cursor::new("garbage\r\n\r\n");
+        assert!(matches!(
+            read_response(&mut response),
+            Err(Error::InvalidResponse(_))
+        ));
+        let mut response = Cursor::new("HTTP/1.1 200 \r\nContent-Length: 10\r\n\r\nshort");
+        assert!(matches!(
+            read_response(&mut response),
+            Err(Error::ReadResponse(_))
+        ));
+    }
+
+    #[test]
+    fn test_replay() {
+        let journal = "{\"timestamp_us\":1,\"method\":\"PUT\",\"path\":\"/boot-source\",\
+                       \"body\":{\"kernel_image_path\":\"vmlinux\"}}\n\
+                       \n\
+                       {\"timestamp_us\":2,\"method\":\"PUT\",\"path\":\"/actions\",\
+                       \"body\":{\"action_type\":\"InstanceStart\"}}\n\
+                       {\"timestamp_us\":3,\"method\":\"PATCH\",\"path\":\"/vm\",\"body\":null}\n";
+
+        // The fake API server succeeds the first `successes` requests and fails the next one.
+        let run_server = |server: UnixStream, successes: usize| {
+            thread::spawn(move || {
+                let mut reader = BufReader::new(&server);
+                let mut writer = &server;
+                let mut requests = Vec::new();
+                for i in 0..=successes {
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line).unwrap() == 0 {
+                        break;
+                    }
+                    // Skip the headers, then read the body.
+                    let mut content_length = 0;
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap() > 2 {
+                        if let Some(len) = line.strip_prefix("Content-Length: ") {
+                            content_length = len.trim().parse().unwrap();
+                        }
+                        line.clear();
+                    }
+                    let mut body = vec![0u8; content_length];
+                    reader.read_exact(&mut body).unwrap();
+                    requests.push((request_line, String::from_utf8(body).unwrap()));
+
+                    let response: &[u8] = if i < successes {
+                        b"HTTP/1.1 204 \r\n\r\n"
+                    } else {
+                        b"HTTP/1.1 400 \r\nContent-Length: 2\r\n\r\nko"
+                    };
+                    writer.write_all(response).unwrap();
+                }
+                requests
+            })
+        };
+
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = run_server(server, 3);
+        assert_eq!(replay(Cursor::new(journal), &client).unwrap(), 3);
+        drop(client);
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                (
+                    "PUT /boot-source HTTP/1.1\r\n".to_string(),
+                    "{\"kernel_image_path\":\"vmlinux\"}".to_string()
+                ),
+                (
+                    "PUT /actions HTTP/1.1\r\n".to_string(),
+                    "{\"action_type\":\"InstanceStart\"}".to_string()
+                ),
+                ("PATCH /vm HTTP/1.1\r\n".to_string(), String::new()),
+            ]
+        );
+
+        // The replay stops at the first failed request.
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = run_server(server, 1);
+        assert!(matches!(
+            replay(Cursor::new(journal), &client),
+            Err(Error::FailedRequest(3, 400, ref body)) if body == "ko"
+        ));
+        drop(client);
+        assert_eq!(server.join().unwrap().len(), 2);
+
+        // Invalid entries are reported with their line.
+        let (client, _server) = UnixStream::pair().unwrap();
+        assert!(matches!(
+            replay(Cursor::new("{\"method\":\"PUT\"}\n"), &client),
+            Err(Error::InvalidEntry(1, _))
+        ));
+    }
+}
@@ -9,7 +9,7 @@ use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
-use api_server::{ApiRequest, ApiResponse, ApiServer};
+use api_server::{ApiJournal, ApiRequest, ApiResponse, ApiServer};
 use event_manager::{EventOps, Events, MutEventSubscriber, SubscriberOps};
 use logger::{error, warn, ProcessTimeReporter};
 use seccompiler::BpfThreadMap;
@@ -55,13 +55,16 @@ impl ApiServerAdapter {
         }
     }
 
-    fn handle_request(&mut self, req_action: VmmAction) {
+    // Returns whether the request succeeded.
+    fn handle_request(&mut self, req_action: VmmAction) -> bool {
         let response = self.controller.handle_request(req_action);
+        let succeeded = response.is_ok();
         // Send back the result.
         self.to_api
             .send(Box::new(response))
             .map_err(|_| ())
             .expect("one-shot channel closed");
+        succeeded
     }
 }
 impl MutEventSubscriber for ApiServerAdapter {
@@ -73,20 +76,20 @@ impl MutEventSubscriber for ApiServerAdapter {
         if source == self.api_event_fd.as_raw_fd() && event_set == EventSet::IN {
             match self.from_api.try_recv() {
                 Ok(api_request) => {
-                    let request_is_pause = *api_request == VmmAction::Pause;
-                    self.handle_request(*api_request);
+                    let request_is_pause = matches!(*api_request, VmmAction::Pause(_));
+                    let paused = self.handle_request(*api_request) && request_is_pause;
 
-                    // If the latest req is a pause request, temporarily switch to a mode where we
-                    // do blocking `recv`s on the `from_api` receiver in a loop, until we get
-                    // unpaused. The device emulation is implicitly paused since we do not
-                    // relinquish control to the event manager because we're not returning from
-                    // `process`.
-                    if request_is_pause {
+                    // If the latest req is a successful pause request, temporarily switch to a
+                    // mode where we do blocking `recv`s on the `from_api` receiver in a loop,
+                    // until we get unpaused. The device emulation is implicitly paused since we
+                    // do not relinquish control to the event manager because we're not returning
+                    // from `process`. A pause which timed out left the microVM running.
+                    if paused {
                         // This loop only attempts to process API requests, so things like the
                         // metric flush timerfd handling are frozen as well.
                         loop {
                             let req = self.from_api.recv().expect("Error receiving API request.");
-                            let req_is_resume = *req == VmmAction::Resume;
+                            let req_is_resume = matches!(*req, VmmAction::Resume(_));
                             self.handle_request(*req);
                             if req_is_resume {
                                 break;
@@ -125,6 +128,7 @@ pub(crate) fn run_with_api(
     api_payload_limit: usize,
     mmds_size_limit: usize,
     metadata_json: Option<&str>,
+    api_journal: Option<ApiJournal>,
 ) -> FcExitCode {
     // FD to notify of API events. This is a blocking eventfd by design.
     // It is used in the config/pre-boot loop which is a simple blocking loop
@@ -148,7 +152,11 @@ pub(crate) fn run_with_api(
     let api_thread = thread::Builder::new()
         .name("fc_api".to_owned())
         .spawn(move || {
-            match ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd).bind_and_run(
+            let mut api_server = ApiServer::new(to_vmm, from_vmm, to_vmm_event_fd);
+            if let Some(journal) = api_journal {
+                api_server.set_journal(journal);
+            }
+            match api_server.bind_and_run(
                 api_bind_path,
                 process_time_reporter,
                 &api_seccomp_filter,
@@ -8,10 +8,11 @@ mod api_server_adapter;
 mod metrics;
 
 use std::fs::{self, File};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::{io, panic, process};
 
+use api_server::ApiJournal;
 use event_manager::SubscriberOps;
 use logger::{error, info, ProcessTimeReporter, StoreMetric, LOGGER, METRICS};
 use seccompiler::BpfThreadMap;
@@ -220,6 +221,10 @@ fn main_exitable() -> FcExitCode {
                 .takes_value(true)
                 .help("Print the data format version of the provided snapshot state file."),
         )
+        .arg(Argument::new("cpuid-dump").takes_value(false).help(
+            "Print the CPUID that the vCPUs of a microVM would get on this host, without \
+             starting one. Only available on x86_64.",
+        ))
         .arg(
             Argument::new("http-api-max-payload-size")
                 .takes_value(true)
@@ -230,6 +235,24 @@ fn main_exitable() -> FcExitCode {
             Argument::new("mmds-size-limit")
                 .takes_value(true)
                 .help("Mmds data store limit, in bytes."),
+        )
+        .arg(
+            Argument::new("journal-path")
+                .takes_value(true)
+                .forbids(vec!["no-api"])
+                .help(
+                    "Path to a file where the successful API requests are recorded, so that they \
+                     can be replayed with firecracker-replay.",
+                ),
+        )
+        .arg(
+            Argument::new("journal-mmds")
+                .takes_value(false)
+                .requires("journal-path")
+                .help(
+                    "Whether or not to record the MMDS requests, along with the data store \
+                     contents, in the API journal.",
+                ),
         );
 
     let arguments = match arg_parser.parse_from_cmdline() {
@@ -258,6 +281,10 @@ fn main_exitable() -> FcExitCode {
                 return vmm::FcExitCode::Ok;
             }
 
+            if arg_parser.arguments().flag_present("cpuid-dump") {
+                return print_cpuid_dump();
+            }
+
             arg_parser.arguments()
         }
     };
@@ -384,6 +411,18 @@ fn main_exitable() -> FcExitCode {
 
         let process_time_reporter =
             ProcessTimeReporter::new(start_time_us, start_time_cpu_us, parent_cpu_time_us);
+
+        let api_journal = match arguments
+            .single_value("journal-path")
+            .map(|path| ApiJournal::new(Path::new(path), arguments.flag_present("journal-mmds")))
+            .transpose()
+        {
+            Ok(journal) => journal,
+            Err(err) => {
+                return generic_error_exit(&format!("Could not open the API journal: {}", err));
+            }
+        };
+
         api_server_adapter::run_with_api(
             &mut seccomp_filters,
             vmm_config_json,
@@ -394,6 +433,7 @@ fn main_exitable() -> FcExitCode {
             api_payload_limit,
             mmds_size_limit,
             metadata_json.as_deref(),
+            api_journal,
         )
     } else {
         let seccomp_filters: BpfThreadMap = seccomp_filters
@@ -479,6 +519,23 @@ fn print_snapshot_data_format(snapshot_path: &str) {
     println!("v{}", key);
 }
 
+// Print the CPUID processed for the first vCPU of a single vCPU microVM on this host.
+#[cfg(target_arch = "x86_64")]
+fn print_cpuid_dump() -> FcExitCode {
+    match vmm::cpuid_dump::host_cpuid_dump() {
+        Ok(dump) => {
+            print!("{}", dump);
+            vmm::FcExitCode::Ok
+        }
+        Err(err) => generic_error_exit(&format!("Unable to dump the CPUID: {}", err)),
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn print_cpuid_dump() -> FcExitCode {
+    generic_error_exit("The CPUID dump is only available on x86_64.")
+}
+
 // Configure and start a microVM as described by the command-line JSON.
 fn build_microvm_from_json(
     seccomp_filters: &BpfThreadMap,
@@ -602,6 +602,9 @@ pub struct NetDeviceMetrics {
     pub tap_read_fails: SharedIncMetric,
     /// Number of times writing to TAP failed.
     pub tap_write_fails: SharedIncMetric,
+    /// Number of times the TX queue processing was deferred to the next event loop iteration
+    /// because it exceeded its time budget.
+    pub tx_budget_exceeded: SharedIncMetric,
     /// Number of transmitted bytes.
     pub tx_bytes_count: SharedIncMetric,
     /// Number of malformed TX frames.
@@ -744,6 +747,10 @@ pub struct VcpuMetrics {
     pub failures: SharedIncMetric,
     /// Failures in configuring the CPUID.
     pub filter_cpuid: SharedIncMetric,
+    /// Time spent processing the CPUID of the vCPUs, in microseconds.
+    pub cpuid_transform_duration_us: SharedIncMetric,
+    /// Number of CPUID entries processed for the vCPUs.
+    pub cpuid_entries_transformed: SharedIncMetric,
 }
 
 /// Metrics specific to the machine manager as a whole.
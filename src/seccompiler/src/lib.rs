@@ -7,7 +7,9 @@
 //! The library crate that defines common helper functions that are generally used in
 //! conjunction with seccompiler-bin.
 
+mod backend;
 mod common;
+mod policy;
 
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
@@ -18,6 +20,9 @@ use bincode::{DefaultOptions, Error as BincodeError, Options};
 use common::BPF_MAX_LEN;
 // Re-export the data types needed for calling the helper functions.
 pub use common::{sock_filter, BpfProgram};
+pub use policy::{
+    cpuid_transformer_seccomp_filter, BpfRule, SeccompError, SeccompPolicyBuilder, SyscallNumber,
+};
 
 /// Type that associates a thread category to a BPF program.
 pub type BpfThreadMap = HashMap<String, Arc<BpfProgram>>;
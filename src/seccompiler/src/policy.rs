@@ -0,0 +1,438 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Programmatic construction of seccomp filters, as an alternative to compiling a JSON policy
+//! with `seccompiler-bin`.
+
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::env::consts::ARCH;
+use std::fmt::{Display, Formatter};
+
+use crate::backend::{
+    Error as FilterError, SeccompAction, SeccompCmpArgLen, SeccompCmpOp, SeccompCondition,
+    SeccompFilter, SeccompRule, SeccompRuleMap,
+};
+use crate::common::BpfProgram;
+
+/// Errors building a seccomp filter with a `SeccompPolicyBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SeccompError {
+    /// A rule checks an argument past the last syscall argument.
+    InvalidArgIndex(u8),
+    /// The filter exceeds the maximum number of instructions that a BPF program can have.
+    TooManyRules,
+    /// The filter is rejected by the seccompiler backend.
+    Filter(String),
+}
+
+impl std::error::Error for SeccompError {}
+
+impl Display for SeccompError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        use self::SeccompError::*;
+
+        match *self {
+            InvalidArgIndex(ref arg_index) => {
+                write!(f, "Invalid syscall argument index: {}.", arg_index)
+            }
+            TooManyRules => write!(f, "The seccomp policy has too many syscalls and rules."),
+            Filter(ref err) => write!(f, "Invalid seccomp filter: {}", err),
+        }
+    }
+}
+
+fn filter_error(err: FilterError) -> SeccompError {
+    match err {
+        FilterError::FilterTooLarge => SeccompError::TooManyRules,
+        err => SeccompError::Filter(err.to_string()),
+    }
+}
+
+/// The syscalls which can be allowed by a `SeccompPolicyBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallNumber {
+    /// `read`
+    Read,
+    /// `write`
+    Write,
+    /// `close`
+    Close,
+    /// `mmap`
+    Mmap,
+    /// `munmap`
+    Munmap,
+    /// `brk`
+    Brk,
+    /// `madvise`
+    Madvise,
+    /// `futex`
+    Futex,
+    /// `exit`
+    Exit,
+    /// `exit_group`
+    ExitGroup,
+    /// `sigaltstack`
+    Sigaltstack,
+    /// `rt_sigprocmask`
+    RtSigprocmask,
+    /// Any other syscall, by number.
+    Other(libc::c_long),
+}
+
+impl From<SyscallNumber> for i64 {
+    fn from(syscall: SyscallNumber) -> i64 {
+        match syscall {
+            SyscallNumber::Read => libc::SYS_read,
+            SyscallNumber::Write => libc::SYS_write,
+            SyscallNumber::Close => libc::SYS_close,
+            SyscallNumber::Mmap => libc::SYS_mmap,
+            SyscallNumber::Munmap => libc::SYS_munmap,
+            SyscallNumber::Brk => libc::SYS_brk,
+            SyscallNumber::Madvise => libc::SYS_madvise,
+            SyscallNumber::Futex => libc::SYS_futex,
+            SyscallNumber::Exit => libc::SYS_exit,
+            SyscallNumber::ExitGroup => libc::SYS_exit_group,
+            SyscallNumber::Sigaltstack => libc::SYS_sigaltstack,
+            SyscallNumber::RtSigprocmask => libc::SYS_rt_sigprocmask,
+            SyscallNumber::Other(nr) => nr,
+        }
+    }
+}
+
+/// Allows a syscall only when one of its arguments has the given value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BpfRule {
+    syscall: i64,
+    arg_index: u8,
+    value: u64,
+}
+
+impl BpfRule {
+    /// Creates a rule allowing `syscall` when its argument number `arg_index` (starting from 0)
+    /// is equal to `value`.
+    pub fn new(syscall: SyscallNumber, arg_index: u8, value: u64) -> Self {
+        BpfRule {
+            syscall: syscall.into(),
+            arg_index,
+            value,
+        }
+    }
+}
+
+/// Builds a seccomp filter for the host architecture from a list of allowed syscalls.
+///
+/// The policy is translated into a `SeccompFilter`, so the BPF program is the same as the one
+/// `seccompiler-bin` compiles from the equivalent JSON policy: the syscalls which aren't
+/// allowed raise `SIGSYS`, same as the default action of the Firecracker filters, while running
+/// on a different architecture kills the process.
+#[derive(Debug, Clone, Default)]
+pub struct SeccompPolicyBuilder {
+    allowed_syscalls: BTreeSet<i64>,
+    custom_rules: Vec<BpfRule>,
+}
+
+impl SeccompPolicyBuilder {
+    /// Creates a builder for a policy which doesn't allow any syscall.
+    pub fn new() -> Self {
+        SeccompPolicyBuilder::default()
+    }
+
+    /// Allows `syscall`, whatever its arguments.
+    pub fn allow_syscall(mut self, syscall: SyscallNumber) -> Self {
+        self.allowed_syscalls.insert(syscall.into());
+        self
+    }
+
+    /// Allows the syscall of `rule` when its argument matches.
+    ///
+    /// The rule has no effect if the syscall is also allowed with `allow_syscall`.
+    pub fn add_rule(mut self, rule: BpfRule) -> Self {
+        self.custom_rules.push(rule);
+        self
+    }
+
+    /// Builds the BPF program, which can be installed with `apply_filter`.
+    pub fn build(&self) -> Result<BpfProgram, SeccompError> {
+        let mut rules = SeccompRuleMap::new();
+        for rule in self.custom_rules.iter() {
+            let condition = SeccompCondition::new(
+                rule.arg_index,
+                SeccompCmpArgLen::Qword,
+                SeccompCmpOp::Eq,
+                rule.value,
+            )
+            .map_err(|_| SeccompError::InvalidArgIndex(rule.arg_index))?;
+            rules
+                .entry(rule.syscall)
+                .or_insert_with(Vec::new)
+                .push(SeccompRule::new(vec![condition], SeccompAction::Allow));
+        }
+
+        // A syscall allowed whatever its arguments can't have other rules.
+        rules.extend(self.allowed_syscalls.iter().map(|syscall| {
+            (
+                *syscall,
+                vec![SeccompRule::new(vec![], SeccompAction::Allow)],
+            )
+        }));
+
+        SeccompFilter::new(rules, SeccompAction::Trap, ARCH)
+            .and_then(TryInto::try_into)
+            .map_err(filter_error)
+    }
+}
+
+/// Syscalls needed by a thread that only runs the cpuid transformers. On top of the ones used
+/// by the transformers, the thread needs `futex` for joining and locking, and `exit`,
+/// `sigaltstack` and `rt_sigprocmask` for exiting, since the standard library removes the
+/// alternate signal stack of the thread and the libc blocks the signals on thread exit.
+const CPUID_TRANSFORMER_SYSCALLS: [SyscallNumber; 9] = [
+    SyscallNumber::Read,
+    SyscallNumber::Write,
+    SyscallNumber::Mmap,
+    SyscallNumber::Munmap,
+    SyscallNumber::Futex,
+    SyscallNumber::Exit,
+    SyscallNumber::ExitGroup,
+    SyscallNumber::Sigaltstack,
+    SyscallNumber::RtSigprocmask,
+];
+
+/// Builds the seccomp filter for a worker thread running the cpuid transformers.
+///
+/// Only `read`, `write`, `mmap`, `munmap`, `futex`, `exit`, `exit_group`, `sigaltstack` and
+/// `rt_sigprocmask` are allowed. Any other syscall raises `SIGSYS`, same as the default action
+/// of the Firecracker filters, while running on a different architecture kills the process.
+///
+/// `madvise` is allowed as well with `MADV_DONTNEED`, which glibc uses to release the stack
+/// of an exiting thread.
+///
+/// The filter can be installed with `apply_filter`.
+pub fn cpuid_transformer_seccomp_filter() -> BpfProgram {
+    CPUID_TRANSFORMER_SYSCALLS
+        .iter()
+        .fold(SeccompPolicyBuilder::new(), |builder, syscall| {
+            builder.allow_syscall(*syscall)
+        })
+        .add_rule(BpfRule::new(
+            SyscallNumber::Madvise,
+            2,
+            libc::MADV_DONTNEED as u64,
+        ))
+        .build()
+        .expect("The cpuid transformer seccomp policy is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::sock_filter;
+
+    // `#define AUDIT_ARCH_X86_64	(EM_X86_64|__AUDIT_ARCH_64BIT|__AUDIT_ARCH_LE)`
+    const AUDIT_ARCH_X86_64: u32 = 62 | 0x8000_0000 | 0x4000_0000;
+    // `#define AUDIT_ARCH_AARCH64	(EM_AARCH64|__AUDIT_ARCH_64BIT|__AUDIT_ARCH_LE)`
+    const AUDIT_ARCH_AARCH64: u32 = 183 | 0x8000_0000 | 0x4000_0000;
+
+    #[cfg(target_arch = "x86_64")]
+    const HOST_AUDIT_ARCH: u32 = AUDIT_ARCH_X86_64;
+    #[cfg(target_arch = "x86_64")]
+    const OTHER_AUDIT_ARCH: u32 = AUDIT_ARCH_AARCH64;
+    #[cfg(target_arch = "aarch64")]
+    const HOST_AUDIT_ARCH: u32 = AUDIT_ARCH_AARCH64;
+    #[cfg(target_arch = "aarch64")]
+    const OTHER_AUDIT_ARCH: u32 = AUDIT_ARCH_X86_64;
+
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+
+    // Runs the filter against a `seccomp_data` built from `arch`, `nr` and `args`, instead of
+    // installing it, since a denied syscall would kill the test process. Only the instructions
+    // emitted for equality checks are supported.
+    fn run_filter_with_args(program: &[sock_filter], arch: u32, nr: u32, args: [u64; 6]) -> u32 {
+        // See /usr/include/linux/bpf_common.h .
+        const BPF_LD_W_ABS: u16 = 0x20;
+        const BPF_JMP_JA: u16 = 0x05;
+        const BPF_JMP_JEQ_K: u16 = 0x15;
+        const BPF_RET_K: u16 = 0x06;
+
+        let mut accumulator = 0;
+        let mut pc = 0;
+
+        loop {
+            let insn = &program[pc];
+            pc += 1;
+            match insn.code {
+                BPF_LD_W_ABS => {
+                    accumulator = match insn.k {
+                        0 => nr,
+                        4 => arch,
+                        offset if offset >= 16 => {
+                            let arg = args[((offset - 16) / 8) as usize];
+                            match offset % 8 {
+                                0 => arg as u32,
+                                _ => (arg >> 32) as u32,
+                            }
+                        }
+                        offset => panic!("Unexpected offset: {}", offset),
+                    }
+                }
+                BPF_JMP_JA => pc += insn.k as usize,
+                BPF_JMP_JEQ_K => {
+                    if accumulator == insn.k {
+                        pc += usize::from(insn.jt);
+                    } else {
+                        pc += usize::from(insn.jf);
+                    }
+                }
+                BPF_RET_K => return insn.k,
+                code => panic!("Unexpected instruction: {}", code),
+            }
+        }
+    }
+
+    fn run_filter(program: &[sock_filter], arch: u32, nr: u32) -> u32 {
+        run_filter_with_args(program, arch, nr, [0; 6])
+    }
+
+    #[test]
+    fn test_cpuid_transformer_seccomp_filter() {
+        let program = cpuid_transformer_seccomp_filter();
+
+        for syscall in CPUID_TRANSFORMER_SYSCALLS {
+            assert_eq!(
+                run_filter(&program, HOST_AUDIT_ARCH, i64::from(syscall) as u32),
+                SECCOMP_RET_ALLOW
+            );
+        }
+
+        for syscall in [libc::SYS_openat, libc::SYS_ioctl, libc::SYS_execve] {
+            assert_eq!(
+                run_filter(&program, HOST_AUDIT_ARCH, syscall as u32),
+                SECCOMP_RET_TRAP
+            );
+        }
+
+        let madvise = |advice: i32| {
+            run_filter_with_args(
+                &program,
+                HOST_AUDIT_ARCH,
+                libc::SYS_madvise as u32,
+                [0, 4096, advice as u64, 0, 0, 0],
+            )
+        };
+        assert_eq!(madvise(libc::MADV_DONTNEED), SECCOMP_RET_ALLOW);
+        assert_eq!(madvise(libc::MADV_WILLNEED), SECCOMP_RET_TRAP);
+
+        assert_eq!(
+            run_filter(&program, OTHER_AUDIT_ARCH, libc::SYS_read as u32),
+            SECCOMP_RET_KILL_PROCESS
+        );
+    }
+
+    #[test]
+    fn test_cpuid_transformer_seccomp_filter_thread() {
+        let (read_fd, write_fd) = {
+            let mut fds = [0; 2];
+            // SAFETY: Safe because `fds` has room for the two file descriptors.
+            assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+            (fds[0], fds[1])
+        };
+
+        // The thread runs and exits under the filter, a denied syscall would kill the test
+        // process.
+        let worker = std::thread::spawn(move || {
+            crate::apply_filter(&cpuid_transformer_seccomp_filter()).unwrap();
+
+            let value = [0x2au8];
+            // SAFETY: Safe because `value` outlives the call and the result is checked.
+            let ret = unsafe { libc::write(write_fd, value.as_ptr().cast(), value.len()) };
+            assert_eq!(ret, 1);
+        });
+        worker.join().unwrap();
+
+        let mut value = [0u8];
+        // SAFETY: Safe because `value` outlives the call and the result is checked.
+        let ret = unsafe { libc::read(read_fd, value.as_mut_ptr().cast(), value.len()) };
+        assert_eq!(ret, 1);
+        assert_eq!(value[0], 0x2a);
+
+        // SAFETY: Safe because the file descriptors were opened above and aren't used anymore.
+        unsafe {
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+    }
+
+    #[test]
+    fn test_seccomp_policy_builder() {
+        const PROT_READ: u64 = libc::PROT_READ as u64;
+        const NR_MMAP: u32 = libc::SYS_mmap as u32;
+
+        // `mmap` is only allowed for read only mappings.
+        let program = SeccompPolicyBuilder::new()
+            .allow_syscall(SyscallNumber::Read)
+            .allow_syscall(SyscallNumber::Other(libc::SYS_getpid))
+            // Allowing a syscall twice doesn't duplicate it.
+            .allow_syscall(SyscallNumber::Read)
+            .add_rule(BpfRule::new(SyscallNumber::Mmap, 2, PROT_READ))
+            .add_rule(BpfRule::new(SyscallNumber::Write, 0, 1 << 32 | 2))
+            // The rules of the syscalls allowed whatever their arguments are ignored.
+            .add_rule(BpfRule::new(SyscallNumber::Read, 0, 0))
+            .build()
+            .unwrap();
+
+        let run = |nr, args| run_filter_with_args(&program, HOST_AUDIT_ARCH, nr, args);
+        assert_eq!(run(libc::SYS_read as u32, [1; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(run(libc::SYS_getpid as u32, [0; 6]), SECCOMP_RET_ALLOW);
+        assert_eq!(run(libc::SYS_openat as u32, [0; 6]), SECCOMP_RET_TRAP);
+
+        assert_eq!(
+            run(NR_MMAP, [0, 4096, PROT_READ, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(
+            run(
+                NR_MMAP,
+                [0, 4096, PROT_READ | libc::PROT_WRITE as u64, 0, 0, 0]
+            ),
+            SECCOMP_RET_TRAP
+        );
+        // Both halves of the argument are compared.
+        let nr_write = libc::SYS_write as u32;
+        assert_eq!(
+            run(nr_write, [1 << 32 | 2, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_ALLOW
+        );
+        assert_eq!(run(nr_write, [2, 0, 0, 0, 0, 0]), SECCOMP_RET_TRAP);
+        assert_eq!(
+            run(nr_write, [1 << 32 | 1, 0, 0, 0, 0, 0]),
+            SECCOMP_RET_TRAP
+        );
+
+        // Running on another architecture kills the process.
+        assert_eq!(
+            run_filter(&program, OTHER_AUDIT_ARCH, libc::SYS_read as u32),
+            SECCOMP_RET_KILL_PROCESS
+        );
+
+        // An empty policy doesn't allow any syscall.
+        let program = SeccompPolicyBuilder::new().build().unwrap();
+        assert_eq!(
+            run_filter(&program, HOST_AUDIT_ARCH, libc::SYS_read as u32),
+            SECCOMP_RET_TRAP
+        );
+
+        assert_eq!(
+            SeccompPolicyBuilder::new()
+                .add_rule(BpfRule::new(SyscallNumber::Mmap, 6, 0))
+                .build(),
+            Err(SeccompError::InvalidArgIndex(6))
+        );
+        let policy = (0..1000).fold(SeccompPolicyBuilder::new(), |builder, nr| {
+            builder.allow_syscall(SyscallNumber::Other(nr))
+        });
+        assert_eq!(policy.build(), Err(SeccompError::TooManyRules));
+    }
+}
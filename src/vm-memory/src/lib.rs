@@ -6,7 +6,7 @@
 // found in the THIRD-PARTY file.
 #![warn(clippy::undocumented_unsafe_blocks)]
 
-use std::io::Error as IoError;
+use std::io::{Error as IoError, ErrorKind};
 use std::os::unix::io::AsRawFd;
 
 use vm_memory_upstream::bitmap::AtomicBitmap;
@@ -25,6 +25,32 @@ pub type GuestMmapRegion = vm_memory_upstream::MmapRegion<Option<AtomicBitmap>>;
 
 const GUARD_PAGE_COUNT: usize = 1;
 
+/// The size of the huge pages backing anonymous guest memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HugePageSize {
+    /// 2 MiB huge pages.
+    TwoMiB,
+    /// 1 GiB huge pages.
+    OneGiB,
+}
+
+impl HugePageSize {
+    /// Returns the size of a huge page, in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            HugePageSize::TwoMiB => 2 << 20,
+            HugePageSize::OneGiB => 1 << 30,
+        }
+    }
+
+    fn mmap_flags(self) -> i32 {
+        match self {
+            HugePageSize::TwoMiB => libc::MAP_HUGETLB | libc::MAP_HUGE_2MB,
+            HugePageSize::OneGiB => libc::MAP_HUGETLB | libc::MAP_HUGE_1GB,
+        }
+    }
+}
+
 /// Build a `MmapRegion` surrounded by guard pages.
 ///
 /// Initially, we map a `PROT_NONE` guard region of size:
@@ -44,11 +70,36 @@ fn build_guarded_region(
     prot: i32,
     flags: i32,
     track_dirty_pages: bool,
+) -> Result<GuestMmapRegion, MmapRegionError> {
+    let page_size = utils::get_page_size().expect("Cannot retrieve page size.");
+    build_aligned_guarded_region(
+        maybe_file_offset,
+        size,
+        prot,
+        flags,
+        track_dirty_pages,
+        page_size,
+    )
+}
+
+/// Same as `build_guarded_region`, but the guard pages and the start of the region are
+/// aligned to `alignment`, which is a power of 2 multiple of the page size. Huge page
+/// mappings must be aligned to the size of the huge pages.
+fn build_aligned_guarded_region(
+    maybe_file_offset: Option<FileOffset>,
+    size: usize,
+    prot: i32,
+    flags: i32,
+    track_dirty_pages: bool,
+    alignment: usize,
 ) -> Result<GuestMmapRegion, MmapRegionError> {
     let page_size = utils::get_page_size().expect("Cannot retrieve page size.");
     // Create the guarded range size (received size + X pages),
     // where X is defined as a constant GUARD_PAGE_COUNT.
-    let guarded_size = size + GUARD_PAGE_COUNT * 2 * page_size;
+    // The kernel rounds the huge page mappings up to a whole number of huge pages, so
+    // the region is followed by at least `GUARD_PAGE_COUNT` aligned pages either way.
+    let aligned_size = (size + alignment - 1) & !(alignment - 1);
+    let guarded_size = aligned_size + GUARD_PAGE_COUNT * 2 * alignment;
 
     // Map the guarded range to PROT_NONE
     // SAFETY: Safe because the parameters are valid.
@@ -75,7 +126,10 @@ fn build_guarded_region(
         None => (-1, 0),
     };
 
-    let region_start_addr = guard_addr as usize + page_size * GUARD_PAGE_COUNT;
+    // The guard range is only page aligned, so the region starts at the first aligned
+    // address after the guard pages.
+    let region_start_addr =
+        (guard_addr as usize + page_size * GUARD_PAGE_COUNT + alignment - 1) & !(alignment - 1);
 
     // Inside the protected range, starting with guard_addr + PAGE_SIZE,
     // map the requested range with received protection and flags
@@ -92,7 +146,10 @@ fn build_guarded_region(
     };
 
     if region_addr == libc::MAP_FAILED {
-        return Err(MmapRegionError::Mmap(IoError::last_os_error()));
+        let err = IoError::last_os_error();
+        // SAFETY: Safe because the guard range was mapped above and nothing else uses it.
+        unsafe { libc::munmap(guard_addr, guarded_size) };
+        return Err(MmapRegionError::Mmap(err));
     }
 
     let bitmap = match track_dirty_pages {
@@ -134,6 +191,52 @@ pub fn create_guest_memory(
     GuestMemoryMmap::from_regions(mmap_regions)
 }
 
+/// Helper for creating anonymous guest memory backed by huge pages.
+///
+/// The huge pages are taken from the pool of the host, configured through
+/// `/proc/sys/vm/nr_hugepages` (or `/sys/kernel/mm/hugepages/hugepages-<size>kB/nr_hugepages`
+/// for the non default sizes), so creating the memory fails if the pool is too small.
+///
+/// The start and the size of every region must be multiples of the huge page size: the huge
+/// page mappings can only be unmapped as a whole number of huge pages.
+pub fn create_huge_page_guest_memory(
+    regions: &[(GuestAddress, usize)],
+    huge_page_size: HugePageSize,
+    track_dirty_pages: bool,
+) -> std::result::Result<GuestMemoryMmap, Error> {
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    // Without `MAP_NORESERVE`, the huge pages are reserved when mapping the memory, so a too
+    // small pool makes `mmap` fail instead of the guest getting a SIGBUS later on.
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | huge_page_size.mmap_flags();
+    let mut mmap_regions = Vec::with_capacity(regions.len());
+
+    // Checked upfront, so that no region is mapped if one of them is misaligned.
+    let alignment = huge_page_size.size();
+    if let Some((addr, size)) = regions
+        .iter()
+        .find(|(addr, size)| addr.0 % alignment as u64 != 0 || size % alignment != 0)
+    {
+        return Err(Error::MmapRegion(MmapRegionError::Mmap(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "The guest memory region at {:#x} of {:#x} bytes is not aligned to the huge page \
+                 size ({:#x} bytes)",
+                addr.0, size, alignment
+            ),
+        ))));
+    }
+
+    for region in regions {
+        let mmap_region =
+            build_aligned_guarded_region(None, region.1, prot, flags, track_dirty_pages, alignment)
+                .map_err(Error::MmapRegion)?;
+
+        mmap_regions.push(GuestRegionMmap::new(mmap_region, region.0)?);
+    }
+
+    GuestMemoryMmap::from_regions(mmap_regions)
+}
+
 pub fn mark_dirty_mem(mem: &GuestMemoryMmap, addr: GuestAddress, len: usize) {
     let _ = mem.try_access(len, addr, |_total, count, caddr, region| {
         if let Some(bitmap) = region.bitmap() {
@@ -396,6 +499,81 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_build_aligned_guarded_region() {
+        let alignment = HugePageSize::TwoMiB.size();
+        let size = alignment + get_page_size().unwrap();
+        let prot = libc::PROT_READ | libc::PROT_WRITE;
+        let flags = libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE;
+
+        let region =
+            build_aligned_guarded_region(None, size, prot, flags, false, alignment).unwrap();
+        assert_eq!(region.size(), size);
+        assert_eq!(region.as_ptr() as usize % alignment, 0);
+
+        validate_guard_region(&region);
+    }
+
+    #[test]
+    fn test_create_huge_page_guest_memory() {
+        assert_eq!(HugePageSize::TwoMiB.size(), 0x20_0000);
+        assert_eq!(HugePageSize::OneGiB.size(), 0x4000_0000);
+
+        let huge_page_size = HugePageSize::TwoMiB.size();
+        let regions = vec![
+            (GuestAddress(0), huge_page_size),
+            (GuestAddress(huge_page_size as u64 * 2), huge_page_size * 2),
+        ];
+        // The host may not have any huge pages configured.
+        match create_huge_page_guest_memory(&regions, HugePageSize::TwoMiB, true) {
+            Ok(guest_memory) => {
+                assert_eq!(guest_memory.num_regions(), 2);
+                for region in guest_memory.iter() {
+                    assert_eq!(region.as_ptr() as usize % huge_page_size, 0);
+                    assert_ne!(region.flags() & libc::MAP_HUGETLB, 0);
+                    assert!(region.bitmap().is_some());
+                }
+            }
+            Err(err) => assert!(matches!(err, Error::MmapRegion(MmapRegionError::Mmap(_)))),
+        }
+    }
+
+    #[test]
+    fn test_create_huge_page_guest_memory_misaligned() {
+        // The x86_64 layout of a 4 GiB guest, which is split around the 768 MiB MMIO gap
+        // below 4 GiB.
+        let mmio_gap_start = (4 << 30) - (768 << 20);
+        let regions = vec![
+            (GuestAddress(0), mmio_gap_start),
+            (GuestAddress(4 << 30), (4 << 30) - mmio_gap_start),
+        ];
+
+        // The regions are multiples of 2 MiB, but not of 1 GiB.
+        for (addr, size) in regions.iter() {
+            assert_eq!(addr.0 % HugePageSize::TwoMiB.size() as u64, 0);
+            assert_eq!(size % HugePageSize::TwoMiB.size(), 0);
+        }
+        match create_huge_page_guest_memory(&regions, HugePageSize::OneGiB, false) {
+            Err(Error::MmapRegion(MmapRegionError::Mmap(err))) => {
+                assert_eq!(err.kind(), ErrorKind::InvalidInput);
+                assert_eq!(
+                    err.to_string(),
+                    "The guest memory region at 0x0 of 0xd0000000 bytes is not aligned to the \
+                     huge page size (0x40000000 bytes)"
+                );
+            }
+            _ => panic!("The misaligned regions must be rejected."),
+        }
+
+        // A misaligned start is rejected as well.
+        let huge_page_size = HugePageSize::TwoMiB.size();
+        let regions = vec![(GuestAddress(0x1000), huge_page_size)];
+        assert!(matches!(
+            create_huge_page_guest_memory(&regions, HugePageSize::TwoMiB, false),
+            Err(Error::MmapRegion(MmapRegionError::Mmap(err))) if err.kind() == ErrorKind::InvalidInput
+        ));
+    }
+
     #[test]
     fn test_mark_dirty_mem() {
         let page_size = utils::get_page_size().unwrap();
@@ -7,12 +7,16 @@ use std::convert::TryFrom;
 use std::fmt::{Display, Formatter};
 use std::io::{self, Read, Seek, SeekFrom};
 use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(target_arch = "x86_64")]
+use std::sync::mpsc::{channel, Receiver};
 use std::sync::{Arc, Mutex};
 
 use arch::InitrdConfig;
 #[cfg(target_arch = "x86_64")]
 use cpuid::common::is_same_model;
 use devices::legacy::serial::ReadableFd;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::Ioapic;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::RTCDevice;
 use devices::legacy::{EventFdTrigger, SerialDevice, SerialEventsWrapper, SerialWrapper};
@@ -32,7 +36,7 @@ use userfaultfd::Uffd;
 use utils::eventfd::EventFd;
 use utils::terminal::Terminal;
 use utils::time::TimestampUs;
-use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+use vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
 #[cfg(target_arch = "aarch64")]
 use vm_superio::Rtc;
 use vm_superio::Serial;
@@ -47,9 +51,11 @@ use crate::persist::{MicrovmState, MicrovmStateError};
 use crate::resources::VmResources;
 use crate::vmm_config::boot_source::BootConfig;
 use crate::vmm_config::instance_info::InstanceInfo;
-use crate::vmm_config::machine_config::{VmConfigError, VmUpdateConfig};
+use crate::vmm_config::machine_config::{HugePageMode, IrqChipMode, VmConfigError, VmUpdateConfig};
 use crate::vstate::system::KvmContext;
 use crate::vstate::vcpu::{Vcpu, VcpuConfig};
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vcpu::{VcpuDebugChannel, VcpuDebugEvent};
 use crate::vstate::vm::Vm;
 use crate::{device_manager, Error, EventManager, Vmm, VmmEventsObserver};
 
@@ -76,6 +82,8 @@ pub enum StartMicrovmError {
     KernelCmdline(String),
     /// Cannot load kernel due to invalid memory configuration or invalid kernel image.
     KernelLoader(linux_loader::loader::Error),
+    /// Cannot lock the guest memory in RAM.
+    LockGuestMemory(io::Error),
     /// Cannot load command line string.
     LoadCommandline(linux_loader::loader::Error),
     /// Cannot start the VM because the kernel builder was not configured.
@@ -147,6 +155,7 @@ impl Display for StartMicrovmError {
                 err_msg = err_msg.replace('\"', "");
                 write!(f, "Cannot load command line string. {}", err_msg)
             }
+            LockGuestMemory(err) => write!(f, "Cannot lock the guest memory: {}", err),
             MissingKernelConfig => write!(f, "Cannot start microvm without kernel configuration."),
             MissingMemSizeConfig => {
                 write!(f, "Cannot start microvm without guest mem_size config.")
@@ -234,6 +243,7 @@ fn create_vmm_and_vcpus(
     uffd: Option<Uffd>,
     track_dirty_pages: bool,
     vcpu_count: u8,
+    irqchip_mode: IrqChipMode,
 ) -> std::result::Result<(Vmm, Vec<Vcpu>), StartMicrovmError> {
     use self::StartMicrovmError::*;
 
@@ -247,21 +257,30 @@ fn create_vmm_and_vcpus(
     // Instantiate the MMIO device manager.
     // 'mmio_base' address has to be an address which is protected by the kernel
     // and is architectural specific.
-    let mmio_device_manager = MMIODeviceManager::new(
+    let mut mmio_device_manager = MMIODeviceManager::new(
         arch::MMIO_MEM_START,
         arch::MMIO_MEM_SIZE,
         (arch::IRQ_BASE, arch::IRQ_MAX),
     )
     .map_err(StartMicrovmError::RegisterMmioDevice)?;
 
-    let vcpus;
+    #[cfg_attr(target_arch = "aarch64", allow(unused_mut))]
+    let mut vcpus;
     // For x86_64 we need to create the interrupt controller before calling `KVM_CREATE_VCPUS`
     // while on aarch64 we need to do it the other way around.
     #[cfg(target_arch = "x86_64")]
     let pio_device_manager = {
-        setup_interrupt_controller(&mut vm)?;
+        setup_interrupt_controller(&mut vm, irqchip_mode)?;
         vcpus = create_vcpus(&vm, vcpu_count, &vcpus_exit_evt).map_err(Internal)?;
 
+        // With a split irqchip, the IOAPIC is emulated in userspace.
+        if irqchip_mode == IrqChipMode::Split {
+            let routing = vm.ioapic_routing().map_err(Error::Vm).map_err(Internal)?;
+            mmio_device_manager
+                .register_mmio_ioapic(Arc::new(Mutex::new(Ioapic::new(Box::new(routing)))))
+                .map_err(RegisterMmioDevice)?;
+        }
+
         // Make stdout non blocking.
         set_stdout_nonblocking();
 
@@ -291,6 +310,10 @@ fn create_vmm_and_vcpus(
         setup_interrupt_controller(&mut vm, vcpu_count)?;
     }
 
+    #[cfg(target_arch = "x86_64")]
+    let (vcpus_debug_evt, vcpus_debug_events) =
+        setup_vcpus_debug_channel(&mut vcpus).map_err(Internal)?;
+
     let vmm = Vmm {
         events_observer: Some(Box::new(SerialStdin::get())),
         instance_info: instance_info.clone(),
@@ -300,6 +323,10 @@ fn create_vmm_and_vcpus(
         uffd,
         vcpus_handles: Vec::new(),
         vcpus_exit_evt,
+        #[cfg(target_arch = "x86_64")]
+        vcpus_debug_evt,
+        #[cfg(target_arch = "x86_64")]
+        vcpus_debug_events,
         mmio_device_manager,
         #[cfg(target_arch = "x86_64")]
         pio_device_manager,
@@ -331,9 +358,18 @@ pub fn build_microvm_for_boot(
         .ok_or(MissingKernelConfig)?;
 
     let track_dirty_pages = vm_resources.track_dirty_pages();
-    let guest_memory =
-        create_guest_memory(vm_resources.vm_config().mem_size_mib, track_dirty_pages)?;
+    let guest_memory = create_guest_memory(
+        vm_resources.vm_config().mem_size_mib,
+        track_dirty_pages,
+        vm_resources.vm_config().huge_pages,
+    )?;
+    if vm_resources.vm_config().mlock_guest_memory {
+        lock_guest_memory(&guest_memory).map_err(StartMicrovmError::LockGuestMemory)?;
+    }
     let vcpu_config = vm_resources.vcpu_config();
+    vm_resources
+        .check_guest_os_irqchip_mode()
+        .map_err(SetVmResources)?;
     let entry_addr = load_kernel(boot_config, &guest_memory)?;
     let initrd = load_initrd_from_config(boot_config, &guest_memory)?;
     // Clone the command-line so that a failed boot doesn't pollute the original.
@@ -347,6 +383,7 @@ pub fn build_microvm_for_boot(
         None,
         track_dirty_pages,
         vcpu_config.vcpu_count,
+        vm_resources.vm_config().irqchip_mode,
     )?;
 
     // The boot timer device needs to be the first device attached in order
@@ -456,6 +493,9 @@ pub enum BuildMicrovmFromSnapshotError {
     /// Failed to update microVM configuration.
     #[error("Failed to update microVM configuration: {0}")]
     VmUpdateConfig(#[from] VmConfigError),
+    /// Failed to lock the guest memory.
+    #[error("Cannot lock the guest memory: {0}")]
+    LockGuestMemory(io::Error),
     /// Failed to restore MMIO device.
     #[error("Failed to restore MMIO device: {0}")]
     RestoreMmioDevice(#[from] MicrovmStateError),
@@ -506,6 +546,8 @@ pub fn build_microvm_from_snapshot(
         uffd,
         track_dirty_pages,
         vcpu_count,
+        // Snapshots can only be taken of microVMs with an in-kernel irqchip.
+        IrqChipMode::InKernel,
     )?;
 
     #[cfg(target_arch = "x86_64")]
@@ -545,8 +587,19 @@ pub fn build_microvm_from_snapshot(
         smt: Some(microvm_state.vm_info.smt),
         cpu_template: Some(microvm_state.vm_info.cpu_template),
         track_dirty_pages: Some(track_dirty_pages),
+        mlock_guest_memory: Some(microvm_state.vm_info.mlock_guest_memory),
+        // The restored guest memory is a private mapping of the memory file, or an anonymous
+        // one populated by the uffd handler, so it is always backed by regular pages.
+        huge_pages: Some(HugePageMode::None),
+        irqchip_mode: Some(IrqChipMode::InKernel),
     })?;
 
+    // With a uffd backend, locking faults in the whole guest memory through the page fault
+    // handler.
+    if vm_resources.vm_config().mlock_guest_memory {
+        lock_guest_memory(&guest_memory).map_err(BuildMicrovmFromSnapshotError::LockGuestMemory)?;
+    }
+
     // Restore the boot source config paths.
     vm_resources.set_boot_source_config(microvm_state.vm_info.boot_source);
 
@@ -592,13 +645,32 @@ pub fn build_microvm_from_snapshot(
 }
 
 /// Creates GuestMemory of `mem_size_mib` MiB in size.
+///
+/// If huge pages are requested, but the host can't provide them, the guest memory is backed by
+/// regular pages instead.
 pub fn create_guest_memory(
     mem_size_mib: usize,
     track_dirty_pages: bool,
+    huge_pages: HugePageMode,
 ) -> std::result::Result<GuestMemoryMmap, StartMicrovmError> {
     let mem_size = mem_size_mib << 20;
     let arch_mem_regions = arch::arch_memory_regions(mem_size);
 
+    if let Some(huge_page_size) = huge_pages.huge_page_size() {
+        match vm_memory::create_huge_page_guest_memory(
+            &arch_mem_regions,
+            huge_page_size,
+            track_dirty_pages,
+        ) {
+            Ok(guest_memory) => return Ok(guest_memory),
+            Err(err) => warn!(
+                "Cannot back the guest memory with {} huge pages, falling back to regular pages: \
+                 {}",
+                huge_pages, err
+            ),
+        }
+    }
+
     vm_memory::create_guest_memory(
         &arch_mem_regions
             .iter()
@@ -609,6 +681,19 @@ pub fn create_guest_memory(
     .map_err(StartMicrovmError::GuestMemoryMmap)
 }
 
+/// Locks the guest memory in RAM, so that it can't be swapped out.
+fn lock_guest_memory(guest_memory: &GuestMemoryMmap) -> io::Result<()> {
+    for region in guest_memory.iter() {
+        // SAFETY: Safe because the region is a valid mapping of `region.size()` bytes, which
+        // outlives the call, and the return value is checked.
+        let ret = unsafe { libc::mlock(region.as_ptr().cast::<libc::c_void>(), region.size()) };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
 fn load_kernel(
     boot_config: &BootConfig,
     guest_memory: &GuestMemoryMmap,
@@ -715,8 +800,11 @@ pub(crate) fn setup_kvm_vm(
 
 /// Sets up the irqchip for a x86_64 microVM.
 #[cfg(target_arch = "x86_64")]
-pub fn setup_interrupt_controller(vm: &mut Vm) -> std::result::Result<(), StartMicrovmError> {
-    vm.setup_irqchip()
+pub fn setup_interrupt_controller(
+    vm: &mut Vm,
+    irqchip_mode: IrqChipMode,
+) -> std::result::Result<(), StartMicrovmError> {
+    vm.setup_irqchip(irqchip_mode)
         .map_err(Error::Vm)
         .map_err(StartMicrovmError::Internal)
 }
@@ -827,6 +915,21 @@ fn create_vcpus(vm: &Vm, vcpu_count: u8, exit_evt: &EventFd) -> super::Result<Ve
     Ok(vcpus)
 }
 
+/// Sets up the channel on which the vcpus report their debug exits to the Vmm.
+#[cfg(target_arch = "x86_64")]
+fn setup_vcpus_debug_channel(
+    vcpus: &mut [Vcpu],
+) -> super::Result<(EventFd, Receiver<VcpuDebugEvent>)> {
+    let debug_evt = EventFd::new(libc::EFD_NONBLOCK).map_err(Error::EventFd)?;
+    let (sender, receiver) = channel();
+    for vcpu in vcpus.iter_mut() {
+        let debug_evt = debug_evt.try_clone().map_err(Error::EventFd)?;
+        vcpu.kvm_vcpu
+            .set_debug_channel(VcpuDebugChannel::new(sender.clone(), debug_evt));
+    }
+    Ok((debug_evt, receiver))
+}
+
 /// Configures the system for booting Linux.
 #[cfg_attr(target_arch = "aarch64", allow(unused))]
 pub fn configure_system_for_boot(
@@ -1094,7 +1197,7 @@ pub mod tests {
     }
 
     pub(crate) fn default_vmm() -> Vmm {
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(128, false, HugePageMode::None).unwrap();
 
         let vcpus_exit_evt = EventFd::new(libc::EFD_NONBLOCK)
             .map_err(Error::EventFd)
@@ -1107,7 +1210,7 @@ pub mod tests {
         let pio_device_manager = default_portio_device_manager();
 
         #[cfg(target_arch = "x86_64")]
-        setup_interrupt_controller(&mut vm).unwrap();
+        setup_interrupt_controller(&mut vm, IrqChipMode::InKernel).unwrap();
 
         #[cfg(target_arch = "aarch64")]
         {
@@ -1125,6 +1228,10 @@ pub mod tests {
             uffd: None,
             vcpus_handles: Vec::new(),
             vcpus_exit_evt,
+            #[cfg(target_arch = "x86_64")]
+            vcpus_debug_evt: EventFd::new(libc::EFD_NONBLOCK).unwrap(),
+            #[cfg(target_arch = "x86_64")]
+            vcpus_debug_events: std::sync::mpsc::channel().1,
             mmio_device_manager,
             #[cfg(target_arch = "x86_64")]
             pio_device_manager,
@@ -1309,28 +1416,50 @@ pub mod tests {
 
         // Case 1: create guest memory without dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, false).unwrap();
+            let guest_memory = create_guest_memory(mem_size, false, HugePageMode::None).unwrap();
             assert!(!is_dirty_tracking_enabled(&guest_memory));
         }
 
         // Case 2: create guest memory with dirty page tracking
         {
-            let guest_memory = create_guest_memory(mem_size, true).unwrap();
+            let guest_memory = create_guest_memory(mem_size, true, HugePageMode::None).unwrap();
+            assert!(is_dirty_tracking_enabled(&guest_memory));
+        }
+
+        // Case 3: create guest memory backed by huge pages, or by regular pages if the host
+        // doesn't have enough of them.
+        {
+            let guest_memory = create_guest_memory(1024, true, HugePageMode::TwoMiB).unwrap();
+            assert_eq!(
+                guest_memory
+                    .iter()
+                    .map(|region| region.size())
+                    .sum::<usize>(),
+                1024 << 20
+            );
             assert!(is_dirty_tracking_enabled(&guest_memory));
         }
     }
 
+    #[test]
+    fn test_lock_guest_memory() {
+        let guest_memory =
+            vm_memory::test_utils::create_anon_guest_memory(&[(GuestAddress(0), 0x1000)], false)
+                .unwrap();
+        lock_guest_memory(&guest_memory).unwrap();
+    }
+
     #[test]
     fn test_create_vcpus() {
         let vcpu_count = 2;
-        let guest_memory = create_guest_memory(128, false).unwrap();
+        let guest_memory = create_guest_memory(128, false, HugePageMode::None).unwrap();
 
         #[allow(unused_mut)]
         let mut vm = setup_kvm_vm(&guest_memory, false).unwrap();
         let evfd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
 
         #[cfg(target_arch = "x86_64")]
-        setup_interrupt_controller(&mut vm).unwrap();
+        setup_interrupt_controller(&mut vm, IrqChipMode::InKernel).unwrap();
 
         let vcpu_vec = create_vcpus(&vm, vcpu_count, &evfd).unwrap();
         assert_eq!(vcpu_vec.len(), vcpu_count as usize);
@@ -1347,6 +1476,9 @@ pub mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         };
 
         let mut cmdline = default_kernel_cmdline();
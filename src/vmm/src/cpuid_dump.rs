@@ -0,0 +1,93 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use std::fmt::{self, Display, Formatter};
+
+use cpuid::common::CpuidEntryDisplay;
+use cpuid::{filter_cpuid, VmSpec};
+use kvm_bindings::{CpuId, KVM_MAX_CPUID_ENTRIES};
+
+use crate::vstate::system::{Error as KvmContextError, KvmContext};
+
+/// Errors associated with dumping the CPUID of the host.
+#[derive(Debug, thiserror::Error)]
+pub enum CpuidDumpError {
+    /// Failed to initialize the KVM context.
+    #[error("Failed to initialize the KVM context: {0}")]
+    KvmContext(KvmContextError),
+    /// Failed to get the CPUID supported by KVM.
+    #[error("Failed to get the supported CPUID: {0}")]
+    SupportedCpuid(kvm_ioctls::Error),
+    /// Failed to create the `VmSpec`.
+    #[error("Failed to create `VmSpec`: {0}")]
+    VmSpec(cpuid::Error),
+    /// Failed to filter the CPUID.
+    #[error("Failed to filter CPUID: {0}")]
+    FilterCpuid(cpuid::Error),
+}
+
+/// The CPUID a microVM vCPU would get on this host.
+///
+/// It is displayed as a table with the vendor of the host, followed by one line per entry.
+#[derive(Debug)]
+pub struct CpuidDump {
+    /// The vendor id of the host.
+    pub vendor_id: [u8; 12],
+    /// The processed CPUID entries.
+    pub cpuid: CpuId,
+}
+
+impl Display for CpuidDump {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Vendor: {}", String::from_utf8_lossy(&self.vendor_id))?;
+        for entry in self.cpuid.as_slice() {
+            writeln!(f, "{}", CpuidEntryDisplay(entry))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the CPUID of the first vCPU of a single vCPU microVM on this host, as processed by
+/// the transformer of the host vendor, without creating a microVM.
+pub fn host_cpuid_dump() -> Result<CpuidDump, CpuidDumpError> {
+    let kvm = KvmContext::new().map_err(CpuidDumpError::KvmContext)?;
+    let mut cpuid = kvm
+        .fd()
+        .get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)
+        .map_err(CpuidDumpError::SupportedCpuid)?;
+
+    // The vendor id is read from the host by `VmSpec`.
+    let vm_spec = VmSpec::builder()
+        .sort_entries(true)
+        .build()
+        .map_err(CpuidDumpError::VmSpec)?;
+    filter_cpuid(&mut cpuid, &vm_spec).map_err(CpuidDumpError::FilterCpuid)?;
+
+    Ok(CpuidDump {
+        vendor_id: *vm_spec.cpu_vendor_id(),
+        cpuid,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use cpuid::common::get_vendor_id_from_host;
+
+    use super::*;
+
+    #[test]
+    fn test_host_cpuid_dump() {
+        let dump = host_cpuid_dump().unwrap();
+        assert_eq!(dump.vendor_id, get_vendor_id_from_host().unwrap());
+
+        let output = dump.to_string();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!("Vendor: {}", String::from_utf8_lossy(&dump.vendor_id))
+        );
+        assert_eq!(lines.len(), dump.cpuid.as_slice().len() + 1);
+        assert!(lines[1].starts_with("function: 0x00000000, index: 0x0,"));
+    }
+}
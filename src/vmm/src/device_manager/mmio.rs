@@ -17,6 +17,8 @@ use arch::DeviceType::Virtio;
 use devices::legacy::RTCDevice;
 #[cfg(target_arch = "aarch64")]
 use devices::legacy::SerialDevice;
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::{Ioapic, IOAPIC_SIZE, IOAPIC_START};
 use devices::pseudo::BootTimer;
 use devices::virtio::{
     Balloon, Block, MmioTransport, Net, VirtioDevice, TYPE_BALLOON, TYPE_BLOCK, TYPE_NET,
@@ -105,6 +107,8 @@ pub struct MMIODeviceManager {
     pub(crate) irq_allocator: IdAllocator,
     pub(crate) address_allocator: AddressAllocator,
     pub(crate) id_to_dev_info: HashMap<(DeviceType, String), MMIODeviceInfo>,
+    #[cfg(target_arch = "x86_64")]
+    pub(crate) ioapic: Option<Arc<Mutex<Ioapic>>>,
 }
 
 impl MMIODeviceManager {
@@ -120,6 +124,8 @@ impl MMIODeviceManager {
                 .map_err(Error::AllocatorError)?,
             bus: devices::Bus::new(),
             id_to_dev_info: HashMap::new(),
+            #[cfg(target_arch = "x86_64")]
+            ioapic: None,
         })
     }
 
@@ -298,6 +304,25 @@ impl MMIODeviceManager {
         self.register_mmio_device(identifier, device_info, Arc::new(Mutex::new(device)))
     }
 
+    #[cfg(target_arch = "x86_64")]
+    /// Register the userspace IOAPIC at its architectural address.
+    ///
+    /// The IOAPIC is not allocated from the MMIO space of the virtio devices and is not
+    /// reported in the device information, as it is not part of the snapshot.
+    pub fn register_mmio_ioapic(&mut self, ioapic: Arc<Mutex<Ioapic>>) -> Result<()> {
+        self.bus
+            .insert(ioapic.clone(), IOAPIC_START, IOAPIC_SIZE)
+            .map_err(Error::Bus)?;
+        self.ioapic = Some(ioapic);
+        Ok(())
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    /// Gets the userspace IOAPIC, if the microVM uses a split irqchip.
+    pub fn ioapic(&self) -> Option<&Arc<Mutex<Ioapic>>> {
+        self.ioapic.as_ref()
+    }
+
     /// Gets the information of the devices registered up to some point in time.
     pub fn get_device_info(&self) -> &HashMap<(DeviceType, String), MMIODeviceInfo> {
         &self.id_to_dev_info
@@ -707,6 +707,9 @@ mod tests {
                 guest_mac: Some(MacAddr::parse_str("00:00:00:00:00:00").unwrap()),
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                stats_reset_on_read: false,
+                enable_ctrl_vq: false,
+                tx_budget_us: None,
             };
             insert_net_device_with_mmds(
                 &mut vmm,
@@ -826,7 +829,10 @@ mod tests {
       "host_dev_name": "hostname",
       "guest_mac": "00:00:00:00:00:00",
       "rx_rate_limiter": null,
-      "tx_rate_limiter": null
+      "tx_rate_limiter": null,
+      "stats_reset_on_read": false,
+      "enable_ctrl_vq": false,
+      "tx_budget_us": null
     }}
   ],
   "vsock": {{
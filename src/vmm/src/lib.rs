@@ -13,6 +13,9 @@
 
 /// Handles setup and initialization a `Vmm` object.
 pub mod builder;
+/// Dump of the CPUID processed for the microVMs of the host.
+#[cfg(target_arch = "x86_64")]
+pub mod cpuid_dump;
 pub(crate) mod device_manager;
 pub mod memory_snapshot;
 /// Save/restore utilities.
@@ -25,6 +28,8 @@ pub mod rpc_interface;
 pub mod seccomp_filters;
 /// Signal handling utilities.
 pub mod signal_handler;
+/// Migrations of the snapshots saved by older Firecracker versions.
+pub mod snapshot_migration;
 /// Utility functions for integration and benchmark testing
 pub mod utilities;
 /// microVM state versions.
@@ -35,17 +40,22 @@ mod vstate;
 
 use std::collections::HashMap;
 use std::os::unix::io::AsRawFd;
+#[cfg(target_arch = "x86_64")]
+use std::sync::mpsc::Receiver;
 use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
 use std::sync::{Arc, Barrier, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt, io};
 
 use arch::DeviceType;
 use devices::legacy::serial::{IER_RDA_BIT, IER_RDA_OFFSET};
+#[cfg(target_arch = "x86_64")]
+use devices::legacy::IOAPIC_NUM_PINS;
 use devices::virtio::balloon::Error as BalloonError;
 use devices::virtio::{
-    Balloon, BalloonConfig, BalloonStats, Block, MmioTransport, Net, BALLOON_DEV_ID, TYPE_BALLOON,
-    TYPE_BLOCK, TYPE_NET,
+    Balloon, BalloonConfig, BalloonStats, Block, BlockDeviceStats, MmioTransport, Net,
+    NetDeviceStats, Vsock, VsockStats, VsockUnixBackend, BALLOON_DEV_ID, TYPE_BALLOON, TYPE_BLOCK,
+    TYPE_NET, TYPE_VSOCK, VSOCK_DEV_ID,
 };
 use devices::BusDevice;
 use event_manager::{EventManager as BaseEventManager, EventOps, Events, MutEventSubscriber};
@@ -64,7 +74,11 @@ use crate::device_manager::legacy::PortIODeviceManager;
 use crate::device_manager::mmio::MMIODeviceManager;
 use crate::memory_snapshot::SnapshotMemory;
 use crate::persist::{MicrovmState, MicrovmStateError, VmInfo};
-use crate::vmm_config::instance_info::{InstanceInfo, VmState};
+use crate::vmm_config::instance_info::{InstanceInfo, VcpuInfo, VmState};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::machine_config::{IrqRoute, IrqRoutingTable};
+#[cfg(target_arch = "x86_64")]
+use crate::vstate::vcpu::VcpuDebugEvent;
 use crate::vstate::vcpu::{Vcpu, VcpuEvent, VcpuHandle, VcpuResponse, VcpuState};
 use crate::vstate::vm::Vm;
 
@@ -178,6 +192,9 @@ pub enum Error {
     /// Cannot send event to vCPU.
     #[error("Cannot send event to vCPU. {0}")]
     VcpuEvent(vstate::vcpu::Error),
+    /// The vCPU does not exist.
+    #[error("Invalid vCPU id: {0}")]
+    VcpuId(u8),
     /// Cannot create a vCPU handle.
     #[error("Cannot create a vCPU handle. {0}")]
     VcpuHandle(vstate::vcpu::Error),
@@ -191,12 +208,18 @@ pub enum Error {
     /// vCPU pause failed.
     #[error("Failed to pause the vCPUs.")]
     VcpuPause,
+    /// Some vCPUs didn't acknowledge the pause in time, so the microVM was kept running.
+    #[error("Timed out pausing the microVM, vCPUs {0:?} are still running.")]
+    VcpuPauseTimeout(Vec<u8>),
     /// vCPU exit failed.
     #[error("Failed to exit the vCPUs.")]
     VcpuExit,
     /// vCPU resume failed.
     #[error("Failed to resume the vCPUs.")]
     VcpuResume,
+    /// Some vCPUs didn't acknowledge the resume in time.
+    #[error("Timed out resuming the microVM, vCPUs {0:?} are not running yet.")]
+    VcpuResumeTimeout(Vec<u8>),
     /// Vcpu send message failed.
     #[error("Failed to message the vCPUs.")]
     VcpuMessage,
@@ -294,6 +317,11 @@ pub struct Vmm {
     vcpus_handles: Vec<VcpuHandle>,
     // Used by Vcpus and devices to initiate teardown; Vmm should never write here.
     vcpus_exit_evt: EventFd,
+    // Used by Vcpus to signal the debug events they sent on `vcpus_debug_events`.
+    #[cfg(target_arch = "x86_64")]
+    vcpus_debug_evt: EventFd,
+    #[cfg(target_arch = "x86_64")]
+    vcpus_debug_events: Receiver<VcpuDebugEvent>,
 
     // Guest VM devices.
     mmio_device_manager: MMIODeviceManager,
@@ -354,6 +382,10 @@ impl Vmm {
             #[cfg(target_arch = "x86_64")]
             vcpu.kvm_vcpu
                 .set_pio_bus(self.pio_device_manager.io_bus.clone());
+            #[cfg(target_arch = "x86_64")]
+            if let Some(ioapic) = self.mmio_device_manager.ioapic() {
+                vcpu.kvm_vcpu.set_ioapic(ioapic.clone());
+            }
 
             self.vcpus_handles
                 .push(vcpu.start_threaded(vcpu_seccomp_filter.clone(), barrier.clone())?);
@@ -367,48 +399,94 @@ impl Vmm {
 
     /// Sends a resume command to the vCPUs.
     pub fn resume_vm(&mut self) -> Result<()> {
+        self.resume_vm_with_timeout(RECV_TIMEOUT_SEC)
+    }
+
+    /// Sends a resume command to the vCPUs and waits up to `timeout` for all of them to
+    /// acknowledge it.
+    ///
+    /// The vCPUs which are late resume on their own once they catch up, so the microVM is
+    /// considered running even on timeout.
+    pub fn resume_vm_with_timeout(&mut self, timeout: Duration) -> Result<()> {
         self.mmio_device_manager.kick_devices();
 
-        // Send the events.
+        let late_vcpus = self.broadcast_vcpu_event(
+            || VcpuEvent::Resume,
+            |response| matches!(response, VcpuResponse::Resumed),
+            timeout,
+        )?;
+
+        self.instance_info.state = VmState::Running;
+        match late_vcpus.is_empty() {
+            true => Ok(()),
+            false => Err(Error::VcpuResumeTimeout(late_vcpus)),
+        }
+    }
+
+    /// Sends a pause command to the vCPUs.
+    pub fn pause_vm(&mut self) -> Result<()> {
+        self.pause_vm_with_timeout(RECV_TIMEOUT_SEC)
+    }
+
+    /// Sends a pause command to the vCPUs and waits up to `timeout` for all of them to
+    /// acknowledge it.
+    ///
+    /// On timeout, the pause is cancelled so that the microVM keeps running: the vCPUs which
+    /// already paused are resumed, the others resume right after pausing.
+    pub fn pause_vm_with_timeout(&mut self, timeout: Duration) -> Result<()> {
+        let running_vcpus = self.broadcast_vcpu_event(
+            || VcpuEvent::Pause,
+            |response| matches!(response, VcpuResponse::Paused),
+            timeout,
+        )?;
+        if running_vcpus.is_empty() {
+            self.instance_info.state = VmState::Paused;
+            return Ok(());
+        }
+
+        self.mmio_device_manager.kick_devices();
         self.vcpus_handles
             .iter()
             .try_for_each(|handle| handle.send_event(VcpuEvent::Resume))
             .map_err(|_| Error::VcpuMessage)?;
-
-        // Check the responses.
-        if self
-            .vcpus_handles
-            .iter()
-            .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
-            .any(|response| !matches!(response, Ok(VcpuResponse::Resumed)))
-        {
-            return Err(Error::VcpuMessage);
+        for (vcpu_id, handle) in self.vcpus_handles.iter().enumerate() {
+            if !running_vcpus.contains(&(vcpu_id as u8))
+                && !matches!(
+                    handle.recv_response(RECV_TIMEOUT_SEC),
+                    Ok(VcpuResponse::Resumed)
+                )
+            {
+                return Err(Error::VcpuMessage);
+            }
         }
 
-        self.instance_info.state = VmState::Running;
-        Ok(())
+        Err(Error::VcpuPauseTimeout(running_vcpus))
     }
 
-    /// Sends a pause command to the vCPUs.
-    pub fn pause_vm(&mut self) -> Result<()> {
-        // Send the events.
+    // Sends an event to all the vCPUs and returns the ids of the ones which didn't respond
+    // within `timeout`.
+    fn broadcast_vcpu_event(
+        &self,
+        event: fn() -> VcpuEvent,
+        expected_response: fn(&VcpuResponse) -> bool,
+        timeout: Duration,
+    ) -> Result<Vec<u8>> {
         self.vcpus_handles
             .iter()
-            .try_for_each(|handle| handle.send_event(VcpuEvent::Pause))
+            .try_for_each(|handle| handle.send_event(event()))
             .map_err(|_| Error::VcpuMessage)?;
 
-        // Check the responses.
-        if self
-            .vcpus_handles
-            .iter()
-            .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
-            .any(|response| !matches!(response, Ok(VcpuResponse::Paused)))
-        {
-            return Err(Error::VcpuMessage);
+        let deadline = Instant::now() + timeout;
+        let mut late_vcpus = Vec::new();
+        for (vcpu_id, handle) in self.vcpus_handles.iter().enumerate() {
+            match handle.recv_response(deadline.saturating_duration_since(Instant::now())) {
+                Ok(response) if expected_response(&response) => (),
+                Err(RecvTimeoutError::Timeout) => late_vcpus.push(vcpu_id as u8),
+                _ => return Err(Error::VcpuMessage),
+            }
         }
 
-        self.instance_info.state = VmState::Paused;
-        Ok(())
+        Ok(late_vcpus)
     }
 
     /// Returns a reference to the inner `GuestMemoryMmap` object.
@@ -465,6 +543,74 @@ impl Vmm {
             .map_err(Error::I8042Error)
     }
 
+    /// Returns the run state of each vCPU of the microVM.
+    pub fn vcpus_info(&self) -> Vec<VcpuInfo> {
+        self.vcpus_handles
+            .iter()
+            .enumerate()
+            .map(|(id, handle)| VcpuInfo {
+                id: id as u8,
+                state: handle.run_status().state(),
+                exit_reason: handle.run_status().exit_reason(),
+            })
+            .collect()
+    }
+
+    /// Enables or disables the single-step mode of a vCPU.
+    ///
+    /// In single-step mode, the vCPU reports a `VcpuDebugEvent` after each guest instruction.
+    #[cfg(target_arch = "x86_64")]
+    pub fn set_single_step(&mut self, vcpu_id: u8, enabled: bool) -> Result<()> {
+        let handle = self
+            .vcpus_handles
+            .get(usize::from(vcpu_id))
+            .ok_or(Error::VcpuId(vcpu_id))?;
+
+        handle
+            .send_event(VcpuEvent::SingleStep(enabled))
+            .map_err(|_| Error::VcpuMessage)?;
+
+        match handle.recv_response(RECV_TIMEOUT_SEC) {
+            Ok(VcpuResponse::SingleStepUpdated) => Ok(()),
+            Ok(VcpuResponse::Error(err)) => Err(Error::VcpuEvent(err)),
+            _ => Err(Error::VcpuMessage),
+        }
+    }
+
+    /// Returns the GSI routing table of the microVM.
+    #[cfg(target_arch = "x86_64")]
+    pub fn irq_routing(&self) -> IrqRoutingTable {
+        let routes = match self.mmio_device_manager.ioapic() {
+            Some(ioapic) => ioapic
+                .lock()
+                .expect("Poisoned lock")
+                .routes()
+                .iter()
+                .filter_map(|route| {
+                    route.msi.map(|msi| IrqRoute::Msi {
+                        gsi: route.pin,
+                        address: (u64::from(msi.address_hi) << 32) | u64::from(msi.address_lo),
+                        data: msi.data,
+                    })
+                })
+                .collect(),
+            // KVM routes the 16 ISA interrupts to both the PICs and the IOAPIC, and the
+            // remaining GSIs to the IOAPIC only.
+            None => (0..IOAPIC_NUM_PINS as u32)
+                .flat_map(|gsi| {
+                    let pic = (gsi < 16).then_some(IrqRoute::Pic { gsi, pin: gsi });
+                    pic.into_iter()
+                        .chain(std::iter::once(IrqRoute::Ioapic { gsi, pin: gsi }))
+                })
+                .collect(),
+        };
+
+        IrqRoutingTable {
+            irqchip_mode: self.vm.irqchip_mode(),
+            routes,
+        }
+    }
+
     /// Saves the state of a paused Microvm.
     pub fn save_state(
         &mut self,
@@ -508,7 +654,7 @@ impl Vmm {
             .vcpus_handles
             .iter()
             // `Iterator::collect` can transform a `Vec<Result>` into a `Result<Vec>`.
-            .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
+            .map(|handle| handle.recv_response(RECV_TIMEOUT_SEC))
             .collect::<std::result::Result<Vec<VcpuResponse>, RecvTimeoutError>>()
             .map_err(|_| MicrovmStateError::UnexpectedVcpuResponse)?;
 
@@ -541,7 +687,7 @@ impl Vmm {
             .vcpus_handles
             .iter()
             // `Iterator::collect` can transform a `Vec<Result>` into a `Result<Vec>`.
-            .map(|handle| handle.response_receiver().recv_timeout(RECV_TIMEOUT_SEC))
+            .map(|handle| handle.recv_response(RECV_TIMEOUT_SEC))
             .collect::<std::result::Result<Vec<VcpuResponse>, RecvTimeoutError>>()
             .map_err(|_| RestoreVcpusError::UnexpectedVcpuResponse)?;
 
@@ -614,6 +760,18 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Returns the I/O counters of the block device with `drive_id` id.
+    pub fn block_device_stats(&self, drive_id: &str) -> Result<BlockDeviceStats> {
+        let mut stats = BlockDeviceStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_BLOCK, drive_id, |block: &mut Block| {
+                stats = block.stats();
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
     /// Updates the rate limiter parameters for net device with `net_id` id.
     pub fn update_net_rate_limiters(
         &mut self,
@@ -631,6 +789,34 @@ impl Vmm {
             .map_err(Error::DeviceManager)
     }
 
+    /// Returns the packet counters of the net device with `net_id` id.
+    pub fn net_interface_stats(&self, net_id: &str) -> Result<NetDeviceStats> {
+        let mut stats = NetDeviceStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(TYPE_NET, net_id, |net: &mut Net| {
+                stats = net.stats();
+                Ok(())
+            })
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
+    /// Returns the connection counters of the vsock device.
+    pub fn vsock_stats(&self) -> Result<VsockStats> {
+        let mut stats = VsockStats::default();
+        self.mmio_device_manager
+            .with_virtio_device_with_id(
+                TYPE_VSOCK,
+                VSOCK_DEV_ID,
+                |vsock: &mut Vsock<VsockUnixBackend>| {
+                    stats = vsock.backend().stats();
+                    Ok(())
+                },
+            )
+            .map_err(Error::DeviceManager)?;
+        Ok(stats)
+    }
+
     /// Returns a reference to the balloon device if present.
     pub fn balloon_config(&self) -> std::result::Result<BalloonConfig, BalloonError> {
         if let Some(busdev) = self.get_bus_device(DeviceType::Virtio(TYPE_BALLOON), BALLOON_DEV_ID)
@@ -891,6 +1077,17 @@ impl MutEventSubscriber for Vmm {
             }
             self.stop(exit_code.unwrap_or(FcExitCode::Ok));
         } else {
+            #[cfg(target_arch = "x86_64")]
+            if source == self.vcpus_debug_evt.as_raw_fd() && event_set == EventSet::IN {
+                let _ = self.vcpus_debug_evt.read();
+                for event in self.vcpus_debug_events.try_iter() {
+                    info!(
+                        "vCPU {} single-stepped, cs: 0x{:x}, rip: 0x{:x}",
+                        event.index, event.cs, event.rip
+                    );
+                }
+                return;
+            }
             error!("Spurious EventManager event for handler: Vmm");
         }
     }
@@ -899,5 +1096,9 @@ impl MutEventSubscriber for Vmm {
         if let Err(err) = ops.add(Events::new(&self.vcpus_exit_evt, EventSet::IN)) {
             error!("Failed to register vmm exit event: {}", err);
         }
+        #[cfg(target_arch = "x86_64")]
+        if let Err(err) = ops.add(Events::new(&self.vcpus_debug_evt, EventSet::IN)) {
+            error!("Failed to register vcpus debug event: {}", err);
+        }
     }
 }
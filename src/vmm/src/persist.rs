@@ -5,7 +5,7 @@
 
 use std::fmt::{Display, Formatter};
 use std::fs::{File, OpenOptions};
-use std::io::{self, Write};
+use std::io::{self, Seek, Write};
 use std::os::unix::io::AsRawFd;
 use std::os::unix::net::UnixStream;
 use std::path::Path;
@@ -31,6 +31,7 @@ use crate::builder::{self, BuildMicrovmFromSnapshotError};
 use crate::device_manager::persist::{DeviceStates, Error as DevicePersistError};
 use crate::memory_snapshot::{GuestMemoryState, SnapshotMemory};
 use crate::resources::VmResources;
+use crate::snapshot_migration::{self, MigrationError};
 #[cfg(target_arch = "x86_64")]
 use crate::version_map::FC_V0_23_SNAP_VERSION;
 use crate::version_map::{FC_V1_0_SNAP_VERSION, FC_V1_1_SNAP_VERSION, FC_VERSION_TO_SNAP_VERSION};
@@ -66,6 +67,13 @@ pub struct VmInfo {
     /// Boot source information.
     #[version(start = 2, default_fn = "def_boot_source", ser_fn = "ser_boot_source")]
     pub boot_source: BootSourceConfig,
+    /// Whether the guest memory is locked in RAM.
+    #[version(
+        start = 2,
+        default_fn = "def_mlock_guest_memory",
+        ser_fn = "ser_mlock_guest_memory"
+    )]
+    pub mlock_guest_memory: bool,
 }
 
 impl VmInfo {
@@ -101,6 +109,20 @@ impl VmInfo {
         warn!("Saving to older snapshot version, boot source information will not be saved.");
         Ok(())
     }
+
+    fn def_mlock_guest_memory(_: u16) -> bool {
+        warn!("Guest memory locking information not found in snapshot.");
+        false
+    }
+
+    fn ser_mlock_guest_memory(&mut self, _target_version: u16) -> VersionizeResult<()> {
+        // v1.1 and older versions do not include guest memory locking info.
+        warn!(
+            "Saving to older snapshot version, guest memory locking information will not be \
+             saved."
+        );
+        Ok(())
+    }
 }
 
 /// Contains the necesary state for saving/restoring a microVM.
@@ -514,6 +536,9 @@ pub enum RestoreFromSnapshotError {
     /// Failed to build microVM from snapshot.
     #[error("Failed to build microVM from snapshot: {0}")]
     Build(#[from] BuildMicrovmFromSnapshotError),
+    /// Failed to migrate the snapshot state to the current version.
+    #[error("Failed to migrate the snapshot state: {0}")]
+    Migration(#[from] MigrationError),
 }
 /// Sub-Error type for [`restore_from_snapshot`] to contain either [`GuestMemoryFromFileError`] or
 /// [`GuestMemoryFromUffdError`] within [`RestoreFromSnapshotError`].
@@ -536,7 +561,11 @@ pub fn restore_from_snapshot(
     version_map: VersionMap,
     vm_resources: &mut VmResources,
 ) -> std::result::Result<Arc<Mutex<Vmm>>, RestoreFromSnapshotError> {
-    let microvm_state = snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    let latest_version = version_map.latest_version();
+    let (snapshot_version, mut microvm_state) =
+        snapshot_state_from_file(&params.snapshot_path, version_map)?;
+    // The snapshot file is left as is, only the loaded state is migrated.
+    snapshot_migration::migrate_loaded_state(&mut microvm_state, snapshot_version, latest_version)?;
 
     // Some sanity checks before building the microvm.
     snapshot_state_sanity_check(&microvm_state)?;
@@ -588,16 +617,68 @@ pub enum SnapshotStateFromFileError {
     Load(#[from] snapshot::Error),
 }
 
-fn snapshot_state_from_file(
+// Returns the data version of the snapshot along with the state.
+pub(crate) fn snapshot_state_from_file(
     snapshot_path: &Path,
     version_map: VersionMap,
-) -> std::result::Result<MicrovmState, SnapshotStateFromFileError> {
+) -> std::result::Result<(u16, MicrovmState), SnapshotStateFromFileError> {
     let mut snapshot_reader =
         File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
     let metadata = std::fs::metadata(snapshot_path).map_err(SnapshotStateFromFileError::Meta)?;
     let snapshot_len = metadata.len() as usize;
-    Snapshot::load(&mut snapshot_reader, snapshot_len, version_map)
-        .map_err(SnapshotStateFromFileError::Load)
+    let snapshot_version = Snapshot::get_data_version(&mut snapshot_reader, &version_map)?;
+    snapshot_reader
+        .rewind()
+        .map_err(SnapshotStateFromFileError::Meta)?;
+    let microvm_state = Snapshot::load(&mut snapshot_reader, snapshot_len, version_map)
+        .map_err(SnapshotStateFromFileError::Load)?;
+
+    Ok((snapshot_version, microvm_state))
+}
+
+/// The metadata of a snapshot, as read from the beginning of its microVM state file.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SnapshotMetadata {
+    /// The data version of the snapshot.
+    pub snapshot_version: u16,
+    /// The Firecracker version which created the snapshot, if the data version is known.
+    pub firecracker_version: Option<String>,
+    /// The guest memory size, in MiB.
+    pub mem_size_mib: u64,
+    /// Whether SMT is enabled.
+    pub smt: bool,
+    /// The CPU template of the microVM.
+    pub cpu_template: CpuFeaturesTemplate,
+}
+
+/// Reads the metadata of the snapshot at `snapshot_path`.
+///
+/// Only the snapshot header and the `VmInfo`, which comes first in the microVM state, are
+/// deserialized: the vCPU and device states aren't read, so the CRC of the file isn't checked
+/// either. The guest memory isn't loaded and no microVM is created, so this can be done at any
+/// time.
+pub fn snapshot_metadata(
+    snapshot_path: &Path,
+    version_map: VersionMap,
+) -> std::result::Result<SnapshotMetadata, SnapshotStateFromFileError> {
+    let mut snapshot_reader =
+        File::open(snapshot_path).map_err(SnapshotStateFromFileError::Open)?;
+    let snapshot_version = Snapshot::get_data_version(&mut snapshot_reader, &version_map)?;
+    snapshot_reader
+        .rewind()
+        .map_err(SnapshotStateFromFileError::Meta)?;
+    let vm_info: VmInfo = Snapshot::unchecked_load(&mut snapshot_reader, version_map)?;
+
+    Ok(SnapshotMetadata {
+        snapshot_version,
+        firecracker_version: FC_VERSION_TO_SNAP_VERSION
+            .iter()
+            .find(|(_, &version)| version == snapshot_version)
+            .map(|(fc_version, _)| fc_version.clone()),
+        mem_size_mib: vm_info.mem_size_mib,
+        smt: vm_info.smt,
+        cpu_template: vm_info.cpu_template,
+    })
 }
 
 /// Error type for [`guest_memory_from_file`].
@@ -799,6 +880,9 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         };
         insert_net_device(
             &mut vmm,
@@ -911,6 +995,65 @@ mod tests {
         assert!(get_snapshot_data_version(&Some("0.24.0".to_string()), &VERSION_MAP, &vmm).is_ok());
     }
 
+    #[test]
+    fn test_snapshot_metadata() {
+        let vmm = default_vmm_with_devices();
+        let vcpu_states = vec![VcpuState::default(), VcpuState::default()];
+        #[cfg(target_arch = "aarch64")]
+        let mpidrs = construct_kvm_mpidrs(&vcpu_states);
+        let microvm_state = MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states,
+            vm_info: VmInfo {
+                mem_size_mib: 128,
+                smt: true,
+                ..Default::default()
+            },
+            #[cfg(target_arch = "aarch64")]
+            vm_state: vmm.vm.save_state(&mpidrs).unwrap(),
+            #[cfg(target_arch = "x86_64")]
+            vm_state: vmm.vm.save_state().unwrap(),
+        };
+
+        let snapshot_file = TempFile::new().unwrap();
+        let snapshot_version = VERSION_MAP.latest_version();
+        Snapshot::new(VERSION_MAP.clone(), snapshot_version)
+            .save(&mut snapshot_file.as_file(), &microvm_state)
+            .unwrap();
+
+        let metadata = snapshot_metadata(snapshot_file.as_path(), VERSION_MAP.clone()).unwrap();
+        assert_eq!(metadata.snapshot_version, snapshot_version);
+        assert_eq!(
+            FC_VERSION_TO_SNAP_VERSION[metadata.firecracker_version.as_ref().unwrap()],
+            snapshot_version
+        );
+        assert_eq!(metadata.mem_size_mib, 128);
+        assert!(metadata.smt);
+        assert_eq!(metadata.cpu_template, CpuFeaturesTemplate::None);
+
+        // The states following the VM info aren't read.
+        let vm_info_file = TempFile::new().unwrap();
+        Snapshot::new(VERSION_MAP.clone(), snapshot_version)
+            .save_without_crc(&mut vm_info_file.as_file(), &microvm_state.vm_info)
+            .unwrap();
+        assert_eq!(
+            snapshot_metadata(vm_info_file.as_path(), VERSION_MAP.clone()).unwrap(),
+            metadata
+        );
+
+        // A truncated snapshot is rejected.
+        snapshot_file.as_file().set_len(16).unwrap();
+        assert!(matches!(
+            snapshot_metadata(snapshot_file.as_path(), VERSION_MAP.clone()),
+            Err(SnapshotStateFromFileError::Load(_))
+        ));
+        assert!(matches!(
+            snapshot_metadata(Path::new("/invalid/snapshot"), VERSION_MAP.clone()),
+            Err(SnapshotStateFromFileError::Open(_))
+        ));
+    }
+
     #[test]
     fn test_create_snapshot_error_display() {
         use vm_memory::GuestMemoryError;
@@ -13,12 +13,14 @@ use utils::net::ipv4addr::is_link_local_valid;
 use crate::device_manager::persist::SharedDeviceType;
 use crate::vmm_config::balloon::*;
 use crate::vmm_config::boot_source::{
-    BootConfig, BootSource, BootSourceConfig, BootSourceConfigError,
+    BootConfig, BootSource, BootSourceConfig, BootSourceConfigError, IOAPIC_EOI_BROADCAST_QUIRK,
 };
 use crate::vmm_config::drive::*;
 use crate::vmm_config::instance_info::InstanceInfo;
 use crate::vmm_config::logger::{init_logger, LoggerConfig, LoggerConfigError};
-use crate::vmm_config::machine_config::{VmConfig, VmConfigError, VmUpdateConfig};
+use crate::vmm_config::machine_config::{
+    HugePageMode, IrqChipMode, VmConfig, VmConfigError, VmUpdateConfig,
+};
 use crate::vmm_config::metrics::{init_metrics, MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::*;
@@ -243,6 +245,22 @@ impl VmResources {
         &self.vm_config
     }
 
+    /// Checks that the irqchip mode works around the quirks of the guest OS.
+    ///
+    /// The guests with `IOAPIC_EOI_BROADCAST_QUIRK` need the IOAPIC emulated in userspace, which
+    /// the user has to opt in, since the split irqchip has no PIC or PIT and can't be
+    /// snapshotted.
+    pub fn check_guest_os_irqchip_mode(&self) -> std::result::Result<(), VmConfigError> {
+        let guest_os = self.boot_source.config.guest_os;
+        if guest_os.quirks() & IOAPIC_EOI_BROADCAST_QUIRK != 0
+            && self.vm_config.irqchip_mode != IrqChipMode::Split
+        {
+            return Err(VmConfigError::InvalidIrqChipModeForGuestOs(guest_os));
+        }
+
+        Ok(())
+    }
+
     /// Update the machine configuration of the microVM.
     pub fn update_vm_config(
         &mut self,
@@ -288,7 +306,41 @@ impl VmResources {
             return Err(VmConfigError::IncompatibleBalloonSize);
         }
 
+        let mlock_guest_memory = machine_config
+            .mlock_guest_memory
+            .unwrap_or(self.vm_config.mlock_guest_memory);
+        if mlock_guest_memory {
+            // The balloon device can't reclaim locked memory.
+            if self.balloon.get().is_some() {
+                return Err(VmConfigError::MlockWithBalloon);
+            }
+            let required = (mem_size_mib as u64) << 20;
+            let limit = memlock_limit();
+            if limit != libc::RLIM_INFINITY && limit < required && !has_cap_ipc_lock() {
+                return Err(VmConfigError::MemlockLimitTooLow { required, limit });
+            }
+        }
+
+        let huge_pages = machine_config
+            .huge_pages
+            .unwrap_or(self.vm_config.huge_pages);
+        if let Some(huge_page_size) = huge_pages.huge_page_size() {
+            // The balloon device releases the guest memory in 4 KiB pages, which can't be
+            // given back to the host out of a huge page.
+            if self.balloon.get().is_some() {
+                return Err(VmConfigError::HugePagesWithBalloon);
+            }
+            let huge_page_size_mib = huge_page_size.size() >> 20;
+            if mem_size_mib % huge_page_size_mib != 0 {
+                return Err(VmConfigError::InvalidMemorySizeForHugePages(
+                    huge_page_size_mib,
+                ));
+            }
+        }
+
         self.vm_config.mem_size_mib = mem_size_mib;
+        self.vm_config.mlock_guest_memory = mlock_guest_memory;
+        self.vm_config.huge_pages = huge_pages;
 
         // Update the CPU template
         if let Some(cpu_template) = machine_config.cpu_template {
@@ -300,6 +352,11 @@ impl VmResources {
             self.vm_config.track_dirty_pages = track_dirty_pages;
         }
 
+        // Update the interrupt controller mode
+        if let Some(irqchip_mode) = machine_config.irqchip_mode {
+            self.vm_config.irqchip_mode = irqchip_mode;
+        }
+
         Ok(())
     }
 
@@ -361,6 +418,14 @@ impl VmResources {
         if config.amount_mib as usize > self.vm_config.mem_size_mib {
             return Err(BalloonConfigError::TooManyPagesRequested);
         }
+        // The balloon can't reclaim locked memory.
+        if self.vm_config.mlock_guest_memory {
+            return Err(BalloonConfigError::MlockedGuestMemory);
+        }
+        // Nor the memory backed by huge pages.
+        if self.vm_config.huge_pages != HugePageMode::None {
+            return Err(BalloonConfigError::HugePages);
+        }
 
         self.balloon.set(config)
     }
@@ -492,6 +557,38 @@ impl From<&VmResources> for VmmConfig {
     }
 }
 
+// Returns the soft `RLIMIT_MEMLOCK` of the process, in bytes.
+fn memlock_limit() -> u64 {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: Safe because `rlim` is a valid `rlimit` and the return value is checked.
+    match unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut rlim) } {
+        0 => rlim.rlim_cur,
+        // The limit can't be read, let `mlock` fail with a proper error when booting.
+        _ => libc::RLIM_INFINITY,
+    }
+}
+
+// Returns whether the process has `CAP_IPC_LOCK`, which lifts the `RLIMIT_MEMLOCK`.
+fn has_cap_ipc_lock() -> bool {
+    // See /usr/include/linux/capability.h .
+    const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+    const CAP_IPC_LOCK: u32 = 14;
+
+    // The `struct __user_cap_header_struct`, made of the version and the pid, 0 standing for
+    // the calling thread.
+    let mut header = [LINUX_CAPABILITY_VERSION_3, 0];
+    // Version 3 uses two `struct __user_cap_data_struct`, for the capabilities 0 to 31 and 32
+    // to 63, each made of the effective, permitted and inheritable sets.
+    let mut data = [[0u32; 3]; 2];
+    // SAFETY: Safe because `header` and `data` have the sizes expected by the version 3 of
+    // `capget`, which only writes within them, and the return value is checked.
+    let ret = unsafe { libc::syscall(libc::SYS_capget, header.as_mut_ptr(), data.as_mut_ptr()) };
+    ret == 0 && data[0][0] & (1 << CAP_IPC_LOCK) != 0
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
@@ -506,10 +603,12 @@ mod tests {
     use super::*;
     use crate::resources::VmResources;
     use crate::vmm_config::boot_source::{
-        BootConfig, BootSource, BootSourceConfig, DEFAULT_KERNEL_CMDLINE,
+        BootConfig, BootSource, BootSourceConfig, GuestOs, DEFAULT_KERNEL_CMDLINE,
     };
     use crate::vmm_config::drive::{BlockBuilder, BlockDeviceConfig, FileEngineType};
-    use crate::vmm_config::machine_config::{CpuFeaturesTemplate, VmConfig, VmConfigError};
+    use crate::vmm_config::machine_config::{
+        CpuFeaturesTemplate, HugePageMode, IrqChipMode, VmConfig, VmConfigError,
+    };
     use crate::vmm_config::net::{NetBuilder, NetworkInterfaceConfig};
     use crate::vmm_config::vsock::tests::default_config;
     use crate::vmm_config::RateLimiterConfig;
@@ -530,6 +629,9 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str("01:23:45:67:89:0a").unwrap()),
             rx_rate_limiter: Some(RateLimiterConfig::default()),
             tx_rate_limiter: Some(RateLimiterConfig::default()),
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         }
     }
 
@@ -1291,6 +1393,9 @@ mod tests {
             smt: Some(true),
             cpu_template: Some(CpuFeaturesTemplate::T2),
             track_dirty_pages: Some(false),
+            mlock_guest_memory: Some(false),
+            huge_pages: Some(HugePageMode::None),
+            irqchip_mode: Some(IrqChipMode::InKernel),
         };
 
         assert_ne!(
@@ -1341,6 +1446,52 @@ mod tests {
         // mem_size_mib compatible with balloon size.
         aux_vm_config.mem_size_mib = Some(256);
         assert!(vm_resources.update_vm_config(&aux_vm_config).is_ok());
+
+        // The guest memory of a microVM with a balloon device can't be locked.
+        aux_vm_config.mlock_guest_memory = Some(true);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::MlockWithBalloon)
+        );
+        assert!(!vm_resources.vm_config.mlock_guest_memory);
+        aux_vm_config.mlock_guest_memory = Some(false);
+
+        // Nor can it be backed by huge pages.
+        aux_vm_config.huge_pages = Some(HugePageMode::TwoMiB);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::HugePagesWithBalloon)
+        );
+        assert_eq!(vm_resources.vm_config.huge_pages, HugePageMode::None);
+        aux_vm_config.huge_pages = Some(HugePageMode::None);
+        aux_vm_config.mlock_guest_memory = Some(true);
+        vm_resources.balloon = BalloonBuilder::new();
+
+        // Locking the guest memory is only allowed within RLIMIT_MEMLOCK, unless the process
+        // has CAP_IPC_LOCK.
+        let limit = memlock_limit();
+        let expected = match limit {
+            libc::RLIM_INFINITY => Ok(()),
+            limit if limit >= 256 << 20 || has_cap_ipc_lock() => Ok(()),
+            limit => Err(VmConfigError::MemlockLimitTooLow {
+                required: 256 << 20,
+                limit,
+            }),
+        };
+        assert_eq!(vm_resources.update_vm_config(&aux_vm_config), expected);
+        assert_eq!(vm_resources.vm_config.mlock_guest_memory, expected.is_ok());
+        aux_vm_config.mlock_guest_memory = Some(false);
+
+        // The memory size must be a multiple of the huge page size.
+        aux_vm_config.huge_pages = Some(HugePageMode::OneGiB);
+        assert_eq!(
+            vm_resources.update_vm_config(&aux_vm_config),
+            Err(VmConfigError::InvalidMemorySizeForHugePages(1024))
+        );
+        assert_eq!(vm_resources.vm_config.huge_pages, HugePageMode::None);
+        aux_vm_config.huge_pages = Some(HugePageMode::TwoMiB);
+        assert!(vm_resources.update_vm_config(&aux_vm_config).is_ok());
+        assert_eq!(vm_resources.vm_config.huge_pages, HugePageMode::TwoMiB);
     }
 
     #[test]
@@ -1371,7 +1522,27 @@ mod tests {
         let mut vm_resources = default_vm_resources();
         vm_resources.balloon = BalloonBuilder::new();
         new_balloon_cfg.amount_mib = 256;
-        assert!(vm_resources.set_balloon_device(new_balloon_cfg).is_err());
+        assert!(vm_resources
+            .set_balloon_device(new_balloon_cfg.clone())
+            .is_err());
+
+        // The balloon device can't be used with a locked guest memory.
+        new_balloon_cfg.amount_mib = 100;
+        vm_resources.vm_config.mlock_guest_memory = true;
+        assert!(matches!(
+            vm_resources.set_balloon_device(new_balloon_cfg.clone()),
+            Err(BalloonConfigError::MlockedGuestMemory)
+        ));
+        assert!(vm_resources.balloon.get().is_none());
+
+        // Nor with a guest memory backed by huge pages.
+        vm_resources.vm_config.mlock_guest_memory = false;
+        vm_resources.vm_config.huge_pages = HugePageMode::TwoMiB;
+        assert!(matches!(
+            vm_resources.set_balloon_device(new_balloon_cfg),
+            Err(BalloonConfigError::HugePages)
+        ));
+        assert!(vm_resources.balloon.get().is_none());
     }
 
     #[test]
@@ -1383,6 +1554,25 @@ mod tests {
         assert!(actual_boot_cfg == expected_boot_cfg);
     }
 
+    #[test]
+    fn test_check_guest_os_irqchip_mode() {
+        let mut vm_resources = default_vm_resources();
+        assert!(vm_resources.check_guest_os_irqchip_mode().is_ok());
+
+        // The Windows guests have to opt in the split irqchip, it's never picked for them.
+        vm_resources.boot_source.config.guest_os = GuestOs::Windows;
+        assert_eq!(
+            vm_resources.check_guest_os_irqchip_mode(),
+            Err(VmConfigError::InvalidIrqChipModeForGuestOs(
+                GuestOs::Windows
+            ))
+        );
+        assert_eq!(vm_resources.vm_config().irqchip_mode, IrqChipMode::InKernel);
+
+        vm_resources.vm_config.irqchip_mode = IrqChipMode::Split;
+        assert!(vm_resources.check_guest_os_irqchip_mode().is_ok());
+    }
+
     #[test]
     fn test_set_boot_source() {
         let tmp_file = TempFile::new().unwrap();
@@ -1391,6 +1581,7 @@ mod tests {
             kernel_image_path: String::from(tmp_file.as_path().to_str().unwrap()),
             initrd_path: Some(String::from(tmp_file.as_path().to_str().unwrap())),
             boot_args: Some(cmdline.to_string()),
+            guest_os: GuestOs::Linux,
         };
 
         let mut vm_resources = default_vm_resources();
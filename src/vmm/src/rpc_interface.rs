@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 use std::result;
 use std::sync::{Arc, Mutex, MutexGuard};
 
@@ -22,7 +23,10 @@ use super::{
     resources::VmResources, Vmm,
 };
 use crate::builder::StartMicrovmError;
-use crate::persist::{CreateSnapshotError, RestoreFromSnapshotError, VmInfo};
+use crate::persist::{
+    self, CreateSnapshotError, RestoreFromSnapshotError, SnapshotMetadata,
+    SnapshotStateFromFileError, VmInfo,
+};
 use crate::resources::VmmConfig;
 use crate::version_map::VERSION_MAP;
 use crate::vmm_config::balloon::{
@@ -30,17 +34,26 @@ use crate::vmm_config::balloon::{
     BalloonUpdateStatsConfig,
 };
 use crate::vmm_config::boot_source::{BootSourceConfig, BootSourceConfigError};
-use crate::vmm_config::drive::{BlockDeviceConfig, BlockDeviceUpdateConfig, DriveError};
-use crate::vmm_config::instance_info::InstanceInfo;
+use crate::vmm_config::drive::{
+    BlockDeviceConfig, BlockDeviceStats, BlockDeviceUpdateConfig, DriveError,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::hotplug::CpuHotPlugSupport;
+use crate::vmm_config::instance_info::VcpuSingleStepConfig;
+use crate::vmm_config::instance_info::{InstanceInfo, VcpuInfo};
 use crate::vmm_config::logger::{LoggerConfig, LoggerConfigError};
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::machine_config::IrqRoutingTable;
 use crate::vmm_config::machine_config::{VmConfig, VmConfigError, VmUpdateConfig};
 use crate::vmm_config::metrics::{MetricsConfig, MetricsConfigError};
 use crate::vmm_config::mmds::{MmdsConfig, MmdsConfigError};
 use crate::vmm_config::net::{
-    NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+    NetDeviceStats, NetworkInterfaceConfig, NetworkInterfaceError, NetworkInterfaceUpdateConfig,
+};
+use crate::vmm_config::snapshot::{
+    CreateSnapshotParams, LoadSnapshotParams, SnapshotType, VmStateChangeConfig,
 };
-use crate::vmm_config::snapshot::{CreateSnapshotParams, LoadSnapshotParams, SnapshotType};
-use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig};
+use crate::vmm_config::vsock::{VsockConfigError, VsockDeviceConfig, VsockStats};
 use crate::vmm_config::{self, RateLimiterUpdate};
 use crate::{EventManager, FcExitCode};
 
@@ -64,16 +77,36 @@ pub enum VmmAction {
     GetBalloonConfig,
     /// Get the ballon device latest statistics.
     GetBalloonStats,
+    /// Get the I/O counters of the block device with the given id. This action can only be
+    /// called after the microVM has booted.
+    GetBlockDeviceStats(String),
+    /// Get whether the vCPUs of the microVM can be hot-plugged.
+    GetCpuHotPlugSupport,
     /// Get complete microVM configuration in JSON format.
     GetFullVmConfig,
+    /// Get the GSI routing table of the microVM. This action can only be called after the
+    /// microVM has booted.
+    #[cfg(target_arch = "x86_64")]
+    GetIrqRouting,
     /// Get MMDS contents.
     GetMMDS,
+    /// Get the packet counters of the network interface with the given id. This action can only
+    /// be called after the microVM has booted.
+    GetNetworkInterfaceStats(String),
+    /// Get the metadata of the snapshot at the given path, without loading it.
+    GetSnapshotMetadata(PathBuf),
     /// Get the machine configuration of the microVM.
     GetVmMachineConfig,
     /// Get microVM instance information.
     GetVmInstanceInfo,
+    /// Get the run state of each vCPU. This action can only be called after the microVM has
+    /// booted.
+    GetVcpus,
     /// Get microVM version.
     GetVmmVersion,
+    /// Get the connection counters of the vsock device. This action can only be called after the
+    /// microVM has booted.
+    GetVsockStats,
     /// Flush the metrics. This action can only be called after the logger has been configured.
     FlushMetrics,
     /// Add a new block device or update one that already exists using the `BlockDeviceConfig` as
@@ -89,18 +122,22 @@ pub enum VmmAction {
     LoadSnapshot(LoadSnapshotParams),
     /// Partial update of the MMDS contents.
     PatchMMDS(Value),
-    /// Pause the guest, by pausing the microVM VCPUs.
-    Pause,
+    /// Pause the guest, by pausing the microVM VCPUs. On timeout, the microVM keeps running.
+    Pause(VmStateChangeConfig),
     /// Repopulate the MMDS contents.
     PutMMDS(Value),
     /// Resume the guest, by resuming the microVM VCPUs.
-    Resume,
+    Resume(VmStateChangeConfig),
     /// Set the balloon device or update the one that already exists using the
     /// `BalloonDeviceConfig` as input. This action can only be called before the microVM
     /// has booted.
     SetBalloonDevice(BalloonDeviceConfig),
     /// Set the MMDS configuration.
     SetMmdsConfiguration(MmdsConfig),
+    /// Enable or disable the single-step mode of a vCPU using the `VcpuSingleStepConfig` as
+    /// input. This action can only be called after the microVM has booted.
+    #[cfg(target_arch = "x86_64")]
+    SetVcpuSingleStep(VcpuSingleStepConfig),
     /// Set the vsock device or update the one that already exists using the
     /// `VsockDeviceConfig` as input. This action can only be called before the microVM has
     /// booted.
@@ -164,6 +201,8 @@ pub enum VmmActionError {
     OperationNotSupportedPostBoot,
     /// The requested operation is not supported before starting the microVM.
     OperationNotSupportedPreBoot,
+    /// The action `GetSnapshotMetadata` failed.
+    SnapshotMetadata(SnapshotStateFromFileError),
     /// The action `StartMicroVm` failed because of an internal error.
     StartMicrovm(StartMicrovmError),
     /// The action `SetVsockDevice` failed because of bad user input.
@@ -200,6 +239,7 @@ impl Display for VmmActionError {
                     "The requested operation is not supported before starting the microVM."
                         .to_string()
                 }
+                SnapshotMetadata(err) => format!("Cannot read the snapshot metadata: {}", err),
                 StartMicrovm(err) => err.to_string(),
                 // The action `SetVsockDevice` failed because of bad user input.
                 VsockConfig(err) => err.to_string(),
@@ -216,18 +256,33 @@ pub enum VmmData {
     BalloonConfig(BalloonDeviceConfig),
     /// The latest balloon device statistics.
     BalloonStats(BalloonStats),
+    /// The I/O counters of a block device.
+    BlockDeviceStats(BlockDeviceStats),
+    /// Whether the vCPUs can be hot-plugged.
+    CpuHotPlugSupport(CpuHotPlugSupport),
     /// No data is sent on the channel.
     Empty,
     /// The complete microVM configuration in JSON format.
     FullVmConfig(VmmConfig),
+    /// The GSI routing table of the microVM.
+    #[cfg(target_arch = "x86_64")]
+    IrqRouting(IrqRoutingTable),
     /// The microVM configuration represented by `VmConfig`.
     MachineConfiguration(VmConfig),
     /// Mmds contents.
     MmdsValue(serde_json::Value),
+    /// The packet counters of a network interface.
+    NetworkInterfaceStats(NetDeviceStats),
+    /// The metadata of a snapshot.
+    SnapshotMetadata(SnapshotMetadata),
     /// The microVM instance information.
     InstanceInformation(InstanceInfo),
+    /// The run state of each vCPU.
+    VcpusInfo(Vec<VcpuInfo>),
     /// The microVM version.
     VmmVersion(String),
+    /// The connection counters of the vsock device.
+    VsockStats(VsockStats),
 }
 
 /// Shorthand result type for external VMM commands.
@@ -268,6 +323,13 @@ trait MmdsRequestHandler {
     }
 }
 
+// Reading the metadata of a snapshot doesn't depend on the state of the microVM.
+fn get_snapshot_metadata(snapshot_path: &Path) -> ActionResult {
+    persist::snapshot_metadata(snapshot_path, VERSION_MAP.clone())
+        .map(VmmData::SnapshotMetadata)
+        .map_err(VmmActionError::SnapshotMetadata)
+}
+
 /// Enables pre-boot setup and instantiation of a Firecracker VMM.
 pub struct PrebootApiController<'a> {
     seccomp_filters: &'a BpfThreadMap,
@@ -408,6 +470,7 @@ impl<'a> PrebootApiController<'a> {
                 .map(|()| VmmData::Empty)
                 .map_err(VmmActionError::Metrics),
             GetBalloonConfig => self.balloon_config(),
+            GetCpuHotPlugSupport => Ok(VmmData::CpuHotPlugSupport(CpuHotPlugSupport::current())),
             GetFullVmConfig => {
                 warn!(
                     "If the VM was restored from snapshot, boot-source, machine-config.smt, and \
@@ -416,6 +479,7 @@ impl<'a> PrebootApiController<'a> {
                 Ok(VmmData::FullVmConfig((&*self.vm_resources).into()))
             }
             GetMMDS => self.get_mmds(),
+            GetSnapshotMetadata(snapshot_path) => get_snapshot_metadata(&snapshot_path),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
@@ -436,15 +500,21 @@ impl<'a> PrebootApiController<'a> {
             // Operations not allowed pre-boot.
             CreateSnapshot(_)
             | FlushMetrics
-            | Pause
-            | Resume
+            | Pause(_)
+            | Resume(_)
             | GetBalloonStats
+            | GetBlockDeviceStats(_)
+            | GetNetworkInterfaceStats(_)
+            | GetVcpus
+            | GetVsockStats
             | UpdateBalloon(_)
             | UpdateBalloonStatistics(_)
             | UpdateBlockDevice(_)
             | UpdateNetworkInterface(_) => Err(VmmActionError::OperationNotSupportedPreBoot),
             #[cfg(target_arch = "x86_64")]
-            SendCtrlAltDel => Err(VmmActionError::OperationNotSupportedPreBoot),
+            GetIrqRouting | SendCtrlAltDel | SetVcpuSingleStep(_) => {
+                Err(VmmActionError::OperationNotSupportedPreBoot)
+            }
         }
     }
 
@@ -625,23 +695,64 @@ impl RuntimeApiController {
                 .latest_balloon_stats()
                 .map(VmmData::BalloonStats)
                 .map_err(|err| VmmActionError::BalloonConfig(BalloonConfigError::from(err))),
+            GetBlockDeviceStats(drive_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .block_device_stats(&drive_id)
+                .map(VmmData::BlockDeviceStats)
+                .map_err(DriveError::DeviceStats)
+                .map_err(VmmActionError::DriveConfig),
+            GetCpuHotPlugSupport => Ok(VmmData::CpuHotPlugSupport(CpuHotPlugSupport::current())),
             GetFullVmConfig => Ok(VmmData::FullVmConfig((&self.vm_resources).into())),
+            #[cfg(target_arch = "x86_64")]
+            GetIrqRouting => Ok(VmmData::IrqRouting(
+                self.vmm.lock().expect("Poisoned lock").irq_routing(),
+            )),
             GetMMDS => self.get_mmds(),
+            GetNetworkInterfaceStats(iface_id) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .net_interface_stats(&iface_id)
+                .map(VmmData::NetworkInterfaceStats)
+                .map_err(NetworkInterfaceError::DeviceStats)
+                .map_err(VmmActionError::NetworkConfig),
+            GetSnapshotMetadata(snapshot_path) => get_snapshot_metadata(&snapshot_path),
             GetVmMachineConfig => Ok(VmmData::MachineConfiguration(
                 self.vm_resources.vm_config().clone(),
             )),
             GetVmInstanceInfo => Ok(VmmData::InstanceInformation(
                 self.vmm.lock().expect("Poisoned lock").instance_info(),
             )),
+            GetVcpus => Ok(VmmData::VcpusInfo(
+                self.vmm.lock().expect("Poisoned lock").vcpus_info(),
+            )),
             GetVmmVersion => Ok(VmmData::VmmVersion(
                 self.vmm.lock().expect("Poisoned lock").version(),
             )),
+            GetVsockStats => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .vsock_stats()
+                .map(VmmData::VsockStats)
+                .map_err(VsockConfigError::DeviceStats)
+                .map_err(VmmActionError::VsockConfig),
             PatchMMDS(value) => self.patch_mmds(value),
-            Pause => self.pause(),
+            Pause(config) => self.pause(config),
             PutMMDS(value) => self.put_mmds(value),
-            Resume => self.resume(),
+            Resume(config) => self.resume(config),
             #[cfg(target_arch = "x86_64")]
             SendCtrlAltDel => self.send_ctrl_alt_del(),
+            #[cfg(target_arch = "x86_64")]
+            SetVcpuSingleStep(config) => self
+                .vmm
+                .lock()
+                .expect("Poisoned lock")
+                .set_single_step(config.vcpu_id, config.enabled)
+                .map(|()| VmmData::Empty)
+                .map_err(VmmActionError::InternalVmm),
             UpdateBalloon(balloon_update) => self
                 .vmm
                 .lock()
@@ -680,10 +791,13 @@ impl RuntimeApiController {
     }
 
     /// Pauses the microVM by pausing the vCPUs.
-    pub fn pause(&mut self) -> ActionResult {
+    pub fn pause(&mut self, config: VmStateChangeConfig) -> ActionResult {
         let pause_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        self.vmm.lock().expect("Poisoned lock").pause_vm()?;
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .pause_vm_with_timeout(config.timeout())?;
 
         let elapsed_time_us =
             update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_pause_vm, pause_start_us);
@@ -693,10 +807,13 @@ impl RuntimeApiController {
     }
 
     /// Resumes the microVM by resuming the vCPUs.
-    pub fn resume(&mut self) -> ActionResult {
+    pub fn resume(&mut self, config: VmStateChangeConfig) -> ActionResult {
         let resume_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
-        self.vmm.lock().expect("Poisoned lock").resume_vm()?;
+        self.vmm
+            .lock()
+            .expect("Poisoned lock")
+            .resume_vm_with_timeout(config.timeout())?;
 
         let elapsed_time_us =
             update_metric_with_elapsed_time(&METRICS.latencies_us.vmm_resume_vm, resume_start_us);
@@ -749,6 +866,7 @@ impl RuntimeApiController {
             smt: vm_cfg.smt,
             cpu_template: vm_cfg.cpu_template,
             boot_source: self.vm_resources.boot_source_config().clone(),
+            mlock_guest_memory: vm_cfg.mlock_guest_memory,
         };
         let create_start_us = utils::time::get_time_us(utils::time::ClockType::Monotonic);
 
@@ -828,6 +946,7 @@ impl RuntimeApiController {
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
+    use std::time::Duration;
 
     use devices::virtio::balloon::{BalloonConfig, Error as BalloonError};
     use devices::virtio::VsockError;
@@ -837,7 +956,10 @@ mod tests {
     use super::*;
     use crate::vmm_config::balloon::BalloonBuilder;
     use crate::vmm_config::drive::{CacheType, FileEngineType};
+    use crate::vmm_config::instance_info::{VcpuExitReason, VcpuRunState};
     use crate::vmm_config::logger::LoggerLevel;
+    #[cfg(target_arch = "x86_64")]
+    use crate::vmm_config::machine_config::IrqChipMode;
     use crate::vmm_config::snapshot::{MemBackendConfig, MemBackendType};
     use crate::vmm_config::vsock::VsockBuilder;
     use crate::HTTP_MAX_PAYLOAD_SIZE;
@@ -863,6 +985,7 @@ mod tests {
                     | (NotSupported(_), NotSupported(_))
                     | (OperationNotSupportedPostBoot, OperationNotSupportedPostBoot)
                     | (OperationNotSupportedPreBoot, OperationNotSupportedPreBoot)
+                    | (SnapshotMetadata(_), SnapshotMetadata(_))
                     | (StartMicrovm(_), StartMicrovm(_))
                     | (VsockConfig(_), VsockConfig(_))
             )
@@ -923,6 +1046,9 @@ mod tests {
             self.vm_config.smt = machine_config.smt.unwrap();
             self.vm_config.cpu_template = machine_config.cpu_template.unwrap();
             self.vm_config.track_dirty_pages = machine_config.track_dirty_pages.unwrap();
+            self.vm_config.mlock_guest_memory = machine_config.mlock_guest_memory.unwrap();
+            self.vm_config.huge_pages = machine_config.huge_pages.unwrap();
+            self.vm_config.irqchip_mode = machine_config.irqchip_mode.unwrap();
 
             Ok(())
         }
@@ -1025,15 +1151,23 @@ mod tests {
     #[derive(Debug, Default, PartialEq, Eq)]
     pub struct MockVmm {
         pub balloon_config_called: bool,
+        pub block_device_stats_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub irq_routing_called: bool,
         pub latest_balloon_stats_called: bool,
+        pub net_interface_stats_called: bool,
         pub pause_called: bool,
         pub resume_called: bool,
         #[cfg(target_arch = "x86_64")]
         pub send_ctrl_alt_del_called: bool,
+        #[cfg(target_arch = "x86_64")]
+        pub set_single_step_called: bool,
         pub update_balloon_config_called: bool,
         pub update_balloon_stats_config_called: bool,
         pub update_block_device_path_called: bool,
         pub update_net_rate_limiters_called: bool,
+        pub vcpus_info_called: bool,
+        pub vsock_stats_called: bool,
         // when `true`, all self methods are forced to fail
         pub force_errors: bool,
     }
@@ -1047,10 +1181,18 @@ mod tests {
             Ok(())
         }
 
-        pub fn pause_vm(&mut self) -> Result<(), VmmError> {
+        pub fn resume_vm_with_timeout(&mut self, _: Duration) -> Result<(), VmmError> {
+            self.resume_vm()
+        }
+
+        pub fn pause_vm_with_timeout(&mut self, timeout: Duration) -> Result<(), VmmError> {
             if self.force_errors {
                 return Err(VmmError::VcpuPause);
             }
+            // Emulate a vCPU which never acknowledges the pause.
+            if timeout.is_zero() {
+                return Err(VmmError::VcpuPauseTimeout(vec![0]));
+            }
             self.pause_called = true;
             Ok(())
         }
@@ -1066,6 +1208,15 @@ mod tests {
             Ok(())
         }
 
+        #[cfg(target_arch = "x86_64")]
+        pub fn set_single_step(&mut self, vcpu_id: u8, _enabled: bool) -> Result<(), VmmError> {
+            if self.force_errors {
+                return Err(VmmError::VcpuId(vcpu_id));
+            }
+            self.set_single_step_called = true;
+            Ok(())
+        }
+
         pub fn balloon_config(&mut self) -> Result<BalloonConfig, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1074,6 +1225,25 @@ mod tests {
             Ok(BalloonConfig::default())
         }
 
+        pub fn block_device_stats(&mut self, _: &str) -> Result<BlockDeviceStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.block_device_stats_called = true;
+            Ok(BlockDeviceStats::default())
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        pub fn irq_routing(&mut self) -> IrqRoutingTable {
+            self.irq_routing_called = true;
+            IrqRoutingTable {
+                irqchip_mode: IrqChipMode::InKernel,
+                routes: Vec::new(),
+            }
+        }
+
         pub fn latest_balloon_stats(&mut self) -> Result<BalloonStats, BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1082,6 +1252,16 @@ mod tests {
             Ok(BalloonStats::default())
         }
 
+        pub fn net_interface_stats(&mut self, _: &str) -> Result<NetDeviceStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.net_interface_stats_called = true;
+            Ok(NetDeviceStats::default())
+        }
+
         pub fn update_balloon_config(&mut self, _: u32) -> Result<(), BalloonError> {
             if self.force_errors {
                 return Err(BalloonError::DeviceNotFound);
@@ -1138,9 +1318,28 @@ mod tests {
             InstanceInfo::default()
         }
 
+        pub fn vcpus_info(&mut self) -> Vec<VcpuInfo> {
+            self.vcpus_info_called = true;
+            vec![VcpuInfo {
+                id: 0,
+                state: VcpuRunState::Running,
+                exit_reason: Some(VcpuExitReason::IoOut),
+            }]
+        }
+
         pub fn version(&self) -> String {
             String::default()
         }
+
+        pub fn vsock_stats(&mut self) -> Result<VsockStats, VmmError> {
+            if self.force_errors {
+                return Err(VmmError::DeviceManager(
+                    crate::device_manager::mmio::Error::DeviceNotFound,
+                ));
+            }
+            self.vsock_stats_called = true;
+            Ok(VsockStats::default())
+        }
     }
 
     // Need to redefine this since the non-test one uses real VmResources
@@ -1265,6 +1464,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_preboot_get_cpu_hot_plug_support() {
+        let req = VmmAction::GetCpuHotPlugSupport;
+        check_preboot_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::CpuHotPlugSupport(CpuHotPlugSupport::current()))
+            )
+        });
+    }
+
     #[test]
     fn test_preboot_get_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1346,6 +1556,9 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         });
         check_preboot_request(req, |result, vm_res| {
             assert_eq!(result, Ok(VmmData::Empty));
@@ -1358,6 +1571,9 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         });
         check_preboot_request_err(
             req,
@@ -1685,17 +1901,25 @@ mod tests {
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::Pause,
+            VmmAction::Pause(VmStateChangeConfig::default()),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
-            VmmAction::Resume,
+            VmmAction::Resume(VmStateChangeConfig::default()),
             VmmActionError::OperationNotSupportedPreBoot,
         );
         check_preboot_request_err(
             VmmAction::GetBalloonStats,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetBlockDeviceStats(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetNetworkInterfaceStats(String::new()),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
         check_preboot_request_err(
             VmmAction::UpdateBalloon(BalloonUpdateConfig { amount_mib: 0 }),
             VmmActionError::OperationNotSupportedPreBoot,
@@ -1732,6 +1956,27 @@ mod tests {
             VmmAction::SendCtrlAltDel,
             VmmActionError::OperationNotSupportedPreBoot,
         );
+        check_preboot_request_err(
+            VmmAction::GetVcpus,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        check_preboot_request_err(
+            VmmAction::GetVsockStats,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::GetIrqRouting,
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
+        #[cfg(target_arch = "x86_64")]
+        check_preboot_request_err(
+            VmmAction::SetVcpuSingleStep(VcpuSingleStepConfig {
+                vcpu_id: 0,
+                enabled: true,
+            }),
+            VmmActionError::OperationNotSupportedPreBoot,
+        );
     }
 
     #[test]
@@ -1744,8 +1989,8 @@ mod tests {
             cmd_step.fetch_add(1, Ordering::SeqCst);
             match cmd_step.load(Ordering::SeqCst) {
                 1 => VmmAction::FlushMetrics,
-                2 => VmmAction::Pause,
-                3 => VmmAction::Resume,
+                2 => VmmAction::Pause(VmStateChangeConfig::default()),
+                3 => VmmAction::Resume(VmStateChangeConfig::default()),
                 4 => VmmAction::StartMicroVm,
                 _ => unreachable!(),
             }
@@ -1837,27 +2082,83 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_runtime_get_cpu_hot_plug_support() {
+        let req = VmmAction::GetCpuHotPlugSupport;
+        check_runtime_request(req, |result, _| {
+            assert_eq!(
+                result,
+                Ok(VmmData::CpuHotPlugSupport(CpuHotPlugSupport::current()))
+            )
+        });
+    }
+
+    #[test]
+    fn test_runtime_get_vcpus() {
+        let req = VmmAction::GetVcpus;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::VcpusInfo(vec![VcpuInfo {
+                    id: 0,
+                    state: VcpuRunState::Running,
+                    exit_reason: Some(VcpuExitReason::IoOut),
+                }]))
+            );
+            assert!(vmm.vcpus_info_called)
+        });
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_get_irq_routing() {
+        let req = VmmAction::GetIrqRouting;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::IrqRouting(IrqRoutingTable {
+                    irqchip_mode: IrqChipMode::InKernel,
+                    routes: Vec::new(),
+                }))
+            );
+            assert!(vmm.irq_routing_called)
+        });
+    }
+
     #[test]
     fn test_runtime_pause() {
-        let req = VmmAction::Pause;
+        let req = VmmAction::Pause(VmStateChangeConfig::default());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
             assert!(vmm.pause_called)
         });
 
-        let req = VmmAction::Pause;
+        let req = VmmAction::Pause(VmStateChangeConfig::default());
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuPause));
+
+        let req = VmmAction::Pause(VmStateChangeConfig {
+            timeout_ms: Some(0),
+        });
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Err(VmmActionError::InternalVmm(VmmError::VcpuPauseTimeout(
+                    vec![0]
+                )))
+            );
+            assert!(!vmm.pause_called)
+        });
     }
 
     #[test]
     fn test_runtime_resume() {
-        let req = VmmAction::Resume;
+        let req = VmmAction::Resume(VmStateChangeConfig::default());
         check_runtime_request(req, |result, vmm| {
             assert_eq!(result, Ok(VmmData::Empty));
             assert!(vmm.resume_called)
         });
 
-        let req = VmmAction::Resume;
+        let req = VmmAction::Resume(VmStateChangeConfig::default());
         check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuResume));
     }
 
@@ -1879,6 +2180,23 @@ mod tests {
         );
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_runtime_set_vcpu_single_step() {
+        let config = VcpuSingleStepConfig {
+            vcpu_id: 1,
+            enabled: true,
+        };
+        let req = VmmAction::SetVcpuSingleStep(config);
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::Empty));
+            assert!(vmm.set_single_step_called)
+        });
+
+        let req = VmmAction::SetVcpuSingleStep(config);
+        check_runtime_request_err(req, VmmActionError::InternalVmm(VmmError::VcpuId(1)));
+    }
+
     #[test]
     fn test_runtime_balloon_config() {
         let req = VmmAction::GetBalloonConfig;
@@ -1994,6 +2312,82 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_runtime_block_device_stats() {
+        let req = VmmAction::GetBlockDeviceStats(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::BlockDeviceStats(BlockDeviceStats::default()))
+            );
+            assert!(vmm.block_device_stats_called)
+        });
+
+        let req = VmmAction::GetBlockDeviceStats(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::DriveConfig(DriveError::DeviceStats(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_runtime_vsock_stats() {
+        let req = VmmAction::GetVsockStats;
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(result, Ok(VmmData::VsockStats(VsockStats::default())));
+            assert!(vmm.vsock_stats_called)
+        });
+
+        let req = VmmAction::GetVsockStats;
+        check_runtime_request_err(
+            req,
+            VmmActionError::VsockConfig(VsockConfigError::DeviceStats(VmmError::DeviceManager(
+                crate::device_manager::mmio::Error::DeviceNotFound,
+            ))),
+        );
+    }
+
+    #[test]
+    fn test_snapshot_metadata() {
+        // The snapshot is read in the same way before and after booting the microVM.
+        let req = VmmAction::GetSnapshotMetadata(PathBuf::from("/invalid/snapshot"));
+        check_preboot_request_err(
+            req,
+            VmmActionError::SnapshotMetadata(SnapshotStateFromFileError::Open(
+                std::io::Error::from_raw_os_error(libc::ENOENT),
+            )),
+        );
+        let req = VmmAction::GetSnapshotMetadata(PathBuf::from("/invalid/snapshot"));
+        check_runtime_request_err(
+            req,
+            VmmActionError::SnapshotMetadata(SnapshotStateFromFileError::Open(
+                std::io::Error::from_raw_os_error(libc::ENOENT),
+            )),
+        );
+    }
+
+    #[test]
+    fn test_runtime_net_interface_stats() {
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
+        check_runtime_request(req, |result, vmm| {
+            assert_eq!(
+                result,
+                Ok(VmmData::NetworkInterfaceStats(NetDeviceStats::default()))
+            );
+            assert!(vmm.net_interface_stats_called)
+        });
+
+        let req = VmmAction::GetNetworkInterfaceStats(String::new());
+        check_runtime_request_err(
+            req,
+            VmmActionError::NetworkConfig(NetworkInterfaceError::DeviceStats(
+                VmmError::DeviceManager(crate::device_manager::mmio::Error::DeviceNotFound),
+            )),
+        );
+    }
+
     #[test]
     fn test_runtime_disallowed() {
         check_runtime_request_err(
@@ -2035,6 +2429,9 @@ mod tests {
                 guest_mac: None,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                stats_reset_on_read: false,
+                enable_ctrl_vq: false,
+                tx_budget_us: None,
             }),
             VmmActionError::OperationNotSupportedPostBoot,
         );
@@ -2137,6 +2534,9 @@ mod tests {
             guest_mac: None,
             rx_rate_limiter: None,
             tx_rate_limiter: None,
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         });
         verify_load_snap_disallowed_after_boot_resources(req, "InsertNetworkDevice");
 
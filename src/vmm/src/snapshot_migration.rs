@@ -0,0 +1,483 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Most changes of the microVM state format are handled by `versionize`, which fills the fields
+//! missing from older snapshots with their defaults. The migrations registered here are meant
+//! for the changes that can't be expressed that way, e.g. a field whose meaning changed. Like
+//! database schema migrations, they are applied in the order of the versions which introduced
+//! them.
+
+use std::ffi::OsString;
+use std::fmt::{self, Display, Formatter};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use snapshot::Snapshot;
+
+use crate::persist::{snapshot_state_from_file, MicrovmState, SnapshotStateFromFileError};
+use crate::version_map::{FC_VERSION_TO_SNAP_VERSION, VERSION_MAP};
+
+/// A Firecracker release version, e.g. `1.2.0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SemanticVersion {
+    /// The major version.
+    pub major: u16,
+    /// The minor version.
+    pub minor: u16,
+    /// The patch version.
+    pub patch: u16,
+}
+
+impl SemanticVersion {
+    /// Creates the `major.minor.patch` version.
+    pub fn new(major: u16, minor: u16, patch: u16) -> Self {
+        SemanticVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Returns the snapshot data version of the release.
+    ///
+    /// Patch releases don't change the snapshot format, so they share the data version of
+    /// `major.minor.0`.
+    pub fn snapshot_version(&self) -> Option<u16> {
+        FC_VERSION_TO_SNAP_VERSION
+            .get(&format!("{}.{}.0", self.major, self.minor))
+            .copied()
+    }
+
+    /// Returns the release which introduced the snapshot data version `snapshot_version`.
+    pub fn from_snapshot_version(snapshot_version: u16) -> Option<Self> {
+        FC_VERSION_TO_SNAP_VERSION
+            .iter()
+            .find(|(_, &version)| version == snapshot_version)
+            .and_then(|(fc_version, _)| fc_version.parse().ok())
+    }
+}
+
+impl Display for SemanticVersion {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for SemanticVersion {
+    type Err = MigrationError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let numbers = version
+            .split('.')
+            .map(u16::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| MigrationError::InvalidVersion(version.to_string()))?;
+
+        match numbers[..] {
+            [major, minor, patch] => Ok(SemanticVersion::new(major, minor, patch)),
+            _ => Err(MigrationError::InvalidVersion(version.to_string())),
+        }
+    }
+}
+
+/// Errors associated with the snapshot migrations.
+#[derive(Debug, thiserror::Error)]
+pub enum MigrationError {
+    /// The version is not formatted as `major.minor.patch`.
+    #[error("Invalid Firecracker version: {0}")]
+    InvalidVersion(String),
+    /// The release doesn't support snapshots.
+    #[error("Firecracker {0} has no snapshot data version.")]
+    UnsupportedVersion(SemanticVersion),
+    /// The snapshot data version doesn't belong to any release.
+    #[error("The snapshot data version {0} doesn't belong to any Firecracker release.")]
+    UnknownSnapshotVersion(u16),
+    /// Snapshots can only be migrated to newer versions.
+    #[error("Cannot migrate a snapshot from Firecracker {from} back to {to}.")]
+    Downgrade {
+        /// The version which saved the snapshot.
+        from: SemanticVersion,
+        /// The requested version.
+        to: SemanticVersion,
+    },
+    /// The snapshot wasn't saved by the expected version.
+    #[error("The snapshot data version is {actual}, expected {expected}.")]
+    VersionMismatch {
+        /// The data version of the version which supposedly saved the snapshot.
+        expected: u16,
+        /// The data version of the snapshot.
+        actual: u16,
+    },
+    /// Failed to read the snapshot.
+    #[error("Cannot read the snapshot: {0}")]
+    Read(#[from] SnapshotStateFromFileError),
+    /// Failed to save the migrated snapshot.
+    #[error("Cannot save the migrated snapshot: {0}")]
+    Save(snapshot::Error),
+    /// Failed to replace the snapshot file.
+    #[error("Cannot replace the snapshot file: {0}")]
+    Io(std::io::Error),
+    /// A migration function failed.
+    #[error("The migration to Firecracker {version} failed: {reason}")]
+    Failed {
+        /// The version the migration is registered for.
+        version: SemanticVersion,
+        /// The reason reported by the migration.
+        reason: String,
+    },
+}
+
+/// Function migrating the microVM state to the format of the version it is registered for.
+pub type MigrationFn = fn(&mut MicrovmState) -> Result<(), String>;
+
+/// Ordered set of snapshot migrations.
+#[derive(Debug, Default)]
+pub struct SnapshotMigrationRegistry {
+    migrations: Vec<(SemanticVersion, MigrationFn)>,
+}
+
+impl SnapshotMigrationRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a migration, applied to the snapshots saved before `version` when they are
+    /// migrated to `version`, or any later one.
+    ///
+    /// The migrations registered for the same version are applied in registration order.
+    pub fn register(&mut self, version: SemanticVersion, migration: MigrationFn) -> &mut Self {
+        let position = self
+            .migrations
+            .partition_point(|(registered, _)| *registered <= version);
+        self.migrations.insert(position, (version, migration));
+        self
+    }
+
+    /// Applies to `state` the migrations registered for the versions in
+    /// `(from_version, to_version]`, oldest first.
+    pub fn migrate_state(
+        &self,
+        state: &mut MicrovmState,
+        from_version: SemanticVersion,
+        to_version: SemanticVersion,
+    ) -> Result<(), MigrationError> {
+        if to_version < from_version {
+            return Err(MigrationError::Downgrade {
+                from: from_version,
+                to: to_version,
+            });
+        }
+
+        for (version, migration) in self
+            .migrations
+            .iter()
+            .filter(|(version, _)| from_version < *version && *version <= to_version)
+        {
+            migration(state).map_err(|reason| MigrationError::Failed {
+                version: *version,
+                reason,
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Migrates the snapshot at `path`, saved by `from_version`, so that it can be loaded by
+    /// `to_version`.
+    ///
+    /// The snapshot is saved with the data version of `to_version` into a temporary file, which
+    /// then replaces the original one, so a failed migration leaves the snapshot untouched.
+    pub fn migrate_snapshot(
+        &self,
+        path: &Path,
+        from_version: SemanticVersion,
+        to_version: SemanticVersion,
+    ) -> Result<(), MigrationError> {
+        if to_version < from_version {
+            return Err(MigrationError::Downgrade {
+                from: from_version,
+                to: to_version,
+            });
+        }
+        let expected_version = from_version
+            .snapshot_version()
+            .ok_or(MigrationError::UnsupportedVersion(from_version))?;
+        let target_version = to_version
+            .snapshot_version()
+            .ok_or(MigrationError::UnsupportedVersion(to_version))?;
+
+        let (snapshot_version, mut state) = snapshot_state_from_file(path, VERSION_MAP.clone())?;
+        if snapshot_version != expected_version {
+            return Err(MigrationError::VersionMismatch {
+                expected: expected_version,
+                actual: snapshot_version,
+            });
+        }
+        self.migrate_state(&mut state, from_version, to_version)?;
+
+        let tmp_path = migration_tmp_path(path);
+        let result = save_state(&tmp_path, target_version, &state)
+            .and_then(|()| fs::rename(&tmp_path, path).map_err(MigrationError::Io));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+}
+
+// Returns the path of the temporary file holding the migrated snapshot, next to the original
+// one so that it can be renamed over it.
+fn migration_tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = OsString::from(path.as_os_str());
+    tmp_path.push(".migrating");
+    PathBuf::from(tmp_path)
+}
+
+fn save_state(
+    path: &Path,
+    snapshot_version: u16,
+    state: &MicrovmState,
+) -> Result<(), MigrationError> {
+    let mut file = File::create(path).map_err(MigrationError::Io)?;
+    Snapshot::new(VERSION_MAP.clone(), snapshot_version)
+        .save(&mut file, state)
+        .map_err(MigrationError::Save)?;
+    file.sync_all().map_err(MigrationError::Io)
+}
+
+lazy_static! {
+    /// The migrations of the snapshots saved by older Firecracker versions.
+    ///
+    /// A migration needs to be registered here when a state change can't be handled by
+    /// `versionize` alone.
+    pub static ref SNAPSHOT_MIGRATIONS: SnapshotMigrationRegistry =
+        SnapshotMigrationRegistry::new();
+}
+
+/// Migrates the snapshot at `path`, saved by `from_version`, so that it can be loaded by
+/// `to_version`, using the registered `SNAPSHOT_MIGRATIONS`.
+pub fn migrate_snapshot(
+    path: &Path,
+    from_version: SemanticVersion,
+    to_version: SemanticVersion,
+) -> Result<(), MigrationError> {
+    SNAPSHOT_MIGRATIONS.migrate_snapshot(path, from_version, to_version)
+}
+
+/// Migrates the state of a snapshot with data version `snapshot_version`, which was just
+/// loaded, to the `latest_version` of this Firecracker.
+pub(crate) fn migrate_loaded_state(
+    state: &mut MicrovmState,
+    snapshot_version: u16,
+    latest_version: u16,
+) -> Result<(), MigrationError> {
+    if snapshot_version == latest_version {
+        return Ok(());
+    }
+
+    let from_version = SemanticVersion::from_snapshot_version(snapshot_version)
+        .ok_or(MigrationError::UnknownSnapshotVersion(snapshot_version))?;
+    let to_version = SemanticVersion::from_snapshot_version(latest_version)
+        .ok_or(MigrationError::UnknownSnapshotVersion(latest_version))?;
+    SNAPSHOT_MIGRATIONS.migrate_state(state, from_version, to_version)
+}
+
+#[cfg(test)]
+mod tests {
+    use snapshot::Persist;
+    use utils::tempfile::TempFile;
+
+    use super::*;
+    use crate::builder::tests::default_vmm;
+    #[cfg(target_arch = "aarch64")]
+    use crate::construct_kvm_mpidrs;
+    use crate::memory_snapshot::SnapshotMemory;
+    use crate::persist::VmInfo;
+    use crate::version_map::{FC_V1_1_SNAP_VERSION, FC_V1_2_SNAP_VERSION};
+
+    fn microvm_state() -> MicrovmState {
+        let vmm = default_vmm();
+        #[cfg(target_arch = "aarch64")]
+        let mpidrs = construct_kvm_mpidrs(&[]);
+        MicrovmState {
+            device_states: vmm.mmio_device_manager.save(),
+            memory_state: vmm.guest_memory().describe(),
+            vcpu_states: Vec::new(),
+            vm_info: VmInfo {
+                mem_size_mib: 128,
+                ..Default::default()
+            },
+            #[cfg(target_arch = "aarch64")]
+            vm_state: vmm.vm.save_state(&mpidrs).unwrap(),
+            #[cfg(target_arch = "x86_64")]
+            vm_state: vmm.vm.save_state().unwrap(),
+        }
+    }
+
+    fn double_mem_size(state: &mut MicrovmState) -> Result<(), String> {
+        state.vm_info.mem_size_mib *= 2;
+        Ok(())
+    }
+
+    fn increment_mem_size(state: &mut MicrovmState) -> Result<(), String> {
+        state.vm_info.mem_size_mib += 1;
+        Ok(())
+    }
+
+    fn failing_migration(_: &mut MicrovmState) -> Result<(), String> {
+        Err(String::from("unsupported state"))
+    }
+
+    #[test]
+    fn test_semantic_version() {
+        let version: SemanticVersion = "1.2.3".parse().unwrap();
+        assert_eq!(version, SemanticVersion::new(1, 2, 3));
+        assert_eq!(version.to_string(), "1.2.3");
+        assert!(SemanticVersion::new(1, 1, 9) < version);
+        assert!(SemanticVersion::new(0, 25, 0) < SemanticVersion::new(1, 0, 0));
+
+        for invalid in ["", "1.2", "1.2.3.4", "1.x.0", "-1.0.0"] {
+            assert!(matches!(
+                invalid.parse::<SemanticVersion>(),
+                Err(MigrationError::InvalidVersion(_))
+            ));
+        }
+
+        // Patch releases share the data version of their minor release.
+        assert_eq!(version.snapshot_version(), Some(FC_V1_2_SNAP_VERSION));
+        assert_eq!(SemanticVersion::new(0, 1, 0).snapshot_version(), None);
+        assert_eq!(
+            SemanticVersion::from_snapshot_version(FC_V1_1_SNAP_VERSION),
+            Some(SemanticVersion::new(1, 1, 0))
+        );
+        assert_eq!(SemanticVersion::from_snapshot_version(u16::MAX), None);
+    }
+
+    #[test]
+    fn test_migrate_state() {
+        let mut registry = SnapshotMigrationRegistry::new();
+        registry
+            .register(SemanticVersion::new(1, 2, 0), increment_mem_size)
+            .register(SemanticVersion::new(1, 1, 0), double_mem_size);
+        let mut state = microvm_state();
+
+        // The migrations are applied by version, not by registration order.
+        registry
+            .migrate_state(
+                &mut state,
+                SemanticVersion::new(1, 0, 0),
+                SemanticVersion::new(1, 2, 0),
+            )
+            .unwrap();
+        assert_eq!(state.vm_info.mem_size_mib, 257);
+
+        // Only the migrations of the versions newer than the source one are applied.
+        registry
+            .migrate_state(
+                &mut state,
+                SemanticVersion::new(1, 1, 0),
+                SemanticVersion::new(1, 2, 0),
+            )
+            .unwrap();
+        assert_eq!(state.vm_info.mem_size_mib, 258);
+        registry
+            .migrate_state(
+                &mut state,
+                SemanticVersion::new(1, 2, 0),
+                SemanticVersion::new(1, 2, 0),
+            )
+            .unwrap();
+        assert_eq!(state.vm_info.mem_size_mib, 258);
+
+        assert!(matches!(
+            registry.migrate_state(
+                &mut state,
+                SemanticVersion::new(1, 2, 0),
+                SemanticVersion::new(1, 1, 0),
+            ),
+            Err(MigrationError::Downgrade { .. })
+        ));
+
+        registry.register(SemanticVersion::new(1, 2, 0), failing_migration);
+        match registry.migrate_state(
+            &mut state,
+            SemanticVersion::new(1, 1, 0),
+            SemanticVersion::new(1, 2, 0),
+        ) {
+            Err(MigrationError::Failed { version, reason }) => {
+                assert_eq!(version, SemanticVersion::new(1, 2, 0));
+                assert_eq!(reason, "unsupported state");
+            }
+            other => panic!("Unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_migrate_snapshot() {
+        let mut registry = SnapshotMigrationRegistry::new();
+        registry.register(SemanticVersion::new(1, 2, 0), double_mem_size);
+        let (v1_1, v1_2) = (SemanticVersion::new(1, 1, 0), SemanticVersion::new(1, 2, 0));
+
+        let snapshot_file = TempFile::new().unwrap();
+        Snapshot::new(VERSION_MAP.clone(), FC_V1_1_SNAP_VERSION)
+            .save(&mut snapshot_file.as_file(), &microvm_state())
+            .unwrap();
+
+        // The source version must match the snapshot.
+        assert!(matches!(
+            registry.migrate_snapshot(snapshot_file.as_path(), v1_2, v1_2),
+            Err(MigrationError::VersionMismatch {
+                expected: FC_V1_2_SNAP_VERSION,
+                actual: FC_V1_1_SNAP_VERSION,
+            })
+        ));
+        assert!(matches!(
+            registry.migrate_snapshot(snapshot_file.as_path(), SemanticVersion::new(0, 1, 0), v1_2),
+            Err(MigrationError::UnsupportedVersion(_))
+        ));
+
+        registry
+            .migrate_snapshot(snapshot_file.as_path(), v1_1, v1_2)
+            .unwrap();
+        let (snapshot_version, state) =
+            snapshot_state_from_file(snapshot_file.as_path(), VERSION_MAP.clone()).unwrap();
+        assert_eq!(snapshot_version, FC_V1_2_SNAP_VERSION);
+        assert_eq!(state.vm_info.mem_size_mib, 256);
+        assert!(!migration_tmp_path(snapshot_file.as_path()).exists());
+
+        // A failed migration leaves the snapshot untouched.
+        registry.register(SemanticVersion::new(1, 2, 0), failing_migration);
+        // The original file was replaced, so it has to be opened again.
+        Snapshot::new(VERSION_MAP.clone(), FC_V1_1_SNAP_VERSION)
+            .save(
+                &mut File::create(snapshot_file.as_path()).unwrap(),
+                &microvm_state(),
+            )
+            .unwrap();
+        assert!(matches!(
+            registry.migrate_snapshot(snapshot_file.as_path(), v1_1, v1_2),
+            Err(MigrationError::Failed { .. })
+        ));
+        let (snapshot_version, state) =
+            snapshot_state_from_file(snapshot_file.as_path(), VERSION_MAP.clone()).unwrap();
+        assert_eq!(snapshot_version, FC_V1_1_SNAP_VERSION);
+        assert_eq!(state.vm_info.mem_size_mib, 128);
+    }
+
+    #[test]
+    fn test_migrate_loaded_state() {
+        let mut state = microvm_state();
+        let latest_version = VERSION_MAP.latest_version();
+
+        assert!(migrate_loaded_state(&mut state, latest_version, latest_version).is_ok());
+        assert!(migrate_loaded_state(&mut state, FC_V1_1_SNAP_VERSION, latest_version).is_ok());
+        assert!(matches!(
+            migrate_loaded_state(&mut state, u16::MAX, latest_version),
+            Err(MigrationError::UnknownSnapshotVersion(u16::MAX))
+        ));
+        assert_eq!(state.vm_info.mem_size_mib, 128);
+    }
+}
@@ -5,7 +5,7 @@
 use std::path::PathBuf;
 
 use crate::resources::VmResources;
-use crate::vmm_config::boot_source::BootSourceConfig;
+use crate::vmm_config::boot_source::{BootSourceConfig, GuestOs};
 use crate::vmm_config::machine_config::{VmConfig, VmUpdateConfig};
 
 pub const DEFAULT_BOOT_ARGS: &str = "reboot=k panic=1 pci=off";
@@ -43,6 +43,7 @@ impl MockBootSourceConfig {
             kernel_image_path: kernel_image_path(None),
             initrd_path: None,
             boot_args: None,
+            guest_os: GuestOs::Linux,
         })
     }
 
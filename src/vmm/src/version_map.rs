@@ -6,12 +6,14 @@
 use std::collections::HashMap;
 
 use devices::virtio::block::persist::BlockState;
+use devices::virtio::net::persist::NetState;
 use devices::virtio::QueueState;
 use lazy_static::lazy_static;
 use versionize::{VersionMap, Versionize};
 
 use crate::device_manager::persist::DeviceStates;
 use crate::persist::VmInfo;
+use crate::vmm_config::boot_source::BootSourceConfig;
 #[cfg(target_arch = "x86_64")]
 use crate::vstate::vcpu::VcpuState;
 
@@ -53,6 +55,8 @@ lazy_static! {
 
         // v1.2 state change mappings.
         version_map.new_version().set_type_version(VmInfo::type_id(), 2);
+        version_map.set_type_version(BootSourceConfig::type_id(), 2);
+        version_map.set_type_version(NetState::type_id(), 2);
         #[cfg(target_arch = "x86_64")]
         version_map.set_type_version(VcpuState::type_id(), 3);
 
@@ -29,6 +29,11 @@ pub enum BalloonConfigError {
     CreateFailure(devices::virtio::balloon::Error),
     /// Failed to update the configuration of the ballon device.
     UpdateFailure(std::io::Error),
+    /// The user tried to configure a balloon device while the guest memory is locked.
+    MlockedGuestMemory,
+    /// The user tried to configure a balloon device while the guest memory is backed by huge
+    /// pages.
+    HugePages,
 }
 
 impl fmt::Display for BalloonConfigError {
@@ -49,6 +54,14 @@ impl fmt::Display for BalloonConfigError {
                 "Error updating the balloon device configuration: {:?}",
                 err
             ),
+            MlockedGuestMemory => write!(
+                f,
+                "The balloon device can't be used when the guest memory is locked."
+            ),
+            HugePages => write!(
+                f,
+                "The balloon device can't be used when the guest memory is backed by huge pages."
+            ),
         }
     }
 }
@@ -228,6 +241,12 @@ pub(crate) mod tests {
 
         let err = StatsNotFound;
         let _ = format!("{}{:?}", err, err);
+
+        let err = MlockedGuestMemory;
+        let _ = format!("{}{:?}", err, err);
+
+        let err = HugePages;
+        let _ = format!("{}{:?}", err, err);
     }
 
     #[test]
@@ -22,6 +22,42 @@ use versionize_derive::Versionize;
 pub const DEFAULT_KERNEL_CMDLINE: &str = "reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0 \
                                           i8042.noaux i8042.nomux i8042.nopnp i8042.dumbkbd";
 
+/// The guest works around the IOAPIC not handling directed EOIs by broadcasting an EOI from
+/// the local APIC for each level triggered interrupt, and expects the IOAPIC to deliver the
+/// interrupt again when the line is still asserted. The in-kernel IOAPIC of KVM doesn't
+/// handle these broadcasts the way the guest expects, so the guest needs the IOAPIC emulated in
+/// userspace, see `IrqChipMode::Split`.
+pub const IOAPIC_EOI_BROADCAST_QUIRK: u32 = 1 << 0;
+
+/// The operating system running in the guest, used to work around its quirks.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, Versionize)]
+pub enum GuestOs {
+    /// A Linux guest, which doesn't need any workaround.
+    Linux,
+    /// A Windows guest.
+    Windows,
+}
+
+impl GuestOs {
+    fn is_linux(&self) -> bool {
+        *self == GuestOs::Linux
+    }
+
+    /// Returns the quirks of the guest, as a mask of the `*_QUIRK` flags.
+    pub fn quirks(&self) -> u32 {
+        match self {
+            GuestOs::Linux => 0,
+            GuestOs::Windows => IOAPIC_EOI_BROADCAST_QUIRK,
+        }
+    }
+}
+
+impl Default for GuestOs {
+    fn default() -> Self {
+        GuestOs::Linux
+    }
+}
+
 /// Strongly typed data structure used to configure the boot source of the
 /// microvm.
 #[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize, Versionize)]
@@ -35,6 +71,16 @@ pub struct BootSourceConfig {
     /// kernel command line is used: `reboot=k panic=1 pci=off nomodules 8250.nr_uarts=0`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub boot_args: Option<String>,
+    /// The operating system of the guest, used to work around its quirks.
+    #[serde(default, skip_serializing_if = "GuestOs::is_linux")]
+    #[version(start = 2, default_fn = "def_guest_os")]
+    pub guest_os: GuestOs,
+}
+
+impl BootSourceConfig {
+    fn def_guest_os(_: u16) -> GuestOs {
+        GuestOs::Linux
+    }
 }
 
 /// Errors associated with actions on `BootSourceConfig`.
@@ -129,6 +175,7 @@ pub(crate) mod tests {
             boot_args: None,
             initrd_path: None,
             kernel_image_path: kernel_path,
+            guest_os: GuestOs::Linux,
         };
 
         let boot_cfg = BootConfig::new(&boot_src_cfg).unwrap();
@@ -138,4 +185,27 @@ pub(crate) mod tests {
             [DEFAULT_KERNEL_CMDLINE.as_bytes(), &[b'\0']].concat()
         );
     }
+
+    #[test]
+    fn test_guest_os() {
+        let config: BootSourceConfig =
+            serde_json::from_str(r#"{"kernel_image_path": "vmlinux"}"#).unwrap();
+        assert_eq!(config.guest_os, GuestOs::Linux);
+        assert_eq!(config.guest_os.quirks(), 0);
+        assert!(!serde_json::to_string(&config).unwrap().contains("guest_os"));
+
+        let config: BootSourceConfig =
+            serde_json::from_str(r#"{"kernel_image_path": "vmlinux", "guest_os": "Windows"}"#)
+                .unwrap();
+        assert_eq!(config.guest_os, GuestOs::Windows);
+        assert_eq!(
+            config.guest_os.quirks() & IOAPIC_EOI_BROADCAST_QUIRK,
+            IOAPIC_EOI_BROADCAST_QUIRK
+        );
+
+        assert!(serde_json::from_str::<BootSourceConfig>(
+            r#"{"kernel_image_path": "vmlinux", "guest_os": "Plan9"}"#
+        )
+        .is_err());
+    }
 }
@@ -10,6 +10,7 @@ use std::sync::{Arc, Mutex};
 use std::{io, result};
 
 pub use devices::virtio::block::device::FileEngineType;
+pub use devices::virtio::block::BlockDeviceStats;
 use devices::virtio::block::Error as BlockError;
 use devices::virtio::Block;
 pub use devices::virtio::CacheType;
@@ -30,6 +31,8 @@ pub enum DriveError {
     CreateBlockDevice(BlockError),
     /// Failed to create a `RateLimiter` object.
     CreateRateLimiter(io::Error),
+    /// Error while getting the statistics of the block device.
+    DeviceStats(VmmError),
     /// Error during drive update (patch).
     DeviceUpdate(VmmError),
     /// The block device path is invalid.
@@ -47,6 +50,7 @@ impl Display for DriveError {
             CreateBlockDevice(err) => write!(f, "Unable to create the block device {:?}", err),
             BlockDeviceUpdateFailed(err) => write!(f, "The update operation failed: {}", err),
             CreateRateLimiter(err) => write!(f, "Cannot create RateLimiter: {}", err),
+            DeviceStats(err) => write!(f, "Error while getting the drive statistics: {}", err),
             DeviceUpdate(err) => write!(f, "Error during drive update (patch): {}", err),
             InvalidBlockDevicePath(path) => write!(f, "Invalid block device path: {}", path),
             OpenBlockDevice(err) => write!(
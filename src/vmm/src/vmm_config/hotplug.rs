@@ -0,0 +1,60 @@
+// Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+
+use serde::{Deserialize, Serialize};
+
+/// The configuration of a CPU hot-add request.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HotPlugCpuConfig {
+    /// The number of vCPUs to add to the running microVM.
+    pub additional_vcpu_count: u8,
+}
+
+/// Whether the vCPUs of a running microVM can be hot-plugged.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CpuHotPlugSupport {
+    /// Whether CPU hot-plug is supported.
+    pub supported: bool,
+    /// Why CPU hot-plug is not supported, if it isn't.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl CpuHotPlugSupport {
+    /// Returns the CPU hot-plug support of this Firecracker build.
+    ///
+    /// CPU hot-plug is not implemented yet, only the API contract is defined.
+    pub fn current() -> Self {
+        CpuHotPlugSupport {
+            supported: false,
+            reason: Some("not implemented".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_plug_cpu_config() {
+        let config: HotPlugCpuConfig =
+            serde_json::from_str(r#"{"additional_vcpu_count": 2}"#).unwrap();
+        assert_eq!(
+            config,
+            HotPlugCpuConfig {
+                additional_vcpu_count: 2
+            }
+        );
+        assert!(serde_json::from_str::<HotPlugCpuConfig>(r#"{"vcpu_count": 2}"#).is_err());
+    }
+
+    #[test]
+    fn test_cpu_hot_plug_support() {
+        assert_eq!(
+            serde_json::to_value(CpuHotPlugSupport::current()).unwrap(),
+            serde_json::json!({ "supported": false, "reason": "not implemented" })
+        );
+    }
+}
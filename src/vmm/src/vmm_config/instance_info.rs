@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 use std::fmt::{self, Display, Formatter};
 
-use serde::{ser, Serialize};
+use serde::{ser, Deserialize, Serialize};
 
 /// Enumerates microVM runtime states.
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -52,3 +52,65 @@ pub struct InstanceInfo {
     /// The name of the application that runs the microVM.
     pub app_name: String,
 }
+
+/// Enumerates the run states of a vCPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum VcpuRunState {
+    /// The vCPU is running guest code or handling an exit.
+    Running,
+    /// The vCPU stopped running, after the guest halted or an emulation error.
+    Halted,
+    /// The vCPU is paused.
+    Paused,
+}
+
+/// Enumerates the KVM exits handled by a vCPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum VcpuExitReason {
+    /// Guest MMIO read (KVM_EXIT_MMIO).
+    MmioRead,
+    /// Guest MMIO write (KVM_EXIT_MMIO).
+    MmioWrite,
+    /// Guest port I/O read (KVM_EXIT_IO).
+    IoIn,
+    /// Guest port I/O write (KVM_EXIT_IO).
+    IoOut,
+    /// The guest executed HLT (KVM_EXIT_HLT).
+    Hlt,
+    /// The guest triggered a shutdown (KVM_EXIT_SHUTDOWN).
+    Shutdown,
+    /// Hardware entry failure (KVM_EXIT_FAIL_ENTRY).
+    FailEntry,
+    /// KVM internal error (KVM_EXIT_INTERNAL_ERROR).
+    InternalError,
+    /// System event, such as a reset or a shutdown (KVM_EXIT_SYSTEM_EVENT).
+    SystemEvent,
+    /// Debug exit, such as a single step (KVM_EXIT_DEBUG).
+    Debug,
+    /// KVM_RUN was interrupted by a signal (KVM_EXIT_INTR).
+    Interrupted,
+    /// Any other exit.
+    Other,
+}
+
+/// Serializable struct that contains the run state of a vCPU.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct VcpuInfo {
+    /// The index of the vCPU.
+    pub id: u8,
+    /// Whether the vCPU is running/halted/paused.
+    pub state: VcpuRunState,
+    /// The last exit handled by the vCPU, if it ran at all.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_reason: Option<VcpuExitReason>,
+}
+
+/// The single-step mode configuration of a vCPU.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VcpuSingleStepConfig {
+    /// The index of the vCPU.
+    pub vcpu_id: u8,
+    /// Whether the vCPU exits after each guest instruction.
+    pub enabled: bool,
+}
@@ -5,6 +5,9 @@ use std::fmt;
 use serde::{de, Deserialize, Serialize};
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
+use vm_memory::HugePageSize;
+
+use crate::vmm_config::boot_source::GuestOs;
 
 /// The default memory size of the VM, in MiB.
 pub const DEFAULT_MEM_SIZE_MIB: usize = 128;
@@ -15,16 +18,32 @@ pub const MAX_SUPPORTED_VCPUS: u8 = 32;
 /// Errors associated with configuring the microVM.
 #[derive(Debug, PartialEq, Eq)]
 pub enum VmConfigError {
+    /// The guest memory can't be backed by huge pages when a balloon device is configured.
+    HugePagesWithBalloon,
     /// The memory size is smaller than the target size set in the balloon device configuration.
     IncompatibleBalloonSize,
+    /// The guest needs the IOAPIC EOI broadcast workaround, which requires a split irqchip.
+    InvalidIrqChipModeForGuestOs(GuestOs),
     /// The memory size is invalid. The memory can only be an unsigned integer.
     InvalidMemorySize,
+    /// The memory size is not a multiple of the size of the huge pages backing it, in MiB.
+    InvalidMemorySizeForHugePages(usize),
     /// The vcpu count is invalid. When SMT is enabled, the `cpu_count` must be either
     /// 1 or an even number.
     InvalidVcpuCount,
     /// Could not get the config of the balloon device from the VM resources, even though a
     /// balloon device was previously installed.
     InvalidVmState,
+    /// The `RLIMIT_MEMLOCK` of the process is lower than the guest memory size, in bytes, and
+    /// the process doesn't have `CAP_IPC_LOCK`, so the guest memory can't be locked.
+    MemlockLimitTooLow {
+        /// The size of the guest memory.
+        required: u64,
+        /// The soft `RLIMIT_MEMLOCK` of the process.
+        limit: u64,
+    },
+    /// The guest memory can't be locked when a balloon device is configured.
+    MlockWithBalloon,
 }
 impl std::error::Error for VmConfigError {}
 
@@ -32,12 +51,27 @@ impl fmt::Display for VmConfigError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::VmConfigError::*;
         match *self {
+            HugePagesWithBalloon => write!(
+                f,
+                "Cannot back the guest memory of a microVM with a balloon device with huge pages."
+            ),
             IncompatibleBalloonSize => write!(
                 f,
                 "The memory size (MiB) is smaller than the previously set balloon device target \
                  size.",
             ),
+            InvalidIrqChipModeForGuestOs(guest_os) => write!(
+                f,
+                "The {:?} guests need the Split irqchip mode, for the IOAPIC EOI broadcast \
+                 workaround.",
+                guest_os
+            ),
             InvalidMemorySize => write!(f, "The memory size (MiB) is invalid.",),
+            InvalidMemorySizeForHugePages(huge_page_size_mib) => write!(
+                f,
+                "The memory size (MiB) must be a multiple of the huge page size ({} MiB).",
+                huge_page_size_mib
+            ),
             InvalidVcpuCount => write!(
                 f,
                 "The vCPU number is invalid! The vCPU number can only be 1 or an even number when \
@@ -48,6 +82,16 @@ impl fmt::Display for VmConfigError {
                 "Could not get the configuration of the previously installed balloon device to \
                  validate the memory size.",
             ),
+            MemlockLimitTooLow { required, limit } => write!(
+                f,
+                "Cannot lock the guest memory: the RLIMIT_MEMLOCK of the process ({} bytes) is \
+                 lower than the guest memory size ({} bytes).",
+                limit, required
+            ),
+            MlockWithBalloon => write!(
+                f,
+                "Cannot lock the guest memory of a microVM with a balloon device."
+            ),
         }
     }
 }
@@ -75,6 +119,19 @@ pub struct VmConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(default)]
     pub track_dirty_pages: bool,
+    /// Locks the guest memory in RAM, so that it can't be swapped out.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mlock_guest_memory: bool,
+    /// The size of the huge pages backing the guest memory, if any.
+    #[serde(default, skip_serializing_if = "HugePageMode::is_none")]
+    pub huge_pages: HugePageMode,
+    /// Selects whether KVM emulates the whole interrupt controller or only the local APICs.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_irqchip_mode",
+        skip_serializing_if = "IrqChipMode::is_in_kernel"
+    )]
+    pub irqchip_mode: IrqChipMode,
 }
 
 impl Default for VmConfig {
@@ -85,6 +142,9 @@ impl Default for VmConfig {
             smt: false,
             cpu_template: CpuFeaturesTemplate::None,
             track_dirty_pages: false,
+            mlock_guest_memory: false,
+            huge_pages: HugePageMode::None,
+            irqchip_mode: IrqChipMode::InKernel,
         }
     }
 }
@@ -94,8 +154,16 @@ impl fmt::Display for VmConfig {
         write!(
             f,
             "{{ \"vcpu_count\": {:?}, \"mem_size_mib\": {:?}, \"smt\": {:?}, \"cpu_template\": \
-             {:?}, \"track_dirty_pages\": {:?} }}",
-            self.vcpu_count, self.mem_size_mib, self.smt, self.cpu_template, self.track_dirty_pages
+             {:?}, \"track_dirty_pages\": {:?}, \"mlock_guest_memory\": {:?}, \
+             \"huge_pages\": {:?}, \"irqchip_mode\": {:?} }}",
+            self.vcpu_count,
+            self.mem_size_mib,
+            self.smt,
+            self.cpu_template,
+            self.track_dirty_pages,
+            self.mlock_guest_memory,
+            self.huge_pages,
+            self.irqchip_mode
         )
     }
 }
@@ -135,6 +203,19 @@ pub struct VmUpdateConfig {
     /// Enables or disables dirty page tracking. Enabling allows incremental snapshots.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub track_dirty_pages: Option<bool>,
+    /// Locks the guest memory in RAM, so that it can't be swapped out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mlock_guest_memory: Option<bool>,
+    /// The size of the huge pages backing the guest memory, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub huge_pages: Option<HugePageMode>,
+    /// Selects whether KVM emulates the whole interrupt controller or only the local APICs.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "deserialize_irqchip_mode"
+    )]
+    pub irqchip_mode: Option<IrqChipMode>,
 }
 
 impl VmUpdateConfig {
@@ -147,6 +228,9 @@ impl VmUpdateConfig {
             && self.cpu_template.is_none()
             && self.smt.is_none()
             && self.track_dirty_pages.is_none()
+            && self.mlock_guest_memory.is_none()
+            && self.huge_pages.is_none()
+            && self.irqchip_mode.is_none()
         {
             return true;
         }
@@ -163,6 +247,9 @@ impl From<VmConfig> for VmUpdateConfig {
             smt: Some(cfg.smt),
             cpu_template: Some(cfg.cpu_template),
             track_dirty_pages: Some(cfg.track_dirty_pages),
+            mlock_guest_memory: Some(cfg.mlock_guest_memory),
+            huge_pages: Some(cfg.huge_pages),
+            irqchip_mode: Some(cfg.irqchip_mode),
         }
     }
 }
@@ -236,6 +323,28 @@ where
     T::deserialize(_d)
 }
 
+/// Deserialization function for the `irqchip_mode` field in `VmConfig` and `VmUpdateConfig`.
+/// This is called only when `irqchip_mode` is present in the JSON configuration.
+fn deserialize_irqchip_mode<'de, D, T>(d: D) -> std::result::Result<T, D::Error>
+where
+    D: de::Deserializer<'de>,
+    T: Deserialize<'de> + PartialEq + From<IrqChipMode>,
+{
+    let val = T::deserialize(d)?;
+
+    // The split irqchip relies on the x86 IOAPIC emulation, so on aarch64 the only
+    // accepted value is `InKernel`.
+    #[cfg(target_arch = "aarch64")]
+    if val == T::from(IrqChipMode::Split) {
+        return Err(de::Error::invalid_value(
+            de::Unexpected::Other("irqchip_mode"),
+            &"The split irqchip is not supported on aarch64",
+        ));
+    }
+
+    Ok(val)
+}
+
 /// Template types available for configuring the CPU features that map
 /// to EC2 instances.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize, Versionize)]
@@ -248,6 +357,11 @@ pub enum CpuFeaturesTemplate {
     T2S,
     /// No CPU template is used.
     None,
+    // The snapshots store the index of the variant, so the new ones go last.
+    /// T2A Template, for AMD hosts.
+    T2A,
+    /// T2CL Template.
+    T2CL,
 }
 
 /// Utility methods for handling CPU template types
@@ -264,6 +378,8 @@ impl fmt::Display for CpuFeaturesTemplate {
             CpuFeaturesTemplate::T2 => write!(f, "T2"),
             CpuFeaturesTemplate::T2S => write!(f, "T2S"),
             CpuFeaturesTemplate::None => write!(f, "None"),
+            CpuFeaturesTemplate::T2A => write!(f, "T2A"),
+            CpuFeaturesTemplate::T2CL => write!(f, "T2CL"),
         }
     }
 }
@@ -274,6 +390,121 @@ impl Default for CpuFeaturesTemplate {
     }
 }
 
+/// The way the interrupt controller of the microVM is emulated.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum IrqChipMode {
+    /// KVM emulates the PIC, the IOAPIC and the local APICs.
+    InKernel,
+    /// KVM only emulates the local APICs, and Firecracker emulates the IOAPIC.
+    /// There is no PIC or PIT in this mode.
+    Split,
+}
+
+impl IrqChipMode {
+    fn is_in_kernel(&self) -> bool {
+        *self == IrqChipMode::InKernel
+    }
+}
+
+impl fmt::Display for IrqChipMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IrqChipMode::InKernel => write!(f, "InKernel"),
+            IrqChipMode::Split => write!(f, "Split"),
+        }
+    }
+}
+
+impl Default for IrqChipMode {
+    fn default() -> Self {
+        IrqChipMode::InKernel
+    }
+}
+
+/// The size of the huge pages backing the guest memory.
+///
+/// The huge pages are taken from the pool of the host, so it must have enough of them for the
+/// whole guest memory, e.g. through `/proc/sys/vm/nr_hugepages` for the default 2 MiB size.
+/// Otherwise, the guest memory falls back to regular pages.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum HugePageMode {
+    /// The guest memory is backed by regular pages.
+    None,
+    /// The guest memory is backed by 2 MiB huge pages.
+    TwoMiB,
+    /// The guest memory is backed by 1 GiB huge pages.
+    OneGiB,
+}
+
+impl HugePageMode {
+    fn is_none(&self) -> bool {
+        *self == HugePageMode::None
+    }
+
+    /// Returns the size of the huge pages, or `None` for regular pages.
+    pub fn huge_page_size(self) -> Option<HugePageSize> {
+        match self {
+            HugePageMode::None => None,
+            HugePageMode::TwoMiB => Some(HugePageSize::TwoMiB),
+            HugePageMode::OneGiB => Some(HugePageSize::OneGiB),
+        }
+    }
+}
+
+impl fmt::Display for HugePageMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HugePageMode::None => write!(f, "None"),
+            HugePageMode::TwoMiB => write!(f, "TwoMiB"),
+            HugePageMode::OneGiB => write!(f, "OneGiB"),
+        }
+    }
+}
+
+impl Default for HugePageMode {
+    fn default() -> Self {
+        HugePageMode::None
+    }
+}
+
+/// A route of the GSI routing table of the microVM.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum IrqRoute {
+    /// The GSI is routed to a pin of the in-kernel PICs.
+    Pic {
+        /// The routed GSI.
+        gsi: u32,
+        /// The pin of the cascaded PICs, between 0 and 15.
+        pin: u32,
+    },
+    /// The GSI is routed to a pin of the in-kernel IOAPIC.
+    Ioapic {
+        /// The routed GSI.
+        gsi: u32,
+        /// The pin of the IOAPIC.
+        pin: u32,
+    },
+    /// The GSI is delivered as an MSI, as programmed in the userspace IOAPIC.
+    Msi {
+        /// The routed GSI.
+        gsi: u32,
+        /// The address of the message.
+        address: u64,
+        /// The data of the message.
+        data: u32,
+    },
+}
+
+/// The GSI routing table of the microVM.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct IrqRoutingTable {
+    /// The way the interrupt controller is emulated.
+    pub irqchip_mode: IrqChipMode,
+    /// The active routes. A GSI can have more than one route, and masked GSIs have none.
+    pub routes: Vec<IrqRoute>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -283,6 +514,75 @@ mod tests {
         assert_eq!(CpuFeaturesTemplate::C3.to_string(), "C3".to_string());
         assert_eq!(CpuFeaturesTemplate::T2.to_string(), "T2".to_string());
         assert_eq!(CpuFeaturesTemplate::T2S.to_string(), "T2S".to_string());
+        assert_eq!(CpuFeaturesTemplate::T2A.to_string(), "T2A".to_string());
+        assert_eq!(CpuFeaturesTemplate::T2CL.to_string(), "T2CL".to_string());
+    }
+
+    #[test]
+    fn test_deserialize_cpu_features_template() {
+        let template: CpuFeaturesTemplate = serde_json::from_str("\"T2A\"").unwrap();
+        assert_eq!(template, CpuFeaturesTemplate::T2A);
+        let template: CpuFeaturesTemplate = serde_json::from_str("\"T2CL\"").unwrap();
+        assert_eq!(template, CpuFeaturesTemplate::T2CL);
+        assert_eq!(
+            serde_json::to_string(&CpuFeaturesTemplate::T2CL).unwrap(),
+            "\"T2CL\""
+        );
+    }
+
+    #[test]
+    fn test_irqchip_mode() {
+        assert_eq!(IrqChipMode::default(), IrqChipMode::InKernel);
+        assert_eq!(IrqChipMode::InKernel.to_string(), "InKernel");
+        assert_eq!(IrqChipMode::Split.to_string(), "Split");
+
+        let config: VmConfig =
+            serde_json::from_str(r#"{"vcpu_count": 2, "mem_size_mib": 256}"#).unwrap();
+        assert_eq!(config.irqchip_mode, IrqChipMode::InKernel);
+        // The default mode is not serialized, to keep the output of older versions.
+        assert!(!serde_json::to_string(&config)
+            .unwrap()
+            .contains("irqchip_mode"));
+
+        let update = serde_json::from_str::<VmUpdateConfig>(r#"{"irqchip_mode": "Split"}"#);
+        #[cfg(target_arch = "x86_64")]
+        {
+            let update = update.unwrap();
+            assert!(!update.is_empty());
+            assert_eq!(update.irqchip_mode, Some(IrqChipMode::Split));
+        }
+        #[cfg(target_arch = "aarch64")]
+        assert!(update.is_err());
+        assert!(
+            serde_json::from_str::<VmUpdateConfig>(r#"{"irqchip_mode": "Userspace"}"#).is_err()
+        );
+    }
+
+    #[test]
+    fn test_huge_page_mode() {
+        assert_eq!(HugePageMode::default(), HugePageMode::None);
+        assert_eq!(HugePageMode::None.huge_page_size(), None);
+        assert_eq!(
+            HugePageMode::TwoMiB.huge_page_size(),
+            Some(HugePageSize::TwoMiB)
+        );
+        assert_eq!(
+            HugePageMode::OneGiB.huge_page_size(),
+            Some(HugePageSize::OneGiB)
+        );
+        assert_eq!(HugePageMode::OneGiB.to_string(), "OneGiB");
+
+        let config: VmConfig =
+            serde_json::from_str(r#"{"vcpu_count": 2, "mem_size_mib": 256}"#).unwrap();
+        assert_eq!(config.huge_pages, HugePageMode::None);
+        assert!(!serde_json::to_string(&config)
+            .unwrap()
+            .contains("huge_pages"));
+
+        let update: VmUpdateConfig = serde_json::from_str(r#"{"huge_pages": "TwoMiB"}"#).unwrap();
+        assert!(!update.is_empty());
+        assert_eq!(update.huge_pages, Some(HugePageMode::TwoMiB));
+        assert!(serde_json::from_str::<VmUpdateConfig>(r#"{"huge_pages": "4KiB"}"#).is_err());
     }
 
     #[test]
@@ -293,5 +593,37 @@ mod tests {
 
         let expected_str = "The memory size (MiB) is invalid.";
         assert_eq!(VmConfigError::InvalidMemorySize.to_string(), expected_str);
+
+        let expected_str = "The memory size (MiB) must be a multiple of the huge page size (1024 \
+                            MiB).";
+        assert_eq!(
+            VmConfigError::InvalidMemorySizeForHugePages(1024).to_string(),
+            expected_str
+        );
+
+        let expected_str = "Cannot lock the guest memory: the RLIMIT_MEMLOCK of the process \
+                            (65536 bytes) is lower than the guest memory size (134217728 bytes).";
+        let err = VmConfigError::MemlockLimitTooLow {
+            required: 128 << 20,
+            limit: 65536,
+        };
+        assert_eq!(err.to_string(), expected_str);
+
+        let expected_str = "Cannot lock the guest memory of a microVM with a balloon device.";
+        assert_eq!(VmConfigError::MlockWithBalloon.to_string(), expected_str);
+
+        let expected_str =
+            "Cannot back the guest memory of a microVM with a balloon device with huge pages.";
+        assert_eq!(
+            VmConfigError::HugePagesWithBalloon.to_string(),
+            expected_str
+        );
+
+        let expected_str = "The Windows guests need the Split irqchip mode, for the IOAPIC EOI \
+                            broadcast workaround.";
+        assert_eq!(
+            VmConfigError::InvalidIrqChipModeForGuestOs(GuestOs::Windows).to_string(),
+            expected_str
+        );
     }
 }
@@ -17,6 +17,8 @@ pub mod balloon;
 pub mod boot_source;
 /// Wrapper for configuring the block devices.
 pub mod drive;
+/// Wrapper for the CPU hot-plug of the microVM.
+pub mod hotplug;
 /// Wrapper over the microVM general information attached to the microVM.
 pub mod instance_info;
 /// Wrapper for configuring the logger.
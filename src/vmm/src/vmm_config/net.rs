@@ -6,6 +6,7 @@ use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 use std::{fmt, result};
 
+pub use devices::virtio::net::NetDeviceStats;
 use devices::virtio::net::TapError;
 use devices::virtio::Net;
 use serde::{Deserialize, Serialize};
@@ -29,6 +30,15 @@ pub struct NetworkInterfaceConfig {
     pub rx_rate_limiter: Option<RateLimiterConfig>,
     /// Rate Limiter for transmitted packages.
     pub tx_rate_limiter: Option<RateLimiterConfig>,
+    /// Whether the packet counters of the interface are reset every time they are read.
+    #[serde(default)]
+    pub stats_reset_on_read: bool,
+    /// Whether the guest can change the MAC address of the interface through a control queue.
+    #[serde(default)]
+    pub enable_ctrl_vq: bool,
+    /// The maximum time, in microseconds, spent transmitting the frames of the guest in a
+    /// single event loop iteration. Unbounded if not set.
+    pub tx_budget_us: Option<u64>,
 }
 
 impl From<&Net> for NetworkInterfaceConfig {
@@ -41,6 +51,9 @@ impl From<&Net> for NetworkInterfaceConfig {
             guest_mac: Some(*net.guest_mac()),
             rx_rate_limiter: rx_rl.into_option(),
             tx_rate_limiter: tx_rl.into_option(),
+            stats_reset_on_read: net.stats_reset_on_read(),
+            enable_ctrl_vq: net.ctrl_vq_enabled(),
+            tx_budget_us: net.tx_budget_us(),
         }
     }
 }
@@ -71,6 +84,9 @@ pub enum NetworkInterfaceError {
     GuestMacAddressInUse(String),
     /// Error during interface update (patch).
     DeviceUpdate(VmmError),
+    /// Error while getting the interface statistics.
+    #[from(ignore)]
+    DeviceStats(VmmError),
     /// Cannot open/create tap device.
     OpenTap(TapError),
 }
@@ -85,6 +101,7 @@ impl fmt::Display for NetworkInterfaceError {
                 write!(f, "The guest MAC address {mac_addr} is already in use.")
             }
             DeviceUpdate(err) => write!(f, "Error during interface update (patch): {}", err),
+            DeviceStats(err) => write!(f, "Error while getting the interface statistics: {}", err),
             OpenTap(err) => {
                 // We are propagating the Tap Error. This error can contain
                 // imbricated quotes which would result in an invalid json.
@@ -179,14 +196,21 @@ impl NetBuilder {
             .transpose()?;
 
         // Create and return the Net device
-        devices::virtio::net::Net::new_with_tap(
+        let mut net = devices::virtio::net::Net::new_with_tap(
             cfg.iface_id,
             cfg.host_dev_name.clone(),
             cfg.guest_mac.as_ref(),
             rx_rate_limiter.unwrap_or_default(),
             tx_rate_limiter.unwrap_or_default(),
         )
-        .map_err(NetworkInterfaceError::CreateNetworkDevice)
+        .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        net.set_stats_reset_on_read(cfg.stats_reset_on_read);
+        net.set_tx_budget_us(cfg.tx_budget_us);
+        if cfg.enable_ctrl_vq {
+            net.enable_ctrl_vq()
+                .map_err(NetworkInterfaceError::CreateNetworkDevice)?;
+        }
+        Ok(net)
     }
 
     /// Returns a vec with the structures used to configure the net devices.
@@ -224,6 +248,9 @@ mod tests {
             guest_mac: Some(MacAddr::parse_str(mac).unwrap()),
             rx_rate_limiter: RateLimiterConfig::default().into_option(),
             tx_rate_limiter: RateLimiterConfig::default().into_option(),
+            stats_reset_on_read: false,
+            enable_ctrl_vq: false,
+            tx_budget_us: None,
         }
     }
 
@@ -235,6 +262,9 @@ mod tests {
                 guest_mac: self.guest_mac,
                 rx_rate_limiter: None,
                 tx_rate_limiter: None,
+                stats_reset_on_read: self.stats_reset_on_read,
+                enable_ctrl_vq: self.enable_ctrl_vq,
+                tx_budget_us: self.tx_budget_us,
             }
         }
     }
@@ -354,7 +384,8 @@ mod tests {
         let host_dev_name = "dev";
         let guest_mac = "01:23:45:67:89:0b";
 
-        let net_if_cfg = create_netif(net_id, host_dev_name, guest_mac);
+        let mut net_if_cfg = create_netif(net_id, host_dev_name, guest_mac);
+        net_if_cfg.tx_budget_us = Some(500);
         assert_eq!(
             net_if_cfg.guest_mac.unwrap(),
             MacAddr::parse_str(guest_mac).unwrap()
@@ -4,6 +4,7 @@
 //! Configurations used in the snapshotting context.
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
@@ -109,6 +110,23 @@ pub enum VmState {
     Resumed,
 }
 
+/// The optional parameters of the pause and resume requests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct VmStateChangeConfig {
+    /// How long to wait for all the vCPUs to acknowledge the request, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl VmStateChangeConfig {
+    /// Returns the timeout of the request, `RECV_TIMEOUT_SEC` if none was given.
+    pub fn timeout(&self) -> Duration {
+        self.timeout_ms
+            .map_or(crate::RECV_TIMEOUT_SEC, Duration::from_millis)
+    }
+}
+
 /// Keeps the microVM state necessary in the snapshotting context.
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
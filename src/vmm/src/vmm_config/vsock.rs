@@ -5,9 +5,12 @@ use std::convert::TryFrom;
 use std::fmt;
 use std::sync::{Arc, Mutex};
 
+pub use devices::virtio::VsockStats;
 use devices::virtio::{Vsock, VsockError, VsockUnixBackend, VsockUnixBackendError};
 use serde::{Deserialize, Serialize};
 
+use crate::Error as VmmError;
+
 type MutexVsockUnix = Arc<Mutex<Vsock<VsockUnixBackend>>>;
 
 /// Errors associated with `NetworkInterfaceConfig`.
@@ -17,6 +20,9 @@ pub enum VsockConfigError {
     CreateVsockBackend(VsockUnixBackendError),
     /// Failed to create the vsock device.
     CreateVsockDevice(VsockError),
+    /// Error while getting the vsock statistics.
+    #[from(ignore)]
+    DeviceStats(VmmError),
 }
 
 impl fmt::Display for VsockConfigError {
@@ -27,6 +33,7 @@ impl fmt::Display for VsockConfigError {
                 write!(f, "Cannot create backend for vsock device: {:?}", err)
             }
             CreateVsockDevice(ref err) => write!(f, "Cannot create vsock device: {:?}", err),
+            DeviceStats(ref err) => write!(f, "Error while getting the vsock statistics: {}", err),
         }
     }
 }
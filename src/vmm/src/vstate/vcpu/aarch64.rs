@@ -22,8 +22,15 @@ use crate::vstate::vm::Vm;
 pub enum Error {
     /// Error configuring the general purpose aarch64 registers.
     ConfigureRegisters(arch::aarch64::regs::Error),
-    /// Cannot open the kvm related file descriptor.
-    CreateFd(kvm_ioctls::Error),
+    /// `KVM_CREATE_VCPU` failed.
+    CreateFailed {
+        /// The index of the vCPU.
+        vcpu_index: u8,
+        /// The error number returned by KVM.
+        errno: i32,
+        /// The likely cause of the failure.
+        hint: &'static str,
+    },
     /// Error getting the Vcpu preferred target on Arm.
     GetPreferredTarget(kvm_ioctls::Error),
     /// Error doing Vcpu Init on Arm.
@@ -46,7 +53,17 @@ impl Display for Error {
                     err
                 )
             }
-            CreateFd(err) => write!(f, "Error in opening the VCPU file descriptor: {}", err),
+            CreateFailed {
+                vcpu_index,
+                errno,
+                hint,
+            } => write!(
+                f,
+                "Cannot create vCPU {}: {} ({})",
+                vcpu_index,
+                std::io::Error::from_raw_os_error(*errno),
+                hint
+            ),
             GetPreferredTarget(err) => {
                 write!(f, "Error retrieving the vcpu preferred target: {}", err)
             }
@@ -77,7 +94,14 @@ impl KvmVcpu {
     /// * `index` - Represents the 0-based CPU index between [0, max vcpus).
     /// * `vm` - The vm to which this vcpu will get attached.
     pub fn new(index: u8, vm: &Vm) -> Result<Self> {
-        let kvm_vcpu = vm.fd().create_vcpu(index.into()).map_err(Error::CreateFd)?;
+        let kvm_vcpu = vm
+            .fd()
+            .create_vcpu(index.into())
+            .map_err(|err| Error::CreateFailed {
+                vcpu_index: index,
+                errno: err.errno(),
+                hint: super::create_vcpu_hint(err.errno()),
+            })?;
 
         Ok(KvmVcpu {
             index,
@@ -223,7 +247,7 @@ mod tests {
         assert!(err.is_err());
         assert_eq!(
             err.err().unwrap().to_string(),
-            "Error in opening the VCPU file descriptor: Bad file descriptor (os error 9)"
+            "Cannot create vCPU 0: Bad file descriptor (os error 9) (no hint available)"
                 .to_string()
         );
     }
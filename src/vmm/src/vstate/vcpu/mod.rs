@@ -6,11 +6,12 @@
 // found in the THIRD-PARTY file.
 
 use std::cell::Cell;
-use std::sync::atomic::{fence, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::atomic::{fence, AtomicU16, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
 #[cfg(test)]
 use std::sync::Mutex;
 use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
 use std::{fmt, io, result, thread};
 
 use kvm_bindings::{KVM_SYSTEM_EVENT_RESET, KVM_SYSTEM_EVENT_SHUTDOWN};
@@ -23,6 +24,7 @@ use utils::eventfd::EventFd;
 use utils::signal::{register_signal_handler, sigrtmin, Killable};
 use utils::sm::StateMachine;
 
+use crate::vmm_config::instance_info::{VcpuExitReason, VcpuRunState};
 use crate::vmm_config::machine_config::CpuFeaturesTemplate;
 use crate::vstate::vm::Vm;
 use crate::FcExitCode;
@@ -68,6 +70,19 @@ pub enum Error {
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Returns a hint about the likely cause of a `KVM_CREATE_VCPU` failure with `errno`.
+pub(crate) fn create_vcpu_hint(errno: i32) -> &'static str {
+    match errno {
+        libc::EINVAL => "the vCPU count may exceed the KVM_CAP_MAX_VCPUS limit of the host",
+        libc::EEXIST => "a vCPU with the same index was already created",
+        libc::EMFILE | libc::ENFILE => {
+            "the file descriptor limit is reached, check `ulimit -n` and /proc/sys/fs/file-max"
+        }
+        libc::ENOMEM => "the host is out of memory",
+        _ => "no hint available",
+    }
+}
+
 /// Encapsulates configuration parameters for the guest vCPUS.
 #[derive(Debug, PartialEq, Eq)]
 pub struct VcpuConfig {
@@ -92,6 +107,86 @@ impl fmt::Display for StartThreadedError {
     }
 }
 
+/// The run state and the last exit reason of a vCPU.
+///
+/// Only the vCPU thread updates it, so it can be read at any time through the `VcpuHandle`
+/// without interrupting the vCPU. The state is kept in the low byte and the exit reason (if
+/// any) in the high byte.
+#[derive(Debug, Default)]
+pub struct VcpuRunStatus(AtomicU16);
+
+impl VcpuRunStatus {
+    const STATES: [VcpuRunState; 3] = [
+        VcpuRunState::Paused,
+        VcpuRunState::Running,
+        VcpuRunState::Halted,
+    ];
+    const EXIT_REASONS: [VcpuExitReason; 12] = [
+        VcpuExitReason::MmioRead,
+        VcpuExitReason::MmioWrite,
+        VcpuExitReason::IoIn,
+        VcpuExitReason::IoOut,
+        VcpuExitReason::Hlt,
+        VcpuExitReason::Shutdown,
+        VcpuExitReason::FailEntry,
+        VcpuExitReason::InternalError,
+        VcpuExitReason::SystemEvent,
+        VcpuExitReason::Debug,
+        VcpuExitReason::Interrupted,
+        VcpuExitReason::Other,
+    ];
+
+    fn set_state(&self, state: VcpuRunState) {
+        let state = Self::STATES.iter().position(|s| *s == state).unwrap() as u16;
+        let status = self.0.load(Ordering::Relaxed);
+        self.0.store((status & 0xff00) | state, Ordering::Relaxed);
+    }
+
+    fn record_exit(&self, exit_reason: VcpuExitReason) {
+        // 0 means that the vCPU has not exited yet.
+        let exit_reason = Self::EXIT_REASONS
+            .iter()
+            .position(|r| *r == exit_reason)
+            .unwrap() as u16
+            + 1;
+        let status = self.0.load(Ordering::Relaxed);
+        self.0
+            .store((exit_reason << 8) | (status & 0xff), Ordering::Relaxed);
+    }
+
+    /// Returns the run state of the vCPU.
+    pub fn state(&self) -> VcpuRunState {
+        Self::STATES[usize::from(self.0.load(Ordering::Relaxed) & 0xff)]
+    }
+
+    /// Returns the last exit handled by the vCPU, if it ran at all.
+    pub fn exit_reason(&self) -> Option<VcpuExitReason> {
+        let exit_reason = usize::from(self.0.load(Ordering::Relaxed) >> 8);
+        exit_reason
+            .checked_sub(1)
+            .map(|index| Self::EXIT_REASONS[index])
+    }
+}
+
+impl From<&VcpuExit<'_>> for VcpuExitReason {
+    fn from(exit: &VcpuExit) -> Self {
+        match exit {
+            VcpuExit::MmioRead(..) => VcpuExitReason::MmioRead,
+            VcpuExit::MmioWrite(..) => VcpuExitReason::MmioWrite,
+            VcpuExit::IoIn(..) => VcpuExitReason::IoIn,
+            VcpuExit::IoOut(..) => VcpuExitReason::IoOut,
+            VcpuExit::Hlt => VcpuExitReason::Hlt,
+            VcpuExit::Shutdown => VcpuExitReason::Shutdown,
+            VcpuExit::FailEntry => VcpuExitReason::FailEntry,
+            VcpuExit::InternalError => VcpuExitReason::InternalError,
+            VcpuExit::SystemEvent(..) => VcpuExitReason::SystemEvent,
+            VcpuExit::Debug(..) => VcpuExitReason::Debug,
+            VcpuExit::Intr => VcpuExitReason::Interrupted,
+            _ => VcpuExitReason::Other,
+        }
+    }
+}
+
 /// A wrapper around creating and using a vcpu.
 pub struct Vcpu {
     // Offers kvm-arch specific functionality.
@@ -107,6 +202,8 @@ pub struct Vcpu {
     response_receiver: Option<Receiver<VcpuResponse>>,
     // The transmitting end of the responses channel owned by the vcpu side.
     response_sender: Sender<VcpuResponse>,
+    // The run state of the vcpu, shared with the handler.
+    run_status: Arc<VcpuRunStatus>,
 
     // Exit reason used to test run_emulation function.
     #[cfg(test)]
@@ -214,6 +311,7 @@ impl Vcpu {
             event_sender: Some(event_sender),
             response_receiver: Some(response_receiver),
             response_sender,
+            run_status: Arc::new(VcpuRunStatus::default()),
             kvm_vcpu,
             #[cfg(test)]
             test_vcpu_exit_reason: Mutex::new(None),
@@ -234,6 +332,7 @@ impl Vcpu {
     ) -> std::result::Result<VcpuHandle, StartThreadedError> {
         let event_sender = self.event_sender.take().expect("vCPU already started");
         let response_receiver = self.response_receiver.take().unwrap();
+        let run_status = self.run_status.clone();
         let vcpu_thread = thread::Builder::new()
             .name(format!("fc_vcpu {}", self.kvm_vcpu.index))
             .spawn(move || {
@@ -248,6 +347,7 @@ impl Vcpu {
         Ok(VcpuHandle::new(
             event_sender,
             response_receiver,
+            run_status,
             vcpu_thread,
         ))
     }
@@ -299,7 +399,7 @@ impl Vcpu {
         match self.event_receiver.try_recv() {
             // Running ---- Pause ----> Paused
             Ok(VcpuEvent::Pause) => {
-                // Nothing special to do.
+                self.run_status.set_state(VcpuRunState::Paused);
                 self.response_sender
                     .send(VcpuResponse::Paused)
                     .expect("failed to send pause status");
@@ -323,6 +423,8 @@ impl Vcpu {
                     )))
                     .expect("failed to send save not allowed status");
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SingleStep(enabled)) => self.single_step(enabled),
             Ok(VcpuEvent::Finish) => return StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(TryRecvError::Disconnected) => {
@@ -341,7 +443,7 @@ impl Vcpu {
         match self.event_receiver.recv() {
             // Paused ---- Resume ----> Running
             Ok(VcpuEvent::Resume) => {
-                // Nothing special to do.
+                self.run_status.set_state(VcpuRunState::Running);
                 self.response_sender
                     .send(VcpuResponse::Resumed)
                     .expect("vcpu channel unexpectedly closed");
@@ -387,6 +489,11 @@ impl Vcpu {
 
                 StateMachine::next(Self::paused)
             }
+            #[cfg(target_arch = "x86_64")]
+            Ok(VcpuEvent::SingleStep(enabled)) => {
+                self.single_step(enabled);
+                StateMachine::next(Self::paused)
+            }
             Ok(VcpuEvent::Finish) => StateMachine::finish(),
             // Unhandled exit of the other end.
             Err(_) => {
@@ -396,6 +503,18 @@ impl Vcpu {
         }
     }
 
+    // Updates the single-step mode, which is allowed both while running and paused.
+    #[cfg(target_arch = "x86_64")]
+    fn single_step(&self, enabled: bool) {
+        let response = match self.kvm_vcpu.set_single_step(enabled) {
+            Ok(()) => VcpuResponse::SingleStepUpdated,
+            Err(err) => VcpuResponse::Error(Error::VcpuResponse(err)),
+        };
+        self.response_sender
+            .send(response)
+            .expect("vcpu channel unexpectedly closed");
+    }
+
     // Transition to the exited state and finish on command.
     fn exit(&mut self, exit_code: FcExitCode) -> StateMachine<Self> {
         // To avoid cycles, all teardown paths take the following route:
@@ -415,6 +534,7 @@ impl Vcpu {
         // Vmm initiated teardown starts from `pub fn Vmm::stop()` (step 4).
         // Once `vmm.shutdown_exit_code` becomes `Some(exit_code)`, it is the upper layer's
         // responsibility to break main event loop and propagate the exit code value.
+        self.run_status.set_state(VcpuRunState::Halted);
         // Signal Vmm of Vcpu exit.
         if let Err(err) = self.exit_evt.write(1) {
             METRICS.vcpu.failures.inc();
@@ -442,7 +562,11 @@ impl Vcpu {
     ///
     /// Returns error or enum specifying whether emulation was handled or interrupted.
     pub fn run_emulation(&self) -> Result<VcpuEmulation> {
-        match self.emulate() {
+        let exit = self.emulate();
+        if let Ok(run) = &exit {
+            self.run_status.record_exit(VcpuExitReason::from(run));
+        }
+        match exit {
             Ok(run) => match run {
                 VcpuExit::MmioRead(addr, data) => {
                     if let Some(mmio_bus) = &self.kvm_vcpu.mmio_bus {
@@ -514,6 +638,7 @@ impl Vcpu {
                 match err.errno() {
                     libc::EAGAIN => Ok(VcpuEmulation::Handled),
                     libc::EINTR => {
+                        self.run_status.record_exit(VcpuExitReason::Interrupted);
                         self.kvm_vcpu.fd.set_kvm_immediate_exit(0);
                         // Notify that this KVM_RUN was interrupted.
                         Ok(VcpuEmulation::Interrupted)
@@ -558,6 +683,9 @@ pub enum VcpuEvent {
     RestoreState(Box<VcpuState>),
     /// Event to save the state of a paused Vcpu.
     SaveState,
+    /// Event to enable or disable the single-step mode of the Vcpu.
+    #[cfg(target_arch = "x86_64")]
+    SingleStep(bool),
 }
 
 /// List of responses that the Vcpu reports.
@@ -576,12 +704,18 @@ pub enum VcpuResponse {
     RestoredState,
     /// Vcpu state is saved.
     SavedState(Box<VcpuState>),
+    /// Vcpu single-step mode is updated.
+    #[cfg(target_arch = "x86_64")]
+    SingleStepUpdated,
 }
 
 /// Wrapper over Vcpu that hides the underlying interactions with the Vcpu thread.
 pub struct VcpuHandle {
     event_sender: Sender<VcpuEvent>,
     response_receiver: Receiver<VcpuResponse>,
+    run_status: Arc<VcpuRunStatus>,
+    // The number of events sent to the vCPU whose response wasn't received yet.
+    pending_responses: AtomicUsize,
     // Rust JoinHandles have to be wrapped in Option if you ever plan on 'join()'ing them.
     // We want to be able to join these threads in tests.
     vcpu_thread: Option<thread::JoinHandle<()>>,
@@ -601,11 +735,14 @@ impl VcpuHandle {
     pub fn new(
         event_sender: Sender<VcpuEvent>,
         response_receiver: Receiver<VcpuResponse>,
+        run_status: Arc<VcpuRunStatus>,
         vcpu_thread: thread::JoinHandle<()>,
     ) -> Self {
         Self {
             event_sender,
             response_receiver,
+            run_status,
+            pending_responses: AtomicUsize::new(0),
             vcpu_thread: Some(vcpu_thread),
         }
     }
@@ -615,6 +752,10 @@ impl VcpuHandle {
     ///
     /// When [`vmm_sys_util::linux::signal::Killable::kill`] errors.
     pub fn send_event(&self, event: VcpuEvent) -> std::result::Result<(), VcpuSendEventError> {
+        // The vCPU thread ends without responding to `Finish`.
+        if !matches!(event, VcpuEvent::Finish) {
+            self.pending_responses.fetch_add(1, Ordering::SeqCst);
+        }
         // Use expect() to crash if the other thread closed this channel.
         self.event_sender
             .send(event)
@@ -631,6 +772,33 @@ impl VcpuHandle {
     pub fn response_receiver(&self) -> &Receiver<VcpuResponse> {
         &self.response_receiver
     }
+
+    /// Waits up to `timeout` for the response to the last event sent to the vCPU.
+    ///
+    /// The responses to the previous events, which the caller gave up waiting for, are
+    /// discarded.
+    pub fn recv_response(
+        &self,
+        timeout: Duration,
+    ) -> std::result::Result<VcpuResponse, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let response = self
+                .response_receiver
+                .recv_timeout(deadline.saturating_duration_since(Instant::now()))?;
+            let pending = self.pending_responses.load(Ordering::SeqCst);
+            self.pending_responses
+                .store(pending.saturating_sub(1), Ordering::SeqCst);
+            if pending <= 1 {
+                return Ok(response);
+            }
+        }
+    }
+
+    /// Returns the run state of the vCPU, as last updated by the vCPU thread.
+    pub fn run_status(&self) -> &VcpuRunStatus {
+        &self.run_status
+    }
 }
 
 // Wait for the Vcpu thread to finish execution
@@ -668,6 +836,8 @@ mod tests {
     use super::*;
     use crate::builder::StartMicrovmError;
     use crate::seccomp_filters::{get_filters, SeccompConfig};
+    #[cfg(target_arch = "x86_64")]
+    use crate::vmm_config::machine_config::IrqChipMode;
     use crate::vstate::vcpu::Error as EmulationError;
     use crate::vstate::vm::tests::setup_vm;
     use crate::vstate::vm::Vm;
@@ -755,6 +925,8 @@ mod tests {
         let res = vcpu.run_emulation();
         assert!(res.is_ok());
         assert_eq!(res.unwrap(), VcpuEmulation::Handled);
+        // Errors are not exits, so the last exit reason is kept.
+        assert_eq!(vcpu.run_status.exit_reason(), Some(VcpuExitReason::Other));
 
         *(vcpu.test_vcpu_exit_reason.lock().unwrap()) = Some(Err(errno::Error::new(libc::ENOSYS)));
         let res = vcpu.run_emulation();
@@ -817,6 +989,8 @@ mod tests {
             match self {
                 Paused | Resumed | Exited(_) => (),
                 Error(_) | NotAllowed(_) | RestoredState | SavedState(_) => (),
+                #[cfg(target_arch = "x86_64")]
+                SingleStepUpdated => (),
             };
             match (self, other) {
                 (Paused, Paused) | (Resumed, Resumed) => true,
@@ -824,6 +998,8 @@ mod tests {
                 (NotAllowed(_), NotAllowed(_))
                 | (RestoredState, RestoredState)
                 | (SavedState(_), SavedState(_)) => true,
+                #[cfg(target_arch = "x86_64")]
+                (SingleStepUpdated, SingleStepUpdated) => true,
                 (Error(ref err), Error(ref other_err)) => {
                     format!("{:?}", err) == format!("{:?}", other_err)
                 }
@@ -843,6 +1019,8 @@ mod tests {
                 SavedState(_) => write!(f, "VcpuResponse::SavedState"),
                 Error(ref err) => write!(f, "VcpuResponse::Error({:?})", err),
                 NotAllowed(ref reason) => write!(f, "VcpuResponse::NotAllowed({})", reason),
+                #[cfg(target_arch = "x86_64")]
+                SingleStepUpdated => write!(f, "VcpuResponse::SingleStepUpdated"),
             }
         }
     }
@@ -862,7 +1040,7 @@ mod tests {
         }
         #[cfg(target_arch = "x86_64")]
         {
-            vm.setup_irqchip().unwrap();
+            vm.setup_irqchip(IrqChipMode::InKernel).unwrap();
             vcpu = Vcpu::new(1, &vm, exit_evt).unwrap();
         }
         (vm, vcpu, gm)
@@ -947,6 +1125,19 @@ mod tests {
         assert!(vcpu.kvm_vcpu.mmio_bus.is_some());
     }
 
+    #[test]
+    fn test_create_vcpu_hint() {
+        assert_eq!(
+            create_vcpu_hint(libc::EEXIST),
+            "a vCPU with the same index was already created"
+        );
+        assert_eq!(
+            create_vcpu_hint(libc::ENFILE),
+            create_vcpu_hint(libc::EMFILE)
+        );
+        assert_eq!(create_vcpu_hint(libc::EBADF), "no hint available");
+    }
+
     #[test]
     fn test_vcpu_tls() {
         let (_, mut vcpu, _) = setup_vcpu(0x1000);
@@ -1029,6 +1220,40 @@ mod tests {
         assert!(success.load(Ordering::Acquire));
     }
 
+    #[test]
+    fn test_vcpu_run_status() {
+        let run_status = VcpuRunStatus::default();
+        assert_eq!(run_status.state(), VcpuRunState::Paused);
+        assert_eq!(run_status.exit_reason(), None);
+
+        run_status.set_state(VcpuRunState::Running);
+        run_status.record_exit(VcpuExitReason::MmioWrite);
+        assert_eq!(run_status.state(), VcpuRunState::Running);
+        assert_eq!(run_status.exit_reason(), Some(VcpuExitReason::MmioWrite));
+
+        // Every exit reason and state can be recorded without affecting the other.
+        for exit_reason in VcpuRunStatus::EXIT_REASONS {
+            run_status.record_exit(exit_reason);
+            assert_eq!(run_status.exit_reason(), Some(exit_reason));
+            assert_eq!(run_status.state(), VcpuRunState::Running);
+        }
+        for state in VcpuRunStatus::STATES {
+            run_status.set_state(state);
+            assert_eq!(run_status.state(), state);
+            assert_eq!(run_status.exit_reason(), Some(VcpuExitReason::Other));
+        }
+
+        assert_eq!(VcpuExitReason::from(&VcpuExit::Hlt), VcpuExitReason::Hlt);
+        assert_eq!(
+            VcpuExitReason::from(&VcpuExit::SystemEvent(1, 0)),
+            VcpuExitReason::SystemEvent
+        );
+        assert_eq!(
+            VcpuExitReason::from(&VcpuExit::Unknown),
+            VcpuExitReason::Other
+        );
+    }
+
     // Sends an event to a vcpu and expects a particular response.
     fn queue_event_expect_response(handle: &VcpuHandle, event: VcpuEvent, response: VcpuResponse) {
         handle
@@ -1036,8 +1261,7 @@ mod tests {
             .expect("failed to send event to vcpu");
         assert_eq!(
             handle
-                .response_receiver()
-                .recv_timeout(RECV_TIMEOUT_SEC)
+                .recv_response(RECV_TIMEOUT_SEC)
                 .expect("did not receive event response from vcpu"),
             response
         );
@@ -1047,11 +1271,19 @@ mod tests {
     fn test_vcpu_pause_resume() {
         let (vcpu_handle, vcpu_exit_evt) = vcpu_configured_for_boot();
 
+        assert_eq!(vcpu_handle.run_status().state(), VcpuRunState::Paused);
+
         // Queue a Resume event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        assert_eq!(vcpu_handle.run_status().state(), VcpuRunState::Running);
 
         // Queue a Pause event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+        assert_eq!(vcpu_handle.run_status().state(), VcpuRunState::Paused);
+        assert_eq!(
+            vcpu_handle.run_status().exit_reason(),
+            Some(VcpuExitReason::Interrupted)
+        );
 
         // Validate vcpu handled the EINTR gracefully and didn't exit.
         let err = vcpu_exit_evt.read().unwrap_err();
@@ -1072,6 +1304,38 @@ mod tests {
         // Queue a Resume event, expect a response.
         queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
 
+        // Only the response to the last event is received, the previous ones are discarded.
+        vcpu_handle.send_event(VcpuEvent::Pause).unwrap();
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+        assert!(matches!(
+            vcpu_handle.recv_response(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+
+        vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_vcpu_single_step_events() {
+        let (vcpu_handle, _vcpu_exit_evt) = vcpu_configured_for_boot();
+
+        // The single-step mode can be toggled while paused.
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SingleStep(true),
+            VcpuResponse::SingleStepUpdated,
+        );
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Resume, VcpuResponse::Resumed);
+
+        // And while running.
+        queue_event_expect_response(
+            &vcpu_handle,
+            VcpuEvent::SingleStep(false),
+            VcpuResponse::SingleStepUpdated,
+        );
+        queue_event_expect_response(&vcpu_handle, VcpuEvent::Pause, VcpuResponse::Paused);
+
         vcpu_handle.send_event(VcpuEvent::Finish).unwrap();
     }
 
@@ -1097,8 +1361,7 @@ mod tests {
             .send_event(VcpuEvent::SaveState)
             .expect("failed to send event to vcpu");
         let vcpu_state = match vcpu_handle
-            .response_receiver()
-            .recv_timeout(RECV_TIMEOUT_SEC)
+            .recv_response(RECV_TIMEOUT_SEC)
             .expect("did not receive event response from vcpu")
         {
             VcpuResponse::SavedState(state) => state,
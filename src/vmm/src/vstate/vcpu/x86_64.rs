@@ -7,18 +7,26 @@
 
 use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
-use std::{fmt, result};
+use std::os::unix::io::AsRawFd;
+use std::ptr::NonNull;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{fmt, io, result};
 
 use arch::x86_64::interrupts;
 use arch::x86_64::msr::SetMSRsError;
 use arch::x86_64::regs::{SetupFpuError, SetupRegistersError, SetupSpecialRegistersError};
-use cpuid::{c3, filter_cpuid, msrs_to_save_by_cpuid, t2, t2s, VmSpec};
+use cpuid::{filter_cpuid, msrs_to_save_by_cpuid, t2s, CpuTemplate, VmSpec};
+use devices::legacy::Ioapic;
 use kvm_bindings::{
-    kvm_debugregs, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_sregs, kvm_vcpu_events, kvm_xcrs,
-    kvm_xsave, CpuId, Msrs, KVM_MAX_MSR_ENTRIES,
+    kvm_debugregs, kvm_guest_debug, kvm_lapic_state, kvm_mp_state, kvm_regs, kvm_run, kvm_sregs,
+    kvm_vcpu_events, kvm_xcrs, kvm_xsave, CpuId, Msrs, KVM_GUESTDBG_ENABLE,
+    KVM_GUESTDBG_SINGLESTEP, KVM_MAX_MSR_ENTRIES, KVM_SYNC_X86_SREGS, KVM_VCPUEVENT_VALID_PAYLOAD,
 };
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use logger::{error, warn, IncMetric, METRICS};
+use utils::eventfd::EventFd;
 use versionize::{VersionMap, Versionize, VersionizeError, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{Address, GuestAddress, GuestMemoryMmap};
@@ -38,6 +46,15 @@ const TSC_KHZ_TOL: f64 = 250.0 / 1_000_000.0;
 pub enum Error {
     /// A call to cpuid instruction failed.
     CpuId(cpuid::Error),
+    /// `KVM_CREATE_VCPU` failed.
+    CreateFailed {
+        /// The index of the vCPU.
+        vcpu_index: u8,
+        /// The error number returned by KVM.
+        errno: i32,
+        /// The likely cause of the failure.
+        hint: &'static str,
+    },
     /// A FamStructWrapper operation has failed.
     Fam(utils::fam::Error),
     /// Error configuring the floating point related registers
@@ -50,8 +67,6 @@ pub enum Error {
     REGSConfiguration(arch::x86_64::regs::Error),
     /// Error configuring the special registers
     SREGSConfiguration(arch::x86_64::regs::Error),
-    /// Cannot open the VCPU file descriptor.
-    VcpuFd(kvm_ioctls::Error),
     /// Failed to get KVM vcpu debug regs.
     VcpuGetDebugRegs(kvm_ioctls::Error),
     /// Failed to get KVM vcpu lapic.
@@ -72,6 +87,8 @@ pub enum Error {
     VcpuGetXcrs(kvm_ioctls::Error),
     /// Failed to get KVM vcpu xsave.
     VcpuGetXsave(kvm_ioctls::Error),
+    /// Failed to map the KVM vcpu run structure.
+    VcpuMapKvmRun(io::Error),
     /// Failed to get KVM vcpu cpuid.
     VcpuGetCpuid(kvm_ioctls::Error),
     /// Failed to get KVM TSC freq.
@@ -100,6 +117,8 @@ pub enum Error {
     VcpuSetXsave(kvm_ioctls::Error),
     /// Failed to set KVM TSC freq.
     VcpuSetTSC(kvm_ioctls::Error),
+    /// Failed to set KVM vcpu guest debug.
+    VcpuSetGuestDebug(kvm_ioctls::Error),
     /// Failed to apply CPU template.
     VcpuTemplateError,
 }
@@ -115,7 +134,17 @@ impl Display for Error {
                 "Cannot set the local interruption due to bad configuration: {:?}",
                 err
             ),
-            VcpuFd(err) => write!(f, "Cannot open the VCPU file descriptor: {}", err),
+            CreateFailed {
+                vcpu_index,
+                errno,
+                hint,
+            } => write!(
+                f,
+                "Cannot create vCPU {}: {} ({})",
+                vcpu_index,
+                std::io::Error::from_raw_os_error(*errno),
+                hint
+            ),
             MSRSConfiguration(err) => write!(f, "Error configuring the MSR registers: {:?}", err),
             REGSConfiguration(err) => write!(
                 f,
@@ -158,7 +187,9 @@ impl Display for Error {
             VcpuSetXcrs(err) => write!(f, "Failed to set KVM vcpu xcrs: {}", err),
             VcpuSetXsave(err) => write!(f, "Failed to set KVM vcpu xsave: {}", err),
             VcpuSetTSC(err) => write!(f, "Failed to set KVM TSC frequency: {}", err),
+            VcpuSetGuestDebug(err) => write!(f, "Failed to set KVM vcpu guest debug: {}", err),
             VcpuTemplateError => write!(f, "Failed to apply CPU template"),
+            VcpuMapKvmRun(err) => write!(f, "Failed to map the KVM vcpu run structure: {}", err),
         }
     }
 }
@@ -184,6 +215,46 @@ impl fmt::Display for SetTscError {
 }
 impl std::error::Error for SetTscError {}
 
+/// The vector of the debug exception (#DB).
+pub const DB_VECTOR: u8 = 1;
+/// The vector of the general protection exception (#GP).
+pub const GP_VECTOR: u8 = 13;
+/// The vector of the page fault exception (#PF).
+pub const PF_VECTOR: u8 = 14;
+
+// The single-step bit of DR6, set on the #DB raised by the single-stepping.
+const DR6_BS: u64 = 1 << 14;
+// The bits of DR6 which are cleared when the corresponding condition is detected, as opposed
+// to the other bits, which are set. The #DB payloads have all their bits active high.
+const DR6_ACTIVE_LOW: u64 = 0xffff_0ff0;
+
+// Folds the payload of a pending exception into the registers, the way KVM reports the vcpu
+// events when `KVM_CAP_EXCEPTION_PAYLOAD` isn't enabled, so that they can be restored
+// regardless of the capability.
+fn fold_exception_payload(
+    events: &mut kvm_vcpu_events,
+    sregs: &mut kvm_sregs,
+    debug_regs: &mut kvm_debugregs,
+) {
+    if events.flags & KVM_VCPUEVENT_VALID_PAYLOAD == 0 {
+        return;
+    }
+
+    if events.exception.pending != 0 && events.exception_has_payload != 0 {
+        match events.exception.nr {
+            PF_VECTOR => sregs.cr2 = events.exception_payload,
+            DB_VECTOR => debug_regs.dr6 = DR6_ACTIVE_LOW ^ events.exception_payload,
+            _ => {}
+        }
+    }
+    // Without the capability, the pending exceptions are reported as injected.
+    events.exception.injected |= events.exception.pending;
+    events.exception.pending = 0;
+    events.exception_has_payload = 0;
+    events.exception_payload = 0;
+    events.flags &= !KVM_VCPUEVENT_VALID_PAYLOAD;
+}
+
 /// Error type for [`KvmVcpu::configure`].
 #[derive(Debug, thiserror::Error)]
 pub enum KvmVcpuConfigureError {
@@ -191,8 +262,6 @@ pub enum KvmVcpuConfigureError {
     VmSpec(cpuid::Error),
     #[error("Failed to filter CPUID: {0}")]
     FilterCpuid(cpuid::Error),
-    #[error("Failed to set CPUID entries: {0}")]
-    SetCpuidEntries(cpuid::Error),
     #[error("Failed to set CPUID: {0}")]
     SetCpuid(#[from] utils::errno::Error),
     #[error("Failed to push MSR entry to FamStructWrapper.")]
@@ -209,6 +278,114 @@ pub enum KvmVcpuConfigureError {
     SetLint(#[from] interrupts::Error),
 }
 
+/// A debug exit of a vcpu in single-step mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VcpuDebugEvent {
+    /// The index of the vcpu.
+    pub index: u8,
+    /// The instruction pointer of the next instruction to execute.
+    pub rip: u64,
+    /// The code segment selector.
+    pub cs: u16,
+}
+
+/// Channel used by the vcpus to report their debug exits.
+///
+/// The event fd is written after each event, so that the receiving end can be polled from
+/// the event loop.
+pub struct VcpuDebugChannel {
+    sender: Sender<VcpuDebugEvent>,
+    event_fd: EventFd,
+}
+
+impl VcpuDebugChannel {
+    /// Creates the sending end of a debug channel.
+    pub fn new(sender: Sender<VcpuDebugEvent>, event_fd: EventFd) -> Self {
+        VcpuDebugChannel { sender, event_fd }
+    }
+
+    fn send(&self, event: VcpuDebugEvent) {
+        if self.sender.send(event).is_err() {
+            // The receiving end is gone, nobody is interested in the event anymore.
+            return;
+        }
+        if let Err(err) = self.event_fd.write(1) {
+            METRICS.vcpu.failures.inc();
+            error!("Failed to signal vcpu debug event: {}", err);
+        }
+    }
+}
+
+/// A mapping of the `kvm_run` structure of a vcpu, through which KVM synchronizes the
+/// registers selected in `kvm_valid_regs` on each exit (`KVM_CAP_SYNC_REGS`).
+///
+/// `VcpuFd` doesn't expose its own mapping, so the structure is mapped a second time. Both
+/// mappings share the same page.
+struct KvmRunMapping {
+    kvm_run: NonNull<kvm_run>,
+}
+
+// SAFETY: The mapping is only accessed by the thread owning the vcpu, like the one of `VcpuFd`.
+unsafe impl Send for KvmRunMapping {}
+
+impl KvmRunMapping {
+    fn new(fd: &VcpuFd) -> Result<Self> {
+        // SAFETY: Safe because the vcpu fd is valid, the size of the mapping doesn't exceed
+        // the `KVM_GET_VCPU_MMAP_SIZE` of the fd and we check the return value.
+        let addr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                std::mem::size_of::<kvm_run>(),
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if addr == libc::MAP_FAILED {
+            return Err(Error::VcpuMapKvmRun(io::Error::last_os_error()));
+        }
+
+        Ok(KvmRunMapping {
+            // `mmap` never returns a null address on success.
+            kvm_run: NonNull::new(addr.cast()).expect("Null kvm_run mapping"),
+        })
+    }
+
+    // Selects the registers that KVM copies to the structure on the next exits.
+    fn set_valid_regs(&self, valid_regs: u32) {
+        // SAFETY: Safe because the mapping is valid for the lifetime of `self`, and KVM only
+        // accesses the structure during `KVM_RUN`, which can't run concurrently as it is
+        // issued by the thread owning the vcpu.
+        unsafe { (*self.kvm_run.as_ptr()).kvm_valid_regs = u64::from(valid_regs) };
+    }
+
+    // Returns the special registers of the last exit, if KVM synchronized them.
+    fn sregs(&self) -> Option<kvm_sregs> {
+        // SAFETY: Safe because the mapping is valid for the lifetime of `self`, and KVM only
+        // accesses the structure during `KVM_RUN`, which can't run concurrently as it is
+        // issued by the thread owning the vcpu. The synchronized registers are the `regs`
+        // member of the union.
+        unsafe {
+            let kvm_run = self.kvm_run.as_ptr();
+            if (*kvm_run).kvm_valid_regs & u64::from(KVM_SYNC_X86_SREGS) == 0 {
+                return None;
+            }
+            Some((*kvm_run).s.regs.sregs)
+        }
+    }
+}
+
+impl Drop for KvmRunMapping {
+    fn drop(&mut self) {
+        // SAFETY: Safe because the mapping was created by `new` with this size and is not
+        // used anymore.
+        unsafe {
+            libc::munmap(self.kvm_run.as_ptr().cast(), std::mem::size_of::<kvm_run>());
+        }
+    }
+}
+
 /// A wrapper around creating and using a kvm x86_64 vcpu.
 pub struct KvmVcpu {
     pub index: u8,
@@ -216,8 +393,13 @@ pub struct KvmVcpu {
 
     pub pio_bus: Option<devices::Bus>,
     pub mmio_bus: Option<devices::Bus>,
+    pub ioapic: Option<Arc<Mutex<Ioapic>>>,
+    pub debug_channel: Option<VcpuDebugChannel>,
 
     msr_list: HashSet<u32>,
+    exception_payload: bool,
+    // Set if KVM can synchronize the special registers, see `set_single_step`.
+    kvm_run: Option<KvmRunMapping>,
 }
 
 impl KvmVcpu {
@@ -228,14 +410,30 @@ impl KvmVcpu {
     /// * `index` - Represents the 0-based CPU index between [0, max vcpus).
     /// * `vm` - The vm to which this vcpu will get attached.
     pub fn new(index: u8, vm: &Vm) -> Result<Self> {
-        let kvm_vcpu = vm.fd().create_vcpu(index.into()).map_err(Error::VcpuFd)?;
+        let kvm_vcpu = vm
+            .fd()
+            .create_vcpu(index.into())
+            .map_err(|err| Error::CreateFailed {
+                vcpu_index: index,
+                errno: err.errno(),
+                hint: super::create_vcpu_hint(err.errno()),
+            })?;
+        let kvm_run = if vm.sync_sregs_supported() {
+            Some(KvmRunMapping::new(&kvm_vcpu)?)
+        } else {
+            None
+        };
 
         Ok(KvmVcpu {
             index,
             fd: kvm_vcpu,
             pio_bus: None,
             mmio_bus: None,
+            ioapic: None,
+            debug_channel: None,
             msr_list: vm.supported_msrs().as_slice().iter().copied().collect(),
+            exception_payload: vm.exception_payload_enabled(),
+            kvm_run,
         })
     }
 
@@ -254,9 +452,28 @@ impl KvmVcpu {
         vcpu_config: &VcpuConfig,
         mut cpuid: CpuId,
     ) -> std::result::Result<(), KvmVcpuConfigureError> {
-        let cpuid_vm_spec = VmSpec::new(self.index, vcpu_config.vcpu_count, vcpu_config.smt)
-            .map_err(KvmVcpuConfigureError::VmSpec)?;
+        // The template is applied by `filter_cpuid`, after the vendor specific changes.
+        let cpu_template = match vcpu_config.cpu_template {
+            CpuFeaturesTemplate::C3 => CpuTemplate::C3,
+            CpuFeaturesTemplate::T2 => CpuTemplate::T2,
+            CpuFeaturesTemplate::T2S => CpuTemplate::T2S,
+            CpuFeaturesTemplate::T2A => CpuTemplate::T2A,
+            CpuFeaturesTemplate::T2CL => CpuTemplate::T2CL,
+            CpuFeaturesTemplate::None => CpuTemplate::None,
+        };
+        let cpuid_vm_spec = VmSpec::builder()
+            .cpu_index(self.index)
+            .cpu_count(vcpu_config.vcpu_count)
+            .smt(vcpu_config.smt)
+            .cpu_template(cpu_template)
+            .build()
+            .map_err(|err| {
+                METRICS.vcpu.filter_cpuid.inc();
+                KvmVcpuConfigureError::VmSpec(err)
+            })?;
 
+        let transform_start = Instant::now();
+        // Counts the vendor specific and the template failures alike, both go through here.
         filter_cpuid(&mut cpuid, &cpuid_vm_spec)
             .map_err(|err| {
                 METRICS.vcpu.filter_cpuid.inc();
@@ -267,17 +484,14 @@ impl KvmVcpu {
                 err
             })
             .map_err(KvmVcpuConfigureError::FilterCpuid)?;
-
-        // Update the CPUID based on the template
-        match vcpu_config.cpu_template {
-            CpuFeaturesTemplate::T2 => t2::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec)
-                .map_err(KvmVcpuConfigureError::SetCpuidEntries)?,
-            CpuFeaturesTemplate::T2S => t2s::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec)
-                .map_err(KvmVcpuConfigureError::SetCpuidEntries)?,
-            CpuFeaturesTemplate::C3 => c3::set_cpuid_entries(&mut cpuid, &cpuid_vm_spec)
-                .map_err(KvmVcpuConfigureError::SetCpuidEntries)?,
-            CpuFeaturesTemplate::None => {}
-        }
+        METRICS
+            .vcpu
+            .cpuid_transform_duration_us
+            .add(transform_start.elapsed().as_micros() as usize);
+        METRICS
+            .vcpu
+            .cpuid_entries_transformed
+            .add(cpuid.as_slice().len());
 
         self.fd
             .set_cpuid2(&cpuid)
@@ -327,6 +541,98 @@ impl KvmVcpu {
         self.pio_bus = Some(pio_bus);
     }
 
+    /// Sets the userspace IOAPIC that receives the EOIs of this vcpu.
+    pub fn set_ioapic(&mut self, ioapic: Arc<Mutex<Ioapic>>) {
+        self.ioapic = Some(ioapic);
+    }
+
+    /// Sets the channel on which the debug exits of this vcpu are reported.
+    pub fn set_debug_channel(&mut self, debug_channel: VcpuDebugChannel) {
+        self.debug_channel = Some(debug_channel);
+    }
+
+    /// Enables or disables the single-step mode, in which KVM exits with `KVM_EXIT_DEBUG`
+    /// after each guest instruction.
+    ///
+    /// When `KVM_CAP_SYNC_REGS` is supported, KVM copies the special registers to `kvm_run` on
+    /// each exit while single-stepping, which saves a `KVM_GET_SREGS` per debug exit. They are
+    /// not synchronized otherwise, as the copy would slow down every other exit.
+    pub fn set_single_step(&self, enabled: bool) -> Result<()> {
+        let debug = kvm_guest_debug {
+            control: if enabled {
+                KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_SINGLESTEP
+            } else {
+                0
+            },
+            ..Default::default()
+        };
+        self.fd
+            .set_guest_debug(&debug)
+            .map_err(Error::VcpuSetGuestDebug)?;
+        if let Some(kvm_run) = &self.kvm_run {
+            kvm_run.set_valid_regs(if enabled { KVM_SYNC_X86_SREGS } else { 0 });
+        }
+        Ok(())
+    }
+
+    /// Injects a hardware exception in the guest, to be delivered on the next `KVM_RUN`.
+    ///
+    /// # Arguments
+    ///
+    /// * `vector` - The exception vector, e.g. `PF_VECTOR`.
+    /// * `error_code` - The error code pushed by the exception, if it has one.
+    /// * `payload` - The faulting address for #PF or the DR6 bits for #DB, all active high.
+    ///
+    /// When `KVM_CAP_EXCEPTION_PAYLOAD` is enabled the payload travels with the exception, so
+    /// that KVM (or the nested hypervisor intercepting the exception) gets it precisely.
+    /// Otherwise CR2/DR6 are written right away.
+    pub fn inject_exception(
+        &self,
+        vector: u8,
+        error_code: Option<u32>,
+        payload: Option<u64>,
+    ) -> Result<()> {
+        let mut events = self
+            .fd
+            .get_vcpu_events()
+            .map_err(Error::VcpuGetVcpuEvents)?;
+        events.exception.nr = vector;
+        events.exception.has_error_code = u8::from(error_code.is_some());
+        events.exception.error_code = error_code.unwrap_or(0);
+
+        if self.exception_payload {
+            // A pending exception has not been delivered yet, so KVM still applies the payload.
+            events.flags |= KVM_VCPUEVENT_VALID_PAYLOAD;
+            events.exception.pending = 1;
+            events.exception.injected = 0;
+            events.exception_has_payload = u8::from(payload.is_some());
+            events.exception_payload = payload.unwrap_or(0);
+        } else {
+            match (vector, payload) {
+                (PF_VECTOR, Some(address)) => {
+                    let mut sregs = self.fd.get_sregs().map_err(Error::VcpuGetSregs)?;
+                    sregs.cr2 = address;
+                    self.fd.set_sregs(&sregs).map_err(Error::VcpuSetSregs)?;
+                }
+                (DB_VECTOR, Some(dr6)) => {
+                    let mut debug_regs =
+                        self.fd.get_debug_regs().map_err(Error::VcpuGetDebugRegs)?;
+                    debug_regs.dr6 = DR6_ACTIVE_LOW ^ dr6;
+                    self.fd
+                        .set_debug_regs(&debug_regs)
+                        .map_err(Error::VcpuSetDebugRegs)?;
+                }
+                _ => {}
+            }
+            events.exception.injected = 1;
+            events.exception.pending = 0;
+        }
+
+        self.fd
+            .set_vcpu_events(&events)
+            .map_err(Error::VcpuSetVcpuEvents)
+    }
+
     /// Get the current TSC frequency for this vCPU.
     ///
     /// # Errors
@@ -380,10 +686,10 @@ impl KvmVcpu {
 
         let mp_state = self.fd.get_mp_state().map_err(Error::VcpuGetMpState)?;
         let regs = self.fd.get_regs().map_err(Error::VcpuGetRegs)?;
-        let sregs = self.fd.get_sregs().map_err(Error::VcpuGetSregs)?;
+        let mut sregs = self.fd.get_sregs().map_err(Error::VcpuGetSregs)?;
         let xsave = self.fd.get_xsave().map_err(Error::VcpuGetXsave)?;
         let xcrs = self.fd.get_xcrs().map_err(Error::VcpuGetXcrs)?;
-        let debug_regs = self.fd.get_debug_regs().map_err(Error::VcpuGetDebugRegs)?;
+        let mut debug_regs = self.fd.get_debug_regs().map_err(Error::VcpuGetDebugRegs)?;
         let lapic = self.fd.get_lapic().map_err(Error::VcpuGetLapic)?;
         let tsc_khz = self.get_tsc_khz().ok().or_else(|| {
             // v0.25 and newer snapshots without TSC will only work on
@@ -399,10 +705,13 @@ impl KvmVcpu {
                 return Err(Error::VcpuGetMSRSIncomplete);
             }
         }
-        let vcpu_events = self
+        let mut vcpu_events = self
             .fd
             .get_vcpu_events()
             .map_err(Error::VcpuGetVcpuEvents)?;
+        // The snapshot may be restored by a VM without `KVM_CAP_EXCEPTION_PAYLOAD`, which
+        // rejects the events carrying a payload.
+        fold_exception_payload(&mut vcpu_events, &mut sregs, &mut debug_regs);
 
         Ok(VcpuState {
             cpuid: self
@@ -470,6 +779,14 @@ impl KvmVcpu {
         // SET_LAPIC must come before SET_MSRS, because the TSC deadline MSR
         // only restores successfully, when the LAPIC is correctly configured.
 
+        let mut vcpu_events = state.vcpu_events;
+        let mut sregs = state.sregs;
+        let mut debug_regs = state.debug_regs;
+        if !self.exception_payload {
+            // The snapshots taken before the payloads were folded on save may carry one.
+            fold_exception_payload(&mut vcpu_events, &mut sregs, &mut debug_regs);
+        }
+
         self.fd
             .set_cpuid2(&state.cpuid)
             .map_err(Error::VcpuSetCpuid)?;
@@ -477,15 +794,13 @@ impl KvmVcpu {
             .set_mp_state(state.mp_state)
             .map_err(Error::VcpuSetMpState)?;
         self.fd.set_regs(&state.regs).map_err(Error::VcpuSetRegs)?;
-        self.fd
-            .set_sregs(&state.sregs)
-            .map_err(Error::VcpuSetSregs)?;
+        self.fd.set_sregs(&sregs).map_err(Error::VcpuSetSregs)?;
         self.fd
             .set_xsave(&state.xsave)
             .map_err(Error::VcpuSetXsave)?;
         self.fd.set_xcrs(&state.xcrs).map_err(Error::VcpuSetXcrs)?;
         self.fd
-            .set_debug_regs(&state.debug_regs)
+            .set_debug_regs(&debug_regs)
             .map_err(Error::VcpuSetDebugRegs)?;
         self.fd
             .set_lapic(&state.lapic)
@@ -497,7 +812,7 @@ impl KvmVcpu {
             }
         }
         self.fd
-            .set_vcpu_events(&state.vcpu_events)
+            .set_vcpu_events(&vcpu_events)
             .map_err(Error::VcpuSetVcpuEvents)?;
         Ok(())
     }
@@ -521,6 +836,42 @@ impl KvmVcpu {
                 }
                 Ok(VcpuEmulation::Handled)
             }
+            VcpuExit::IoapicEoi(vector) => {
+                if let Some(ioapic) = &self.ioapic {
+                    ioapic
+                        .lock()
+                        .expect("Poisoned lock")
+                        .end_of_interrupt(vector);
+                }
+                Ok(VcpuEmulation::Handled)
+            }
+            VcpuExit::Debug(debug_exit) => {
+                // While single-stepping, KVM exits on all the #DB, including the ones raised
+                // by the guest itself (e.g. its own hardware breakpoints), which are given
+                // back to the guest.
+                if debug_exit.exception == u32::from(DB_VECTOR) && debug_exit.dr6 & DR6_BS == 0 {
+                    self.inject_exception(DB_VECTOR, None, Some(debug_exit.dr6 ^ DR6_ACTIVE_LOW))
+                        .map_err(super::Error::VcpuResponse)?;
+                    return Ok(VcpuEmulation::Handled);
+                }
+
+                if let Some(debug_channel) = &self.debug_channel {
+                    let sregs = match self.kvm_run.as_ref().and_then(KvmRunMapping::sregs) {
+                        Some(sregs) => sregs,
+                        None => self
+                            .fd
+                            .get_sregs()
+                            .map_err(|err| super::Error::VcpuResponse(Error::VcpuGetSregs(err)))?,
+                    };
+                    // KVM reports the linear address of the instruction.
+                    debug_channel.send(VcpuDebugEvent {
+                        index: self.index,
+                        rip: debug_exit.pc.wrapping_sub(sregs.cs.base),
+                        cs: sregs.cs.selector,
+                    });
+                }
+                Ok(VcpuEmulation::Handled)
+            }
             unexpected_exit => {
                 METRICS.vcpu.failures.inc();
                 // TODO: Are we sure we want to finish running a vcpu upon
@@ -619,10 +970,12 @@ mod tests {
 
     use std::os::unix::io::AsRawFd;
 
-    use cpuid::common::{get_vendor_id_from_host, VENDOR_ID_INTEL};
+    use cpuid::common::{get_vendor_id_from_host, VENDOR_ID_AMD, VENDOR_ID_INTEL};
+    use kvm_bindings::kvm_debug_exit_arch;
     use kvm_ioctls::Cap;
 
     use super::*;
+    use crate::vmm_config::machine_config::IrqChipMode;
     use crate::vstate::vm::tests::setup_vm;
     use crate::vstate::vm::Vm;
 
@@ -646,12 +999,29 @@ mod tests {
     }
 
     fn setup_vcpu(mem_size: usize) -> (Vm, KvmVcpu, GuestMemoryMmap) {
-        let (vm, vm_mem) = setup_vm(mem_size);
-        vm.setup_irqchip().unwrap();
+        let (mut vm, vm_mem) = setup_vm(mem_size);
+        vm.setup_irqchip(IrqChipMode::InKernel).unwrap();
         let vcpu = KvmVcpu::new(0, &vm).unwrap();
         (vm, vcpu, vm_mem)
     }
 
+    #[test]
+    fn test_create_vcpu() {
+        let (vm, _vcpu, _) = setup_vcpu(0x10000);
+
+        // The index is already used by the first vCPU.
+        match KvmVcpu::new(0, &vm) {
+            Err(err @ Error::CreateFailed { .. }) => assert_eq!(
+                err.to_string(),
+                format!(
+                    "Cannot create vCPU 0: {} (a vCPU with the same index was already created)",
+                    std::io::Error::from_raw_os_error(libc::EEXIST)
+                )
+            ),
+            _ => panic!("Unexpected result."),
+        }
+    }
+
     #[test]
     fn test_configure_vcpu() {
         let (vm, mut vcpu, vm_mem) = setup_vcpu(0x10000);
@@ -662,6 +1032,7 @@ mod tests {
             cpu_template: CpuFeaturesTemplate::None,
         };
 
+        let entries_transformed = METRICS.vcpu.cpuid_entries_transformed.count();
         assert!(vcpu
             .configure(
                 &vm_mem,
@@ -670,6 +1041,7 @@ mod tests {
                 vm.supported_cpuid().clone()
             )
             .is_ok());
+        assert!(METRICS.vcpu.cpuid_entries_transformed.count() > entries_transformed);
 
         // Test configure while using the T2 template.
         vcpu_config.cpu_template = CpuFeaturesTemplate::T2;
@@ -698,16 +1070,45 @@ mod tests {
             vm.supported_cpuid().clone(),
         );
 
+        // Test configure while using the T2CL template.
+        vcpu_config.cpu_template = CpuFeaturesTemplate::T2CL;
+        let t2cl_res = vcpu.configure(
+            &vm_mem,
+            GuestAddress(0),
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        );
+
+        // Test configure while using the T2A template.
+        vcpu_config.cpu_template = CpuFeaturesTemplate::T2A;
+        let t2a_res = vcpu.configure(
+            &vm_mem,
+            GuestAddress(0),
+            &vcpu_config,
+            vm.supported_cpuid().clone(),
+        );
+
         match &get_vendor_id_from_host().unwrap() {
             VENDOR_ID_INTEL => {
                 assert!(t2_res.is_ok());
                 assert!(c3_res.is_ok());
                 assert!(t2s_res.is_ok());
+                assert!(t2cl_res.is_ok());
+                assert!(t2a_res.is_err());
+            }
+            VENDOR_ID_AMD => {
+                assert!(t2_res.is_err());
+                assert!(c3_res.is_err());
+                assert!(t2s_res.is_err());
+                assert!(t2cl_res.is_err());
+                assert!(t2a_res.is_ok());
             }
             _ => {
                 assert!(t2_res.is_err());
                 assert!(c3_res.is_err());
                 assert!(t2s_res.is_err());
+                assert!(t2cl_res.is_err());
+                assert!(t2a_res.is_err());
             }
         }
     }
@@ -771,4 +1172,139 @@ mod tests {
             assert!(vcpu.set_tsc_khz(state.tsc_khz.unwrap()).is_err());
         }
     }
+
+    #[test]
+    fn test_single_step() {
+        let (_vm, mut vcpu, _) = setup_vcpu(0x1000);
+        vcpu.set_single_step(true).unwrap();
+        vcpu.set_single_step(false).unwrap();
+
+        // At reset, CS has the 0xf000 selector and the 0xffff0000 base.
+        let debug_exit = kvm_debug_exit_arch {
+            exception: u32::from(DB_VECTOR),
+            pc: 0xffff_fff0,
+            dr6: DR6_ACTIVE_LOW ^ DR6_BS,
+            ..Default::default()
+        };
+
+        // Without a debug channel, the debug exits are ignored.
+        assert_eq!(
+            vcpu.run_arch_emulation(VcpuExit::Debug(debug_exit))
+                .unwrap(),
+            VcpuEmulation::Handled
+        );
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let event_fd = EventFd::new(libc::EFD_NONBLOCK).unwrap();
+        vcpu.set_debug_channel(VcpuDebugChannel::new(sender, event_fd.try_clone().unwrap()));
+        assert_eq!(
+            vcpu.run_arch_emulation(VcpuExit::Debug(debug_exit))
+                .unwrap(),
+            VcpuEmulation::Handled
+        );
+        assert_eq!(event_fd.read().unwrap(), 1);
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            VcpuDebugEvent {
+                index: 0,
+                rip: 0xfff0,
+                cs: 0xf000,
+            }
+        );
+
+        // The #DB raised by a hardware breakpoint of the guest is given back to the guest.
+        let guest_debug_exit = kvm_debug_exit_arch {
+            dr6: DR6_ACTIVE_LOW ^ 0x1,
+            ..debug_exit
+        };
+        assert_eq!(
+            vcpu.run_arch_emulation(VcpuExit::Debug(guest_debug_exit))
+                .unwrap(),
+            VcpuEmulation::Handled
+        );
+        assert!(receiver.try_recv().is_err());
+        let events = vcpu.fd.get_vcpu_events().unwrap();
+        assert_eq!(events.exception.nr, DB_VECTOR);
+        assert_eq!(events.exception.pending | events.exception.injected, 1);
+    }
+
+    #[test]
+    fn test_sync_sregs() {
+        let (vm, vcpu, _) = setup_vcpu(0x1000);
+        assert_eq!(vcpu.kvm_run.is_some(), vm.sync_sregs_supported());
+        let kvm_run = match &vcpu.kvm_run {
+            Some(kvm_run) => kvm_run,
+            None => return,
+        };
+
+        // The special registers are only synchronized while single-stepping.
+        assert!(kvm_run.sregs().is_none());
+        vcpu.set_single_step(true).unwrap();
+        assert!(kvm_run.sregs().is_some());
+        vcpu.set_single_step(false).unwrap();
+        assert!(kvm_run.sregs().is_none());
+    }
+
+    #[test]
+    fn test_inject_exception() {
+        let (vm, vcpu, _) = setup_vcpu(0x1000);
+
+        vcpu.inject_exception(PF_VECTOR, Some(0x2), Some(0xdead_b000))
+            .unwrap();
+        let events = vcpu.fd.get_vcpu_events().unwrap();
+        assert_eq!(events.exception.nr, PF_VECTOR);
+        assert_eq!(events.exception.has_error_code, 1);
+        assert_eq!(events.exception.error_code, 0x2);
+        if vm.exception_payload_enabled() {
+            assert_eq!(events.exception.pending, 1);
+            assert_eq!(events.exception_has_payload, 1);
+            assert_eq!(events.exception_payload, 0xdead_b000);
+        } else {
+            assert_eq!(events.exception.injected, 1);
+            assert_eq!(vcpu.fd.get_sregs().unwrap().cr2, 0xdead_b000);
+        }
+
+        // The payload is folded into the registers on save.
+        let state = vcpu.save_state().unwrap();
+        assert_eq!(state.vcpu_events.flags & KVM_VCPUEVENT_VALID_PAYLOAD, 0);
+        assert_eq!(state.vcpu_events.exception.injected, 1);
+        assert_eq!(state.sregs.cr2, 0xdead_b000);
+        assert!(vcpu.restore_state(&state).is_ok());
+
+        // Exceptions without an error code nor a payload.
+        vcpu.inject_exception(GP_VECTOR, None, None).unwrap();
+        let events = vcpu.fd.get_vcpu_events().unwrap();
+        assert_eq!(events.exception.nr, GP_VECTOR);
+        assert_eq!(events.exception.has_error_code, 0);
+        assert_eq!(events.exception_has_payload, 0);
+    }
+
+    #[test]
+    fn test_fold_exception_payload() {
+        let mut events = kvm_vcpu_events::default();
+        events.flags = KVM_VCPUEVENT_VALID_PAYLOAD;
+        events.exception.nr = DB_VECTOR;
+        events.exception.pending = 1;
+        events.exception_has_payload = 1;
+        // The single-step bit.
+        events.exception_payload = DR6_BS;
+        let mut sregs = kvm_sregs::default();
+        let mut debug_regs = kvm_debugregs::default();
+
+        fold_exception_payload(&mut events, &mut sregs, &mut debug_regs);
+        assert_eq!(events.flags, 0);
+        assert_eq!(events.exception.injected, 1);
+        assert_eq!(events.exception.pending, 0);
+        assert_eq!(events.exception_has_payload, 0);
+        assert_eq!(debug_regs.dr6, 0xffff_4ff0);
+        assert_eq!(sregs.cr2, 0);
+
+        // The events without a payload are left untouched.
+        let mut events = kvm_vcpu_events::default();
+        events.exception.nr = PF_VECTOR;
+        events.exception.injected = 1;
+        let expected = events;
+        fold_exception_payload(&mut events, &mut sregs, &mut debug_regs);
+        assert_eq!(events, expected);
+    }
 }
@@ -6,6 +6,14 @@
 // found in the THIRD-PARTY file.
 
 use std::fmt::Formatter;
+#[cfg(target_arch = "x86_64")]
+use std::fs::File;
+#[cfg(target_arch = "x86_64")]
+use std::io;
+#[cfg(target_arch = "x86_64")]
+use std::os::raw::{c_uint, c_ulong};
+#[cfg(target_arch = "x86_64")]
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::{fmt, result};
 
 #[cfg(target_arch = "aarch64")]
@@ -13,17 +21,35 @@ use arch::aarch64::gic::GICDevice;
 #[cfg(target_arch = "aarch64")]
 use arch::aarch64::gic::GicState;
 #[cfg(target_arch = "x86_64")]
+use devices::legacy::{IoapicRoute, IoapicRouting, IOAPIC_NUM_PINS};
+#[cfg(target_arch = "x86_64")]
 use kvm_bindings::{
-    kvm_clock_data, kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList,
-    KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC, KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE,
-    KVM_MAX_CPUID_ENTRIES, KVM_PIT_SPEAKER_DUMMY,
+    kvm_clock_data, kvm_enable_cap, kvm_irq_routing, kvm_irq_routing_entry, kvm_irq_routing_msi,
+    kvm_irqchip, kvm_pit_config, kvm_pit_state2, CpuId, MsrList, KVM_CAP_EXCEPTION_PAYLOAD,
+    KVM_CAP_SPLIT_IRQCHIP, KVM_CAP_SYNC_REGS, KVM_CLOCK_TSC_STABLE, KVM_IRQCHIP_IOAPIC,
+    KVM_IRQCHIP_PIC_MASTER, KVM_IRQCHIP_PIC_SLAVE, KVM_IRQ_ROUTING_MSI, KVM_MAX_CPUID_ENTRIES,
+    KVM_PIT_SPEAKER_DUMMY, KVM_SYNC_X86_SREGS,
 };
 use kvm_bindings::{kvm_userspace_memory_region, KVM_MEM_LOG_DIRTY_PAGES};
 use kvm_ioctls::{Kvm, VmFd};
+#[cfg(target_arch = "x86_64")]
+use utils::ioctl::{ioctl_with_ref, ioctl_with_val};
+#[cfg(target_arch = "x86_64")]
+use utils::{ioctl_io_nr, ioctl_ioc_nr, ioctl_iow_nr};
 use versionize::{VersionMap, Versionize, VersionizeResult};
 use versionize_derive::Versionize;
 use vm_memory::{Address, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
 
+#[cfg(target_arch = "x86_64")]
+use crate::vmm_config::machine_config::IrqChipMode;
+
+#[cfg(target_arch = "x86_64")]
+const KVMIO: c_uint = 0xAE;
+#[cfg(target_arch = "x86_64")]
+ioctl_io_nr!(KVM_CHECK_EXTENSION, KVMIO, 0x03);
+#[cfg(target_arch = "x86_64")]
+ioctl_iow_nr!(KVM_SET_GSI_ROUTING, KVMIO, 0x6a, kvm_irq_routing);
+
 /// Errors associated with the wrappers over KVM ioctls.
 #[derive(Debug)]
 pub enum Error {
@@ -59,6 +85,9 @@ pub enum Error {
     VmSetIrqChip(kvm_ioctls::Error),
     /// Cannot configure the microvm.
     VmSetup(kvm_ioctls::Error),
+    #[cfg(target_arch = "x86_64")]
+    /// The state of a split irqchip cannot be saved.
+    SplitIrqChipState,
     #[cfg(target_arch = "aarch64")]
     SaveGic(arch::aarch64::gic::Error),
     #[cfg(target_arch = "aarch64")]
@@ -126,6 +155,11 @@ impl fmt::Display for Error {
             VmSetClock(err) => write!(f, "Failed to set KVM vm clock: {}", err),
             #[cfg(target_arch = "x86_64")]
             VmSetIrqChip(err) => write!(f, "Failed to set KVM vm irqchip: {}", err),
+            #[cfg(target_arch = "x86_64")]
+            SplitIrqChipState => write!(
+                f,
+                "Saving the state of a microVM with a split irqchip is not supported"
+            ),
             #[cfg(target_arch = "aarch64")]
             SaveGic(err) => write!(f, "Failed to save the VM's GIC state: {:?}", err),
             #[cfg(target_arch = "aarch64")]
@@ -145,6 +179,12 @@ pub struct Vm {
     supported_cpuid: CpuId,
     #[cfg(target_arch = "x86_64")]
     supported_msrs: MsrList,
+    #[cfg(target_arch = "x86_64")]
+    irqchip_mode: IrqChipMode,
+    #[cfg(target_arch = "x86_64")]
+    exception_payload: bool,
+    #[cfg(target_arch = "x86_64")]
+    sync_sregs: bool,
 
     // Arm specific fields.
     // On aarch64 we need to keep around the fd obtained by creating the VGIC device.
@@ -165,6 +205,10 @@ impl Vm {
         #[cfg(target_arch = "x86_64")]
         let supported_msrs =
             arch::x86_64::msr::supported_guest_msrs(kvm).map_err(Error::GuestMSRs)?;
+        #[cfg(target_arch = "x86_64")]
+        let exception_payload = Self::enable_exception_payload(&vm_fd)?;
+        #[cfg(target_arch = "x86_64")]
+        let sync_sregs = Self::check_sync_sregs(&vm_fd);
 
         Ok(Vm {
             fd: vm_fd,
@@ -172,6 +216,12 @@ impl Vm {
             supported_cpuid,
             #[cfg(target_arch = "x86_64")]
             supported_msrs,
+            #[cfg(target_arch = "x86_64")]
+            irqchip_mode: IrqChipMode::InKernel,
+            #[cfg(target_arch = "x86_64")]
+            exception_payload,
+            #[cfg(target_arch = "x86_64")]
+            sync_sregs,
             #[cfg(target_arch = "aarch64")]
             irqchip_handle: None,
         })
@@ -189,6 +239,63 @@ impl Vm {
         &self.supported_msrs
     }
 
+    /// Returns whether the exceptions injected in the vcpus of this Vm can carry a payload
+    /// (`KVM_CAP_EXCEPTION_PAYLOAD`).
+    #[cfg(target_arch = "x86_64")]
+    pub fn exception_payload_enabled(&self) -> bool {
+        self.exception_payload
+    }
+
+    /// Returns whether KVM can copy the special registers of the vcpus of this Vm to their
+    /// `kvm_run` structure on exit (`KVM_CAP_SYNC_REGS`).
+    #[cfg(target_arch = "x86_64")]
+    pub fn sync_sregs_supported(&self) -> bool {
+        self.sync_sregs
+    }
+
+    // Checks whether `KVM_CAP_SYNC_REGS` covers the special registers.
+    #[cfg(target_arch = "x86_64")]
+    fn check_sync_sregs(vm_fd: &VmFd) -> bool {
+        // The capability reports the mask of the registers KVM can synchronize, which is
+        // lost by `VmFd::check_extension`.
+        // SAFETY: Safe because the VM fd is valid and we check the return value.
+        let ret = unsafe {
+            ioctl_with_val(
+                vm_fd,
+                KVM_CHECK_EXTENSION(),
+                c_ulong::from(KVM_CAP_SYNC_REGS),
+            )
+        };
+        ret > 0 && (ret as u32) & KVM_SYNC_X86_SREGS != 0
+    }
+
+    // Enables `KVM_CAP_EXCEPTION_PAYLOAD` if the host supports it, in which case KVM delivers
+    // the payload of the injected exceptions (CR2 for #PF, DR6 for #DB) along with them instead
+    // of updating the registers beforehand.
+    #[cfg(target_arch = "x86_64")]
+    fn enable_exception_payload(vm_fd: &VmFd) -> Result<bool> {
+        // `Cap` doesn't know about this capability, so check it directly.
+        // SAFETY: Safe because the VM fd is valid and we check the return value.
+        let ret = unsafe {
+            ioctl_with_val(
+                vm_fd,
+                KVM_CHECK_EXTENSION(),
+                c_ulong::from(KVM_CAP_EXCEPTION_PAYLOAD),
+            )
+        };
+        if ret <= 0 {
+            return Ok(false);
+        }
+
+        let mut cap = kvm_enable_cap {
+            cap: KVM_CAP_EXCEPTION_PAYLOAD,
+            ..Default::default()
+        };
+        cap.args[0] = 1;
+        vm_fd.enable_cap(&cap).map_err(Error::VmSetup)?;
+        Ok(true)
+    }
+
     /// Initializes the guest memory.
     pub fn memory_init(
         &mut self,
@@ -208,17 +315,56 @@ impl Vm {
         Ok(())
     }
 
-    /// Creates the irq chip and an in-kernel device model for the PIT.
+    /// Creates the irq chip and, unless the irqchip is split, an in-kernel device model for the
+    /// PIT.
+    ///
+    /// With a split irqchip KVM only emulates the local APICs, and the IOAPIC has to be
+    /// emulated in userspace.
     #[cfg(target_arch = "x86_64")]
-    pub fn setup_irqchip(&self) -> Result<()> {
-        self.fd.create_irq_chip().map_err(Error::VmSetup)?;
-        // We need to enable the emulation of a dummy speaker port stub so that writing to port 0x61
-        // (i.e. KVM_SPEAKER_BASE_ADDRESS) does not trigger an exit to user space.
-        let pit_config = kvm_pit_config {
-            flags: KVM_PIT_SPEAKER_DUMMY,
-            ..Default::default()
-        };
-        self.fd.create_pit2(pit_config).map_err(Error::VmSetup)
+    pub fn setup_irqchip(&mut self, irqchip_mode: IrqChipMode) -> Result<()> {
+        match irqchip_mode {
+            IrqChipMode::InKernel => {
+                self.fd.create_irq_chip().map_err(Error::VmSetup)?;
+                // We need to enable the emulation of a dummy speaker port stub so that writing to
+                // port 0x61 (i.e. KVM_SPEAKER_BASE_ADDRESS) does not trigger an exit to user space.
+                let pit_config = kvm_pit_config {
+                    flags: KVM_PIT_SPEAKER_DUMMY,
+                    ..Default::default()
+                };
+                self.fd.create_pit2(pit_config).map_err(Error::VmSetup)?;
+            }
+            IrqChipMode::Split => {
+                // The argument is the number of IOAPIC pins KVM reserves routes for.
+                let mut cap = kvm_enable_cap {
+                    cap: KVM_CAP_SPLIT_IRQCHIP,
+                    ..Default::default()
+                };
+                cap.args[0] = IOAPIC_NUM_PINS as u64;
+                self.fd.enable_cap(&cap).map_err(Error::VmSetup)?;
+            }
+        }
+        self.irqchip_mode = irqchip_mode;
+        Ok(())
+    }
+
+    /// Returns the way the irqchip of this Vm is emulated.
+    #[cfg(target_arch = "x86_64")]
+    pub fn irqchip_mode(&self) -> IrqChipMode {
+        self.irqchip_mode
+    }
+
+    /// Returns an object programming the routes of a userspace IOAPIC into this Vm.
+    #[cfg(target_arch = "x86_64")]
+    pub fn ioapic_routing(&self) -> Result<KvmIoapicRouting> {
+        // SAFETY: Safe because the VM fd is valid and we check the return value.
+        let vm_fd = unsafe { libc::dup(self.fd.as_raw_fd()) };
+        if vm_fd < 0 {
+            return Err(Error::VmFd(kvm_ioctls::Error::last()));
+        }
+        Ok(KvmIoapicRouting {
+            // SAFETY: Safe because we have just duplicated the fd, so we own it.
+            vm_fd: unsafe { File::from_raw_fd(vm_fd) },
+        })
     }
 
     /// Creates the GIC (Global Interrupt Controller).
@@ -248,6 +394,11 @@ impl Vm {
     #[cfg(target_arch = "x86_64")]
     /// Saves and returns the Kvm Vm state.
     pub fn save_state(&self) -> Result<VmState> {
+        // The state of the userspace IOAPIC is not part of the snapshot format.
+        if self.irqchip_mode == IrqChipMode::Split {
+            return Err(Error::SplitIrqChipState);
+        }
+
         let pitstate = self.fd.get_pit2().map_err(Error::VmGetPit2)?;
 
         let mut clock = self.fd.get_clock().map_err(Error::VmGetClock)?;
@@ -373,6 +524,55 @@ impl Vm {
     }
 }
 
+/// The GSI routing table of a split irqchip, laid out as a `kvm_irq_routing` with its entries.
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+struct IoapicRoutingTable {
+    nr: u32,
+    flags: u32,
+    entries: [kvm_irq_routing_entry; IOAPIC_NUM_PINS],
+}
+
+/// Programs the pins of a userspace IOAPIC as MSI routes of their GSIs.
+#[cfg(target_arch = "x86_64")]
+pub struct KvmIoapicRouting {
+    vm_fd: File,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl IoapicRouting for KvmIoapicRouting {
+    fn set_routes(&mut self, routes: &[IoapicRoute]) -> io::Result<()> {
+        let mut table = IoapicRoutingTable {
+            nr: 0,
+            flags: 0,
+            entries: [kvm_irq_routing_entry::default(); IOAPIC_NUM_PINS],
+        };
+
+        let msi_routes = routes
+            .iter()
+            .filter_map(|route| route.msi.map(|msi| (route.pin, msi)));
+        for (entry, (gsi, msi)) in table.entries.iter_mut().zip(msi_routes) {
+            entry.gsi = gsi;
+            entry.type_ = KVM_IRQ_ROUTING_MSI;
+            entry.u.msi = kvm_irq_routing_msi {
+                address_lo: msi.address_lo,
+                address_hi: msi.address_hi,
+                data: msi.data,
+                ..Default::default()
+            };
+            table.nr += 1;
+        }
+
+        // SAFETY: Safe because the fd is a VM fd, the table is laid out as the kernel expects and
+        // holds `nr` entries. We check the return value.
+        let ret = unsafe { ioctl_with_ref(&self.vm_fd, KVM_SET_GSI_ROUTING(), &table) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
 #[cfg(target_arch = "x86_64")]
 #[derive(Versionize)]
 /// Structure holding VM kvm state.
@@ -399,6 +599,8 @@ pub(crate) mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
     use std::os::unix::io::FromRawFd;
 
+    #[cfg(target_arch = "x86_64")]
+    use devices::legacy::MsiMessage;
     use vm_memory::GuestAddress;
 
     use super::*;
@@ -466,8 +668,8 @@ pub(crate) mod tests {
         // Irqchips, clock and pitstate are not configured so trying to save state should fail.
         assert!(vm.save_state().is_err());
 
-        let (vm, _mem) = setup_vm(0x1000);
-        vm.setup_irqchip().unwrap();
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip(IrqChipMode::InKernel).unwrap();
 
         let vm_state = vm.save_state().unwrap();
         assert_eq!(
@@ -479,12 +681,38 @@ pub(crate) mod tests {
         assert_eq!(vm_state.pic_slave.chip_id, KVM_IRQCHIP_PIC_SLAVE);
         assert_eq!(vm_state.ioapic.chip_id, KVM_IRQCHIP_IOAPIC);
 
-        let (vm, _mem) = setup_vm(0x1000);
-        vm.setup_irqchip().unwrap();
+        let (mut vm, _mem) = setup_vm(0x1000);
+        vm.setup_irqchip(IrqChipMode::InKernel).unwrap();
 
         assert!(vm.restore_state(&vm_state).is_ok());
     }
 
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn test_setup_split_irqchip() {
+        let (mut vm, _mem) = setup_vm(0x1000);
+        assert_eq!(vm.irqchip_mode(), IrqChipMode::InKernel);
+        vm.setup_irqchip(IrqChipMode::Split).unwrap();
+        assert_eq!(vm.irqchip_mode(), IrqChipMode::Split);
+
+        // There is no in-kernel IOAPIC or PIT to save.
+        assert!(matches!(vm.save_state(), Err(Error::SplitIrqChipState)));
+
+        let mut routing = vm.ioapic_routing().unwrap();
+        let routes = [
+            IoapicRoute {
+                pin: 4,
+                msi: Some(MsiMessage {
+                    address_lo: 0xfee0_0000,
+                    address_hi: 0,
+                    data: 0x24,
+                }),
+            },
+            IoapicRoute { pin: 5, msi: None },
+        ];
+        routing.set_routes(&routes).unwrap();
+    }
+
     #[test]
     fn test_set_kvm_memory_regions() {
         let kvm_context = KvmContext::new().unwrap();
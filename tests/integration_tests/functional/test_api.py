@@ -1264,6 +1264,8 @@ def test_get_full_config_after_restoring_snapshot(bin_cloner_path):
             "host_dev_name": DEFAULT_TAP_NAME,
             "rx_rate_limiter": None,
             "tx_rate_limiter": tx_rl,
+            "stats_reset_on_read": False,
+            "enable_ctrl_vq": False,
         }
     ]
     # Create a snapshot builder from a microvm.
@@ -1374,6 +1376,8 @@ def test_get_full_config(test_microvm_with_api):
             "guest_mac": "06:00:00:00:00:01",
             "rx_rate_limiter": None,
             "tx_rate_limiter": tx_rl,
+            "stats_reset_on_read": False,
+            "enable_ctrl_vq": False,
         }
     ]
 
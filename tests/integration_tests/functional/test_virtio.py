@@ -0,0 +1,49 @@
+# Copyright 2022 Amazon.com, Inc. or its affiliates. All Rights Reserved.
+# SPDX-License-Identifier: Apache-2.0
+"""Tests the virtio feature negotiation with the kernel drivers of the guest."""
+
+from framework.utils_vsock import VSOCK_UDS_PATH
+from host_tools.network import SSHConnection
+
+# The bit of the VIRTIO_F_VERSION_1 feature.
+VIRTIO_F_VERSION_1 = 32
+# The device status bits set by a driver which completed the feature negotiation.
+FEATURES_OK = 0x8
+DRIVER_OK = 0x4
+
+
+def test_virtio_version_1(test_microvm_with_api, network_config):
+    """
+    Test that the kernel drivers negotiate VIRTIO_F_VERSION_1 with every device.
+
+    @type: functional
+    """
+    vm = test_microvm_with_api
+    vm.spawn()
+    vm.basic_config()
+    _tap, _, _ = vm.ssh_network_config(network_config, "1")
+    vm.vsock.put(vsock_id="vsock0", guest_cid=3, uds_path="/{}".format(VSOCK_UDS_PATH))
+    response = vm.balloon.put(
+        amount_mib=0, deflate_on_oom=True, stats_polling_interval_s=0
+    )
+    assert vm.api_session.is_status_no_content(response.status_code)
+    vm.start()
+
+    conn = SSHConnection(vm.ssh_config)
+    _, stdout, _ = conn.execute_command("ls /sys/bus/virtio/devices")
+    devices = stdout.read().split()
+    # The block, net, vsock and balloon devices.
+    assert len(devices) == 4
+
+    for device in devices:
+        path = "/sys/bus/virtio/devices/{}".format(device)
+
+        # The features are listed as a string of 0s and 1s, starting with bit 0.
+        _, stdout, _ = conn.execute_command("cat {}/features".format(path))
+        features = stdout.read().strip()
+        assert features[VIRTIO_F_VERSION_1] == "1", device
+
+        # The device accepted the features acked by the driver.
+        _, stdout, _ = conn.execute_command("cat {}/status".format(path))
+        status = int(stdout.read().strip(), 16)
+        assert status & (FEATURES_OK | DRIVER_OK) == FEATURES_OK | DRIVER_OK, device